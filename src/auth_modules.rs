@@ -0,0 +1,215 @@
+//!
+//! Credential profiles of `/probe`.
+//!
+//! The servers probed by `/probe` often need credentials other than those given by
+//! `--user`. `--auth-modules <file>` reads named profiles, referenced by the `auth_module`
+//! parameter of `/probe` like `auth_modules` of postgres_exporter, and the profiles used
+//! for targets by default:
+//!
+//! ```json
+//! {
+//!   "auth_modules": {
+//!     "prod": { "user": "monitor", "password_file": "/run/secrets/prod-monitor" },
+//!     "staging": { "user": "monitor", "password_env": "STAGING_PASSWORD", "dbname": "app" }
+//!   },
+//!   "targets": { "db1.example.com": "prod", "10.0.1.5:6432": "staging" }
+//! }
+//! ```
+//!
+//! A password file is watched, so that a rotated password is picked up by the next
+//! connection without restarting the exporter. A profile replaces the credentials of `--user`
+//! altogether. The password of the exporter is never sent to a probed server: a target
+//! without a profile is connected to as `--user` with only the password found for its host
+//! in the password file of libpq.
+//!
+use anyhow::{bail, Context};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use url::Host;
+
+use crate::postgres_connection::{parse_host_port, PgConnectionConfig};
+use crate::secret_files::SecretFile;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthModule {
+    pub user: String,
+    /// The database connected to, which defaults to that of `--dbname`.
+    pub dbname: Option<String>,
+    password: Option<String>,
+    password_file: Option<PathBuf>,
+    password_env: Option<String>,
+    /// The contents of `password_file`, set by `load`.
+    #[serde(skip)]
+    password_secret: Option<SecretFile>,
+}
+
+impl AuthModule {
+    /// Returns the password, which is read from the file or the environment variable if
+    /// given so.
+    fn password(&self) -> anyhow::Result<Option<String>> {
+        if let Some(secret) = &self.password_secret {
+            return Ok(Some(secret.get()));
+        }
+        if let Some(name) = &self.password_env {
+            return std::env::var(name)
+                .map(Some)
+                .with_context(|| format!("failed to read ${name}"));
+        }
+        Ok(self.password.clone())
+    }
+
+    /// Returns `postgres` with the credentials of this profile.
+    pub fn apply(&self, postgres: PgConnectionConfig) -> anyhow::Result<PgConnectionConfig> {
+        let postgres = postgres
+            .set_user(Some(self.user.clone()))
+            .set_password(self.password()?);
+        Ok(match &self.dbname {
+            Some(dbname) => postgres.set_dbname(Some(dbname.clone())),
+            None => postgres,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthModules {
+    #[serde(default)]
+    auth_modules: HashMap<String, AuthModule>,
+    /// Profiles of targets given without `auth_module`, by `<host>[:<port>]`.
+    #[serde(default)]
+    targets: HashMap<String, String>,
+}
+
+impl AuthModules {
+    /// Returns the profile to connect to `target` with: `name` if given, or the default
+    /// one of the target. `None` means the user of the exporter, without its password.
+    pub fn select(&self, name: Option<&str>, target: &str) -> anyhow::Result<Option<&AuthModule>> {
+        let name = match name {
+            Some(name) => name,
+            None => match self.default_of(target) {
+                Some(name) => name,
+                None => return Ok(None),
+            },
+        };
+        match self.auth_modules.get(name) {
+            Some(module) => Ok(Some(module)),
+            None => bail!("unknown auth module `{name}`"),
+        }
+    }
+
+    fn default_of(&self, target: &str) -> Option<&str> {
+        let key = normalize(target).ok()?;
+        self.targets
+            .iter()
+            .find(|(t, _)| normalize(t).is_ok_and(|t| t == key))
+            .map(|(_, name)| name.as_str())
+    }
+
+    /// Returns the password files of the profiles, which are to be watched.
+    pub fn secret_files(&self) -> Vec<SecretFile> {
+        self.auth_modules
+            .values()
+            .filter_map(|m| m.password_secret.clone())
+            .collect()
+    }
+}
+
+/// Returns `target` with the default port, so that `db` and `db:5432` are the same target.
+fn normalize(target: &str) -> anyhow::Result<(Host, u16)> {
+    let (host, port) = parse_host_port(target)?;
+    let host = match host {
+        Host::Domain(name) => Host::Domain(name.to_ascii_lowercase()),
+        host => host,
+    };
+    Ok((host, port.unwrap_or(5432)))
+}
+
+pub fn load(path: &Path) -> anyhow::Result<AuthModules> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let mut modules: AuthModules = serde_json::from_str(&content)
+        .with_context(|| format!("failed to parse {}", path.display()))?;
+    validate(&modules)?;
+    for (name, module) in modules.auth_modules.iter_mut() {
+        if let Some(path) = &module.password_file {
+            module.password_secret =
+                Some(SecretFile::load(path).with_context(|| format!("auth module {name}"))?);
+        }
+    }
+    Ok(modules)
+}
+
+fn validate(modules: &AuthModules) -> anyhow::Result<()> {
+    for (name, module) in &modules.auth_modules {
+        let sources = [
+            module.password.is_some(),
+            module.password_file.is_some(),
+            module.password_env.is_some(),
+        ];
+        if sources.iter().filter(|s| **s).count() > 1 {
+            bail!("auth module {name}: give only one of password, password_file and password_env");
+        }
+    }
+    for (target, name) in &modules.targets {
+        normalize(target).with_context(|| format!("invalid target `{target}`"))?;
+        if !modules.auth_modules.contains_key(name) {
+            bail!("target {target}: unknown auth module `{name}`");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests_auth_modules {
+    use crate::auth_modules::{validate, AuthModules};
+
+    fn parse(json: &str) -> AuthModules {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_select() {
+        let modules = parse(
+            r#"{
+                "auth_modules": {
+                    "prod": { "user": "monitor", "password": "secret" },
+                    "staging": { "user": "staging" }
+                },
+                "targets": { "DB1.example.com": "prod", "10.0.1.5:6432": "staging" }
+            }"#,
+        );
+        assert!(validate(&modules).is_ok());
+        let user = |name: Option<&str>, target: &str| {
+            modules
+                .select(name, target)
+                .unwrap()
+                .map(|m| m.user.clone())
+        };
+        assert_eq!(
+            user(None, "db1.example.com:5432").as_deref(),
+            Some("monitor")
+        );
+        assert_eq!(user(None, "10.0.1.5:6432").as_deref(), Some("staging"));
+        assert_eq!(user(None, "10.0.1.5"), None);
+        assert_eq!(
+            user(Some("staging"), "db1.example.com").as_deref(),
+            Some("staging")
+        );
+        assert!(modules.select(Some("dev"), "db1.example.com").is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&parse(
+            r#"{ "auth_modules": { "a": { "user": "u", "password": "p", "password_env": "P" } } }"#
+        ))
+        .is_err());
+        assert!(validate(&parse(r#"{ "targets": { "db": "a" } }"#)).is_err());
+        assert!(serde_json::from_str::<AuthModules>(
+            r#"{ "auth_modules": { "a": { "user": "u", "sslcert": "x" } } }"#
+        )
+        .is_err());
+    }
+}
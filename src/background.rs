@@ -0,0 +1,195 @@
+//!
+//! Background collection mode.
+//!
+//! Instead of querying PostgreSQL on every scrape, metrics are gathered on a fixed interval
+//! and `/metrics` serves the latest snapshot. This bounds the load on the database no
+//! matter how many Prometheus servers scrape the exporter, and keeps scrapes fast even when
+//! collection is slow. If a collection fails, the previous snapshot keeps being served, so
+//! `pg_exporter_last_success_timestamp_seconds` should be used to detect stale data.
+//!
+//! Collectors can be scheduled on different intervals, e.g., a short one for cheap
+//! collectors and a long one for size queries, and each schedule is jittered.
+//!
+//! Optionally, the latest snapshot is persisted to disk and served again right after a
+//! restart (flagged by `pg_exporter_snapshot_stale`) until the first collection succeeds,
+//! so that brief restarts don't create gaps.
+//!
+//! Optionally, samples carry the time they were collected (client-side timestamps), so
+//! Prometheus records the true observation time rather than the scrape time.
+//!
+use prometheus::{core::Collector, IntGauge};
+use rand::Rng;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::help;
+use crate::snapshot_file;
+
+type Snapshot = Arc<RwLock<Vec<prometheus::proto::MetricFamily>>>;
+
+struct Snapshots {
+    tasks: RwLock<Vec<Snapshot>>,
+    /// Families restored from `path` at startup, served until the first collection succeeds.
+    restored: RwLock<Vec<prometheus::proto::MetricFamily>>,
+    path: Option<PathBuf>,
+}
+
+impl Snapshots {
+    fn latest(&self) -> Vec<prometheus::proto::MetricFamily> {
+        self.tasks
+            .read()
+            .unwrap()
+            .iter()
+            .flat_map(|snapshot| snapshot.read().unwrap().clone())
+            .collect()
+    }
+
+    /// Writes the latest snapshots of all the tasks to `path`.
+    fn persist(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let metrics = self.latest();
+        if let Err(e) = snapshot_file::write(path, &metrics) {
+            tracing::warn!(
+                "failed to persist the snapshot to {}: {e:#}",
+                path.display()
+            );
+        }
+    }
+}
+
+pub struct BackgroundCollector {
+    with_timestamps: bool,
+    snapshots: Arc<Snapshots>,
+}
+
+impl BackgroundCollector {
+    /// If `with_timestamps` is set, samples are stamped with the time they were collected.
+    /// If `persist_path` is set, the latest snapshot is written to the file after every
+    /// collection, and the one left by a previous run is served until the first collection
+    /// succeeds.
+    pub fn new(with_timestamps: bool, persist_path: Option<PathBuf>) -> Self {
+        let restored = match &persist_path {
+            Some(path) if path.exists() => match snapshot_file::read(path) {
+                Ok(metrics) => {
+                    tracing::info!(
+                        families = metrics.len(),
+                        "restored the snapshot from {}",
+                        path.display()
+                    );
+                    metrics
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "failed to restore the snapshot from {}: {e:#}",
+                        path.display()
+                    );
+                    vec![]
+                }
+            },
+            _ => vec![],
+        };
+
+        BackgroundCollector {
+            with_timestamps,
+            snapshots: Arc::new(Snapshots {
+                tasks: RwLock::new(vec![]),
+                restored: RwLock::new(restored),
+                path: persist_path,
+            }),
+        }
+    }
+
+    /// Spawns a task awaiting `collect` every `interval`, delayed by a random duration of
+    /// up to `jitter` so that collections scheduled with the same interval don't hit the
+    /// database at once. The first collection is delayed likewise. `collect` returns `None` to keep the previous snapshot. Must be
+    /// called in a Tokio runtime.
+    pub fn spawn<F, Fut>(&mut self, interval: Duration, jitter: Duration, collect: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Option<Vec<prometheus::proto::MetricFamily>>> + Send,
+    {
+        let snapshot: Snapshot = Arc::new(RwLock::new(vec![]));
+        self.snapshots.tasks.write().unwrap().push(snapshot.clone());
+
+        let with_timestamps = self.with_timestamps;
+        let snapshots = self.snapshots.clone();
+        tokio::spawn(async move {
+            let delay = rand::thread_rng().gen_range(Duration::ZERO..=jitter);
+            tokio::time::sleep(delay).await;
+            loop {
+                if let Some(mut metrics) = collect().await {
+                    if with_timestamps {
+                        set_timestamps(&mut metrics, SystemTime::now());
+                    }
+                    // Restored families are not served nor persisted again once fresh data
+                    // exists, since they would be stale forever if no longer collected
+                    snapshots.restored.write().unwrap().clear();
+                    *snapshot.write().unwrap() = metrics;
+
+                    let snapshots = snapshots.clone();
+                    let _ = tokio::task::spawn_blocking(move || snapshots.persist()).await;
+                }
+                let delay = rand::thread_rng().gen_range(Duration::ZERO..=jitter);
+                tokio::time::sleep(interval + delay).await;
+            }
+        });
+    }
+
+    /// Returns the latest snapshots of all the tasks, which are empty until their first
+    /// collections complete unless restored from a persisted snapshot.
+    pub fn latest(&self) -> Vec<prometheus::proto::MetricFamily> {
+        let mut metrics = self.snapshots.latest();
+        if self.snapshots.path.is_some() {
+            let restored = self.snapshots.restored.read().unwrap();
+            let m = IntGauge::new(
+                "pg_exporter_snapshot_stale",
+                help::PG_EXPORTER_SNAPSHOT_STALE,
+            )
+            .unwrap();
+            m.set(!restored.is_empty() as i64);
+            metrics.extend(restored.iter().cloned());
+            metrics.append(&mut m.collect());
+        }
+        metrics
+    }
+}
+
+fn set_timestamps(metrics: &mut [prometheus::proto::MetricFamily], time: SystemTime) {
+    let timestamp_ms = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64;
+    for family in metrics.iter_mut() {
+        for metric in family.mut_metric().iter_mut() {
+            metric.set_timestamp_ms(timestamp_ms);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_set_timestamps {
+    use crate::background::set_timestamps;
+    use prometheus::{core::Collector, Encoder, IntGauge, TextEncoder};
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_encoded_with_timestamps() {
+        let m = IntGauge::new("test_gauge", "help").unwrap();
+        m.set(1);
+        let mut metrics = m.collect();
+        set_timestamps(
+            &mut metrics,
+            SystemTime::UNIX_EPOCH + Duration::from_millis(1700000000123),
+        );
+
+        let mut buf = vec![];
+        TextEncoder::new().encode(&metrics, &mut buf).unwrap();
+        assert!(String::from_utf8(buf)
+            .unwrap()
+            .ends_with("test_gauge 1 1700000000123\n"));
+    }
+}
@@ -0,0 +1,417 @@
+//!
+//! Backup health from pgBackRest or Barman.
+//!
+//! Backups are taken by tools outside PostgreSQL, so their health is usually monitored
+//! separately from the database. The collector runs `pgbackrest info --output=json` or
+//! `barman -f json list-backups` and `barman -f json check` on the exporter host, and
+//! exports the age and size of the last backup of every type and the WAL archive status.
+//! The tools can take seconds to query the repository, so their output is reused for an
+//! interval.
+//!
+//! See <https://pgbackrest.org/command.html#command-info> and
+//! <https://docs.pgbarman.org/release/latest/#check>
+//!
+use anyhow::{anyhow, bail, Context};
+use once_cell::sync::Lazy;
+use prometheus::{core::Collector, IntGaugeVec, Opts};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::Read;
+use std::process::{Command, ExitStatus, Stdio};
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::Mutex;
+
+use crate::help;
+
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The backup tool to query, given as `pgbackrest[:<stanza>]` or `barman:<server>`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BackupSource {
+    /// pgBackRest, for all the stanzas if none is given.
+    Pgbackrest(Option<String>),
+    Barman(String),
+}
+
+impl FromStr for BackupSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (tool, name) = match s.split_once(':') {
+            Some((tool, name)) => (tool, Some(name.to_string())),
+            None => (s, None),
+        };
+        match (tool, name) {
+            ("pgbackrest", name) => Ok(BackupSource::Pgbackrest(name)),
+            ("barman", Some(server)) => Ok(BackupSource::Barman(server)),
+            ("barman", None) => bail!("expected `barman:<server>`"),
+            _ => bail!("expected `pgbackrest[:<stanza>]` or `barman:<server>`, got `{s}`"),
+        }
+    }
+}
+
+impl BackupSource {
+    fn tool(&self) -> &'static str {
+        match self {
+            BackupSource::Pgbackrest(_) => "pgbackrest",
+            BackupSource::Barman(_) => "barman",
+        }
+    }
+}
+
+/// Health of the backups of a stanza of pgBackRest or a server of Barman.
+#[derive(Debug, Default, PartialEq)]
+struct BackupStatus {
+    name: String,
+    ok: bool,
+    wal_archive_ok: bool,
+    /// Stop time in Unix seconds and size in bytes of the last backup, by the backup type.
+    last_backups: HashMap<String, (u64, Option<u64>)>,
+}
+
+/// A subset of an element of the output of `pgbackrest info --output=json`.
+#[derive(Deserialize)]
+struct PgbackrestStanza {
+    name: String,
+    status: PgbackrestStanzaStatus,
+    #[serde(default)]
+    backup: Vec<PgbackrestBackup>,
+    #[serde(default)]
+    archive: Vec<PgbackrestArchive>,
+}
+
+#[derive(Deserialize)]
+struct PgbackrestStanzaStatus {
+    code: i64,
+}
+
+#[derive(Deserialize)]
+struct PgbackrestBackup {
+    #[serde(rename = "type")]
+    kind: String,
+    timestamp: PgbackrestTimestamp,
+    info: Option<PgbackrestBackupInfo>,
+}
+
+#[derive(Deserialize)]
+struct PgbackrestTimestamp {
+    stop: u64,
+}
+
+#[derive(Deserialize)]
+struct PgbackrestBackupInfo {
+    size: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct PgbackrestArchive {
+    max: Option<String>,
+}
+
+fn parse_pgbackrest(json: &str) -> anyhow::Result<Vec<BackupStatus>> {
+    let stanzas: Vec<PgbackrestStanza> = serde_json::from_str(json)?;
+    Ok(stanzas
+        .into_iter()
+        .map(|stanza| {
+            let mut last_backups = HashMap::new();
+            // Backups are listed from the oldest
+            for backup in stanza.backup {
+                last_backups.insert(
+                    backup.kind,
+                    (backup.timestamp.stop, backup.info.and_then(|i| i.size)),
+                );
+            }
+            BackupStatus {
+                name: stanza.name,
+                ok: stanza.status.code == 0,
+                wal_archive_ok: stanza.archive.iter().any(|a| a.max.is_some()),
+                last_backups,
+            }
+        })
+        .collect())
+}
+
+/// A subset of a backup in the output of `barman -f json list-backups <server>`.
+#[derive(Deserialize)]
+struct BarmanBackup {
+    status: String,
+    end_time_timestamp: Option<String>,
+    size_bytes: Option<u64>,
+}
+
+/// A check in the output of `barman -f json check <server>`.
+#[derive(Deserialize)]
+struct BarmanCheck {
+    status: String,
+}
+
+fn parse_barman(server: &str, backups: &str, checks: &str) -> anyhow::Result<BackupStatus> {
+    let mut backups: HashMap<String, Vec<BarmanBackup>> = serde_json::from_str(backups)?;
+    let mut checks: HashMap<String, HashMap<String, BarmanCheck>> = serde_json::from_str(checks)?;
+    let backups = backups.remove(server).unwrap_or_default();
+    let checks = checks
+        .remove(server)
+        .ok_or_else(|| anyhow!("no checks of {server}"))?;
+
+    // Barman takes full backups only, listed from the newest
+    let mut last_backups = HashMap::new();
+    if let Some(backup) = backups.iter().find(|b| b.status == "DONE") {
+        if let Some(stop) = backup
+            .end_time_timestamp
+            .as_deref()
+            .and_then(|t| t.parse::<f64>().ok())
+        {
+            last_backups.insert("full".to_string(), (stop as u64, backup.size_bytes));
+        }
+    }
+    let check_ok = |name: &str| checks.get(name).is_some_and(|c| c.status == "OK");
+    Ok(BackupStatus {
+        name: server.to_string(),
+        ok: checks.values().all(|c| c.status == "OK"),
+        wal_archive_ok: check_ok("wal archive") && check_ok("continuous archiving"),
+        last_backups,
+    })
+}
+
+/// Runs `program` and returns its exit status and standard output, killing it after
+/// `COMMAND_TIMEOUT`.
+fn run(program: &str, args: &[&str]) -> anyhow::Result<(ExitStatus, String)> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("failed to run {program}"))?;
+    // Read the output in the background so that a large one doesn't block the child
+    let mut stdout = child.stdout.take().unwrap();
+    let reader = std::thread::spawn(move || {
+        let mut buf = String::new();
+        stdout.read_to_string(&mut buf).map(|_| buf)
+    });
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if started.elapsed() > COMMAND_TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("{program} timed out");
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    };
+    let stdout = reader
+        .join()
+        .map_err(|_| anyhow!("failed to read the output of {program}"))??;
+    Ok((status, stdout))
+}
+
+/// Runs `program` and returns its standard output, failing if it exits non-zero.
+fn run_successfully(program: &str, args: &[&str]) -> anyhow::Result<String> {
+    let (status, stdout) = run(program, args)?;
+    if !status.success() {
+        bail!("{program} exited with {status}");
+    }
+    Ok(stdout)
+}
+
+fn query(source: &BackupSource) -> anyhow::Result<Vec<BackupStatus>> {
+    match source {
+        BackupSource::Pgbackrest(stanza) => {
+            let stanza = stanza.as_ref().map(|s| format!("--stanza={s}"));
+            let mut args = vec!["info", "--output=json"];
+            args.extend(stanza.as_deref());
+            parse_pgbackrest(&run_successfully("pgbackrest", &args)?)
+        }
+        BackupSource::Barman(server) => {
+            let backups = run_successfully("barman", &["-f", "json", "list-backups", server])?;
+            // `check` exits non-zero if any check fails, which is reported in its output
+            let (_, checks) = run("barman", &["-f", "json", "check", server])?;
+            Ok(vec![parse_barman(server, &backups, &checks)?])
+        }
+    }
+}
+
+fn status_metrics(
+    tool: &str,
+    statuses: Option<&[BackupStatus]>,
+    now: SystemTime,
+) -> Vec<prometheus::proto::MetricFamily> {
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    let up = IntGaugeVec::new(Opts::new("pg_backup_up", help::PG_BACKUP_UP), &["tool"]).unwrap();
+    up.with_label_values(&[tool]).set(statuses.is_some() as i64);
+    metrics.append(&mut up.collect());
+
+    let Some(statuses) = statuses else {
+        return metrics;
+    };
+
+    let labels = ["tool", "name"];
+    let ok = IntGaugeVec::new(
+        Opts::new("pg_backup_status_ok", help::PG_BACKUP_STATUS_OK),
+        &labels,
+    )
+    .unwrap();
+    let wal_archive_ok = IntGaugeVec::new(
+        Opts::new("pg_backup_wal_archive_ok", help::PG_BACKUP_WAL_ARCHIVE_OK),
+        &labels,
+    )
+    .unwrap();
+    let labels = ["tool", "name", "type"];
+    let last_age = IntGaugeVec::new(
+        Opts::new(
+            "pg_backup_last_age_seconds",
+            help::PG_BACKUP_LAST_AGE_SECONDS,
+        ),
+        &labels,
+    )
+    .unwrap();
+    let last_size = IntGaugeVec::new(
+        Opts::new("pg_backup_last_size_bytes", help::PG_BACKUP_LAST_SIZE_BYTES),
+        &labels,
+    )
+    .unwrap();
+
+    let now = now
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    for status in statuses {
+        ok.with_label_values(&[tool, &status.name])
+            .set(status.ok as i64);
+        wal_archive_ok
+            .with_label_values(&[tool, &status.name])
+            .set(status.wal_archive_ok as i64);
+        for (kind, (stop, size)) in &status.last_backups {
+            last_age
+                .with_label_values(&[tool, &status.name, kind])
+                .set(now.saturating_sub(*stop) as i64);
+            if let Some(size) = size {
+                last_size
+                    .with_label_values(&[tool, &status.name, kind])
+                    .set(*size as i64);
+            }
+        }
+    }
+
+    metrics.append(&mut ok.collect());
+    metrics.append(&mut wal_archive_ok.collect());
+    metrics.append(&mut last_age.collect());
+    metrics.append(&mut last_size.collect());
+    metrics.retain(|m| !m.get_metric().is_empty());
+    metrics
+}
+
+/// The statuses last queried. `None` if the query failed.
+type CachedStatuses = (Instant, Option<Vec<BackupStatus>>);
+
+/// Held while querying, so that concurrent scrapes wait for the same query instead of
+/// running the tool again.
+static CACHE: Lazy<Mutex<Option<CachedStatuses>>> = Lazy::new(|| Mutex::new(None));
+
+/// Gathers the metrics of the backups from `source`, querying it at most every `interval`.
+pub async fn collect(
+    source: &BackupSource,
+    interval: Duration,
+) -> Vec<prometheus::proto::MetricFamily> {
+    crate::info_span!("get_backup_status");
+
+    let mut cache = CACHE.lock().await;
+    if !cache
+        .as_ref()
+        .is_some_and(|(queried_at, _)| queried_at.elapsed() < interval)
+    {
+        // The backup tools are run as child processes, which blocks
+        let query_source = source.clone();
+        let statuses = match tokio::task::spawn_blocking(move || query(&query_source)).await {
+            Ok(Ok(statuses)) => Some(statuses),
+            Ok(Err(e)) => {
+                tracing::warn!("failed to query {}: {e:#}", source.tool());
+                None
+            }
+            Err(e) => {
+                tracing::warn!("failed to query {}: {e:#}", source.tool());
+                None
+            }
+        };
+        *cache = Some((Instant::now(), statuses));
+    }
+    let statuses = cache.as_ref().and_then(|(_, s)| s.as_deref());
+    status_metrics(source.tool(), statuses, SystemTime::now())
+}
+
+#[cfg(test)]
+mod tests_backup {
+    use crate::backup::{parse_barman, parse_pgbackrest, BackupSource};
+
+    #[test]
+    fn test_backup_source() {
+        assert_eq!(
+            "pgbackrest".parse::<BackupSource>().unwrap(),
+            BackupSource::Pgbackrest(None)
+        );
+        assert_eq!(
+            "pgbackrest:main".parse::<BackupSource>().unwrap(),
+            BackupSource::Pgbackrest(Some("main".to_string()))
+        );
+        assert_eq!(
+            "barman:pg".parse::<BackupSource>().unwrap(),
+            BackupSource::Barman("pg".to_string())
+        );
+        assert!("barman".parse::<BackupSource>().is_err());
+        assert!("wal-g".parse::<BackupSource>().is_err());
+    }
+
+    #[test]
+    fn test_parse_pgbackrest() {
+        let statuses = parse_pgbackrest(
+            r#"[{
+                "name": "main",
+                "status": {"code": 0, "message": "ok"},
+                "archive": [{"id": "15-1", "min": "000000010000000000000001", "max": "000000010000000000000009"}],
+                "backup": [
+                    {"type": "full", "timestamp": {"start": 100, "stop": 200}, "info": {"size": 1000, "delta": 1000}},
+                    {"type": "incr", "timestamp": {"start": 300, "stop": 400}, "info": {"size": 1100, "delta": 10}},
+                    {"type": "full", "timestamp": {"start": 500, "stop": 600}, "info": {"size": 1200, "delta": 1200}}
+                ]
+            }, {
+                "name": "new",
+                "status": {"code": 2, "message": "no valid backups"},
+                "archive": [],
+                "backup": []
+            }]"#,
+        )
+        .unwrap();
+        assert_eq!(statuses.len(), 2);
+        assert!(statuses[0].ok);
+        assert!(statuses[0].wal_archive_ok);
+        assert_eq!(statuses[0].last_backups["full"], (600, Some(1200)));
+        assert_eq!(statuses[0].last_backups["incr"], (400, Some(1100)));
+        assert!(!statuses[1].ok);
+        assert!(!statuses[1].wal_archive_ok);
+        assert!(statuses[1].last_backups.is_empty());
+    }
+
+    #[test]
+    fn test_parse_barman() {
+        let status = parse_barman(
+            "pg",
+            r#"{"pg": [
+                {"backup_id": "20231002T000000", "status": "FAILED"},
+                {"backup_id": "20231001T000000", "status": "DONE", "end_time_timestamp": "1696119000", "size_bytes": 4096}
+            ]}"#,
+            r#"{"pg": {
+                "wal archive": {"status": "OK"},
+                "continuous archiving": {"status": "OK"},
+                "minimum redundancy requirements": {"status": "FAILED", "hint": "have 1 backups, expected at least 2"}
+            }}"#,
+        )
+        .unwrap();
+        assert!(!status.ok);
+        assert!(status.wal_archive_ok);
+        assert_eq!(status.last_backups["full"], (1696119000, Some(4096)));
+    }
+}
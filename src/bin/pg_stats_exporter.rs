@@ -1,15 +1,67 @@
 //!
 //! A PostgreSQL metrics exporter for Prometheus.
 //!
-use anyhow::{anyhow, bail};
-use clap::{Arg, Command};
+use anyhow::{anyhow, bail, Context};
+use clap::{
+    builder::PossibleValuesParser, parser::ValueSource, value_parser, Arg, ArgAction, ArgMatches,
+    Command,
+};
+use hyper::server::{
+    accept::{self, Accept},
+    conn::{AddrIncoming, AddrStream},
+};
+use hyper::service::{make_service_fn, service_fn, Service};
+use hyper::{Body, Request, Response};
+use once_cell::sync::OnceCell;
 use pg_stats_exporter::{
-    logging,
+    auth_modules,
+    background::BackgroundCollector,
+    backup::BackupSource,
+    bootstrap,
+    cancellation::ScrapeCancellation,
+    checkplugin,
+    client_addr::IpCidr,
+    compatibility,
+    config::{Config, LogFormat, LoggingConfig, CONNECTION_OPTIONS},
+    custom_queries,
+    federation::{Downstream, Federation},
+    history::History,
+    leader_election::LeaderElection,
+    log_tailer, logging,
+    metrics::{self, CollectorOptions, ScrapeTimestamps},
+    notifier::{self, Notifier, WebhookFormat},
+    pgpass,
+    pool::Pool,
     postgres_connection::{parse_host_port, PgConnectionConfig},
-    project_git_version, routes, tcp_listener,
+    postgres_tls::{SslMode, SslParams},
+    privileges::PrivilegeReport,
+    probe::AllowedTarget,
+    project_git_version,
+    redaction::{self, Redaction},
+    routes,
+    secret_files::{self, SecretFile},
+    server_metrics::{self, TrackedConnection},
+    tcp_listener::{self, SocketOptions},
+    tls_config, top, unix_socket,
+    zabbix::{self, ZabbixTarget},
 };
-use routes::State;
-use std::sync::Arc;
+use routerify::RequestServiceBuilder;
+use routes::{ScrapeErrorBehavior, State, WarmUp};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::ffi::OsString;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tls_listener::TlsListener;
+use tokio::sync::{Notify, Semaphore};
+use tokio::task::JoinSet;
+use tokio_rustls::{rustls::ServerConfig, server::TlsStream, TlsAcceptor};
+use tokio_stream::StreamExt;
 
 project_git_version!(GIT_VERSION);
 
@@ -21,88 +73,1126 @@ fn version() -> String {
 }
 
 fn main() -> anyhow::Result<()> {
-    // TODO: Replace `println` with `tracing::info!`
-    println!(
-        "pg_stats_exporter v{} listening on {}",
-        version(),
-        PG_STATS_EXPORTER_API
+    // TODO: Use attributes to parse CLI arguments
+    let arg_matches = with_config_file(cli().get_matches())?;
+    redaction::init(
+        arg_matches
+            .get_one::<String>("redaction")
+            .unwrap()
+            .parse()?,
     );
 
-    // TODO: Use attributes to parse CLI arguments
-    let arg_matches = cli().get_matches();
-
-    let postgres = arg_matches
-        .get_one::<String>("postgres")
-        .map(|s| s.as_str())
-        .unwrap_or("127.0.0.1:5432")
-        .to_string();
-
-    let user = arg_matches
-        .get_one::<String>("user")
-        .map(|s| s.as_str())
-        .unwrap_or("docker")
-        .to_string();
-
-    let dbname = arg_matches
-        .get_one::<String>("dbname")
-        .map(|s| s.as_str())
-        .unwrap_or("postgres")
-        .to_string();
-
-    let (host, port) = parse_host_port(postgres).expect("Unable to parse `postgres`");
-    let port = port.unwrap_or(5432);
-    let postgres = PgConnectionConfig::new_host_port(host, port)
-        .set_user(Some(user))
-        .set_dbname(Some(dbname));
-    if !postgres.can_connect() {
-        bail!("Failed to connect to {}", postgres.raw_address());
-    }
-
-    let state = Arc::new(State {
-        pgnode: Box::leak(Box::new(postgres)),
-    });
-
-    let runtime = tokio::runtime::Builder::new_multi_thread()
-        .thread_name("http server")
-        // if you change the number of worker threads please change the constant below
-        .enable_all()
-        .build()?;
-
-    runtime.block_on(async {
+    let (postgres, mut ssl) = match arg_matches.get_one::<String>("dsn") {
+        Some(dsn) => PgConnectionConfig::from_dsn(dsn)?,
+        None => {
+            let postgres = arg_matches
+                .get_one::<String>("postgres")
+                .map(|s| s.as_str())
+                .unwrap_or("127.0.0.1:5432");
+            let user = arg_matches
+                .get_one::<String>("user")
+                .map(|s| s.as_str())
+                .unwrap_or("docker");
+            let dbname = arg_matches
+                .get_one::<String>("dbname")
+                .map(|s| s.as_str())
+                .unwrap_or("postgres");
+            let (host, port) = parse_host_port(postgres).expect("Unable to parse `postgres`");
+            let postgres = PgConnectionConfig::new_host_port(host, port.unwrap_or(5432))
+                .set_user(Some(user.to_string()))
+                .set_dbname(Some(dbname.to_string()));
+            (postgres, SslParams::default())
+        }
+    };
+
+    // The options override the parameters of `--dsn`
+    if let Some(mode) = arg_matches.get_one::<String>("sslmode") {
+        ssl.mode = Some(mode.parse()?);
+    }
+    if let Some(path) = arg_matches.get_one::<PathBuf>("sslrootcert") {
+        ssl.root_cert = Some(path.clone());
+    }
+    if let Some(path) = arg_matches.get_one::<PathBuf>("sslcert") {
+        ssl.cert = Some(path.clone());
+    }
+    if let Some(path) = arg_matches.get_one::<PathBuf>("sslkey") {
+        ssl.key = Some(path.clone());
+    }
+    let tls = ssl.load()?;
+
+    // In the order of libpq, except that `--password-file` comes first
+    let password_file = arg_matches
+        .get_one::<PathBuf>("password-file")
+        .map(|path| SecretFile::load(path))
+        .transpose()?;
+    let password = std::env::var("PGPASSWORD").ok();
+    let postgres = match &password_file {
+        Some(secret) => postgres.set_password_file(Some(secret.clone())),
+        None if postgres.has_password() => postgres,
+        None => postgres
+            .set_password(password)
+            .set_passfile(pgpass::default_path()),
+    };
+    let postgres = postgres.set_tls(tls.clone());
+    // PostgreSQL isn't accessed when federating other exporters
+    let federating = arg_matches.contains_id("federate");
+    let reachable = federating || postgres.can_connect();
+    if !reachable {
+        if arg_matches.get_flag("exit-if-unreachable") {
+            bail!("Failed to connect to {}", postgres.raw_address());
+        }
+        eprintln!(
+            "Failed to connect to {}, starting anyway",
+            postgres.raw_address()
+        );
+    }
+
+    let standby = match arg_matches.get_one::<String>("standby") {
+        Some(standby) => {
+            let (host, port) = parse_host_port(standby).expect("Unable to parse `standby`");
+            Some(
+                postgres
+                    .clone()
+                    .set_host(host)
+                    .set_port(port.unwrap_or(5432)),
+            )
+        }
+        None => None,
+    };
+
+    // `--collector.largest_relations` takes a number instead of a boolean
+    let collector_flag = |name: &str| {
+        arg_matches
+            .try_get_one::<bool>(&format!("collector.{name}"))
+            .ok()
+            .flatten()
+            .copied()
+    };
+    let mut collector_options = CollectorOptions {
+        hot_updates: collector_flag("hot_updates") == Some(true),
+        toast: collector_flag("toast") == Some(true),
+        statements_delta: collector_flag("statements_delta") == Some(true),
+        largest_relations: arg_matches
+            .get_one::<i64>("collector.largest_relations")
+            .copied(),
+        vacuum_recency: collector_flag("vacuum_recency") == Some(true),
+        vacuum_recency_tables: arg_matches
+            .get_one::<String>("collector.vacuum_recency.tables")
+            .cloned(),
+        logical_slots: arg_matches
+            .get_many::<String>("collector.logical_slots.slots")
+            .map(|slots| slots.cloned().collect()),
+        filesystem: collector_flag("filesystem") == Some(true),
+        data_directory: arg_matches
+            .get_one::<PathBuf>("collector.filesystem.data_directory")
+            .cloned(),
+        log_directory: arg_matches.get_one::<PathBuf>("log-directory").cloned(),
+        patroni_url: arg_matches.get_one::<String>("patroni-url").cloned(),
+        backup: arg_matches.get_one::<BackupSource>("backup-tool").cloned(),
+        standby,
+        consistent_snapshot: arg_matches.get_flag("consistent-snapshot"),
+        compatibility_profile: arg_matches
+            .get_one::<String>("compatibility-profile")
+            .filter(|s| *s != "auto")
+            .map(|s| s.parse())
+            .transpose()?,
+        compatibility_disabled_collectors: arg_matches
+            .get_many::<String>("compatibility-disabled-collectors")
+            .map(|names| names.cloned().collect()),
+        disabled_collectors: metrics::COLLECTORS
+            .iter()
+            .filter(|name| collector_flag(name) == Some(false))
+            .map(|name| name.to_string())
+            .collect(),
+        ..Default::default()
+    };
+    if let Some(secs) = arg_matches.get_one::<u64>("collector.largest_relations.interval") {
+        collector_options.largest_relations_interval = Duration::from_secs(*secs);
+    }
+    if let Some(secs) = arg_matches.get_one::<u64>("backup-tool.interval") {
+        collector_options.backup_interval = Duration::from_secs(*secs);
+    }
+    collector_options.allow_unsafe_queries = arg_matches.get_flag("allow-unsafe-queries");
+    if let Some(path) = arg_matches.get_one::<PathBuf>("custom-queries") {
+        collector_options.custom_queries =
+            custom_queries::load(path, collector_options.allow_unsafe_queries)?;
+    }
+
+    // The subcommands talk to PostgreSQL once and exit, so a single thread is enough
+    let subcommand_runtime = || {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+    };
+    match arg_matches.subcommand() {
+        Some(("bootstrap", sub_matches)) => {
+            return subcommand_runtime()?.block_on(bootstrap(
+                &postgres,
+                &collector_options,
+                sub_matches,
+            ))
+        }
+        Some(("checkplugin", sub_matches)) => {
+            let status = subcommand_runtime()?.block_on(checkplugin::run(
+                &postgres,
+                &collector_options,
+                sub_matches.get_one::<String>("metric").unwrap(),
+                *sub_matches.get_one::<f64>("warn").unwrap(),
+                *sub_matches.get_one::<f64>("crit").unwrap(),
+            ));
+            std::process::exit(status.exit_code());
+        }
+        Some(("top", sub_matches)) => {
+            return subcommand_runtime()?.block_on(top::run(
+                &postgres,
+                &collector_options,
+                Duration::from_secs(*sub_matches.get_one::<u64>("interval").unwrap()),
+                sub_matches.get_one::<u64>("iterations").copied(),
+            ))
+        }
+        _ => {}
+    }
+
+    let listener_config = load_listener_config(&arg_matches)?;
+
+    let mut runtime_builder = tokio::runtime::Builder::new_multi_thread();
+    runtime_builder.thread_name("http server").enable_all();
+    if let Some(threads) = arg_matches.get_one::<u64>("runtime-threads") {
+        runtime_builder.worker_threads(*threads as usize);
+    }
+    if let Some(threads) = arg_matches.get_one::<u64>("max-blocking-threads") {
+        runtime_builder.max_blocking_threads(*threads as usize);
+    }
+    let runtime = runtime_builder.build()?;
+
+    let forced = runtime.block_on(async {
         // TODO: Write logs to a file
-        let _logging_guard = logging::init("pg_stats_exporter")
+        let logging_config = LoggingConfig {
+            level: arg_matches.get_one::<String>("log-level").cloned(),
+            format: arg_matches
+                .get_one::<String>("log-format")
+                .map(|s| s.parse())
+                .transpose()?,
+        };
+        let logging_guard = logging::init("pg_stats_exporter", &logging_config)
             .await
             .expect("Failed to initialize logging");
 
-        let http_listener = tcp_listener::bind(PG_STATS_EXPORTER_API)?;
-        let router = routes::make_router(state)?
-            .build()
-            .map_err(|err| anyhow!(err))?;
-        let service = routerify::RouterService::new(router).unwrap();
-        let server = hyper::Server::from_tcp(http_listener)?
-            .serve(service)
-            .with_graceful_shutdown(shutdown_watcher());
+        if let Some(url) = arg_matches.get_one::<String>("notify-webhook") {
+            let routing_key = arg_matches
+                .get_one::<PathBuf>("notify-pagerduty-routing-key-file")
+                .map(|path| SecretFile::load(path))
+                .transpose()?;
+            let format = arg_matches
+                .get_one::<String>("notify-webhook-format")
+                .unwrap()
+                .parse()?;
+            notifier::init(Notifier::new(url, format, routing_key, version())?);
+        }
 
-        // Run the server until shutdown requested
-        if let Err(e) = server.await {
-            eprintln!("Server error: {}", e);
+        // Summarize the effective configuration first so that misconfigurations can be told
+        // from the first lines of the logs
+        let targets = if federating {
+            arg_matches
+                .get_many::<Downstream>("federate")
+                .into_iter()
+                .flatten()
+                .map(|d| d.redacted())
+                .collect::<Vec<_>>()
+        } else {
+            std::iter::once(&postgres)
+                .chain(&collector_options.standby)
+                .map(|c| c.redacted_url())
+                .collect()
+        };
+        tracing::info!(
+            version = %version(),
+            listen = %join_addrs(&listener_config.listen),
+            tls = listener_config.tls_config.is_some(),
+            targets = %targets.join(", "),
+            collectors = %metrics::COLLECTORS
+                .iter()
+                .filter(|name| metrics::is_enabled(name, &collector_options))
+                .copied()
+                .collect::<Vec<_>>()
+                .join(","),
+            "starting pg_stats_exporter"
+        );
+
+        // Start background threads after logging is initialized so that their logs are kept
+        if let Some(dir) = &collector_options.log_directory {
+            log_tailer::spawn(dir, Duration::from_secs(1))
+                .map_err(|e| anyhow!("Failed to tail server logs in {}: {}", dir.display(), e))?;
+        }
+
+        let leader_election = match arg_matches.get_one::<i64>("ha-lock-key") {
+            Some(key) => Some(Arc::new(LeaderElection::spawn(
+                postgres.clone(),
+                *key,
+                Duration::from_secs(5),
+            )?)),
+            None => None,
+        };
+
+        let pgnode: &'static PgConnectionConfig = Box::leak(Box::new(postgres));
+        let pool = Pool::new(
+            arg_matches
+                .get_one::<u64>("max-connections")
+                .map(|n| *n as usize),
+        );
+        let scrape_timestamps = Arc::new(ScrapeTimestamps::new(pgnode.raw_address()));
+
+        // The privilege check and the warm-up collection need PostgreSQL, so they are retried
+        // in the background if it is not up yet, e.g., when started before the database in
+        // a container. Scrapes meanwhile report `pg_up 0`.
+        let privilege_report = Arc::new(OnceCell::new());
+        let warm_up = Arc::new(Mutex::new(None));
+        if !federating {
+            // The first background collections run right away and serve as the warm-up
+            let background = arg_matches.contains_id("collection-interval");
+            let checks = startup_checks(
+                pgnode,
+                pool.clone(),
+                collector_options.clone(),
+                privilege_report.clone(),
+                (!background).then(|| warm_up.clone()),
+                scrape_timestamps.clone(),
+            );
+            if reachable {
+                checks.await;
+            } else {
+                tokio::spawn(checks);
+            }
+        }
+
+        let background = arg_matches
+            .get_one::<u64>("collection-interval")
+            .map(|secs| {
+                // Group collectors by their intervals so that each group shares a connection,
+                // and is up or down on its own in `pg_up`
+                let mut schedules: BTreeMap<u64, Vec<&'static str>> = BTreeMap::new();
+                for name in metrics::COLLECTORS.iter() {
+                    let interval = arg_matches
+                        .get_many::<(String, u64)>("collector-interval")
+                        .into_iter()
+                        .flatten()
+                        .rfind(|(collector, _)| collector == name)
+                        .map_or(*secs, |(_, secs)| *secs);
+                    schedules.entry(interval).or_default().push(name);
+                }
+
+                let mut background = BackgroundCollector::new(
+                    arg_matches.get_flag("collection-timestamps"),
+                    arg_matches.get_one::<PathBuf>("snapshot-file").cloned(),
+                );
+                for (secs, names) in schedules {
+                    let collector_options = collector_options.clone();
+                    let leader_election = leader_election.clone();
+                    let scrape_timestamps = scrape_timestamps.clone();
+                    let pool = pool.clone();
+                    let interval = Duration::from_secs(secs);
+                    let names = Arc::new(names);
+                    background.spawn(interval, interval / 10, move || {
+                        let collector_options = collector_options.clone();
+                        let leader_election = leader_election.clone();
+                        let scrape_timestamps = scrape_timestamps.clone();
+                        let pool = pool.clone();
+                        let names = names.clone();
+                        async move {
+                            if leader_election.as_ref().is_some_and(|e| !e.is_active()) {
+                                return None;
+                            }
+                            scrape_timestamps.record_scrape();
+                            match metrics::gather_collectors(
+                                &pool,
+                                pgnode,
+                                &collector_options,
+                                &names,
+                                &ScrapeCancellation::default(),
+                            )
+                            .await
+                            {
+                                Ok(metrics) => {
+                                    scrape_timestamps.record_group_success(secs);
+                                    Some(metrics)
+                                }
+                                Err(e) => {
+                                    tracing::warn!(
+                                        "failed to collect from {}: {e:#}",
+                                        pgnode.raw_address()
+                                    );
+                                    scrape_timestamps.record_group_failure(secs);
+                                    None
+                                }
+                            }
+                        }
+                    });
+                }
+                background
+            });
+
+        let state = Arc::new(State {
+            pgnode,
+            pool,
+            collector_options,
+            leader_election,
+            background,
+            scrape_timestamps,
+            privilege_report,
+            scrape_error_behavior: match arg_matches
+                .get_one::<String>("scrape-error-behavior")
+                .map(|s| s.as_str())
+            {
+                Some("unavailable") => ScrapeErrorBehavior::Unavailable,
+                _ => ScrapeErrorBehavior::Up,
+            },
+            memory_soft_limit: arg_matches
+                .get_one::<usize>("memory-soft-limit-bytes")
+                .copied(),
+            max_response_bytes: arg_matches.get_one::<usize>("max-response-bytes").copied(),
+            trusted_proxies: arg_matches
+                .get_many::<IpCidr>("trusted-proxies")
+                .into_iter()
+                .flatten()
+                .copied()
+                .collect(),
+            cors_allowed_origins: arg_matches
+                .get_many::<String>("cors-allowed-origins")
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect(),
+            history: arg_matches
+                .get_one::<u64>("diff-retention")
+                .map(|secs| History::new(Duration::from_secs(*secs))),
+            federation: federating.then(|| {
+                Federation::new(
+                    arg_matches
+                        .get_many::<Downstream>("federate")
+                        .into_iter()
+                        .flatten()
+                        .cloned()
+                        .collect(),
+                    Duration::from_secs(*arg_matches.get_one::<u64>("federate-timeout").unwrap()),
+                )
+            }),
+            scrape_semaphore: arg_matches
+                .get_one::<u64>("max-concurrent-scrapes")
+                .map(|n| Arc::new(Semaphore::new(*n as usize))),
+            probe_allowed_targets: arg_matches
+                .get_many::<AllowedTarget>("probe-allowed-targets")
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect(),
+            auth_modules: match arg_matches.get_one::<PathBuf>("auth-modules") {
+                Some(path) => auth_modules::load(path)?,
+                None => Default::default(),
+            },
+            metrics_cache_max_age: arg_matches
+                .get_one::<u64>("metrics-cache-max-age")
+                .map(|secs| Duration::from_secs(*secs)),
+            query_text_max_length: *arg_matches
+                .get_one::<usize>("query-text-max-length")
+                .unwrap(),
+            readiness_timeout: Duration::from_secs(
+                *arg_matches.get_one::<u64>("readiness-timeout").unwrap(),
+            ),
+            scrape_timeout: arg_matches
+                .get_one::<u64>("scrape-timeout")
+                .map(|secs| Duration::from_secs(*secs)),
+            version: version(),
+            listen_addresses: Default::default(),
+            warm_up,
+        });
+
+        let mut secrets = state.auth_modules.secret_files();
+        secrets.extend(password_file.clone());
+        if !secrets.is_empty() {
+            if let Err(e) = secret_files::watch_secrets(secrets) {
+                tracing::warn!("failed to watch the password files: {e:#}");
+            }
+        }
+
+        // The certificates of the connections, rotated like the password files
+        let ssl_files: Vec<PathBuf> = [&ssl.root_cert, &ssl.cert, &ssl.key]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect();
+        if !ssl_files.is_empty() {
+            let res = secret_files::watch(ssl_files, move |path| match ssl.load() {
+                Ok(new) => {
+                    tls.replace(&new);
+                    tracing::info!("reloaded {}", path.display());
+                }
+                Err(e) => tracing::warn!("failed to reload the TLS certificates: {e:#}"),
+            });
+            if let Err(e) = res {
+                tracing::warn!("failed to watch the TLS certificates: {e:#}");
+            }
+        }
+
+        if let Some(path) = arg_matches.get_one::<PathBuf>("unix-socket") {
+            unix_socket::spawn(path, state.clone())
+                .map_err(|e| anyhow!("Failed to bind {}: {}", path.display(), e))?;
+        }
+
+        if let Some(server) = arg_matches.get_one::<String>("zabbix-server") {
+            zabbix::spawn(
+                ZabbixTarget {
+                    server: server.clone(),
+                    host: arg_matches
+                        .get_one::<String>("zabbix-host")
+                        .cloned()
+                        .unwrap(),
+                    key_prefix: arg_matches
+                        .get_one::<String>("zabbix-key-prefix")
+                        .cloned()
+                        .unwrap_or_default(),
+                },
+                Duration::from_secs(*arg_matches.get_one::<u64>("zabbix-interval").unwrap()),
+                state.clone(),
+            );
         }
 
-        anyhow::Ok(())
+        let mut listen = listener_config.listen;
+        let mut socket_options = listener_config.socket_options;
+        let mut http_listeners = tcp_listener::bind_all(&listen, &socket_options)?;
+        let mut bound = local_addrs(&http_listeners)?;
+        tracing::info!(listen = %join_addrs(&bound), "listening");
+        *state.listen_addresses.write().unwrap() = bound.clone();
+        notifier::notify(notifier::Event::Started);
+        let mut drain = Arc::new(Notify::new());
+        let mut server = tokio::spawn(serve(
+            try_clone_all(&http_listeners)?,
+            listener_config.tls_config,
+            state.clone(),
+            &arg_matches,
+            drain.clone(),
+        )?);
+
+        // Run the server until shutdown requested. On SIGHUP or when the TLS files change, the
+        // listen address, the TLS material and the log level are reloaded, and a new server
+        // starts accepting before the current one is drained, so that rotating certificates
+        // doesn't drop scrapes.
+        let shutdown = Arc::new(Notify::new());
+        tokio::spawn(shutdown_watcher(shutdown.clone()));
+        let mut sighup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+        // Rotated TLS material, e.g., renewed by cert-manager, is reloaded as on SIGHUP
+        let tls_rotated = Arc::new(Notify::new());
+        if let (Some(cert), Some(key)) = (
+            arg_matches.get_one::<PathBuf>("tls-cert-file"),
+            arg_matches.get_one::<PathBuf>("tls-key-file"),
+        ) {
+            let tls_rotated = tls_rotated.clone();
+            let res = secret_files::watch(vec![cert.clone(), key.clone()], move |path| {
+                tracing::info!("{} changed, reloading the listener", path.display());
+                tls_rotated.notify_one();
+            });
+            if let Err(e) = res {
+                tracing::warn!("failed to watch the TLS material, reload it with SIGHUP: {e:#}");
+            }
+        }
+        let stopped = loop {
+            tokio::select! {
+                res = &mut server => break Some(res),
+                _ = shutdown.notified() => break None,
+                _ = sighup.recv() => {}
+                _ = tls_rotated.notified() => {}
+            }
+            // The configuration file is read again, so that a listen address and a log level
+            // changed there take effect. The other settings are kept.
+            let reloaded = cli()
+                .try_get_matches()
+                .map_err(anyhow::Error::from)
+                .and_then(with_config_file);
+            if let Ok(arg_matches) = &reloaded {
+                let level = arg_matches.get_one::<String>("log-level");
+                if let Err(e) = logging_guard.set_level(level.map(|s| s.as_str())) {
+                    tracing::warn!(
+                        "failed to reload the log level, keeping the current one: {e:#}"
+                    );
+                }
+            }
+            let reloaded = reloaded
+                .and_then(|arg_matches| load_listener_config(&arg_matches))
+                .and_then(|config| {
+                    // The sockets are shared with the new server if the addresses are unchanged
+                    let listeners =
+                        if config.listen == listen && config.socket_options == socket_options {
+                            try_clone_all(&http_listeners)?
+                        } else {
+                            tcp_listener::bind_all(&config.listen, &config.socket_options)?
+                        };
+                    let new_drain = Arc::new(Notify::new());
+                    let tls = config.tls_config.is_some();
+                    let new_server = serve(
+                        try_clone_all(&listeners)?,
+                        config.tls_config,
+                        state.clone(),
+                        &arg_matches,
+                        new_drain.clone(),
+                    )?;
+                    let config = (config.listen, config.socket_options);
+                    Ok((config, tls, listeners, new_drain, new_server))
+                });
+            match reloaded {
+                Ok(((new_listen, new_socket_options), tls, listeners, new_drain, new_server)) => {
+                    let new_bound = local_addrs(&listeners)?;
+                    tracing::info!(listen = %join_addrs(&new_bound), tls, "reloaded the listener");
+                    *state.listen_addresses.write().unwrap() = new_bound.clone();
+                    let old_server = std::mem::replace(&mut server, tokio::spawn(new_server));
+                    std::mem::replace(&mut drain, new_drain).notify_one();
+                    let old_bound = std::mem::replace(&mut bound, new_bound);
+                    tokio::spawn(async move {
+                        match old_server.await {
+                            Ok(Ok(())) => tracing::info!(
+                                listen = %join_addrs(&old_bound),
+                                "drained the old listener"
+                            ),
+                            Ok(Err(e)) => tracing::warn!("failed to drain the old listener: {e:#}"),
+                            Err(e) => tracing::warn!("failed to drain the old listener: {e:#}"),
+                        }
+                    });
+                    listen = new_listen;
+                    socket_options = new_socket_options;
+                    http_listeners = listeners;
+                }
+                Err(e) => {
+                    tracing::warn!("failed to reload the listener, keeping the current one: {e:#}")
+                }
+            }
+        };
+
+        // In-flight scrapes are waited for, up to `--shutdown-timeout` if given
+        let shutdown_timeout = arg_matches
+            .get_one::<u64>("shutdown-timeout")
+            .map(|secs| Duration::from_secs(*secs));
+        let stopped = match stopped {
+            Some(res) => Some(res),
+            None => {
+                drain.notify_one();
+                let deadline = async {
+                    match shutdown_timeout {
+                        Some(timeout) => tokio::time::sleep(timeout).await,
+                        None => std::future::pending().await,
+                    }
+                };
+                tokio::select! {
+                    res = server => Some(res),
+                    _ = deadline => None,
+                }
+            }
+        };
+        let forced = match stopped {
+            Some(Ok(Ok(()))) => {
+                tracing::info!(shutdown = "clean", "server stopped");
+                false
+            }
+            Some(Ok(Err(e))) => {
+                eprintln!("Server error: {}", e);
+                false
+            }
+            Some(Err(e)) => {
+                eprintln!("Server error: {}", e);
+                false
+            }
+            None => {
+                tracing::warn!(
+                    shutdown = "forced",
+                    timeout_secs = shutdown_timeout.unwrap_or_default().as_secs(),
+                    "in-flight requests did not finish in time, exiting anyway"
+                );
+                true
+            }
+        };
+
+        notifier::notify_now(notifier::Event::Stopped).await;
+
+        anyhow::Ok(forced)
+    })?;
+    if forced {
+        // Dropping the runtime would wait for the blocking threads still running, e.g., of
+        // backup tools
+        runtime.shutdown_background();
+    }
+    Ok(())
+}
+
+/// Checks the privileges of the role and collects once if `warm_up` is given, waiting for
+/// PostgreSQL to be reachable first.
+async fn startup_checks(
+    pgnode: &'static PgConnectionConfig,
+    pool: Pool,
+    collector_options: CollectorOptions,
+    privilege_report: Arc<OnceCell<PrivilegeReport>>,
+    warm_up: Option<Arc<Mutex<Option<WarmUp>>>>,
+    scrape_timestamps: Arc<ScrapeTimestamps>,
+) {
+    let mut backoff = Duration::from_secs(1);
+    let conn = loop {
+        match pgnode.connect().await {
+            Ok(conn) => break conn,
+            Err(e) => {
+                tracing::warn!(
+                    "failed to connect to {}, retrying in {}s: {e:#}",
+                    pgnode.raw_address(),
+                    backoff.as_secs()
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(Duration::from_secs(30));
+            }
+        }
+    };
+
+    // Collectors disabled by the compatibility profile need no privileges
+    let report = match compatibility::init(&conn, &collector_options).await {
+        Ok(()) => PrivilegeReport::check(&conn, &collector_options).await,
+        Err(e) => Err(e),
+    };
+    let report = match report {
+        Ok(report) => Some(report),
+        Err(e) => {
+            tracing::warn!("failed to check privileges: {e:#}");
+            None
+        }
+    };
+    if let Some(report) = report {
+        report.log();
+        let _ = collector_options
+            .function_fallbacks
+            .set(report.function_fallbacks());
+        let _ = privilege_report.set(report);
+    }
+
+    // Collect once before accepting scrapes so that misconfigurations or missing
+    // privileges show up right away in the startup logs. The result is served to the first
+    // scrape, which then doesn't pay the cold-start latency.
+    let Some(warm_up) = warm_up else {
+        return;
+    };
+    let started = std::time::Instant::now();
+    scrape_timestamps.record_scrape();
+    match metrics::gather(
+        &pool,
+        pgnode,
+        &collector_options,
+        &ScrapeCancellation::default(),
+    )
+    .await
+    {
+        Ok(metrics) => {
+            tracing::info!(
+                families = metrics.len(),
+                elapsed_ms = started.elapsed().as_millis(),
+                "warm-up collection completed"
+            );
+            scrape_timestamps.record_success();
+            *warm_up.lock().unwrap() = Some((std::time::Instant::now(), metrics));
+        }
+        Err(e) => {
+            tracing::error!("warm-up collection failed: {e:#}");
+            scrape_timestamps.record_failure();
+        }
+    }
+}
+
+/// Prints the statements granting a role the privileges the enabled collectors require,
+/// or executes them with `--apply`.
+async fn bootstrap(
+    postgres: &PgConnectionConfig,
+    collector_options: &CollectorOptions,
+    sub_matches: &ArgMatches,
+) -> anyhow::Result<()> {
+    let role = sub_matches.get_one::<String>("role").unwrap();
+    let conn = postgres.connect().await?;
+    let statements = bootstrap::statements(
+        &conn,
+        role,
+        collector_options,
+        sub_matches.get_flag("helpers"),
+    )
+    .await?;
+    for statement in statements {
+        println!("{statement}");
+        if sub_matches.get_flag("apply") {
+            conn.batch_execute(&statement).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Where and how the metrics server listens, which is reloaded on SIGHUP, also from the
+/// configuration file.
+struct ListenerConfig {
+    listen: Vec<SocketAddr>,
+    socket_options: SocketOptions,
+    tls_config: Option<ServerConfig>,
+}
+
+fn load_listener_config(arg_matches: &ArgMatches) -> anyhow::Result<ListenerConfig> {
+    let tls_config = match (
+        arg_matches.get_one::<PathBuf>("tls-cert-file"),
+        arg_matches.get_one::<PathBuf>("tls-key-file"),
+    ) {
+        (Some(cert), Some(key)) => Some(tls_config::load_server_config(cert, key)?),
+        _ => None,
+    };
+    Ok(ListenerConfig {
+        listen: arg_matches
+            .get_many::<SocketAddr>("listen")
+            .unwrap()
+            .copied()
+            .collect(),
+        socket_options: SocketOptions {
+            reuse_address: !arg_matches.get_flag("tcp-disable-reuse-address"),
+            reuse_port: arg_matches.get_flag("tcp-reuse-port"),
+        },
+        tls_config,
+    })
+}
+
+fn try_clone_all(
+    listeners: &[std::net::TcpListener],
+) -> std::io::Result<Vec<std::net::TcpListener>> {
+    listeners.iter().map(|l| l.try_clone()).collect()
+}
+
+/// Returns the addresses `listeners` are bound to, which tell the ports chosen for port 0.
+fn local_addrs(listeners: &[std::net::TcpListener]) -> std::io::Result<Vec<SocketAddr>> {
+    listeners.iter().map(|l| l.local_addr()).collect()
+}
+
+fn join_addrs(addrs: &[SocketAddr]) -> String {
+    addrs
+        .iter()
+        .map(|addr| addr.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Serves the routes on all of `listeners` until `drain` is notified, after which the
+/// requests in flight are completed. Fails as soon as one of the listeners fails.
+fn serve(
+    listeners: Vec<std::net::TcpListener>,
+    tls_config: Option<ServerConfig>,
+    state: Arc<State>,
+    arg_matches: &ArgMatches,
+    drain: Arc<Notify>,
+) -> anyhow::Result<Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>> {
+    let mut drains = vec![];
+    let servers = listeners
+        .into_iter()
+        .map(|listener| {
+            let drain = Arc::new(Notify::new());
+            drains.push(drain.clone());
+            serve_listener(
+                listener,
+                tls_config.clone(),
+                state.clone(),
+                arg_matches,
+                drain,
+            )
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(Box::pin(async move {
+        let mut servers = servers.into_iter().fold(JoinSet::new(), |mut set, server| {
+            set.spawn(server);
+            set
+        });
+        let mut draining = false;
+        loop {
+            tokio::select! {
+                _ = drain.notified(), if !draining => {
+                    draining = true;
+                    for drain in &drains {
+                        drain.notify_one();
+                    }
+                }
+                // The other servers are aborted when the set is dropped
+                res = servers.join_next() => match res {
+                    None => return Ok(()),
+                    Some(Ok(Ok(()))) => {}
+                    Some(Ok(Err(e))) => return Err(e.into()),
+                    Some(Err(e)) => return Err(e.into()),
+                },
+            }
+        }
+    }))
+}
+
+/// Serves the routes on `listener` until `drain` is notified, after which the requests in
+/// flight are completed. Both HTTP/1.1 and HTTP/2, either with prior knowledge (h2c) or
+/// negotiated over TLS, are served.
+fn serve_listener(
+    listener: std::net::TcpListener,
+    tls_config: Option<ServerConfig>,
+    state: Arc<State>,
+    arg_matches: &ArgMatches,
+    drain: Arc<Notify>,
+) -> anyhow::Result<Pin<Box<dyn Future<Output = hyper::Result<()>> + Send>>> {
+    listener.set_nonblocking(true)?;
+    let mut incoming = AddrIncoming::from_listener(tokio::net::TcpListener::from_std(listener)?)?;
+    incoming.set_keepalive(
+        arg_matches
+            .get_one::<u64>("tcp-keepalive")
+            .map(|secs| Duration::from_secs(*secs)),
+    );
+    let router = routes::make_router(state)?
+        .build()
+        .map_err(|err| anyhow!(err))?;
+    let builder = RequestServiceBuilder::new(router).map_err(|err| anyhow!(err))?;
+    let drained = async move { drain.notified().await };
+
+    Ok(match tls_config {
+        None => {
+            let service = make_service_fn(move |conn: &TrackedConnection<AddrStream>| {
+                let service =
+                    counting_service(builder.build(conn.get_ref().remote_addr()), conn.requests());
+                async move { Ok::<_, Infallible>(service) }
+            });
+            let listener = accept::poll_fn(move |cx| {
+                Pin::new(&mut incoming).poll_accept(cx).map(|conn| {
+                    conn.map(|conn| {
+                        server_metrics::connection_accepted();
+                        conn.map(TrackedConnection::new)
+                    })
+                })
+            });
+            Box::pin(
+                configure_server(hyper::Server::builder(listener), arg_matches)
+                    .serve(service)
+                    .with_graceful_shutdown(drained),
+            )
+        }
+        Some(tls_config) => {
+            let service =
+                make_service_fn(move |conn: &TrackedConnection<TlsStream<AddrStream>>| {
+                    let service = counting_service(
+                        builder.build(conn.get_ref().get_ref().0.remote_addr()),
+                        conn.requests(),
+                    );
+                    async move { Ok::<_, Infallible>(service) }
+                });
+            // A failed handshake of a client must not stop the server
+            let listener = TlsListener::new(TlsAcceptor::from(Arc::new(tls_config)), incoming)
+                .filter_map(|conn| {
+                    server_metrics::connection_accepted();
+                    match conn {
+                        Ok(conn) => Some(Ok::<_, std::io::Error>(TrackedConnection::new(conn))),
+                        Err(e) => {
+                            server_metrics::tls_handshake_failed();
+                            tracing::warn!("TLS handshake failed: {e:#}");
+                            None
+                        }
+                    }
+                });
+            Box::pin(
+                configure_server(
+                    hyper::Server::builder(accept::from_stream(listener)),
+                    arg_matches,
+                )
+                .serve(service)
+                .with_graceful_shutdown(drained),
+            )
+        }
+    })
+}
+
+/// Counts the requests served by `service` in `requests`.
+fn counting_service<B, S>(
+    mut service: S,
+    requests: Arc<AtomicU64>,
+) -> impl Service<Request<Body>, Response = Response<B>, Error = S::Error, Future = S::Future>
+where
+    B: hyper::body::HttpBody,
+    S: Service<Request<Body>, Response = Response<B>>,
+    S::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    service_fn(move |req| {
+        requests.fetch_add(1, Ordering::Relaxed);
+        service.call(req)
     })
 }
 
-async fn shutdown_watcher() {
-    // Wait for the CTRL+C signal
-    tokio::signal::ctrl_c()
-        .await
-        .expect("Failed to install CTRL+C signal handler");
+/// Applies the keep-alive and idle-timeout settings to the metrics server.
+fn configure_server<I>(
+    builder: hyper::server::Builder<I>,
+    arg_matches: &ArgMatches,
+) -> hyper::server::Builder<I> {
+    let mut builder = builder
+        .http1_keepalive(!arg_matches.get_flag("http-disable-keepalive"))
+        .http2_keep_alive_interval(
+            arg_matches
+                .get_one::<u64>("http2-keepalive-interval")
+                .map(|secs| Duration::from_secs(*secs)),
+        );
+    if let Some(secs) = arg_matches.get_one::<u64>("http-idle-timeout") {
+        builder = builder
+            .http1_header_read_timeout(Duration::from_secs(*secs))
+            .http2_keep_alive_timeout(Duration::from_secs(*secs));
+    }
+    builder
+}
+
+async fn shutdown_watcher(shutdown: Arc<Notify>) {
+    // Wait for the CTRL+C signal, or SIGTERM sent by container runtimes to stop a pod
+    let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .expect("Failed to install SIGTERM signal handler");
+    tokio::select! {
+        res = tokio::signal::ctrl_c() => res.expect("Failed to install CTRL+C signal handler"),
+        _ = sigterm.recv() => {}
+    }
+    shutdown.notify_one();
+}
+
+/// Parses the command line again with the settings of `--config` that aren't given on the
+/// command line or in the environment, inserted as options so that clap validates them like
+/// the other options.
+fn with_config_file(arg_matches: ArgMatches) -> anyhow::Result<ArgMatches> {
+    let Some(path) = arg_matches.get_one::<PathBuf>("config") else {
+        return Ok(arg_matches);
+    };
+    let config = Config::load(path)?;
+    let given = |name: &str| {
+        matches!(
+            arg_matches.value_source(name),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        )
+    };
+    let connection_given = CONNECTION_OPTIONS.iter().any(|name| given(name));
+    let options = config
+        .options()
+        .into_iter()
+        .filter(|(name, _)| !given(name))
+        .filter(|(name, _)| !connection_given || !CONNECTION_OPTIONS.contains(&name.as_str()))
+        .flat_map(|(name, values)| {
+            values
+                .into_iter()
+                .map(move |value| OsString::from(format!("--{name}={value}")))
+        });
+    // Before the subcommand if any, since they are options of the exporter
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    args.splice(1..1, options);
+    cli()
+        .try_get_matches_from(args)
+        .with_context(|| format!("invalid settings in {}", path.display()))
+}
+
+fn parse_collector_interval(s: &str) -> Result<(String, u64), String> {
+    let (name, secs) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected `<collector>=<seconds>`, got `{s}`"))?;
+    if !metrics::COLLECTORS.contains(&name) {
+        return Err(format!(
+            "unknown collector `{name}`, expected one of: {}",
+            metrics::COLLECTORS.join(", ")
+        ));
+    }
+    match secs.parse::<u64>() {
+        Ok(secs) if secs > 0 => Ok((name.to_string(), secs)),
+        _ => Err(format!("invalid interval `{secs}`")),
+    }
 }
 
 fn cli() -> Command {
-    Command::new("PostgreSQL metrics exporter")
+    let cli = Command::new("PostgreSQL metrics exporter")
         // TODO: Use version() instead
         .version(CRATE_PKG_VERSION)
+        .subcommand(
+            Command::new("bootstrap")
+                .about("Print the statements granting a role the privileges the enabled collectors require; connect as a superuser")
+                .arg(
+                    Arg::new("role")
+                        .long("role")
+                        .required(true)
+                        .help("Role the exporter connects as"),
+                )
+                .arg(
+                    Arg::new("apply")
+                        .long("apply")
+                        .action(ArgAction::SetTrue)
+                        .help("Execute the statements instead of only printing them"),
+                )
+                .arg(
+                    Arg::new("helpers")
+                        .long("helpers")
+                        .action(ArgAction::SetTrue)
+                        .help("Also create security-definer helper functions for functions checking for superuser internally"),
+                ),
+        )
+        .subcommand(
+            Command::new("checkplugin")
+                .about("Collect once and check a metric against thresholds with Nagios plugin exit codes and perfdata")
+                .arg(
+                    Arg::new("metric")
+                        .long("metric")
+                        .required(true)
+                        .help("Name of the counter or gauge to check; the largest value of its series is compared"),
+                )
+                .arg(
+                    Arg::new("warn")
+                        .long("warn")
+                        .required(true)
+                        .value_parser(value_parser!(f64))
+                        .help("Value above which the check is WARNING"),
+                )
+                .arg(
+                    Arg::new("crit")
+                        .long("crit")
+                        .required(true)
+                        .value_parser(value_parser!(f64))
+                        .help("Value above which the check is CRITICAL"),
+                ),
+        )
+        .subcommand(
+            Command::new("top")
+                .about("Show key metrics in the terminal, refreshed every interval, for triage without Grafana")
+                .arg(
+                    Arg::new("interval")
+                        .long("interval")
+                        .value_parser(value_parser!(u64).range(1..))
+                        .default_value("2")
+                        .help("Seconds between refreshes"),
+                )
+                .arg(
+                    Arg::new("iterations")
+                        .long("iterations")
+                        .value_parser(value_parser!(u64).range(1..))
+                        .help("Exit after refreshing this many times"),
+                ),
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .env("PG_STATS_EXPORTER_CONFIG")
+                .value_parser(value_parser!(PathBuf))
+                .help("TOML file of the listen addresses, the PostgreSQL connection, the collectors, the scrape timeout and the logging; the command line options take precedence"),
+        )
+        .arg(
+            Arg::new("listen")
+                .long("listen")
+                .env("PG_STATS_EXPORTER_LISTEN")
+                .value_parser(value_parser!(SocketAddr))
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .default_value(PG_STATS_EXPORTER_API)
+                .help("Addresses to serve HTTP on, comma-separated or repeated, e.g. 0.0.0.0:9753 or [::]:9753 to accept connections from other hosts, or both for dual-stack"),
+        )
+        .arg(
+            Arg::new("tcp-disable-reuse-address")
+                .long("tcp-disable-reuse-address")
+                .action(ArgAction::SetTrue)
+                .help("Do not set SO_REUSEADDR on the listening sockets"),
+        )
+        .arg(
+            Arg::new("tcp-reuse-port")
+                .long("tcp-reuse-port")
+                .action(ArgAction::SetTrue)
+                .help("Set SO_REUSEPORT on the listening sockets, so that several exporters can share the addresses"),
+        )
         .arg(
             Arg::new("postgres")
                 .long("postgres")
@@ -118,6 +1208,456 @@ fn cli() -> Command {
                 .long("dbname")
                 .help("PostgreSQL database name used to access a `postgres` address"),
         )
+        .arg(
+            Arg::new("dsn")
+                .long("dsn")
+                .conflicts_with_all(["postgres", "user", "dbname"])
+                .help("libpq connection string to collect metrics from instead of `postgres`, `user` and `dbname`, e.g., `postgresql://user@host1,host2/db?sslmode=require` or `host=/var/run/postgresql dbname=db`"),
+        )
+        .arg(
+            Arg::new("password-file")
+                .long("password-file")
+                .value_parser(value_parser!(PathBuf))
+                .help("File containing the password of `user`, reloaded when changed; otherwise `PGPASSWORD` or the password file of libpq (`PGPASSFILE` or `~/.pgpass`) is used"),
+        )
+        .arg(
+            Arg::new("sslmode")
+                .long("sslmode")
+                .value_parser(SslMode::VALUES)
+                .help("TLS mode of the PostgreSQL connections, as `sslmode` of libpq; `prefer` by default"),
+        )
+        .arg(
+            Arg::new("sslrootcert")
+                .long("sslrootcert")
+                .value_parser(value_parser!(PathBuf))
+                .help("PEM root certificates to verify the PostgreSQL server certificate with; required by `verify-ca` and `verify-full`"),
+        )
+        .arg(
+            Arg::new("sslcert")
+                .long("sslcert")
+                .value_parser(value_parser!(PathBuf))
+                .requires("sslkey")
+                .help("PEM client certificate to authenticate to PostgreSQL with"),
+        )
+        .arg(
+            Arg::new("sslkey")
+                .long("sslkey")
+                .value_parser(value_parser!(PathBuf))
+                .requires("sslcert")
+                .help("PEM private key of `sslcert`"),
+        )
+        .arg(
+            Arg::new("log-level")
+                .long("log-level")
+                .help("Log filter directives, e.g., `info,pg_stats_exporter::metrics=debug`, overriding `RUST_LOG`; reloaded on SIGHUP"),
+        )
+        .arg(
+            Arg::new("log-format")
+                .long("log-format")
+                .value_parser(LogFormat::VALUES)
+                .help("Format of the logs (default: text)"),
+        )
+        .arg(
+            Arg::new("redaction")
+                .long("redaction")
+                .value_parser(Redaction::VALUES)
+                .default_value("full")
+                .help("Sensitive data to redact from the logs and /queries/{queryid}: `full` masks the passwords of connection strings and strips the literals of SQL and the parameters of statements, `secrets` only masks the passwords, and `off` redacts nothing"),
+        )
+        .arg(
+            Arg::new("exit-if-unreachable")
+                .long("exit-if-unreachable")
+                .action(ArgAction::SetTrue)
+                .help("Exit at startup if `postgres` is unreachable instead of retrying in the background"),
+        )
+        .arg(
+            Arg::new("standby")
+                .long("standby")
+                .help("PostgreSQL standby address to collect heavy statistics (the largest relations) from instead of `postgres`"),
+        )
+        .arg(
+            Arg::new("consistent-snapshot")
+                .long("consistent-snapshot")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("standby")
+                .help("Run the collectors of a scrape in a single REPEATABLE READ transaction so that the exported values are mutually consistent"),
+        )
+        .arg(
+            Arg::new("compatibility-profile")
+                .long("compatibility-profile")
+                .value_parser(["auto", "postgres", "cockroachdb", "greenplum", "aurora"])
+                .default_value("auto")
+                .help("Compatibility profile disabling the collectors that rely on catalogs the server lacks; detected from the server if `auto`"),
+        )
+        .arg(
+            Arg::new("compatibility-disabled-collectors")
+                .long("compatibility-disabled-collectors")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .value_parser(PossibleValuesParser::new(metrics::COLLECTORS.iter()))
+                .help("Comma-separated collectors to disable instead of the ones of the compatibility profile"),
+        )
+        .arg(
+            collector_arg("hot_updates")
+                .help("Export per-table HOT update counts and ratios"),
+        )
+        .arg(
+            collector_arg("toast")
+                .help("Export per-table TOAST relation sizes and access counts"),
+        )
+        .arg(
+            collector_arg("statements_delta")
+                .help("Export per-statement calls and execution time per second since the previous scrape, for the statements executed in between; not exported by `/probe`"),
+        )
+        .arg(
+            Arg::new("collector.largest_relations")
+                .long("collector.largest_relations")
+                .value_parser(value_parser!(i64).range(1..))
+                .help("Export the given number of largest tables and indexes by on-disk size"),
+        )
+        .arg(
+            Arg::new("collector.largest_relations.interval")
+                .long("collector.largest_relations.interval")
+                .value_parser(value_parser!(u64))
+                .help(
+                    "Seconds to reuse the largest tables and indexes across scrapes (default: 300)",
+                ),
+        )
+        .arg(
+            collector_arg("vacuum_recency")
+                .help("Export per-table ages of the last vacuum and analyze; `=false` disables the collector"),
+        )
+        .arg(
+            Arg::new("collector.vacuum_recency.tables")
+                .long("collector.vacuum_recency.tables")
+                .help("Regular expression on `<schema>.<table>` to select tables for `collector.vacuum_recency`"),
+        )
+        .arg(
+            Arg::new("collector.logical_slots.slots")
+                .long("collector.logical_slots.slots")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .help("Comma-separated logical replication slots to export the lag and spill counters of; all the logical slots if not set"),
+        )
+        .arg(
+            collector_arg("filesystem")
+                .help("Export the sizes and free space of the filesystems of the data directory, WAL and tablespaces; the exporter has to run on the database host"),
+        )
+        .arg(
+            Arg::new("collector.filesystem.data_directory")
+                .long("collector.filesystem.data_directory")
+                .value_parser(value_parser!(PathBuf))
+                .requires("collector.filesystem")
+                .help("Data directory as seen from the exporter, if it differs from the `data_directory` setting or the role can't read it; tablespaces are then reached through `pg_tblspc`"),
+        )
+        .arg(
+            Arg::new("log-directory")
+                .long("log-directory")
+                .value_parser(value_parser!(PathBuf))
+                .help("Directory of PostgreSQL csvlog files to tail for log-based metrics"),
+        )
+        .arg(
+            Arg::new("collection-interval")
+                .long("collection-interval")
+                .value_parser(value_parser!(u64).range(1..))
+                .help("Collect metrics in the background every given seconds and serve the latest ones instead of collecting them on every scrape"),
+        )
+        .arg(
+            Arg::new("collector-interval")
+                .long("collector-interval")
+                .action(ArgAction::Append)
+                .value_parser(parse_collector_interval)
+                .requires("collection-interval")
+                .help("Background collection interval of a collector as `<collector>=<seconds>`, overriding `collection-interval`; can be repeated"),
+        )
+        .arg(
+            Arg::new("collection-timestamps")
+                .long("collection-timestamps")
+                .action(ArgAction::SetTrue)
+                .requires("collection-interval")
+                .help("Attach the time metrics were collected in the background to the exported samples"),
+        )
+        .arg(
+            Arg::new("metrics-cache-max-age")
+                .long("metrics-cache-max-age")
+                .value_parser(value_parser!(u64))
+                .requires("collection-interval")
+                .help("Seconds proxies may cache /metrics responses for with `Cache-Control: max-age`, which are otherwise marked `no-store`"),
+        )
+        .arg(
+            Arg::new("snapshot-file")
+                .long("snapshot-file")
+                .value_parser(value_parser!(PathBuf))
+                .requires("collection-interval")
+                .help("File to persist the latest background snapshot to and serve it from after a restart until the first collection succeeds"),
+        )
+        .arg(
+            Arg::new("scrape-timeout")
+                .long("scrape-timeout")
+                .value_parser(value_parser!(u64).range(1..))
+                .help("Seconds after which a scrape is abandoned if Prometheus doesn't send `X-Prometheus-Scrape-Timeout-Seconds`; unbounded by default"),
+        )
+        .arg(
+            Arg::new("readiness-timeout")
+                .long("readiness-timeout")
+                .value_parser(value_parser!(u64).range(1..))
+                .default_value("5")
+                .help("Seconds the test query of /readyz may take before the exporter is reported unready"),
+        )
+        .arg(
+            Arg::new("query-text-max-length")
+                .long("query-text-max-length")
+                .value_parser(value_parser!(usize))
+                .default_value("1000")
+                .help("Maximum number of characters of the statements served on /queries/{queryid}"),
+        )
+        .arg(
+            Arg::new("max-response-bytes")
+                .long("max-response-bytes")
+                .value_parser(value_parser!(usize))
+                .help("Maximum size of a /metrics response; metric families exceeding it are dropped"),
+        )
+        .arg(
+            Arg::new("tls-cert-file")
+                .long("tls-cert-file")
+                .value_parser(value_parser!(PathBuf))
+                .requires("tls-key-file")
+                .help("PEM certificate chain to serve metrics over TLS with; reloaded on SIGHUP and when changed"),
+        )
+        .arg(
+            Arg::new("tls-key-file")
+                .long("tls-key-file")
+                .value_parser(value_parser!(PathBuf))
+                .requires("tls-cert-file")
+                .help("PEM private key of `tls-cert-file`"),
+        )
+        .arg(
+            Arg::new("http-disable-keepalive")
+                .long("http-disable-keepalive")
+                .action(ArgAction::SetTrue)
+                .help("Close HTTP/1.1 connections after every response"),
+        )
+        .arg(
+            Arg::new("http-idle-timeout")
+                .long("http-idle-timeout")
+                .value_parser(value_parser!(u64).range(1..))
+                .help("Seconds to wait for a request on an idle HTTP/1.1 connection, or for an HTTP/2 keep-alive ping to be acknowledged, before closing the connection"),
+        )
+        .arg(
+            Arg::new("shutdown-timeout")
+                .long("shutdown-timeout")
+                .value_parser(value_parser!(u64))
+                .help("Seconds to wait for in-flight requests on shutdown before exiting anyway; waits indefinitely by default"),
+        )
+        .arg(
+            Arg::new("http2-keepalive-interval")
+                .long("http2-keepalive-interval")
+                .value_parser(value_parser!(u64).range(1..))
+                .help("Seconds between HTTP/2 keep-alive pings on idle connections"),
+        )
+        .arg(
+            Arg::new("tcp-keepalive")
+                .long("tcp-keepalive")
+                .value_parser(value_parser!(u64).range(1..))
+                .help("Seconds before sending TCP keep-alive probes on idle connections"),
+        )
+        .arg(
+            Arg::new("max-connections")
+                .long("max-connections")
+                .value_parser(value_parser!(u64).range(1..))
+                .help("Maximum number of connections open to a database, waited for by scrapes at the limit (default: unlimited)"),
+        )
+        .arg(
+            Arg::new("runtime-threads")
+                .long("runtime-threads")
+                .value_parser(value_parser!(u64).range(1..))
+                .help("Number of worker threads of the runtime (default: the number of CPUs)"),
+        )
+        .arg(
+            Arg::new("max-blocking-threads")
+                .long("max-blocking-threads")
+                .value_parser(value_parser!(u64).range(1..))
+                .help("Maximum number of threads running blocking work, e.g., backup tools (default: 512)"),
+        )
+        .arg(
+            Arg::new("scrape-error-behavior")
+                .long("scrape-error-behavior")
+                .value_parser(["up", "unavailable"])
+                .default_value("up")
+                .help("Response of /metrics when PostgreSQL is unreachable: 200 with `pg_up 0` (up) or 503 (unavailable)"),
+        )
+        .arg(
+            Arg::new("trusted-proxies")
+                .long("trusted-proxies")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .value_parser(value_parser!(IpCidr))
+                .help("Comma-separated CIDRs of reverse proxies whose Forwarded/X-Forwarded-For headers tell the client address logged"),
+        )
+        .arg(
+            Arg::new("probe-allowed-targets")
+                .long("probe-allowed-targets")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .value_parser(value_parser!(AllowedTarget))
+                .help("Comma-separated CIDRs and host names with optional ports that /probe may collect from, e.g. 10.0.0.0/8:6432; a CIDR without a port allows only 5432; /probe rejects every target by default"),
+        )
+        .arg(
+            Arg::new("auth-modules")
+                .long("auth-modules")
+                .value_parser(value_parser!(PathBuf))
+                .help("JSON file of credential profiles that /probe connects to targets with"),
+        )
+        .arg(
+            Arg::new("cors-allowed-origins")
+                .long("cors-allowed-origins")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .help("Comma-separated origins, or `*` for any, allowed to read the responses from browsers via CORS"),
+        )
+        .arg(
+            Arg::new("diff-retention")
+                .long("diff-retention")
+                .value_parser(value_parser!(u64))
+                .help("Seconds to keep the counters of past collections for in memory, enabling `/diff`"),
+        )
+        .arg(
+            Arg::new("federate")
+                .long("federate")
+                .action(ArgAction::Append)
+                .value_parser(value_parser!(Downstream))
+                .conflicts_with_all(["collection-interval", "ha-lock-key"])
+                .help("Serve the metrics of a downstream exporter given as `<cluster>=<url>` with a `cluster` label instead of collecting from PostgreSQL; can be repeated"),
+        )
+        .arg(
+            Arg::new("federate-timeout")
+                .long("federate-timeout")
+                .value_parser(value_parser!(u64).range(1..))
+                .default_value("10")
+                .help("Seconds to wait for each downstream exporter of `federate`"),
+        )
+        .arg(
+            Arg::new("unix-socket")
+                .long("unix-socket")
+                .value_parser(value_parser!(PathBuf))
+                .conflicts_with("federate")
+                .help("Unix socket path on which every connection gets a dump of the metrics in the text format, for host agents"),
+        )
+        .arg(
+            Arg::new("zabbix-server")
+                .long("zabbix-server")
+                .requires("zabbix-host")
+                .conflicts_with("federate")
+                .help("Zabbix server or proxy address, e.g., `zabbix:10051`, to push the metrics to as trapper items"),
+        )
+        .arg(
+            Arg::new("zabbix-host")
+                .long("zabbix-host")
+                .help("Host name in Zabbix the items pushed to `zabbix-server` belong to"),
+        )
+        .arg(
+            Arg::new("zabbix-key-prefix")
+                .long("zabbix-key-prefix")
+                .help("Prefix of the item keys pushed to `zabbix-server`"),
+        )
+        .arg(
+            Arg::new("zabbix-interval")
+                .long("zabbix-interval")
+                .value_parser(value_parser!(u64).range(1..))
+                .default_value("60")
+                .help("Seconds between pushes to `zabbix-server`"),
+        )
+        .arg(
+            Arg::new("notify-webhook")
+                .long("notify-webhook")
+                .env("PG_STATS_EXPORTER_NOTIFY_WEBHOOK")
+                .help("URL to POST events to as JSON: the exporter starting or stopping, and PostgreSQL or a collector failing or recovering"),
+        )
+        .arg(
+            Arg::new("notify-webhook-format")
+                .long("notify-webhook-format")
+                .value_parser(WebhookFormat::VALUES)
+                .default_value("generic")
+                .requires("notify-webhook")
+                .help("Format of the events POSTed to `notify-webhook`: `slack` for incoming webhooks, and `pagerduty` for the Events API v2"),
+        )
+        .arg(
+            Arg::new("notify-pagerduty-routing-key-file")
+                .long("notify-pagerduty-routing-key-file")
+                .value_parser(value_parser!(PathBuf))
+                .requires("notify-webhook")
+                .help("File containing the routing key of the PagerDuty integration, required by `--notify-webhook-format=pagerduty`"),
+        )
+        .arg(
+            Arg::new("memory-soft-limit-bytes")
+                .long("memory-soft-limit-bytes")
+                .value_parser(value_parser!(usize))
+                .help("Bytes allocated by the exporter above which collected metrics are dropped; requires the `memory-accounting` feature"),
+        )
+        .arg(
+            Arg::new("max-concurrent-scrapes")
+                .long("max-concurrent-scrapes")
+                .value_parser(value_parser!(u64).range(1..))
+                .help("Maximum number of scrapes in flight; more scrapes are rejected with 503"),
+        )
+        .arg(
+            Arg::new("ha-lock-key")
+                .long("ha-lock-key")
+                .value_parser(value_parser!(i64))
+                .help("Advisory lock key to elect the only instance collecting metrics among exporters for the same database"),
+        )
+        .arg(
+            Arg::new("patroni-url")
+                .long("patroni-url")
+                .help("URL of the Patroni REST API to export the cluster role from, e.g. http://127.0.0.1:8008/patroni"),
+        )
+        .arg(
+            Arg::new("backup-tool")
+                .long("backup-tool")
+                .value_parser(value_parser!(BackupSource))
+                .help("Backup tool to export the backup status from, run on this host: `pgbackrest[:<stanza>]` or `barman:<server>`"),
+        )
+        .arg(
+            Arg::new("backup-tool.interval")
+                .long("backup-tool.interval")
+                .value_parser(value_parser!(u64))
+                .requires("backup-tool")
+                .help("Seconds to reuse the backup status across scrapes (default: 300)"),
+        )
+        .arg(
+            Arg::new("custom-queries")
+                .long("custom-queries")
+                .value_parser(value_parser!(PathBuf))
+                .help("JSON file of user-defined queries whose results are exported"),
+        )
+        .arg(
+            Arg::new("allow-unsafe-queries")
+                .long("allow-unsafe-queries")
+                .action(ArgAction::SetTrue)
+                .requires("custom-queries")
+                .help("Run custom queries with data-modifying keywords or functions instead of refusing them at startup, and outside a read-only transaction"),
+        );
+    // Every collector without a flag of its own above can be disabled
+    metrics::COLLECTORS.iter().fold(cli, |cli, name| {
+        let id = format!("collector.{name}");
+        if cli.get_arguments().any(|arg| arg.get_id() == id.as_str()) {
+            return cli;
+        }
+        cli.arg(collector_arg(name).help(format!(
+            "Run the `{name}` collector (default: true); disable it with `--collector.{name}=false`"
+        )))
+    })
+}
+
+/// `--collector.<name>`, which enables an opt-in collector, or disables any collector with
+/// `=false`.
+fn collector_arg(name: &str) -> Arg {
+    Arg::new(format!("collector.{name}"))
+        .long(format!("collector.{name}"))
+        .value_parser(value_parser!(bool))
+        .num_args(0..=1)
+        .require_equals(true)
+        .default_missing_value("true")
 }
 
 #[test]
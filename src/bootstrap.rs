@@ -0,0 +1,118 @@
+//!
+//! Generates the statements granting a role the privileges the enabled collectors require.
+//!
+//! Some functions, e.g., the ones of pg_statsinfo, check for superuser internally, so EXECUTE
+//! on them is not enough. For those, security-definer helper functions owned by the
+//! superuser running the bootstrap can be created in the `stats_exporter` schema, and the
+//! collectors fall back to them when the role lacks the privileges on the original ones.
+//!
+use tokio_postgres::{Client, Error};
+
+use crate::metrics::{self, CollectorOptions};
+use crate::privileges::{self, Requirement};
+
+/// Schema of the security-definer helper functions.
+pub const HELPER_SCHEMA: &str = "stats_exporter";
+
+/// Returns the name of the helper function wrapping the function `signature`, e.g.,
+/// `cpustats` for `statsinfo.cpustats()`.
+pub fn helper_name(signature: &str) -> &str {
+    let name = signature.split('(').next().unwrap_or(signature);
+    name.rsplit('.').next().unwrap_or(name)
+}
+
+/// Returns the result type of the function `signature` usable in `RETURNS`, or `None` if
+/// the function doesn't exist. OUT parameters are turned into a `TABLE` type.
+async fn result_type(conn: &Client, signature: &str) -> Result<Option<String>, Error> {
+    let row = conn.query_opt(
+        "
+        SELECT
+            COALESCE(
+                'TABLE(' || (
+                    SELECT
+                        string_agg(format('%I %s', args.name, format_type(args.type, NULL)), ', ' ORDER BY args.ord)
+                    FROM
+                        unnest(proc.proargnames, proc.proallargtypes, proc.proargmodes)
+                            WITH ORDINALITY AS args(name, type, mode, ord)
+                    WHERE
+                        args.mode IN ('o', 't')
+                ) || ')',
+                pg_get_function_result(proc.oid)
+            )
+        FROM
+            pg_proc AS proc
+        WHERE
+            proc.oid = to_regprocedure($1)::oid
+        ",
+        &[&signature],
+    ).await?;
+    Ok(row.map(|row| row.get(0)))
+}
+
+/// Returns the statements granting `role` the privileges the collectors enabled by `options`
+/// require, followed by the ones creating helper functions if `helpers` is set.
+pub async fn statements(
+    conn: &Client,
+    role: &str,
+    options: &CollectorOptions,
+    helpers: bool,
+) -> Result<Vec<String>, Error> {
+    let role: String = conn
+        .query_one("SELECT quote_ident($1)", &[&role])
+        .await?
+        .get(0);
+
+    let mut requirements: Vec<Requirement> = vec![];
+    for name in metrics::COLLECTORS.iter() {
+        if metrics::is_enabled(name, options) {
+            for requirement in privileges::requirements(name, options) {
+                if !requirements.contains(requirement) {
+                    requirements.push(*requirement);
+                }
+            }
+        }
+    }
+
+    let mut statements = vec![];
+    for requirement in &requirements {
+        let statement = requirement.grant_statement(&role);
+        if !statements.contains(&statement) {
+            statements.push(statement);
+        }
+    }
+
+    if helpers {
+        statements.push(format!("CREATE SCHEMA IF NOT EXISTS {HELPER_SCHEMA};"));
+        statements.push(format!("GRANT USAGE ON SCHEMA {HELPER_SCHEMA} TO {role};"));
+        for requirement in &requirements {
+            let Requirement::Function(signature) = requirement else {
+                continue;
+            };
+            let Some(result_type) = result_type(conn, signature).await? else {
+                tracing::warn!("{signature} does not exist, skipping its helper function");
+                continue;
+            };
+            let helper = format!("{HELPER_SCHEMA}.{}()", helper_name(signature));
+            statements.push(format!(
+                "CREATE OR REPLACE FUNCTION {helper} RETURNS {result_type} \
+                 LANGUAGE sql SECURITY DEFINER SET search_path = pg_catalog \
+                 AS $$ SELECT * FROM {signature} $$;"
+            ));
+            statements.push(format!("REVOKE ALL ON FUNCTION {helper} FROM PUBLIC;"));
+            statements.push(format!("GRANT EXECUTE ON FUNCTION {helper} TO {role};"));
+        }
+    }
+
+    Ok(statements)
+}
+
+#[cfg(test)]
+mod tests_helper_name {
+    use crate::bootstrap::helper_name;
+
+    #[test]
+    fn test_helper_name() {
+        assert_eq!(helper_name("statsinfo.cpustats()"), "cpustats");
+        assert_eq!(helper_name("pg_hba_file_rules()"), "pg_hba_file_rules");
+    }
+}
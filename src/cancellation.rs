@@ -0,0 +1,151 @@
+//!
+//! Cancellation of scrapes whose client has given up.
+//!
+//! A scrape is abandoned once the scrape timeout Prometheus sends in
+//! `X-Prometheus-Scrape-Timeout-Seconds` passes or the client disconnects, but its
+//! collectors would otherwise run to completion, e.g., an expensive bloat query on an
+//! already struggling server. `ScrapeCancellation` is threaded from the request down to the
+//! collectors, which stop before the next collector once cancelled, and the query in flight
+//! is canceled with a cancel request when the deadline passes or the client disconnects.
+//!
+//! A registered connection is closed instead of returned to the pool unless `finish` is
+//! reached, so that a cancel request still on its way can't hit the next scrape reusing
+//! it, even if the scrape is dropped in the middle of a collector.
+//!
+use once_cell::sync::Lazy;
+use prometheus::{core::Collector, IntCounterVec, Opts};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio_postgres::CancelToken;
+
+use crate::help;
+use crate::pool::PooledClient;
+use crate::postgres_tls::PgTls;
+
+static CANCELLED_SCRAPES: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "pg_exporter_scrapes_cancelled_total",
+            help::PG_EXPORTER_SCRAPES_CANCELLED_TOTAL,
+        ),
+        &["reason"],
+    )
+    .unwrap()
+});
+
+#[derive(Clone, Copy, Debug)]
+pub enum CancelReason {
+    /// The scrape timeout of the client has passed.
+    Deadline,
+    /// The client disconnected before the response.
+    Disconnect,
+}
+
+impl CancelReason {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CancelReason::Deadline => "deadline",
+            CancelReason::Disconnect => "disconnect",
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct ScrapeCancellation {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    deadline: Option<Instant>,
+    cancelled: AtomicBool,
+    /// Connections of the collectors running, to send cancel requests to over the TLS
+    /// settings they were connected with.
+    cancel_tokens: Mutex<Vec<Registered>>,
+}
+
+struct Registered {
+    cancel_token: CancelToken,
+    tls: PgTls,
+    /// Closes the connection when dropped while a cancel request may be sent to it.
+    discard_on_drop: Arc<AtomicBool>,
+}
+
+impl ScrapeCancellation {
+    pub fn new(deadline: Option<Instant>) -> Self {
+        ScrapeCancellation {
+            inner: Arc::new(Inner {
+                deadline,
+                ..Default::default()
+            }),
+        }
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.inner.deadline
+    }
+
+    /// Returns true if the scrape was cancelled or its deadline has passed.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::Relaxed)
+            || self
+                .inner
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Registers a connection made with `tls`, whose query in flight is canceled by
+    /// `cancel_queries`. The connection is closed when dropped until `finish` is called.
+    pub fn register(&self, conn: &PooledClient, tls: &PgTls) {
+        let discard_on_drop = conn.discard_on_drop();
+        discard_on_drop.store(true, Ordering::Release);
+        self.inner.cancel_tokens.lock().unwrap().push(Registered {
+            cancel_token: conn.cancel_token(),
+            tls: tls.clone(),
+            discard_on_drop,
+        });
+    }
+
+    /// Forgets the registered connections once the collectors are done with them, which
+    /// lets them back in the pool unless a cancel request was already sent to them.
+    pub fn finish(&self) {
+        for registered in self.inner.cancel_tokens.lock().unwrap().drain(..) {
+            registered.discard_on_drop.store(false, Ordering::Release);
+        }
+    }
+
+    /// Cancels the scrape so that no more collectors run.
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Cancels the scrape and the queries in flight.
+    pub async fn cancel_queries(&self, reason: CancelReason) {
+        self.cancel();
+        let cancel_tokens = std::mem::take(&mut *self.inner.cancel_tokens.lock().unwrap());
+        if cancel_tokens.is_empty() {
+            // The collectors are done, or haven't connected yet and will stop right away
+            return;
+        }
+        CANCELLED_SCRAPES
+            .with_label_values(&[reason.as_str()])
+            .inc();
+        tracing::info!("canceling the queries of the scrape: {}", reason.as_str());
+        // The connections stay marked to be discarded, as `finish` can't find them anymore
+        for Registered {
+            cancel_token, tls, ..
+        } in cancel_tokens
+        {
+            if let Err(e) = cancel_token.cancel_query(tls).await {
+                tracing::warn!("failed to cancel the query of an abandoned scrape: {e:#}");
+            }
+        }
+    }
+}
+
+pub fn collect() -> Vec<prometheus::proto::MetricFamily> {
+    let mut metrics = CANCELLED_SCRAPES.collect();
+    metrics.retain(|m| !m.get_metric().is_empty());
+    metrics
+}
@@ -0,0 +1,189 @@
+//!
+//! `checkplugin` subcommand for Nagios and Icinga.
+//!
+//! Classic check-based monitoring runs a plugin per check and reads its exit status and
+//! output. `pg_stats_exporter checkplugin --metric <name> --warn <n> --crit <n>` collects
+//! once with the collectors, compares the largest value of the metric with the thresholds,
+//! and prints a status line with perfdata for every series.
+//!
+//! See <https://nagios-plugins.org/doc/guidelines.html#AEN78>
+//!
+use prometheus::proto::{MetricFamily, MetricType};
+
+use crate::cancellation::ScrapeCancellation;
+use crate::compatibility;
+use crate::metrics::{self, CollectorOptions};
+use crate::pool::Pool;
+use crate::postgres_connection::PgConnectionConfig;
+use crate::privileges::PrivilegeReport;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    Ok,
+    Warning,
+    Critical,
+    Unknown,
+}
+
+impl Status {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Status::Ok => 0,
+            Status::Warning => 1,
+            Status::Critical => 2,
+            Status::Unknown => 3,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Status::Ok => "OK",
+            Status::Warning => "WARNING",
+            Status::Critical => "CRITICAL",
+            Status::Unknown => "UNKNOWN",
+        }
+    }
+}
+
+/// Returns the series of the counter or gauge `name` in `metrics` as `(labels, value)`.
+fn series(metrics: &[MetricFamily], name: &str) -> Option<Vec<(String, f64)>> {
+    let family = metrics.iter().find(|m| m.get_name() == name)?;
+    let mut series: Vec<(String, f64)> = family
+        .get_metric()
+        .iter()
+        .filter_map(|m| {
+            let value = match family.get_field_type() {
+                MetricType::COUNTER => m.get_counter().get_value(),
+                MetricType::GAUGE => m.get_gauge().get_value(),
+                _ => return None,
+            };
+            let labels: Vec<String> = m
+                .get_label()
+                .iter()
+                .map(|l| format!("{}={}", l.get_name(), l.get_value()))
+                .collect();
+            Some((labels.join(","), value))
+        })
+        .collect();
+    // Vectors are collected in an arbitrary order
+    series.sort_by(|a, b| a.0.cmp(&b.0));
+    Some(series)
+}
+
+/// Evaluates the largest value of `name` against the thresholds, returning the status and
+/// the output line.
+pub fn evaluate(metrics: &[MetricFamily], name: &str, warn: f64, crit: f64) -> (Status, String) {
+    let series = match series(metrics, name) {
+        Some(series) if !series.is_empty() => series,
+        _ => {
+            return (
+                Status::Unknown,
+                format!("PG_STATS_EXPORTER UNKNOWN - {name} is not collected"),
+            )
+        }
+    };
+    let max = series
+        .iter()
+        .map(|(_, value)| *value)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let status = if max > crit {
+        Status::Critical
+    } else if max > warn {
+        Status::Warning
+    } else {
+        Status::Ok
+    };
+    let perfdata: Vec<String> = series
+        .iter()
+        .map(|(labels, value)| {
+            let label = if labels.is_empty() {
+                name.to_string()
+            } else {
+                format!("{name}{{{labels}}}")
+            };
+            format!("'{}'={value};{warn};{crit}", label.replace('\'', "''"))
+        })
+        .collect();
+    (
+        status,
+        format!(
+            "PG_STATS_EXPORTER {} - {name} = {max} | {}",
+            status.as_str(),
+            perfdata.join(" ")
+        ),
+    )
+}
+
+/// Collects once, prints the result of the check, and returns its status.
+pub async fn run(
+    postgres: &PgConnectionConfig,
+    options: &CollectorOptions,
+    name: &str,
+    warn: f64,
+    crit: f64,
+) -> Status {
+    let collect = async {
+        // Skip the collectors the role lacks the privileges for instead of failing
+        let conn = postgres.connect().await?;
+        compatibility::init(&conn, options).await?;
+        let report = PrivilegeReport::check(&conn, options).await?;
+        let _ = options.function_fallbacks.set(report.function_fallbacks());
+        anyhow::Ok(
+            metrics::gather(
+                &Pool::default(),
+                postgres,
+                options,
+                &ScrapeCancellation::default(),
+            )
+            .await?,
+        )
+    };
+    let (status, output) = match collect.await {
+        Ok(metrics) => evaluate(&metrics, name, warn, crit),
+        Err(e) => (
+            Status::Unknown,
+            format!(
+                "PG_STATS_EXPORTER UNKNOWN - failed to collect from {}: {e:#}",
+                postgres.raw_address()
+            ),
+        ),
+    };
+    println!("{output}");
+    status
+}
+
+#[cfg(test)]
+mod tests_evaluate {
+    use crate::checkplugin::{evaluate, Status};
+    use prometheus::{core::Collector, GaugeVec, Opts};
+
+    #[test]
+    fn test_evaluate() {
+        let lag = GaugeVec::new(Opts::new("pg_lag_seconds", "help"), &["standby"]).unwrap();
+        lag.with_label_values(&["s1"]).set(10.0);
+        lag.with_label_values(&["s2"]).set(45.0);
+        let metrics = lag.collect();
+
+        assert_eq!(
+            evaluate(&metrics, "pg_lag_seconds", 30.0, 120.0),
+            (
+                Status::Warning,
+                "PG_STATS_EXPORTER WARNING - pg_lag_seconds = 45 | \
+                 'pg_lag_seconds{standby=s1}'=10;30;120 'pg_lag_seconds{standby=s2}'=45;30;120"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            evaluate(&metrics, "pg_lag_seconds", 60.0, 120.0).0,
+            Status::Ok
+        );
+        assert_eq!(
+            evaluate(&metrics, "pg_lag_seconds", 5.0, 40.0).0,
+            Status::Critical
+        );
+        assert_eq!(
+            evaluate(&metrics, "pg_missing", 5.0, 40.0).0,
+            Status::Unknown
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//!
+//! Client addresses of requests forwarded by trusted reverse proxies.
+//!
+//! Behind a reverse proxy, the peer address of every request is the proxy's. If the peer is
+//! in one of the trusted proxy CIDRs, the client address is taken from the `Forwarded` or
+//! `X-Forwarded-For` header instead, walking the hops from the nearest one and skipping the
+//! trusted proxies. The headers are ignored for other peers, since anyone can set them.
+//!
+//! See <https://www.rfc-editor.org/rfc/rfc7239>
+//!
+use anyhow::{anyhow, bail};
+use hyper::header::{HeaderMap, FORWARDED};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// A network given in the CIDR notation, e.g., `10.0.0.0/8`. A plain address is a network
+/// of itself.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IpCidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl FromStr for IpCidr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| anyhow!("invalid address in `{s}`"))?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(len) => len
+                .parse()
+                .map_err(|_| anyhow!("invalid prefix length in `{s}`"))?,
+            None => max_len,
+        };
+        if prefix_len > max_len {
+            bail!("prefix length in `{s}` must be at most {max_len}");
+        }
+        Ok(IpCidr { addr, prefix_len })
+    }
+}
+
+impl IpCidr {
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, canonical(addr)) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(addr) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Turns IPv4-mapped IPv6 addresses, e.g., of dual-stack listeners, into IPv4 ones.
+fn canonical(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+        IpAddr::V4(_) => addr,
+    }
+}
+
+/// Parses a node of `Forwarded`'s `for` or of `X-Forwarded-For`, which may be quoted and
+/// have a port, e.g., `"[2001:db8::1]:4711"`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(addr) = node.parse::<IpAddr>() {
+        return Some(addr);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    node.strip_prefix('[')
+        .and_then(|node| node.strip_suffix(']'))
+        .and_then(|node| node.parse().ok())
+}
+
+/// Returns the forwarding hops in the headers, from the farthest one. `Forwarded` takes
+/// precedence over `X-Forwarded-For`. Obfuscated or unknown nodes are `None`.
+fn forwarded_hops(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let forwarded: Vec<&str> = headers
+        .get_all(FORWARDED)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .collect();
+    if !forwarded.is_empty() {
+        return forwarded
+            .iter()
+            .flat_map(|v| v.split(','))
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                    .and_then(|(_, node)| parse_node(node))
+            })
+            .collect();
+    }
+    headers
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(parse_node)
+        .collect()
+}
+
+/// Returns the address of the client whose request came from `peer`.
+pub fn client_addr(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpCidr]) -> IpAddr {
+    let is_trusted = |addr: IpAddr| trusted_proxies.iter().any(|cidr| cidr.contains(addr));
+    let mut client = peer;
+    if !is_trusted(client) {
+        return client;
+    }
+    for hop in forwarded_hops(headers).into_iter().rev() {
+        match hop {
+            Some(addr) => {
+                client = addr;
+                if !is_trusted(addr) {
+                    break;
+                }
+            }
+            // Nothing beyond an unidentifiable hop can be trusted
+            None => break,
+        }
+    }
+    client
+}
+
+#[cfg(test)]
+mod tests_client_addr {
+    use crate::client_addr::{client_addr, IpCidr};
+    use hyper::header::{HeaderMap, HeaderValue};
+
+    #[test]
+    fn test_ip_cidr() {
+        let cidr: IpCidr = "10.0.0.0/8".parse().unwrap();
+        assert!(cidr.contains("10.1.2.3".parse().unwrap()));
+        assert!(cidr.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!cidr.contains("192.168.0.1".parse().unwrap()));
+        let cidr: IpCidr = "fd00::/8".parse().unwrap();
+        assert!(cidr.contains("fd12::1".parse().unwrap()));
+        assert!(!cidr.contains("10.1.2.3".parse().unwrap()));
+        let cidr: IpCidr = "127.0.0.1".parse().unwrap();
+        assert!(cidr.contains("127.0.0.1".parse().unwrap()));
+        assert!(!cidr.contains("127.0.0.2".parse().unwrap()));
+        assert!("0.0.0.0/0"
+            .parse::<IpCidr>()
+            .unwrap()
+            .contains("1.2.3.4".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<IpCidr>().is_err());
+        assert!("proxy".parse::<IpCidr>().is_err());
+    }
+
+    #[test]
+    fn test_client_addr() {
+        let trusted: Vec<IpCidr> = vec!["10.0.0.0/8".parse().unwrap()];
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("203.0.113.9, 198.51.100.1, 10.0.0.2"),
+        );
+        // The nearest untrusted hop is the client, as the ones beyond it may be spoofed
+        assert_eq!(
+            client_addr("10.0.0.1".parse().unwrap(), &headers, &trusted),
+            "198.51.100.1".parse::<std::net::IpAddr>().unwrap()
+        );
+        // Headers from untrusted peers are ignored
+        assert_eq!(
+            client_addr("192.0.2.1".parse().unwrap(), &headers, &trusted),
+            "192.0.2.1".parse::<std::net::IpAddr>().unwrap()
+        );
+
+        headers.insert(
+            "forwarded",
+            HeaderValue::from_static("for=\"[2001:db8::1]:4711\";proto=https, for=10.0.0.2"),
+        );
+        assert_eq!(
+            client_addr("10.0.0.1".parse().unwrap(), &headers, &trusted),
+            "2001:db8::1".parse::<std::net::IpAddr>().unwrap()
+        );
+        headers.insert("forwarded", HeaderValue::from_static("for=unknown"));
+        assert_eq!(
+            client_addr("10.0.0.1".parse().unwrap(), &headers, &trusted),
+            "10.0.0.1".parse::<std::net::IpAddr>().unwrap()
+        );
+    }
+}
@@ -0,0 +1,227 @@
+//!
+//! Compatibility profiles for servers speaking the PostgreSQL wire protocol.
+//!
+//! CockroachDB, Greenplum and Aurora accept connections like PostgreSQL but lack some of
+//! its catalogs and functions, e.g., `pg_hba_file_rules()` or pg_statsinfo, and a collector
+//! querying them would fail the whole scrape. The server is detected at startup, and the
+//! collectors its profile lists are disabled. Both the profile and its collectors can be
+//! overridden with `--compatibility-profile` and `--compatibility-disabled-collectors`.
+//!
+use anyhow::bail;
+use prometheus::{core::Collector, IntGaugeVec, Opts};
+use std::str::FromStr;
+use tokio_postgres::{Client, Error};
+
+use crate::help;
+use crate::metrics::CollectorOptions;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Flavor {
+    Postgres,
+    Cockroachdb,
+    Greenplum,
+    Aurora,
+}
+
+impl Flavor {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Flavor::Postgres => "postgres",
+            Flavor::Cockroachdb => "cockroachdb",
+            Flavor::Greenplum => "greenplum",
+            Flavor::Aurora => "aurora",
+        }
+    }
+
+    /// Returns the collectors relying on catalogs or functions the server lacks.
+    pub fn disabled_collectors(&self) -> &'static [&'static str] {
+        match self {
+            Flavor::Postgres => &[],
+            // Most of the statistics views are empty stubs
+            Flavor::Cockroachdb => &[
+                "cpustats",
+                "tablespaces",
+                "loadavg",
+                "memory",
+                "devicestats",
+                "pg_stat_statements_info",
+                "stats_reset",
+                "hba_file",
+                "deadlocks",
+                "vacuum_recency",
+                "foreign_data",
+                "hot_updates",
+                "toast",
+                "largest_relations",
+                "log",
+                "logical_slots",
+            ],
+            Flavor::Greenplum => &[
+                "cpustats",
+                "tablespaces",
+                "loadavg",
+                "memory",
+                "devicestats",
+                "pg_stat_statements_info",
+                "hba_file",
+                "log",
+            ],
+            // The host isn't accessible, so neither are its files nor its CPU statistics
+            Flavor::Aurora => &[
+                "cpustats",
+                "tablespaces",
+                "loadavg",
+                "memory",
+                "devicestats",
+                "hba_file",
+                "log",
+            ],
+        }
+    }
+}
+
+impl FromStr for Flavor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "postgres" => Flavor::Postgres,
+            "cockroachdb" => Flavor::Cockroachdb,
+            "greenplum" => Flavor::Greenplum,
+            "aurora" => Flavor::Aurora,
+            _ => bail!("unknown compatibility profile `{s}`"),
+        })
+    }
+}
+
+/// Returns the flavor telling itself apart in the output of `version()`, if any.
+fn flavor_of_version(version: &str) -> Option<Flavor> {
+    if version.contains("CockroachDB") {
+        Some(Flavor::Cockroachdb)
+    } else if version.contains("Greenplum") {
+        Some(Flavor::Greenplum)
+    } else {
+        None
+    }
+}
+
+/// Detects the server `conn` is connected to.
+pub async fn detect(conn: &Client) -> Result<Flavor, Error> {
+    let version: String = conn.query_one("SELECT version()", &[]).await?.get(0);
+    if let Some(flavor) = flavor_of_version(&version) {
+        return Ok(flavor);
+    }
+    // Aurora reports the version of PostgreSQL it is compatible with, but has a function
+    // for its own
+    let aurora: bool = conn
+        .query_one("SELECT to_regproc('aurora_version') IS NOT NULL", &[])
+        .await?
+        .get(0);
+    Ok(if aurora {
+        Flavor::Aurora
+    } else {
+        Flavor::Postgres
+    })
+}
+
+/// The profile in effect, resolved at startup.
+#[derive(Clone, Debug)]
+pub struct Profile {
+    pub flavor: Flavor,
+    pub disabled_collectors: Vec<String>,
+    /// Whether `flavor` was detected rather than configured.
+    pub detected: bool,
+}
+
+impl Profile {
+    /// Resolves the profile of the server `conn` is connected to, unless `flavor` is given,
+    /// disabling `disabled_collectors` if given instead of the ones of the profile.
+    pub async fn resolve(
+        conn: &Client,
+        flavor: Option<Flavor>,
+        disabled_collectors: Option<&[String]>,
+    ) -> Result<Self, Error> {
+        let (flavor, detected) = match flavor {
+            Some(flavor) => (flavor, false),
+            None => (detect(conn).await?, true),
+        };
+        let disabled_collectors = match disabled_collectors {
+            Some(names) => names.to_vec(),
+            None => flavor
+                .disabled_collectors()
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+        };
+        Ok(Profile {
+            flavor,
+            disabled_collectors,
+            detected,
+        })
+    }
+
+    pub fn log(&self) {
+        if self.flavor == Flavor::Postgres && self.disabled_collectors.is_empty() {
+            return;
+        }
+        tracing::info!(
+            detected = self.detected,
+            "using the {} compatibility profile, disabled collectors: {}",
+            self.flavor.as_str(),
+            self.disabled_collectors.join(", ")
+        );
+    }
+
+    pub fn collect(&self) -> Vec<prometheus::proto::MetricFamily> {
+        let m = IntGaugeVec::new(
+            Opts::new(
+                "pg_exporter_compatibility_profile",
+                help::PG_EXPORTER_COMPATIBILITY_PROFILE,
+            ),
+            &["profile"],
+        )
+        .unwrap();
+        m.with_label_values(&[self.flavor.as_str()]).set(1);
+        m.collect()
+    }
+}
+
+/// Resolves the profile as configured in `options` and sets it in `options.profile`.
+pub async fn init(conn: &Client, options: &CollectorOptions) -> Result<(), Error> {
+    let profile = Profile::resolve(
+        conn,
+        options.compatibility_profile,
+        options.compatibility_disabled_collectors.as_deref(),
+    )
+    .await?;
+    profile.log();
+    let _ = options.profile.set(profile);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests_flavor {
+    use crate::compatibility::{flavor_of_version, Flavor};
+
+    #[test]
+    fn test_flavor_of_version() {
+        assert_eq!(
+            flavor_of_version(
+                "CockroachDB CCL v23.1.11 (x86_64-pc-linux-gnu, built 2023/09/27, go1.19.10)"
+            ),
+            Some(Flavor::Cockroachdb)
+        );
+        assert_eq!(
+            flavor_of_version(
+                "PostgreSQL 12.12 (Greenplum Database 7.0.0 build commit:0a7a3566) on x86_64-pc-linux-gnu"
+            ),
+            Some(Flavor::Greenplum)
+        );
+        assert_eq!(
+            flavor_of_version("PostgreSQL 15.4 on x86_64-pc-linux-gnu, compiled by gcc"),
+            None
+        );
+        assert_eq!("aurora".parse::<Flavor>().unwrap(), Flavor::Aurora);
+        assert!("mysql".parse::<Flavor>().is_err());
+    }
+}
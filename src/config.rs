@@ -0,0 +1,310 @@
+//!
+//! The configuration file of `--config`, in TOML:
+//!
+//! ```toml
+//! listen = ["0.0.0.0:9753"]
+//!
+//! [postgres]
+//! dsn = "postgresql://monitor@db1.example.com/postgres"
+//! password_file = "/run/secrets/monitor"
+//! sslmode = "verify-full"
+//! sslrootcert = "/etc/ssl/certs/db-ca.pem"
+//!
+//! [collectors]
+//! hot_updates = true
+//! largest_relations = 20
+//! cpustats = false
+//!
+//! [scrape]
+//! timeout = 10
+//! collection_interval = 30
+//!
+//! [scrape.collector_intervals]
+//! toast = 300
+//! largest_relations = 3600
+//!
+//! [logging]
+//! level = "info,pg_stats_exporter::metrics=debug"
+//! format = "json"
+//! ```
+//!
+//! `redaction = "secrets"` at the top level sets `--redaction`.
+//!
+//! Each setting stands for a command line option, which takes precedence if given, so that a
+//! shared file can be overridden per host. The connection settings are taken as a whole,
+//! i.e., none of them are used if `--dsn`, `--postgres`, `--user` or `--dbname` is given.
+//!
+//! The settings not given on the command line or in the environment are inserted into the
+//! arguments as options, which are parsed again. Clap doesn't check the requirements and
+//! conflicts between options against default values, so the settings are validated as if
+//! they were given on the command line instead. Only TOML is read; YAML isn't supported.
+//!
+//! The file is read again on SIGHUP, when `listen` and `logging.level` take effect; the
+//! other settings are read at startup only.
+//!
+use anyhow::{bail, Context};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use crate::metrics;
+use crate::redaction::Redaction;
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Addresses to serve HTTP on.
+    pub listen: Vec<SocketAddr>,
+    pub postgres: PostgresConfig,
+    /// Collectors to enable or disable by name, with the number of relations for
+    /// `largest_relations`.
+    pub collectors: BTreeMap<String, CollectorSetting>,
+    pub scrape: ScrapeConfig,
+    pub logging: LoggingConfig,
+    /// Sensitive data to redact from the logs and `/queries/{queryid}`.
+    pub redaction: Option<Redaction>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PostgresConfig {
+    pub dsn: Option<String>,
+    /// `host:port` of the server, as `--postgres`.
+    pub address: Option<String>,
+    pub user: Option<String>,
+    pub dbname: Option<String>,
+    pub password_file: Option<PathBuf>,
+    pub sslmode: Option<String>,
+    pub sslrootcert: Option<PathBuf>,
+    pub sslcert: Option<PathBuf>,
+    pub sslkey: Option<PathBuf>,
+    pub standby: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum CollectorSetting {
+    Enabled(bool),
+    Limit(i64),
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScrapeConfig {
+    /// Seconds a scrape may take if Prometheus doesn't send its scrape timeout.
+    pub timeout: Option<u64>,
+    /// Seconds between background collections.
+    pub collection_interval: Option<u64>,
+    /// Seconds between background collections of a collector by name, overriding
+    /// `collection_interval`.
+    pub collector_intervals: BTreeMap<String, u64>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// Filter directives as `RUST_LOG`, e.g., `info,pg_stats_exporter::metrics=debug`.
+    pub level: Option<String>,
+    pub format: Option<LogFormat>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl LogFormat {
+    pub const VALUES: [&'static str; 2] = ["text", "json"];
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => bail!("unknown log format `{s}`"),
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            LogFormat::Text => "text",
+            LogFormat::Json => "json",
+        })
+    }
+}
+
+/// Options of the connection to PostgreSQL, which are given all together or not at all.
+pub const CONNECTION_OPTIONS: [&str; 4] = ["dsn", "postgres", "user", "dbname"];
+
+impl Config {
+    /// Reads the configuration in `path`.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Self::parse(&content).with_context(|| format!("failed to parse {}", path.display()))
+    }
+
+    fn parse(content: &str) -> anyhow::Result<Self> {
+        let config: Config = toml::from_str(content)?;
+        for (name, setting) in &config.collectors {
+            if !metrics::COLLECTORS.contains(&name.as_str()) {
+                bail!("unknown collector `{name}`");
+            }
+            match (name.as_str(), setting) {
+                ("largest_relations", CollectorSetting::Limit(n)) if *n < 1 => {
+                    bail!("collector `largest_relations` takes a positive number")
+                }
+                (
+                    "largest_relations",
+                    CollectorSetting::Limit(_) | CollectorSetting::Enabled(false),
+                ) => {}
+                ("largest_relations", _) => bail!("collector `largest_relations` takes a number"),
+                (_, CollectorSetting::Enabled(_)) => {}
+                _ => bail!("collector `{name}` takes a boolean"),
+            }
+        }
+        for (name, secs) in &config.scrape.collector_intervals {
+            if !metrics::COLLECTORS.contains(&name.as_str()) {
+                bail!("unknown collector `{name}`");
+            }
+            if *secs == 0 {
+                bail!("invalid interval `{secs}` of collector `{name}`");
+            }
+        }
+        Ok(config)
+    }
+
+    /// Returns the command line options the settings stand for, as the long names of the
+    /// options and their values.
+    pub fn options(&self) -> Vec<(String, Vec<String>)> {
+        let mut options = vec![];
+        let mut push = |name: &str, values: Vec<String>| options.push((name.to_string(), values));
+        if !self.listen.is_empty() {
+            push(
+                "listen",
+                self.listen.iter().map(|addr| addr.to_string()).collect(),
+            );
+        }
+
+        let postgres = &self.postgres;
+        let path = |path: &Option<PathBuf>| path.as_ref().map(|p| p.display().to_string());
+        for (name, value) in [
+            ("dsn", postgres.dsn.clone()),
+            ("postgres", postgres.address.clone()),
+            ("user", postgres.user.clone()),
+            ("dbname", postgres.dbname.clone()),
+            ("password-file", path(&postgres.password_file)),
+            ("sslmode", postgres.sslmode.clone()),
+            ("sslrootcert", path(&postgres.sslrootcert)),
+            ("sslcert", path(&postgres.sslcert)),
+            ("sslkey", path(&postgres.sslkey)),
+            ("standby", postgres.standby.clone()),
+            ("scrape-timeout", self.scrape.timeout.map(|n| n.to_string())),
+            (
+                "collection-interval",
+                self.scrape.collection_interval.map(|n| n.to_string()),
+            ),
+            ("log-level", self.logging.level.clone()),
+            ("log-format", self.logging.format.map(|f| f.to_string())),
+            ("redaction", self.redaction.map(|r| r.to_string())),
+        ] {
+            if let Some(value) = value {
+                push(name, vec![value]);
+            }
+        }
+
+        for (name, setting) in &self.collectors {
+            let value = match setting {
+                // `--collector.largest_relations` takes a number and is disabled by default
+                CollectorSetting::Enabled(false) if name == "largest_relations" => continue,
+                CollectorSetting::Enabled(enabled) => enabled.to_string(),
+                CollectorSetting::Limit(n) => n.to_string(),
+            };
+            push(&format!("collector.{name}"), vec![value]);
+        }
+        if !self.scrape.collector_intervals.is_empty() {
+            push(
+                "collector-interval",
+                self.scrape
+                    .collector_intervals
+                    .iter()
+                    .map(|(name, secs)| format!("{name}={secs}"))
+                    .collect(),
+            );
+        }
+        options
+    }
+}
+
+#[cfg(test)]
+mod tests_config {
+    use crate::config::{CollectorSetting, Config, LogFormat};
+
+    #[test]
+    fn test_options() {
+        let config = Config::parse(
+            r#"
+listen = ["0.0.0.0:9753", "[::]:9753"]
+[postgres]
+address = "db1.example.com:5432"
+user = "monitor"
+sslmode = "require"
+[collectors]
+hot_updates = true
+cpustats = false
+largest_relations = 20
+[scrape]
+timeout = 10
+collection_interval = 30
+collector_intervals = { largest_relations = 3600, toast = 300 }
+[logging]
+format = "json"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.collectors.get("largest_relations"),
+            Some(&CollectorSetting::Limit(20))
+        );
+        assert_eq!(config.logging.format, Some(LogFormat::Json));
+        let options: Vec<_> = config
+            .options()
+            .into_iter()
+            .map(|(name, values)| format!("{name}={}", values.join(",")))
+            .collect();
+        assert_eq!(
+            options,
+            vec![
+                "listen=0.0.0.0:9753,[::]:9753",
+                "postgres=db1.example.com:5432",
+                "user=monitor",
+                "sslmode=require",
+                "scrape-timeout=10",
+                "collection-interval=30",
+                "log-format=json",
+                "collector.cpustats=false",
+                "collector.hot_updates=true",
+                "collector.largest_relations=20",
+                "collector-interval=largest_relations=3600,toast=300",
+            ]
+        );
+
+        assert!(Config::parse("[collectors]\nno_such_collector = true").is_err());
+        assert!(Config::parse("[collectors]\nhot_updates = 1").is_err());
+        assert!(Config::parse("[scrape.collector_intervals]\nno_such_collector = 60").is_err());
+        assert!(Config::parse("[scrape.collector_intervals]\ntoast = 0").is_err());
+        assert!(Config::parse("[postgres]\nhost = \"db\"").is_err());
+        assert!(Config::parse("listen = []\nlisten = []").is_err());
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+}
@@ -0,0 +1,763 @@
+//!
+//! Metrics of user-defined queries.
+//!
+//! `--custom-queries <file>` reads a JSON array of queries whose result rows are exported,
+//! e.g., the depth of an application's job queue:
+//!
+//! ```json
+//! [
+//!   {
+//!     "name": "app_jobs",
+//!     "query": "SELECT queue, count(*)::float8 AS depth FROM jobs GROUP BY queue",
+//!     "labels": ["queue"],
+//!     "values": [{ "column": "depth", "type": "gauge", "help": "Jobs waiting in a queue" }]
+//!   }
+//! ]
+//! ```
+//!
+//! exports `app_jobs_depth{queue="..."}`. Numeric, boolean, date and timestamp columns are
+//! converted into values, and a NULL value skips the sample unless `"null": <value>` is set
+//! for the column.
+//!
+//! The exporter usually runs with a privileged role, so unless `--allow-unsafe-queries` is
+//! given, the queries are run in a read-only transaction, which PostgreSQL enforces. A query
+//! is also refused at startup if it contains more than one statement or a keyword or
+//! function that modifies data or the server, which is best-effort but catches mistakes
+//! early. Queries are run as prepared statements, so PostgreSQL refuses multiple statements
+//! even with `--allow-unsafe-queries`.
+//!
+use anyhow::{bail, Context};
+use once_cell::sync::Lazy;
+use prometheus::{core::Collector, CounterVec, GaugeVec, IntCounterVec, Opts};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::Path;
+use tokio_postgres::types::{FromSql, Type};
+use tokio_postgres::{Client, Error, Row};
+
+use crate::help;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueType {
+    #[default]
+    Gauge,
+    Counter,
+}
+
+/// A column of the result exported as the value of a metric.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ValueColumn {
+    pub column: String,
+    #[serde(default, rename = "type")]
+    pub value_type: ValueType,
+    pub help: Option<String>,
+    /// Value of the sample if the column is NULL, which skips the sample if not set.
+    #[serde(rename = "null")]
+    pub null_default: Option<f64>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CustomQuery {
+    /// Prefix of the names of the metrics, followed by the names of the value columns.
+    pub name: String,
+    pub query: String,
+    /// Columns of the result exported as labels.
+    #[serde(default)]
+    pub labels: Vec<String>,
+    pub values: Vec<ValueColumn>,
+}
+
+impl CustomQuery {
+    fn metric_name(&self, value: &ValueColumn) -> String {
+        format!("{}_{}", self.name, value.column)
+    }
+}
+
+/// Keywords of statements and clauses that modify data, the schema or the session.
+const UNSAFE_KEYWORDS: &[&str] = &[
+    "abort",
+    "alter",
+    "analyze",
+    "begin",
+    "call",
+    "checkpoint",
+    "cluster",
+    "comment",
+    "commit",
+    "copy",
+    "create",
+    "deallocate",
+    "delete",
+    "discard",
+    "do",
+    "drop",
+    "execute",
+    "grant",
+    "import",
+    "insert",
+    "into",
+    "listen",
+    "load",
+    "lock",
+    "merge",
+    "notify",
+    "prepare",
+    "reassign",
+    "refresh",
+    "reindex",
+    "reset",
+    "revoke",
+    "rollback",
+    "savepoint",
+    "security",
+    "set",
+    "truncate",
+    "unlisten",
+    "update",
+    "vacuum",
+];
+
+/// Functions with side effects on the server, callable from a `SELECT`.
+const UNSAFE_FUNCTIONS: &[&str] = &[
+    "dblink",
+    "dblink_exec",
+    "lo_create",
+    "lo_export",
+    "lo_from_bytea",
+    "lo_import",
+    "lo_put",
+    "lo_truncate",
+    "lo_truncate64",
+    "lo_unlink",
+    "lowrite",
+    "nextval",
+    "pg_backup_start",
+    "pg_backup_stop",
+    "pg_cancel_backend",
+    "pg_create_logical_replication_slot",
+    "pg_create_physical_replication_slot",
+    "pg_create_restore_point",
+    "pg_current_xact_id",
+    "pg_drop_replication_slot",
+    "pg_file_rename",
+    "pg_file_unlink",
+    "pg_file_write",
+    "pg_logical_emit_message",
+    "pg_logical_slot_get_binary_changes",
+    "pg_logical_slot_get_changes",
+    "pg_notify",
+    "pg_promote",
+    "pg_reload_conf",
+    "pg_replication_slot_advance",
+    "pg_rotate_logfile",
+    "pg_start_backup",
+    "pg_stop_backup",
+    "pg_switch_wal",
+    "pg_terminate_backend",
+    "pg_wal_replay_pause",
+    "pg_wal_replay_resume",
+    "set_config",
+    "setval",
+    "txid_current",
+];
+
+/// Prefixes of families of functions with side effects, e.g., `pg_stat_reset_shared()`.
+const UNSAFE_FUNCTION_PREFIXES: &[&str] = &[
+    "pg_advisory",
+    "pg_copy_",
+    "pg_replication_origin_",
+    "pg_stat_reset",
+    "pg_stat_statements_reset",
+];
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    /// A keyword or an unquoted identifier, folded to lower case.
+    Word(String),
+    QuotedIdentifier(String),
+    Semicolon,
+    Other,
+}
+
+/// Splits `sql` into tokens the way the lexer of PostgreSQL does, skipping comments and
+/// literals, so that a keyword in a string isn't taken for a statement and one hidden
+/// behind a comment or a quote isn't missed.
+///
+/// See <https://www.postgresql.org/docs/current/sql-syntax-lexical.html>
+fn tokenize(sql: &str) -> anyhow::Result<Vec<Token>> {
+    let chars: Vec<char> = sql.chars().collect();
+    let is_word_start = |c: char| c.is_alphabetic() || c == '_' || !c.is_ascii();
+    let is_word_char = |c: char| is_word_start(c) || c.is_ascii_digit() || c == '$';
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '-' && next == Some('-') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && next == Some('*') {
+            // Block comments nest
+            let mut depth = 0;
+            loop {
+                match (chars.get(i), chars.get(i + 1)) {
+                    (Some('/'), Some('*')) => {
+                        depth += 1;
+                        i += 2;
+                    }
+                    (Some('*'), Some('/')) => {
+                        depth -= 1;
+                        i += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    (Some(_), _) => i += 1,
+                    (None, _) => bail!("unterminated comment"),
+                }
+            }
+        } else if c == '\'' {
+            i = skip_quoted(&chars, i + 1, '\'', false).context("unterminated string")?;
+            tokens.push(Token::Other);
+        } else if c == '"' {
+            let start = i + 1;
+            i = skip_quoted(&chars, start, '"', false).context("unterminated identifier")?;
+            let ident: String = chars[start..i - 1].iter().collect();
+            tokens.push(Token::QuotedIdentifier(ident.replace("\"\"", "\"")));
+        } else if c == '$' && next.is_some_and(|c| c.is_ascii_digit()) {
+            // A parameter, e.g., `$1`
+            i += 1;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            tokens.push(Token::Other);
+        } else if c == '$' {
+            let mut end = i + 1;
+            if next.is_some_and(is_word_start) {
+                while end < chars.len() && is_word_char(chars[end]) && chars[end] != '$' {
+                    end += 1;
+                }
+            }
+            if chars.get(end) != Some(&'$') {
+                i += 1;
+                tokens.push(Token::Other);
+                continue;
+            }
+            // A dollar-quoted string, e.g., `$tag$...$tag$`
+            let tag = &chars[i..=end];
+            i = end + 1;
+            loop {
+                if i + tag.len() > chars.len() {
+                    bail!("unterminated dollar-quoted string");
+                }
+                if &chars[i..i + tag.len()] == tag {
+                    i += tag.len();
+                    break;
+                }
+                i += 1;
+            }
+            tokens.push(Token::Other);
+        } else if c.is_ascii_digit() {
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            tokens.push(Token::Other);
+        } else if is_word_start(c) {
+            let start = i;
+            while i < chars.len() && is_word_char(chars[i]) {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect::<String>().to_lowercase();
+            if word == "u" && chars.get(i) == Some(&'&') && chars.get(i + 1) == Some(&'"') {
+                // Escapes in an identifier, e.g., `U&"set\0066config"`, would hide its name
+                bail!("identifiers with Unicode escapes are not allowed");
+            } else if word == "e" && chars.get(i) == Some(&'\'') {
+                // A string with C-style escapes, e.g., `E'it\'s'`
+                i = skip_quoted(&chars, i + 1, '\'', true).context("unterminated string")?;
+                tokens.push(Token::Other);
+            } else {
+                tokens.push(Token::Word(word));
+            }
+        } else if c == ';' {
+            i += 1;
+            tokens.push(Token::Semicolon);
+        } else {
+            i += 1;
+            tokens.push(Token::Other);
+        }
+    }
+    Ok(tokens)
+}
+
+/// Returns the position after the `quote` closing the literal starting at `i`, where a
+/// doubled `quote` stands for itself, and so does an escaped character if `escapes`.
+fn skip_quoted(chars: &[char], mut i: usize, quote: char, escapes: bool) -> Option<usize> {
+    loop {
+        match chars.get(i) {
+            Some('\\') if escapes => i += 2,
+            Some(&c) if c == quote => {
+                if chars.get(i + 1) == Some(&quote) {
+                    i += 2;
+                } else {
+                    return Some(i + 1);
+                }
+            }
+            Some(_) => i += 1,
+            None => return None,
+        }
+    }
+}
+
+fn is_unsafe_function(name: &str) -> bool {
+    UNSAFE_FUNCTIONS.contains(&name)
+        || UNSAFE_FUNCTION_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+}
+
+/// Refuses `sql` unless it is a single statement without data-modifying keywords or
+/// functions with side effects. A column named like such a keyword has to be quoted.
+pub fn validate(sql: &str) -> anyhow::Result<()> {
+    let tokens = tokenize(sql)?;
+    // A trailing semicolon is fine, but nothing may follow it
+    if let Some(pos) = tokens.iter().position(|t| *t == Token::Semicolon) {
+        if tokens[pos + 1..].iter().any(|t| *t != Token::Semicolon) {
+            bail!("multiple statements are not allowed");
+        }
+    }
+    for token in &tokens {
+        match token {
+            Token::Word(word) if UNSAFE_KEYWORDS.contains(&word.as_str()) => {
+                bail!("`{}` is not allowed", word.to_uppercase())
+            }
+            Token::Word(name) | Token::QuotedIdentifier(name) if is_unsafe_function(name) => {
+                bail!("`{name}` is not allowed")
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn is_valid_name(name: &str, colons: bool) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || (colons && c == ':'))
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || (colons && c == ':'))
+}
+
+/// Reads the queries in `path`, refusing the unsafe ones unless `allow_unsafe`.
+pub fn load(path: &Path, allow_unsafe: bool) -> anyhow::Result<Vec<CustomQuery>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let queries: Vec<CustomQuery> = serde_json::from_str(&content)
+        .with_context(|| format!("failed to parse {}", path.display()))?;
+    for q in &queries {
+        for value in &q.values {
+            let name = q.metric_name(value);
+            if !is_valid_name(&name, true) {
+                bail!("custom query {}: invalid metric name `{name}`", q.name);
+            }
+        }
+        if let Some(label) = q.labels.iter().find(|l| !is_valid_name(l, false)) {
+            bail!("custom query {}: invalid label name `{label}`", q.name);
+        }
+        if !allow_unsafe {
+            validate(&q.query).with_context(|| {
+                format!(
+                    "custom query {} is refused, use --allow-unsafe-queries to run it anyway",
+                    q.name
+                )
+            })?;
+        }
+    }
+    Ok(queries)
+}
+
+/// Failures to convert a column of a custom query, which skip the sample instead of the
+/// whole scrape, by the name of the query.
+static CONVERSION_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "pg_exporter_custom_query_conversion_errors_total",
+            help::PG_EXPORTER_CUSTOM_QUERY_CONVERSION_ERRORS_TOTAL,
+        ),
+        &["query"],
+    )
+    .unwrap()
+});
+
+/// Seconds between 1970-01-01 and 2000-01-01, the epoch of the dates and timestamps of
+/// PostgreSQL.
+const POSTGRES_EPOCH: f64 = 946_684_800.0;
+
+/// Decodes a `numeric` in the binary format, which is a header of the number of digits, the
+/// weight of the first digit, the sign and the display scale, followed by base-10000 digits.
+///
+/// See <https://github.com/postgres/postgres/blob/REL_15_STABLE/src/backend/utils/adt/numeric.c#L1076-L1114>
+fn numeric_to_f64(raw: &[u8]) -> Result<f64, Box<dyn std::error::Error + Sync + Send>> {
+    let word = |i: usize| -> Result<u16, Box<dyn std::error::Error + Sync + Send>> {
+        match raw.get(i * 2..i * 2 + 2) {
+            Some(b) => Ok(u16::from_be_bytes([b[0], b[1]])),
+            None => Err("invalid numeric".into()),
+        }
+    };
+    let ndigits = word(0)? as usize;
+    let weight = word(1)? as i16 as i32;
+    let sign = word(2)?;
+    // Parsing the digits as a decimal rounds correctly, unlike summing their powers
+    let mut digits = String::from("0");
+    for i in 0..ndigits {
+        digits.push_str(&format!("{:04}", word(4 + i)?));
+    }
+    let exponent = 4 * (weight - ndigits as i32 + 1);
+    let value: f64 = format!("{digits}e{exponent}").parse()?;
+    Ok(match sign {
+        0x0000 => value,
+        0x4000 => -value,
+        0xC000 => f64::NAN,
+        0xD000 => f64::INFINITY,
+        0xF000 => f64::NEG_INFINITY,
+        _ => return Err("invalid numeric sign".into()),
+    })
+}
+
+/// Converts microseconds since the epoch of PostgreSQL into seconds since the Unix epoch,
+/// keeping `infinity` and `-infinity`.
+fn timestamp_to_f64(micros: i64) -> f64 {
+    match micros {
+        i64::MAX => f64::INFINITY,
+        i64::MIN => f64::NEG_INFINITY,
+        _ => micros as f64 / 1e6 + POSTGRES_EPOCH,
+    }
+}
+
+/// A value column coerced to a sample: numbers as they are, booleans as 0 or 1, and dates
+/// and timestamps as seconds since the Unix epoch.
+struct Sample(f64);
+
+impl<'a> FromSql<'a> for Sample {
+    fn from_sql(
+        ty: &Type,
+        raw: &'a [u8],
+    ) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> {
+        let value = match *ty {
+            Type::BOOL => bool::from_sql(ty, raw)? as i64 as f64,
+            Type::INT2 => i16::from_sql(ty, raw)? as f64,
+            Type::INT4 => i32::from_sql(ty, raw)? as f64,
+            Type::INT8 => i64::from_sql(ty, raw)? as f64,
+            Type::OID => u32::from_sql(ty, raw)? as f64,
+            Type::FLOAT4 => f32::from_sql(ty, raw)? as f64,
+            Type::FLOAT8 => f64::from_sql(ty, raw)?,
+            Type::NUMERIC => numeric_to_f64(raw)?,
+            Type::TIMESTAMP | Type::TIMESTAMPTZ => {
+                timestamp_to_f64(i64::from_sql(&Type::INT8, raw)?)
+            }
+            Type::DATE => match i32::from_sql(&Type::INT4, raw)? {
+                i32::MAX => f64::INFINITY,
+                i32::MIN => f64::NEG_INFINITY,
+                days => days as f64 * 86400.0 + POSTGRES_EPOCH,
+            },
+            _ => return Err(format!("cannot convert {ty} into a sample").into()),
+        };
+        Ok(Sample(value))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        matches!(
+            *ty,
+            Type::BOOL
+                | Type::INT2
+                | Type::INT4
+                | Type::INT8
+                | Type::OID
+                | Type::FLOAT4
+                | Type::FLOAT8
+                | Type::NUMERIC
+                | Type::TIMESTAMP
+                | Type::TIMESTAMPTZ
+                | Type::DATE
+        )
+    }
+}
+
+/// A label column coerced to text. Besides strings, integers and booleans are accepted.
+struct Label(String);
+
+impl<'a> FromSql<'a> for Label {
+    fn from_sql(
+        ty: &Type,
+        raw: &'a [u8],
+    ) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> {
+        let value = match *ty {
+            Type::BOOL => bool::from_sql(ty, raw)?.to_string(),
+            Type::INT2 => i16::from_sql(ty, raw)?.to_string(),
+            Type::INT4 => i32::from_sql(ty, raw)?.to_string(),
+            Type::INT8 => i64::from_sql(ty, raw)?.to_string(),
+            Type::OID => u32::from_sql(ty, raw)?.to_string(),
+            _ => String::from_sql(ty, raw)?,
+        };
+        Ok(Label(value))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        matches!(
+            *ty,
+            Type::BOOL | Type::INT2 | Type::INT4 | Type::INT8 | Type::OID
+        ) || <String as FromSql>::accepts(ty)
+    }
+}
+
+/// Returns the label values of `row`, where NULL is an empty string.
+fn row_labels(row: &Row, labels: &[String]) -> Result<Vec<String>, Error> {
+    labels
+        .iter()
+        .map(|l| {
+            Ok(row
+                .try_get::<_, Option<Label>>(l.as_str())?
+                .map(|l| l.0)
+                .unwrap_or_default())
+        })
+        .collect()
+}
+
+/// Returns the value of `row` for `value`, or its default if NULL. `None` skips the sample.
+fn row_value(row: &Row, value: &ValueColumn) -> Result<Option<f64>, Error> {
+    Ok(row
+        .try_get::<_, Option<Sample>>(value.column.as_str())?
+        .map(|s| s.0)
+        .or(value.null_default))
+}
+
+/// Runs `queries` and exports their results, in a read-only transaction if `read_only`. A
+/// value or label that can't be converted skips its sample, and a row repeating the labels
+/// of an earlier one is skipped, which are counted in
+/// `pg_exporter_custom_query_conversion_errors_total`.
+#[tracing::instrument(name = "get_custom_queries", skip_all)]
+pub async fn collect(
+    conn: &Client,
+    queries: &[CustomQuery],
+    read_only: bool,
+) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    if !read_only {
+        return run(conn, queries).await;
+    }
+    conn.batch_execute("BEGIN READ ONLY").await?;
+    let result = run(conn, queries).await;
+    // Nothing is to be kept, and a failed query has aborted the transaction anyway
+    conn.batch_execute("ROLLBACK").await?;
+    result
+}
+
+async fn run(
+    conn: &Client,
+    queries: &[CustomQuery],
+) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    for q in queries {
+        let errors = CONVERSION_ERRORS.with_label_values(&[&q.name]);
+        let rows = conn.query(&q.query, &[]).await?;
+        let labels: Vec<&str> = q.labels.iter().map(|l| l.as_str()).collect();
+        let mut label_values = vec![];
+        let mut seen = HashSet::new();
+        for row in rows.iter() {
+            match row_labels(row, &q.labels) {
+                // Samples of the same labels would overwrite or add up to each other
+                Ok(values) if !seen.insert(values.clone()) => {
+                    tracing::warn!(
+                        query = q.name,
+                        "duplicate labels {values:?}, skipping the row"
+                    );
+                    errors.inc_by(q.values.len() as u64);
+                    label_values.push(None);
+                }
+                Ok(values) => label_values.push(Some(values)),
+                Err(e) => {
+                    tracing::warn!(query = q.name, "failed to convert labels: {e}");
+                    errors.inc_by(q.values.len() as u64);
+                    label_values.push(None);
+                }
+            }
+        }
+        for value in &q.values {
+            let name = q.metric_name(value);
+            let help = value
+                .help
+                .clone()
+                .unwrap_or_else(|| format!("{} of the custom query {}", value.column, q.name));
+            let gauge = GaugeVec::new(Opts::new(&name, &help), &labels).unwrap();
+            let counter = CounterVec::new(Opts::new(&name, &help), &labels).unwrap();
+            for (row, label_values) in rows.iter().zip(&label_values) {
+                let Some(label_values) = label_values else {
+                    continue;
+                };
+                let label_values: Vec<&str> = label_values.iter().map(|v| v.as_str()).collect();
+                match (row_value(row, value), value.value_type) {
+                    (Ok(None), _) => {}
+                    (Ok(Some(v)), ValueType::Gauge) => {
+                        gauge.with_label_values(&label_values).set(v)
+                    }
+                    (Ok(Some(v)), ValueType::Counter) if v >= 0.0 => {
+                        counter.with_label_values(&label_values).inc_by(v)
+                    }
+                    (Ok(Some(v)), ValueType::Counter) if v.is_nan() => {
+                        tracing::warn!(query = q.name, "NaN value of counter {name}");
+                        errors.inc();
+                    }
+                    (Ok(Some(v)), ValueType::Counter) => {
+                        tracing::warn!(query = q.name, "negative value {v} of counter {name}");
+                        errors.inc();
+                    }
+                    (Err(e), _) => {
+                        tracing::warn!(query = q.name, "failed to convert {}: {e}", value.column);
+                        errors.inc();
+                    }
+                }
+            }
+            match value.value_type {
+                ValueType::Gauge => metrics.append(&mut gauge.collect()),
+                ValueType::Counter => metrics.append(&mut counter.collect()),
+            }
+        }
+    }
+    // Only the errors of `queries`, e.g., not of the ones of another target, and from zero
+    let errors = IntCounterVec::new(
+        Opts::new(
+            "pg_exporter_custom_query_conversion_errors_total",
+            help::PG_EXPORTER_CUSTOM_QUERY_CONVERSION_ERRORS_TOTAL,
+        ),
+        &["query"],
+    )
+    .unwrap();
+    for q in queries {
+        errors
+            .with_label_values(&[&q.name])
+            .inc_by(CONVERSION_ERRORS.with_label_values(&[&q.name]).get());
+    }
+    metrics.append(&mut errors.collect());
+
+    metrics.retain(|m| !m.get_metric().is_empty());
+    Ok(metrics)
+}
+
+#[cfg(test)]
+mod tests_coercion {
+    use crate::custom_queries::{numeric_to_f64, timestamp_to_f64};
+
+    fn numeric(words: &[u16]) -> Vec<u8> {
+        words.iter().flat_map(|w| w.to_be_bytes()).collect()
+    }
+
+    #[test]
+    fn test_numeric_to_f64() {
+        // 12345.678 = 1 2345 . 6780
+        assert_eq!(
+            numeric_to_f64(&numeric(&[3, 1, 0x0000, 3, 1, 2345, 6780])).unwrap(),
+            12345.678
+        );
+        // -0.0042 = 0 . 0042
+        assert_eq!(
+            numeric_to_f64(&numeric(&[1, 0xFFFF, 0x4000, 4, 42])).unwrap(),
+            -0.0042
+        );
+        // 20000 = 2 (0000)
+        assert_eq!(
+            numeric_to_f64(&numeric(&[1, 1, 0x0000, 0, 2])).unwrap(),
+            20000.0
+        );
+        assert_eq!(numeric_to_f64(&numeric(&[0, 0, 0x0000, 0])).unwrap(), 0.0);
+        assert!(numeric_to_f64(&numeric(&[0, 0, 0xC000, 0]))
+            .unwrap()
+            .is_nan());
+        assert_eq!(
+            numeric_to_f64(&numeric(&[0, 0, 0xF000, 0])).unwrap(),
+            f64::NEG_INFINITY
+        );
+        assert!(numeric_to_f64(&numeric(&[2, 0, 0x0000, 0, 1])).is_err());
+    }
+
+    #[test]
+    fn test_timestamp_to_f64() {
+        assert_eq!(timestamp_to_f64(0), 946_684_800.0);
+        assert_eq!(timestamp_to_f64(-946_684_800_000_000), 0.0);
+        assert_eq!(timestamp_to_f64(1_500_000), 946_684_801.5);
+        assert_eq!(timestamp_to_f64(i64::MAX), f64::INFINITY);
+    }
+}
+
+#[cfg(test)]
+mod tests_validate {
+    use crate::custom_queries::validate;
+
+    #[test]
+    fn test_validate() {
+        for sql in [
+            "SELECT count(*) FROM pg_stat_activity",
+            "SELECT 1;",
+            "SELECT 1; -- done",
+            "SELECT 1;;",
+            "SELECT deleted_at, updated_by FROM t",
+            "SELECT 'DELETE FROM t' AS q",
+            "SELECT 1 AS \"delete\"",
+            "SELECT E'it\\'s; DROP TABLE t; --'",
+            "SELECT 'it''s; DROP TABLE t; --'",
+            "/* ; */ SELECT 1",
+            "/* /* */ DROP TABLE t; */ SELECT 1",
+            "SELECT $$; DELETE FROM t$$",
+            "SELECT $a$ $$; DELETE FROM t; $$ $a$",
+            "SELECT a$b FROM t WHERE x = $1",
+            "SELECT 1e5, 3.14",
+            "SELECT U&'d\\0061t\\+000061'",
+        ] {
+            assert!(validate(sql).is_ok(), "{sql}");
+        }
+    }
+
+    #[test]
+    fn test_validate_bypass_attempts() {
+        for sql in [
+            "DELETE FROM t",
+            "SELECT 1; DELETE FROM t",
+            "SELECT 1\n;\nDROP TABLE t",
+            "SELECT 1; SELECT 2",
+            "select 1; dElEtE from t",
+            "WITH d AS (DELETE FROM t RETURNING *) SELECT * FROM d",
+            "SELECT * INTO t2 FROM t",
+            "SELECT * FROM t FOR UPDATE",
+            "EXPLAIN ANALYZE DELETE FROM t",
+            // With standard_conforming_strings, a backslash doesn't escape a quote
+            "SELECT 'a\\'; DELETE FROM t; --'",
+            "SELECT \"a\"\"; DELETE FROM t; --\"; DELETE FROM t",
+            "/* /* */ */ DROP TABLE t",
+            "SELECT $$ $a$ $$; DELETE FROM t",
+            "SELECT set_config('log_statement', 'none', false)",
+            "SELECT pg_catalog.PG_TERMINATE_BACKEND(pid) FROM pg_stat_activity",
+            "SELECT \"set_config\"('a', 'b', false)",
+            "SELECT U&\"set\\0066config\"('a', 'b', false)",
+            "SELECT u&\"set!0066config\" UESCAPE '!' ('a', 'b', false)",
+            "SELECT pg_notify('c', 'p')",
+            "SELECT pg_logical_emit_message(true, 'p', 'm')",
+            "SELECT lowrite(0, 'x')",
+            "SELECT pg_copy_logical_replication_slot('a', 'b')",
+            "SELECT pg_replication_origin_advance('o', '0/0')",
+            "SELECT pg_stat_reset_shared('bgwriter')",
+            "SELECT pg_advisory_lock(1)",
+            "SELECT nextval('s')",
+            "DO $$ BEGIN PERFORM 1; END $$",
+            "COPY t TO PROGRAM 'rm -rf /'",
+            "SELECT 'unterminated",
+            "SELECT 1 /* unterminated",
+            "SELECT $x$ unterminated",
+        ] {
+            assert!(validate(sql).is_err(), "{sql}");
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//!
+//! Filesystem-level metrics of the data directory, read on the database host.
+//!
+//! `pg_tablespace_size()` tells how much the database uses, but not how much room is left
+//! on the volumes, which is what runs out. When the exporter runs on the database host, the
+//! filesystems of the data directory, the WAL directory and the tablespaces are checked with
+//! `statvfs`, and the size of the data directory is summed over its files. Walking the
+//! directory is expensive on large clusters, so its size is reused for an interval.
+//!
+use nix::sys::statvfs::statvfs;
+use once_cell::sync::Lazy;
+use prometheus::{core::Collector, IntGauge, IntGaugeVec, Opts};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tokio_postgres::{Client, Error};
+
+use crate::help;
+
+/// How long the size of the data directory is reused across scrapes.
+const DIRECTORY_SIZE_INTERVAL: Duration = Duration::from_secs(300);
+
+/// The size of the data directory last summed, with the time it was summed at. Held while
+/// summing, so that concurrent scrapes wait for the same walk.
+static DIRECTORY_SIZE: Lazy<Mutex<Option<(Instant, PathBuf, u64)>>> =
+    Lazy::new(|| Mutex::new(None));
+
+/// Returns the total size of the files under `path`. Files that vanish or can't be read
+/// while walking, e.g., temporary files, are skipped.
+fn directory_size(path: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return 0;
+    };
+    let mut size = 0;
+    for entry in entries.flatten() {
+        // WAL and tablespaces may be symlinks to other volumes, which are not followed
+        let Ok(metadata) = entry.path().symlink_metadata() else {
+            continue;
+        };
+        if metadata.is_dir() {
+            size += directory_size(&entry.path());
+        } else if metadata.is_file() {
+            size += metadata.len();
+        }
+    }
+    size
+}
+
+async fn cached_directory_size(path: &Path) -> u64 {
+    let mut cache = DIRECTORY_SIZE.lock().await;
+    match cache.as_ref() {
+        Some((summed_at, cached_path, size))
+            if cached_path == path && summed_at.elapsed() < DIRECTORY_SIZE_INTERVAL =>
+        {
+            *size
+        }
+        _ => {
+            // Walking the directory blocks
+            let walked = path.to_path_buf();
+            let size = tokio::task::spawn_blocking(move || directory_size(&walked))
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::warn!("failed to sum the size of {}: {e:#}", path.display());
+                    0
+                });
+            *cache = Some((Instant::now(), path.to_path_buf(), size));
+            size
+        }
+    }
+}
+
+/// Collects the metrics of the filesystems of `data_directory`, or of the data directory
+/// of the server if not given, which requires `pg_read_all_settings`. If given, the
+/// tablespaces are reached through their symlinks in `pg_tblspc` under `data_directory`
+/// rather than at their locations on the server.
+#[tracing::instrument(name = "get_filesystem_stats", skip_all)]
+pub async fn collect(
+    conn: &Client,
+    data_directory: Option<&Path>,
+) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    let remapped = data_directory.is_some();
+    let data_directory = match data_directory {
+        Some(path) => path.to_path_buf(),
+        None => PathBuf::from(
+            conn.query_one("SELECT current_setting('data_directory')", &[])
+                .await?
+                .get::<_, String>(0),
+        ),
+    };
+    if !data_directory.is_dir() {
+        tracing::warn!(
+            "data directory {} is not accessible from the exporter, skipping filesystem metrics",
+            data_directory.display()
+        );
+        return Ok(metrics);
+    }
+
+    let mut volumes = vec![
+        ("data".to_string(), data_directory.clone()),
+        ("wal".to_string(), data_directory.join("pg_wal")),
+    ];
+    let rows = conn
+        .query(
+            "
+        SELECT
+            spc.spcname::text,
+            pg_tablespace_location(spc.oid),
+            spc.oid::text
+        FROM
+            pg_tablespace AS spc
+        WHERE
+            pg_tablespace_location(spc.oid) <> ''
+    ",
+            &[],
+        )
+        .await?;
+    for row in rows.iter() {
+        let path = if remapped {
+            data_directory
+                .join("pg_tblspc")
+                .join(row.get::<_, String>(2))
+        } else {
+            PathBuf::from(row.get::<_, String>(1))
+        };
+        volumes.push((format!("tablespace:{}", row.get::<_, String>(0)), path));
+    }
+
+    let labels = ["volume", "path"];
+    let size = IntGaugeVec::new(
+        Opts::new("pg_filesystem_size_bytes", help::PG_FILESYSTEM_SIZE_BYTES),
+        &labels,
+    )
+    .unwrap();
+    let avail = IntGaugeVec::new(
+        Opts::new("pg_filesystem_avail_bytes", help::PG_FILESYSTEM_AVAIL_BYTES),
+        &labels,
+    )
+    .unwrap();
+    let files_free = IntGaugeVec::new(
+        Opts::new("pg_filesystem_files_free", help::PG_FILESYSTEM_FILES_FREE),
+        &labels,
+    )
+    .unwrap();
+    for (volume, path) in &volumes {
+        let stat = match statvfs(path) {
+            Ok(stat) => stat,
+            Err(e) => {
+                tracing::warn!("failed to statvfs {}: {e}", path.display());
+                continue;
+            }
+        };
+        let path = path.to_string_lossy();
+        let label_values = [volume.as_str(), path.as_ref()];
+        let fragment_size = stat.fragment_size() as i64;
+        size.with_label_values(&label_values)
+            .set(stat.blocks() as i64 * fragment_size);
+        avail
+            .with_label_values(&label_values)
+            .set(stat.blocks_available() as i64 * fragment_size);
+        files_free
+            .with_label_values(&label_values)
+            .set(stat.files_available() as i64);
+    }
+    metrics.append(&mut size.collect());
+    metrics.append(&mut avail.collect());
+    metrics.append(&mut files_free.collect());
+
+    let m = IntGauge::new(
+        "pg_data_directory_size_bytes",
+        help::PG_DATA_DIRECTORY_SIZE_BYTES,
+    )
+    .unwrap();
+    m.set(cached_directory_size(&data_directory).await as i64);
+    metrics.append(&mut m.collect());
+
+    metrics.retain(|m| !m.get_metric().is_empty());
+    Ok(metrics)
+}
+
+#[cfg(test)]
+mod tests_directory_size {
+    use crate::filesystem::directory_size;
+
+    #[test]
+    fn test_directory_size() {
+        let dir = std::env::temp_dir().join(format!("pg_stats_exporter_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("base/1")).unwrap();
+        std::fs::write(dir.join("PG_VERSION"), "15\n").unwrap();
+        std::fs::write(dir.join("base/1/1259"), vec![0; 8192]).unwrap();
+        assert_eq!(directory_size(&dir), 8195);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(directory_size(&dir), 0);
+    }
+}
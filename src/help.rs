@@ -0,0 +1,862 @@
+//!
+//! Help text of the metrics.
+//!
+//! Prometheus stores the help text of a metric as its metadata, and a changed text churns
+//! the metadata of every series in the TSDB. The help text of all the metrics with static
+//! names lives here, so that a change is deliberate: `help_catalog.txt` holds the released
+//! text, and `test_catalog` fails until it is updated along with a constant. The names of
+//! the constants are the metric names in upper case.
+//!
+//! Metrics named at runtime, e.g., of custom queries, are not covered.
+//!
+
+// `background`
+pub const PG_EXPORTER_SNAPSHOT_STALE: &str =
+    "Whether some metrics are served from the snapshot persisted by a previous run";
+
+// `backup`
+pub const PG_BACKUP_UP: &str =
+    "Whether the backup tool could be queried for the status of the backups";
+pub const PG_BACKUP_STATUS_OK: &str =
+    "Whether the backup tool reports no error for a pgBackRest stanza or a Barman server";
+pub const PG_BACKUP_WAL_ARCHIVE_OK: &str =
+    "Whether WAL is archived to the repository of a pgBackRest stanza or a Barman server";
+pub const PG_BACKUP_LAST_AGE_SECONDS: &str = "Seconds since the last backup of a type finished";
+pub const PG_BACKUP_LAST_SIZE_BYTES: &str = "Size of the database in the last backup of a type";
+
+// `cancellation`
+pub const PG_EXPORTER_SCRAPES_CANCELLED_TOTAL: &str =
+    "Number of scrapes whose queries were canceled, by the reason";
+
+// `compatibility`
+pub const PG_EXPORTER_COMPATIBILITY_PROFILE: &str =
+    "Compatibility profile in effect for the server, resolved at startup";
+
+// `custom_queries`
+pub const PG_EXPORTER_CUSTOM_QUERY_CONVERSION_ERRORS_TOTAL: &str =
+    "Values of a custom query skipped since they or their labels couldn't be converted or the labels were duplicated";
+
+// `federation`
+pub const PG_EXPORTER_FEDERATION_UP: &str =
+    "Whether the last scrape of a downstream exporter succeeded";
+
+// `filesystem`
+pub const PG_FILESYSTEM_SIZE_BYTES: &str = "Size of the filesystem of a volume of the database";
+pub const PG_FILESYSTEM_AVAIL_BYTES: &str =
+    "Free space on the filesystem of a volume of the database available to non-root users";
+pub const PG_FILESYSTEM_FILES_FREE: &str =
+    "Free inodes on the filesystem of a volume of the database available to non-root users";
+pub const PG_DATA_DIRECTORY_SIZE_BYTES: &str =
+    "Total size of the files in the data directory, excluding symlinked WAL and tablespaces";
+
+// `leader_election`
+pub const PG_EXPORTER_ACTIVE: &str =
+    "Whether this exporter instance holds the leader lock and actively collects metrics";
+
+// `log_tailer`
+pub const PG_LOG_MESSAGES_TOTAL: &str =
+    "Number of ERROR, FATAL and PANIC messages in the server log";
+pub const PG_CANCELED_STATEMENTS_TOTAL: &str =
+    "Number of statements canceled by a timeout or a user request, read from the server log";
+pub const PG_LOG_EVENTS_TOTAL: &str =
+    "Number of checkpoint and autovacuum events in the server log";
+pub const PG_LOG_LAST_CHECKPOINT_DURATION_SECONDS: &str =
+    "Seconds the last checkpoint or restartpoint in the server log spent in each phase";
+pub const PG_LOG_LAST_CHECKPOINT_BUFFERS_WRITTEN: &str =
+    "Number of buffers written by the last checkpoint or restartpoint in the server log";
+pub const PG_AUTH_FAILURES_TOTAL: &str =
+    "Number of connection attempts rejected during authentication, read from the server log";
+pub const PG_LOG_STATEMENT_DURATION_SECONDS: &str =
+    "Duration of statements logged by log_min_duration_statement or auto_explain, read from the server log";
+
+// `memory`
+pub const PG_EXPORTER_MEMORY_ALLOCATED_BYTES: &str = "Bytes currently allocated by the exporter";
+pub const PG_EXPORTER_MEMORY_PEAK_ALLOCATED_BYTES: &str =
+    "Maximum bytes allocated by the exporter at once since it started";
+
+// `metrics`
+pub const PG_STATSINFO_CPU_SECONDS_TOTAL: &str =
+    "Seconds the CPUs of the database host spent in each mode";
+pub const PG_STATSINFO_TABLESPACE_BYTES: &str =
+    "Available and total space of the device of a tablespace";
+pub const PG_STATSINFO_LOAD1: &str = "Load average of the database host over 1 minute";
+pub const PG_STATSINFO_LOAD5: &str = "Load average of the database host over 5 minutes";
+pub const PG_STATSINFO_LOAD15: &str = "Load average of the database host over 15 minutes";
+pub const PG_STATSINFO_MEMORY_BYTES: &str = "Memory of the database host by kind";
+pub const PG_STATSINFO_DEVICE_READ_BYTES_TOTAL: &str =
+    "Bytes read from a device of the database host";
+pub const PG_STATSINFO_DEVICE_WRITTEN_BYTES_TOTAL: &str =
+    "Bytes written to a device of the database host";
+pub const PG_STATSINFO_DEVICE_READ_TIME_SECONDS_TOTAL: &str =
+    "Time spent reading from a device of the database host";
+pub const PG_STATSINFO_DEVICE_WRITE_TIME_SECONDS_TOTAL: &str =
+    "Time spent writing to a device of the database host";
+pub const PG_STATSINFO_DEVICE_IO_TIME_SECONDS_TOTAL: &str =
+    "Time a device of the database host spent doing I/O";
+pub const PG_STATSINFO_DEVICE_IO_QUEUE: &str =
+    "I/O requests in flight on a device of the database host";
+pub const PG_STATSINFO_DEVICE_OVERFLOWS: &str =
+    "Overflows of the device counters detected by pg_statsinfo by counter";
+pub const PG_STAT_STATEMENTS_DEALLOC_TOTAL: &str = "Number of times pg_stat_statements entries were deallocated because more distinct statements than pg_stat_statements.max were observed";
+pub const PG_STAT_STATEMENTS_STATS_RESET_AGE_SECONDS: &str =
+    "Seconds since all the statistics in pg_stat_statements were last reset";
+pub const PG_STAT_DATABASE_STATS_RESET_AGE_SECONDS: &str =
+    "Seconds since the statistics of a database were last reset";
+pub const PG_STAT_BGWRITER_STATS_RESET_AGE_SECONDS: &str =
+    "Seconds since the statistics in pg_stat_bgwriter were last reset";
+pub const PG_STAT_USER_TABLES_N_TUP_UPD_TOTAL: &str =
+    "Number of rows updated in a table, including HOT updates";
+pub const PG_STAT_USER_TABLES_N_TUP_HOT_UPD_TOTAL: &str =
+    "Number of rows HOT updated in a table, i.e., with no separate index update required";
+pub const PG_STAT_USER_TABLES_HOT_UPDATE_RATIO: &str =
+    "Ratio of HOT updates to all updates in a table";
+pub const PG_TOAST_SIZE_BYTES: &str =
+    "Disk space used by the TOAST table of a table, including its index";
+pub const PG_TOAST_BLKS_READ_TOTAL: &str =
+    "Number of disk blocks read from the TOAST table of a table";
+pub const PG_TOAST_BLKS_HIT_TOTAL: &str = "Number of buffer hits in the TOAST table of a table";
+pub const PG_TOAST_IDX_SCAN_TOTAL: &str =
+    "Number of index scans, i.e., TOAST value fetches, on the TOAST table of a table";
+pub const PG_ROLE_COUNT: &str = "Number of roles having an attribute ('all' counts every role)";
+pub const PG_ROLE_PASSWORD_EXPIRY_SECONDS: &str =
+    "Seconds until the password of a role expires, negative if already expired";
+pub const PG_SETTINGS_CHECKSUM_INFO: &str =
+    "MD5 checksum of the sorted server configuration in pg_settings";
+pub const PG_SETTINGS_PENDING_RESTART: &str =
+    "Number of settings changed in the configuration files that need a restart to be applied";
+pub const PG_STAT_DATABASE_DEADLOCKS_TOTAL: &str = "Number of deadlocks detected in a database";
+pub const PG_STAT_DATABASE_CONFLICTS_TOTAL: &str =
+    "Number of queries canceled due to conflicts with recovery in a database on standby servers";
+pub const PG_STAT_ACTIVITY_IDLE_IN_TRANSACTION_AGE_SECONDS: &str =
+    "How long sessions have been idle in a transaction";
+pub const PG_STAT_ACTIVITY_QUERY_AGE_SECONDS: &str =
+    "How long currently running queries have been running";
+pub const PG_STAT_STATEMENTS_MEAN_EXEC_TIME_SECONDS: &str =
+    "Mean time spent executing a statement, for the top statements by total execution time";
+pub const PG_STAT_STATEMENTS_STDDEV_EXEC_TIME_SECONDS: &str = "Standard deviation of time spent executing a statement, for the top statements by total execution time";
+pub const PG_STAT_STATEMENTS_CALLS_PER_SECOND: &str =
+    "Calls of a statement per second since the previous scrape";
+pub const PG_STAT_STATEMENTS_EXEC_TIME_SECONDS_PER_SECOND: &str =
+    "Time spent executing a statement per second since the previous scrape";
+pub const PG_STAT_USER_TABLES_LAST_VACUUM_AGE_SECONDS: &str =
+    "Seconds since a table was last vacuumed, manually or by autovacuum";
+pub const PG_STAT_USER_TABLES_LAST_ANALYZE_AGE_SECONDS: &str =
+    "Seconds since a table was last analyzed, manually or by autovacuum";
+pub const PG_STAT_USER_TABLES_MAX_LAST_VACUUM_AGE_SECONDS: &str =
+    "Maximum seconds since a table was last vacuumed over all the tables";
+pub const PG_STAT_USER_TABLES_MAX_LAST_ANALYZE_AGE_SECONDS: &str =
+    "Maximum seconds since a table was last analyzed over all the tables";
+pub const PG_HBA_FILE_RULES_COUNT: &str = "Number of valid entries in pg_hba.conf";
+pub const PG_HBA_FILE_RULES_ERRORS: &str =
+    "Number of lines in pg_hba.conf that could not be parsed";
+pub const PG_HBA_FILE_RULES_CHECKSUM_INFO: &str = "MD5 checksum of the entries in pg_hba.conf";
+pub const PG_IDENT_FILE_MAPPINGS_COUNT: &str = "Number of valid entries in pg_ident.conf";
+pub const PG_IDENT_FILE_MAPPINGS_ERRORS: &str =
+    "Number of lines in pg_ident.conf that could not be parsed";
+pub const PG_IDENT_FILE_MAPPINGS_CHECKSUM_INFO: &str =
+    "MD5 checksum of the entries in pg_ident.conf";
+pub const PG_FOREIGN_SERVERS: &str = "Number of foreign servers per foreign-data wrapper";
+pub const PG_FOREIGN_TABLES: &str = "Number of foreign tables per foreign server";
+pub const PG_USER_MAPPINGS: &str = "Number of user mappings per foreign server";
+pub const PG_LARGEST_RELATION_SIZE_BYTES: &str =
+    "Disk space used by one of the largest tables or indexes";
+pub const TIMESCALEDB_HYPERTABLE_CHUNKS: &str = "Number of chunks of a hypertable";
+pub const TIMESCALEDB_HYPERTABLE_SIZE_BYTES: &str =
+    "Total disk space used by a hypertable, including indexes and TOAST";
+pub const TIMESCALEDB_HYPERTABLE_COMPRESSED_CHUNKS: &str =
+    "Number of compressed chunks of a hypertable with compression enabled";
+pub const TIMESCALEDB_HYPERTABLE_COMPRESSION_RATIO: &str = "Ratio of the size of the compressed chunks of a hypertable before compression to the size after";
+pub const TIMESCALEDB_JOB_RUNS_TOTAL: &str = "Number of runs of a background job";
+pub const TIMESCALEDB_JOB_FAILURES_TOTAL: &str = "Number of failed runs of a background job";
+pub const TIMESCALEDB_JOB_LAST_RUN_DURATION_SECONDS: &str =
+    "Duration of the last run of a background job";
+pub const TIMESCALEDB_JOB_LAST_RUN_SUCCESS: &str =
+    "Whether the last run of a background job succeeded";
+pub const CITUS_NODE_ACTIVE: &str = "Whether a node of the Citus cluster is active";
+pub const CITUS_TABLE_SHARDS: &str = "Number of shard placements of a distributed table on a node";
+pub const CITUS_TABLE_SHARD_SIZE_BYTES: &str =
+    "Total size of the shard placements of a distributed table on a node";
+pub const CITUS_REBALANCE_SHARD_MOVES: &str =
+    "Number of shard moves of the running rebalance, by their state";
+pub const PGVECTOR_INDEXES: &str = "Number of vector indexes by the access method";
+pub const PGVECTOR_INDEX_SIZE_BYTES: &str = "Disk space used by a vector index";
+pub const PGVECTOR_INDEX_INFO: &str =
+    "Build parameters of a vector index, with the ones not applicable to the access method empty";
+pub const POSTGIS_INFO: &str =
+    "Versions of PostGIS and the libraries it is built with, empty if not used";
+pub const POSTGIS_COLUMNS: &str =
+    "Number of spatial columns by the type, i.e., geometry or geography";
+pub const POSTGIS_SPATIAL_INDEXES: &str =
+    "Number of GiST, SP-GiST and BRIN indexes on spatial columns";
+pub const PG_PARTMAN_MAINTENANCE_LAST_RUN_AGE_SECONDS: &str =
+    "Seconds since the last successful maintenance of a partition set";
+pub const PG_PARTMAN_PREMADE_PARTITIONS: &str =
+    "Number of child tables of a time-based partition set starting in the future";
+pub const PG_PARTMAN_NEWEST_PARTITION_REMAINING_SECONDS: &str = "Seconds until the newest child table of a time-based partition set ends, negative once rows go to the default partition";
+pub const PG_CRON_JOB_ACTIVE: &str = "Whether a pg_cron job is scheduled";
+pub const PG_CRON_JOB_LAST_RUN_SUCCESS: &str =
+    "Whether the last finished run of a pg_cron job succeeded";
+pub const PG_CRON_JOB_LAST_RUN_DURATION_SECONDS: &str =
+    "Duration of the last finished run of a pg_cron job";
+pub const PG_CRON_JOB_LAST_SUCCESS_AGE_SECONDS: &str =
+    "Seconds since the last successful run of a pg_cron job ended";
+pub const PG_REPLICATION_SLOTS_LOGICAL_ACTIVE: &str =
+    "Whether a consumer is connected to a logical replication slot";
+pub const PG_REPLICATION_SLOTS_CONFIRMED_FLUSH_LAG_BYTES: &str =
+    "Bytes of WAL written since the position a logical replication slot's consumer confirmed";
+pub const PG_STAT_REPLICATION_SLOTS_SPILL_TXNS_TOTAL: &str =
+    "Number of transactions spilled to disk while decoding for a logical replication slot";
+pub const PG_STAT_REPLICATION_SLOTS_SPILL_COUNT_TOTAL: &str = "Number of times transactions were spilled to disk while decoding for a logical replication slot";
+pub const PG_STAT_REPLICATION_SLOTS_SPILL_BYTES_TOTAL: &str =
+    "Bytes of decoded transaction data spilled to disk for a logical replication slot";
+pub const PG_EXPORTER_CLOCK_SKEW_SECONDS: &str =
+    "Seconds the clock of the server is ahead of the clock of the exporter, negative if behind";
+pub const PG_EXPORTER_CLOCK_SKEW_UNCERTAINTY_SECONDS: &str =
+    "Maximum error of pg_exporter_clock_skew_seconds, half the round trip of the query";
+pub const PG_UP: &str = "Whether the last scrape could reach PostgreSQL";
+pub const PG_STATS_EXPORTER_UP: &str =
+    "Whether the last collection connected to PostgreSQL and ran the collectors";
+pub const PG_EXPORTER_LAST_SCRAPE_TIMESTAMP_SECONDS: &str =
+    "Unix time when metrics were last collected from a target";
+pub const PG_EXPORTER_LAST_SUCCESS_TIMESTAMP_SECONDS: &str =
+    "Unix time when metrics were last collected from a target successfully";
+pub const PG_EXPORTER_STANDBY_ROUTING: &str =
+    "Whether heavy statistics are collected from the standby instead of the primary";
+pub const PG_STATS_EXPORTER_COLLECTOR_SUCCESS: &str =
+    "Whether a collector succeeded in the last collection";
+pub const PG_STATS_EXPORTER_SCRAPE_DURATION_SECONDS: &str =
+    "Seconds a collector took to collect its metrics";
+pub const PG_STATS_EXPORTER_SCRAPE_ERRORS_TOTAL: &str =
+    "Number of collectors that failed and of collections that failed to connect to PostgreSQL";
+pub const PG_STATS_EXPORTER_LAST_SCRAPE_SUCCESS_TIMESTAMP_SECONDS: &str =
+    "Unix time when a collection from a target last succeeded in running every collector";
+pub const PG_EXPORTER_RESPONSE_TRUNCATED_FAMILIES: &str =
+    "Number of metric families dropped from the last response because it exceeded the maximum size";
+pub const PG_STAT_DATABASE_NUMBACKENDS: &str =
+    "Number of backends currently connected to a database";
+pub const PG_STAT_DATABASE_XACT_COMMIT_TOTAL: &str =
+    "Number of transactions in a database that have been committed";
+pub const PG_STAT_DATABASE_XACT_ROLLBACK_TOTAL: &str =
+    "Number of transactions in a database that have been rolled back";
+pub const PG_STAT_DATABASE_BLKS_READ_TOTAL: &str = "Number of disk blocks read in a database";
+pub const PG_STAT_DATABASE_BLKS_HIT_TOTAL: &str =
+    "Number of times disk blocks were found already in the buffer cache in a database";
+pub const PG_STAT_DATABASE_TUP_RETURNED_TOTAL: &str = "Number of live rows fetched by sequential scans and index entries returned by index scans in a database";
+pub const PG_STAT_DATABASE_TUP_FETCHED_TOTAL: &str =
+    "Number of live rows fetched by index scans in a database";
+pub const PG_STAT_DATABASE_TUP_INSERTED_TOTAL: &str =
+    "Number of rows inserted by queries in a database";
+pub const PG_STAT_DATABASE_TUP_UPDATED_TOTAL: &str =
+    "Number of rows updated by queries in a database";
+pub const PG_STAT_DATABASE_TUP_DELETED_TOTAL: &str =
+    "Number of rows deleted by queries in a database";
+pub const PG_STAT_DATABASE_TEMP_FILES_TOTAL: &str =
+    "Number of temporary files created by queries in a database";
+pub const PG_STAT_DATABASE_TEMP_BYTES_TOTAL: &str =
+    "Total amount of data written to temporary files by queries in a database";
+pub const PG_STAT_DATABASE_SESSION_TIME_SECONDS_TOTAL: &str =
+    "Time spent by sessions in a database";
+pub const PG_STAT_DATABASE_ACTIVE_TIME_SECONDS_TOTAL: &str =
+    "Time spent executing SQL statements in a database";
+pub const PG_STAT_DATABASE_IDLE_IN_TRANSACTION_TIME_SECONDS_TOTAL: &str =
+    "Time spent idling while in a transaction in a database";
+pub const PG_STAT_DATABASE_SESSIONS_TOTAL: &str = "Number of sessions established to a database";
+pub const PG_STAT_DATABASE_SESSIONS_ABANDONED_TOTAL: &str =
+    "Number of sessions to a database terminated because connection to the client was lost";
+pub const PG_STAT_DATABASE_SESSIONS_FATAL_TOTAL: &str =
+    "Number of sessions to a database terminated by fatal errors";
+pub const PG_STAT_DATABASE_SESSIONS_KILLED_TOTAL: &str =
+    "Number of sessions to a database terminated by operator intervention";
+pub const PG_STAT_BGWRITER_CHECKPOINTS_TIMED_TOTAL: &str =
+    "Number of scheduled checkpoints that have been performed";
+pub const PG_STAT_BGWRITER_CHECKPOINTS_REQ_TOTAL: &str =
+    "Number of requested checkpoints that have been performed";
+pub const PG_STAT_BGWRITER_CHECKPOINT_WRITE_TIME_SECONDS_TOTAL: &str =
+    "Time spent writing files to disk during checkpoints";
+pub const PG_STAT_BGWRITER_BUFFERS_CHECKPOINT_TOTAL: &str =
+    "Number of buffers written during checkpoints";
+pub const PG_STAT_BGWRITER_BUFFERS_CLEAN_TOTAL: &str =
+    "Number of buffers written by the background writer";
+pub const PG_STAT_BGWRITER_BUFFERS_BACKEND_TOTAL: &str =
+    "Number of buffers written directly by backends, not reported since PostgreSQL 17";
+pub const PG_STAT_BGWRITER_MAXWRITTEN_CLEAN_TOTAL: &str = "Number of times the background writer stopped a cleaning scan because it had written too many buffers";
+pub const PG_STAT_REPLICATION_LAG_BYTES: &str =
+    "Bytes of WAL not yet sent, written, flushed or replayed by a standby";
+pub const PG_STAT_REPLICATION_SYNC_STATE: &str = "Synchronous state of a standby, always 1";
+pub const PG_REPLICATION_SLOTS_RETAINED_BYTES: &str =
+    "Bytes of WAL retained by a replication slot since its restart_lsn";
+pub const PG_REPLICATION_SLOTS_WAL_STATUS: &str =
+    "Availability of the WAL files claimed by a replication slot, always 1";
+pub const PG_REPLICATION_SLOTS_INACTIVE_SECONDS: &str =
+    "Time since a replication slot became inactive";
+pub const PG_STAT_ACTIVITY_CONNECTIONS: &str =
+    "Number of client connections by database, state and wait event type";
+pub const PG_STAT_ACTIVITY_MAX_TRANSACTION_AGE_SECONDS: &str =
+    "Age of the longest running transaction in a database";
+pub const PG_STAT_ACTIVITY_MAX_QUERY_AGE_SECONDS: &str =
+    "Age of the longest running query in a database";
+
+// `patroni`
+pub const PG_PATRONI_UP: &str = "Whether the Patroni REST API responded successfully";
+pub const PG_PATRONI_ROLE: &str = "Role of the node in the Patroni cluster, always 1";
+pub const PG_PATRONI_TIMELINE: &str = "Timeline of the node";
+pub const PG_PATRONI_PAUSED: &str = "Whether the Patroni cluster is in maintenance mode";
+
+// `pool`
+pub const PG_EXPORTER_POOL_SIZE: &str = "Number of connections the exporter has open to PostgreSQL";
+pub const PG_EXPORTER_POOL_IDLE_CONNECTIONS: &str =
+    "Number of open connections not used by any collection";
+pub const PG_EXPORTER_POOL_CONNECTIONS: &str =
+    "Number of connections the exporter has open to PostgreSQL, by the database";
+pub const PG_EXPORTER_POOL_WAIT_SECONDS: &str = "Time waited for a connection to PostgreSQL";
+pub const PG_EXPORTER_POOL_CONNECTION_ERRORS_TOTAL: &str =
+    "Number of failed attempts to establish a connection to PostgreSQL";
+
+// `privileges`
+pub const PG_EXPORTER_COLLECTOR_PRIVILEGES_OK: &str =
+    "Whether the role has the privileges a collector requires, checked at startup";
+
+// `routes`
+pub const PG_EXPORTER_RESPONSE_FLUSHES_TOTAL: &str =
+    "Number of chunks of /metrics responses sent to the clients";
+pub const PG_EXPORTER_RESPONSE_SEND_BLOCKED_SECONDS_TOTAL: &str =
+    "Time the encoding of /metrics responses was blocked waiting for the clients to receive chunks";
+pub const PG_EXPORTER_RESPONSE_BYTES: &str = "Size of /metrics responses sent to the clients";
+
+// `server_metrics`
+pub const PG_EXPORTER_HTTP_CONNECTIONS_ACCEPTED_TOTAL: &str =
+    "Number of TCP connections accepted by the metrics server";
+pub const PG_EXPORTER_HTTP_CONNECTIONS_ACTIVE: &str =
+    "Number of connections to the metrics server currently open";
+pub const PG_EXPORTER_HTTP_TLS_HANDSHAKE_FAILURES_TOTAL: &str =
+    "Number of connections to the metrics server closed by a failed TLS handshake";
+pub const PG_EXPORTER_HTTP_CONNECTION_REQUESTS: &str =
+    "Number of requests served on a connection to the metrics server, observed when it is closed";
+
+/// Names of the metrics and their help text.
+pub const CATALOG: &[(&str, &str)] = &[
+    ("pg_exporter_snapshot_stale", PG_EXPORTER_SNAPSHOT_STALE),
+    ("pg_backup_up", PG_BACKUP_UP),
+    ("pg_backup_status_ok", PG_BACKUP_STATUS_OK),
+    ("pg_backup_wal_archive_ok", PG_BACKUP_WAL_ARCHIVE_OK),
+    ("pg_backup_last_age_seconds", PG_BACKUP_LAST_AGE_SECONDS),
+    ("pg_backup_last_size_bytes", PG_BACKUP_LAST_SIZE_BYTES),
+    (
+        "pg_exporter_scrapes_cancelled_total",
+        PG_EXPORTER_SCRAPES_CANCELLED_TOTAL,
+    ),
+    (
+        "pg_exporter_compatibility_profile",
+        PG_EXPORTER_COMPATIBILITY_PROFILE,
+    ),
+    (
+        "pg_exporter_custom_query_conversion_errors_total",
+        PG_EXPORTER_CUSTOM_QUERY_CONVERSION_ERRORS_TOTAL,
+    ),
+    ("pg_exporter_federation_up", PG_EXPORTER_FEDERATION_UP),
+    ("pg_filesystem_size_bytes", PG_FILESYSTEM_SIZE_BYTES),
+    ("pg_filesystem_avail_bytes", PG_FILESYSTEM_AVAIL_BYTES),
+    ("pg_filesystem_files_free", PG_FILESYSTEM_FILES_FREE),
+    ("pg_data_directory_size_bytes", PG_DATA_DIRECTORY_SIZE_BYTES),
+    ("pg_exporter_active", PG_EXPORTER_ACTIVE),
+    ("pg_log_messages_total", PG_LOG_MESSAGES_TOTAL),
+    ("pg_canceled_statements_total", PG_CANCELED_STATEMENTS_TOTAL),
+    ("pg_log_events_total", PG_LOG_EVENTS_TOTAL),
+    (
+        "pg_log_last_checkpoint_duration_seconds",
+        PG_LOG_LAST_CHECKPOINT_DURATION_SECONDS,
+    ),
+    (
+        "pg_log_last_checkpoint_buffers_written",
+        PG_LOG_LAST_CHECKPOINT_BUFFERS_WRITTEN,
+    ),
+    ("pg_auth_failures_total", PG_AUTH_FAILURES_TOTAL),
+    (
+        "pg_log_statement_duration_seconds",
+        PG_LOG_STATEMENT_DURATION_SECONDS,
+    ),
+    (
+        "pg_exporter_memory_allocated_bytes",
+        PG_EXPORTER_MEMORY_ALLOCATED_BYTES,
+    ),
+    (
+        "pg_exporter_memory_peak_allocated_bytes",
+        PG_EXPORTER_MEMORY_PEAK_ALLOCATED_BYTES,
+    ),
+    (
+        "pg_statsinfo_cpu_seconds_total",
+        PG_STATSINFO_CPU_SECONDS_TOTAL,
+    ),
+    (
+        "pg_statsinfo_tablespace_bytes",
+        PG_STATSINFO_TABLESPACE_BYTES,
+    ),
+    ("pg_statsinfo_load1", PG_STATSINFO_LOAD1),
+    ("pg_statsinfo_load5", PG_STATSINFO_LOAD5),
+    ("pg_statsinfo_load15", PG_STATSINFO_LOAD15),
+    ("pg_statsinfo_memory_bytes", PG_STATSINFO_MEMORY_BYTES),
+    (
+        "pg_statsinfo_device_read_bytes_total",
+        PG_STATSINFO_DEVICE_READ_BYTES_TOTAL,
+    ),
+    (
+        "pg_statsinfo_device_written_bytes_total",
+        PG_STATSINFO_DEVICE_WRITTEN_BYTES_TOTAL,
+    ),
+    (
+        "pg_statsinfo_device_read_time_seconds_total",
+        PG_STATSINFO_DEVICE_READ_TIME_SECONDS_TOTAL,
+    ),
+    (
+        "pg_statsinfo_device_write_time_seconds_total",
+        PG_STATSINFO_DEVICE_WRITE_TIME_SECONDS_TOTAL,
+    ),
+    (
+        "pg_statsinfo_device_io_time_seconds_total",
+        PG_STATSINFO_DEVICE_IO_TIME_SECONDS_TOTAL,
+    ),
+    ("pg_statsinfo_device_io_queue", PG_STATSINFO_DEVICE_IO_QUEUE),
+    (
+        "pg_statsinfo_device_overflows",
+        PG_STATSINFO_DEVICE_OVERFLOWS,
+    ),
+    (
+        "pg_stat_statements_dealloc_total",
+        PG_STAT_STATEMENTS_DEALLOC_TOTAL,
+    ),
+    (
+        "pg_stat_statements_stats_reset_age_seconds",
+        PG_STAT_STATEMENTS_STATS_RESET_AGE_SECONDS,
+    ),
+    (
+        "pg_stat_database_stats_reset_age_seconds",
+        PG_STAT_DATABASE_STATS_RESET_AGE_SECONDS,
+    ),
+    (
+        "pg_stat_bgwriter_stats_reset_age_seconds",
+        PG_STAT_BGWRITER_STATS_RESET_AGE_SECONDS,
+    ),
+    (
+        "pg_stat_user_tables_n_tup_upd_total",
+        PG_STAT_USER_TABLES_N_TUP_UPD_TOTAL,
+    ),
+    (
+        "pg_stat_user_tables_n_tup_hot_upd_total",
+        PG_STAT_USER_TABLES_N_TUP_HOT_UPD_TOTAL,
+    ),
+    (
+        "pg_stat_user_tables_hot_update_ratio",
+        PG_STAT_USER_TABLES_HOT_UPDATE_RATIO,
+    ),
+    ("pg_toast_size_bytes", PG_TOAST_SIZE_BYTES),
+    ("pg_toast_blks_read_total", PG_TOAST_BLKS_READ_TOTAL),
+    ("pg_toast_blks_hit_total", PG_TOAST_BLKS_HIT_TOTAL),
+    ("pg_toast_idx_scan_total", PG_TOAST_IDX_SCAN_TOTAL),
+    ("pg_role_count", PG_ROLE_COUNT),
+    (
+        "pg_role_password_expiry_seconds",
+        PG_ROLE_PASSWORD_EXPIRY_SECONDS,
+    ),
+    ("pg_settings_checksum_info", PG_SETTINGS_CHECKSUM_INFO),
+    ("pg_settings_pending_restart", PG_SETTINGS_PENDING_RESTART),
+    (
+        "pg_stat_database_deadlocks_total",
+        PG_STAT_DATABASE_DEADLOCKS_TOTAL,
+    ),
+    (
+        "pg_stat_database_conflicts_total",
+        PG_STAT_DATABASE_CONFLICTS_TOTAL,
+    ),
+    (
+        "pg_stat_activity_idle_in_transaction_age_seconds",
+        PG_STAT_ACTIVITY_IDLE_IN_TRANSACTION_AGE_SECONDS,
+    ),
+    (
+        "pg_stat_activity_query_age_seconds",
+        PG_STAT_ACTIVITY_QUERY_AGE_SECONDS,
+    ),
+    (
+        "pg_stat_statements_mean_exec_time_seconds",
+        PG_STAT_STATEMENTS_MEAN_EXEC_TIME_SECONDS,
+    ),
+    (
+        "pg_stat_statements_stddev_exec_time_seconds",
+        PG_STAT_STATEMENTS_STDDEV_EXEC_TIME_SECONDS,
+    ),
+    (
+        "pg_stat_statements_calls_per_second",
+        PG_STAT_STATEMENTS_CALLS_PER_SECOND,
+    ),
+    (
+        "pg_stat_statements_exec_time_seconds_per_second",
+        PG_STAT_STATEMENTS_EXEC_TIME_SECONDS_PER_SECOND,
+    ),
+    (
+        "pg_stat_user_tables_last_vacuum_age_seconds",
+        PG_STAT_USER_TABLES_LAST_VACUUM_AGE_SECONDS,
+    ),
+    (
+        "pg_stat_user_tables_last_analyze_age_seconds",
+        PG_STAT_USER_TABLES_LAST_ANALYZE_AGE_SECONDS,
+    ),
+    (
+        "pg_stat_user_tables_max_last_vacuum_age_seconds",
+        PG_STAT_USER_TABLES_MAX_LAST_VACUUM_AGE_SECONDS,
+    ),
+    (
+        "pg_stat_user_tables_max_last_analyze_age_seconds",
+        PG_STAT_USER_TABLES_MAX_LAST_ANALYZE_AGE_SECONDS,
+    ),
+    ("pg_hba_file_rules_count", PG_HBA_FILE_RULES_COUNT),
+    ("pg_hba_file_rules_errors", PG_HBA_FILE_RULES_ERRORS),
+    (
+        "pg_hba_file_rules_checksum_info",
+        PG_HBA_FILE_RULES_CHECKSUM_INFO,
+    ),
+    ("pg_ident_file_mappings_count", PG_IDENT_FILE_MAPPINGS_COUNT),
+    (
+        "pg_ident_file_mappings_errors",
+        PG_IDENT_FILE_MAPPINGS_ERRORS,
+    ),
+    (
+        "pg_ident_file_mappings_checksum_info",
+        PG_IDENT_FILE_MAPPINGS_CHECKSUM_INFO,
+    ),
+    ("pg_foreign_servers", PG_FOREIGN_SERVERS),
+    ("pg_foreign_tables", PG_FOREIGN_TABLES),
+    ("pg_user_mappings", PG_USER_MAPPINGS),
+    (
+        "pg_largest_relation_size_bytes",
+        PG_LARGEST_RELATION_SIZE_BYTES,
+    ),
+    (
+        "timescaledb_hypertable_chunks",
+        TIMESCALEDB_HYPERTABLE_CHUNKS,
+    ),
+    (
+        "timescaledb_hypertable_size_bytes",
+        TIMESCALEDB_HYPERTABLE_SIZE_BYTES,
+    ),
+    (
+        "timescaledb_hypertable_compressed_chunks",
+        TIMESCALEDB_HYPERTABLE_COMPRESSED_CHUNKS,
+    ),
+    (
+        "timescaledb_hypertable_compression_ratio",
+        TIMESCALEDB_HYPERTABLE_COMPRESSION_RATIO,
+    ),
+    ("timescaledb_job_runs_total", TIMESCALEDB_JOB_RUNS_TOTAL),
+    (
+        "timescaledb_job_failures_total",
+        TIMESCALEDB_JOB_FAILURES_TOTAL,
+    ),
+    (
+        "timescaledb_job_last_run_duration_seconds",
+        TIMESCALEDB_JOB_LAST_RUN_DURATION_SECONDS,
+    ),
+    (
+        "timescaledb_job_last_run_success",
+        TIMESCALEDB_JOB_LAST_RUN_SUCCESS,
+    ),
+    ("citus_node_active", CITUS_NODE_ACTIVE),
+    ("citus_table_shards", CITUS_TABLE_SHARDS),
+    ("citus_table_shard_size_bytes", CITUS_TABLE_SHARD_SIZE_BYTES),
+    ("citus_rebalance_shard_moves", CITUS_REBALANCE_SHARD_MOVES),
+    ("pgvector_indexes", PGVECTOR_INDEXES),
+    ("pgvector_index_size_bytes", PGVECTOR_INDEX_SIZE_BYTES),
+    ("pgvector_index_info", PGVECTOR_INDEX_INFO),
+    ("postgis_info", POSTGIS_INFO),
+    ("postgis_columns", POSTGIS_COLUMNS),
+    ("postgis_spatial_indexes", POSTGIS_SPATIAL_INDEXES),
+    (
+        "pg_partman_maintenance_last_run_age_seconds",
+        PG_PARTMAN_MAINTENANCE_LAST_RUN_AGE_SECONDS,
+    ),
+    (
+        "pg_partman_premade_partitions",
+        PG_PARTMAN_PREMADE_PARTITIONS,
+    ),
+    (
+        "pg_partman_newest_partition_remaining_seconds",
+        PG_PARTMAN_NEWEST_PARTITION_REMAINING_SECONDS,
+    ),
+    ("pg_cron_job_active", PG_CRON_JOB_ACTIVE),
+    ("pg_cron_job_last_run_success", PG_CRON_JOB_LAST_RUN_SUCCESS),
+    (
+        "pg_cron_job_last_run_duration_seconds",
+        PG_CRON_JOB_LAST_RUN_DURATION_SECONDS,
+    ),
+    (
+        "pg_cron_job_last_success_age_seconds",
+        PG_CRON_JOB_LAST_SUCCESS_AGE_SECONDS,
+    ),
+    (
+        "pg_replication_slots_logical_active",
+        PG_REPLICATION_SLOTS_LOGICAL_ACTIVE,
+    ),
+    (
+        "pg_replication_slots_confirmed_flush_lag_bytes",
+        PG_REPLICATION_SLOTS_CONFIRMED_FLUSH_LAG_BYTES,
+    ),
+    (
+        "pg_stat_replication_slots_spill_txns_total",
+        PG_STAT_REPLICATION_SLOTS_SPILL_TXNS_TOTAL,
+    ),
+    (
+        "pg_stat_replication_slots_spill_count_total",
+        PG_STAT_REPLICATION_SLOTS_SPILL_COUNT_TOTAL,
+    ),
+    (
+        "pg_stat_replication_slots_spill_bytes_total",
+        PG_STAT_REPLICATION_SLOTS_SPILL_BYTES_TOTAL,
+    ),
+    (
+        "pg_exporter_clock_skew_seconds",
+        PG_EXPORTER_CLOCK_SKEW_SECONDS,
+    ),
+    (
+        "pg_exporter_clock_skew_uncertainty_seconds",
+        PG_EXPORTER_CLOCK_SKEW_UNCERTAINTY_SECONDS,
+    ),
+    ("pg_up", PG_UP),
+    ("pg_stats_exporter_up", PG_STATS_EXPORTER_UP),
+    (
+        "pg_exporter_last_scrape_timestamp_seconds",
+        PG_EXPORTER_LAST_SCRAPE_TIMESTAMP_SECONDS,
+    ),
+    (
+        "pg_exporter_last_success_timestamp_seconds",
+        PG_EXPORTER_LAST_SUCCESS_TIMESTAMP_SECONDS,
+    ),
+    ("pg_exporter_standby_routing", PG_EXPORTER_STANDBY_ROUTING),
+    (
+        "pg_stats_exporter_collector_success",
+        PG_STATS_EXPORTER_COLLECTOR_SUCCESS,
+    ),
+    (
+        "pg_stats_exporter_scrape_duration_seconds",
+        PG_STATS_EXPORTER_SCRAPE_DURATION_SECONDS,
+    ),
+    (
+        "pg_stats_exporter_scrape_errors_total",
+        PG_STATS_EXPORTER_SCRAPE_ERRORS_TOTAL,
+    ),
+    (
+        "pg_stats_exporter_last_scrape_success_timestamp_seconds",
+        PG_STATS_EXPORTER_LAST_SCRAPE_SUCCESS_TIMESTAMP_SECONDS,
+    ),
+    (
+        "pg_exporter_response_truncated_families",
+        PG_EXPORTER_RESPONSE_TRUNCATED_FAMILIES,
+    ),
+    ("pg_stat_database_numbackends", PG_STAT_DATABASE_NUMBACKENDS),
+    (
+        "pg_stat_database_xact_commit_total",
+        PG_STAT_DATABASE_XACT_COMMIT_TOTAL,
+    ),
+    (
+        "pg_stat_database_xact_rollback_total",
+        PG_STAT_DATABASE_XACT_ROLLBACK_TOTAL,
+    ),
+    (
+        "pg_stat_database_blks_read_total",
+        PG_STAT_DATABASE_BLKS_READ_TOTAL,
+    ),
+    (
+        "pg_stat_database_blks_hit_total",
+        PG_STAT_DATABASE_BLKS_HIT_TOTAL,
+    ),
+    (
+        "pg_stat_database_tup_returned_total",
+        PG_STAT_DATABASE_TUP_RETURNED_TOTAL,
+    ),
+    (
+        "pg_stat_database_tup_fetched_total",
+        PG_STAT_DATABASE_TUP_FETCHED_TOTAL,
+    ),
+    (
+        "pg_stat_database_tup_inserted_total",
+        PG_STAT_DATABASE_TUP_INSERTED_TOTAL,
+    ),
+    (
+        "pg_stat_database_tup_updated_total",
+        PG_STAT_DATABASE_TUP_UPDATED_TOTAL,
+    ),
+    (
+        "pg_stat_database_tup_deleted_total",
+        PG_STAT_DATABASE_TUP_DELETED_TOTAL,
+    ),
+    (
+        "pg_stat_database_temp_files_total",
+        PG_STAT_DATABASE_TEMP_FILES_TOTAL,
+    ),
+    (
+        "pg_stat_database_temp_bytes_total",
+        PG_STAT_DATABASE_TEMP_BYTES_TOTAL,
+    ),
+    (
+        "pg_stat_database_session_time_seconds_total",
+        PG_STAT_DATABASE_SESSION_TIME_SECONDS_TOTAL,
+    ),
+    (
+        "pg_stat_database_active_time_seconds_total",
+        PG_STAT_DATABASE_ACTIVE_TIME_SECONDS_TOTAL,
+    ),
+    (
+        "pg_stat_database_idle_in_transaction_time_seconds_total",
+        PG_STAT_DATABASE_IDLE_IN_TRANSACTION_TIME_SECONDS_TOTAL,
+    ),
+    (
+        "pg_stat_database_sessions_total",
+        PG_STAT_DATABASE_SESSIONS_TOTAL,
+    ),
+    (
+        "pg_stat_database_sessions_abandoned_total",
+        PG_STAT_DATABASE_SESSIONS_ABANDONED_TOTAL,
+    ),
+    (
+        "pg_stat_database_sessions_fatal_total",
+        PG_STAT_DATABASE_SESSIONS_FATAL_TOTAL,
+    ),
+    (
+        "pg_stat_database_sessions_killed_total",
+        PG_STAT_DATABASE_SESSIONS_KILLED_TOTAL,
+    ),
+    (
+        "pg_stat_bgwriter_checkpoints_timed_total",
+        PG_STAT_BGWRITER_CHECKPOINTS_TIMED_TOTAL,
+    ),
+    (
+        "pg_stat_bgwriter_checkpoints_req_total",
+        PG_STAT_BGWRITER_CHECKPOINTS_REQ_TOTAL,
+    ),
+    (
+        "pg_stat_bgwriter_checkpoint_write_time_seconds_total",
+        PG_STAT_BGWRITER_CHECKPOINT_WRITE_TIME_SECONDS_TOTAL,
+    ),
+    (
+        "pg_stat_bgwriter_buffers_checkpoint_total",
+        PG_STAT_BGWRITER_BUFFERS_CHECKPOINT_TOTAL,
+    ),
+    (
+        "pg_stat_bgwriter_buffers_clean_total",
+        PG_STAT_BGWRITER_BUFFERS_CLEAN_TOTAL,
+    ),
+    (
+        "pg_stat_bgwriter_buffers_backend_total",
+        PG_STAT_BGWRITER_BUFFERS_BACKEND_TOTAL,
+    ),
+    (
+        "pg_stat_bgwriter_maxwritten_clean_total",
+        PG_STAT_BGWRITER_MAXWRITTEN_CLEAN_TOTAL,
+    ),
+    (
+        "pg_stat_replication_lag_bytes",
+        PG_STAT_REPLICATION_LAG_BYTES,
+    ),
+    (
+        "pg_stat_replication_sync_state",
+        PG_STAT_REPLICATION_SYNC_STATE,
+    ),
+    (
+        "pg_replication_slots_retained_bytes",
+        PG_REPLICATION_SLOTS_RETAINED_BYTES,
+    ),
+    (
+        "pg_replication_slots_wal_status",
+        PG_REPLICATION_SLOTS_WAL_STATUS,
+    ),
+    (
+        "pg_replication_slots_inactive_seconds",
+        PG_REPLICATION_SLOTS_INACTIVE_SECONDS,
+    ),
+    ("pg_stat_activity_connections", PG_STAT_ACTIVITY_CONNECTIONS),
+    (
+        "pg_stat_activity_max_transaction_age_seconds",
+        PG_STAT_ACTIVITY_MAX_TRANSACTION_AGE_SECONDS,
+    ),
+    (
+        "pg_stat_activity_max_query_age_seconds",
+        PG_STAT_ACTIVITY_MAX_QUERY_AGE_SECONDS,
+    ),
+    ("pg_patroni_up", PG_PATRONI_UP),
+    ("pg_patroni_role", PG_PATRONI_ROLE),
+    ("pg_patroni_timeline", PG_PATRONI_TIMELINE),
+    ("pg_patroni_paused", PG_PATRONI_PAUSED),
+    ("pg_exporter_pool_size", PG_EXPORTER_POOL_SIZE),
+    (
+        "pg_exporter_pool_idle_connections",
+        PG_EXPORTER_POOL_IDLE_CONNECTIONS,
+    ),
+    ("pg_exporter_pool_connections", PG_EXPORTER_POOL_CONNECTIONS),
+    (
+        "pg_exporter_pool_wait_seconds",
+        PG_EXPORTER_POOL_WAIT_SECONDS,
+    ),
+    (
+        "pg_exporter_pool_connection_errors_total",
+        PG_EXPORTER_POOL_CONNECTION_ERRORS_TOTAL,
+    ),
+    (
+        "pg_exporter_collector_privileges_ok",
+        PG_EXPORTER_COLLECTOR_PRIVILEGES_OK,
+    ),
+    (
+        "pg_exporter_response_flushes_total",
+        PG_EXPORTER_RESPONSE_FLUSHES_TOTAL,
+    ),
+    (
+        "pg_exporter_response_send_blocked_seconds_total",
+        PG_EXPORTER_RESPONSE_SEND_BLOCKED_SECONDS_TOTAL,
+    ),
+    ("pg_exporter_response_bytes", PG_EXPORTER_RESPONSE_BYTES),
+    (
+        "pg_exporter_http_connections_accepted_total",
+        PG_EXPORTER_HTTP_CONNECTIONS_ACCEPTED_TOTAL,
+    ),
+    (
+        "pg_exporter_http_connections_active",
+        PG_EXPORTER_HTTP_CONNECTIONS_ACTIVE,
+    ),
+    (
+        "pg_exporter_http_tls_handshake_failures_total",
+        PG_EXPORTER_HTTP_TLS_HANDSHAKE_FAILURES_TOTAL,
+    ),
+    (
+        "pg_exporter_http_connection_requests",
+        PG_EXPORTER_HTTP_CONNECTION_REQUESTS,
+    ),
+];
+
+#[cfg(test)]
+mod tests_catalog {
+    use crate::help::CATALOG;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_catalog() {
+        let catalog: String = CATALOG
+            .iter()
+            .map(|(name, help)| format!("{name}\t{help}\n"))
+            .collect();
+        assert_eq!(
+            catalog,
+            include_str!("help_catalog.txt"),
+            "help text changed, update help_catalog.txt if intended"
+        );
+    }
+
+    #[test]
+    fn test_help_text() {
+        let mut names = HashSet::new();
+        for (name, help) in CATALOG {
+            assert!(names.insert(name), "{name} is listed twice");
+            assert!(
+                help.starts_with(|c: char| c.is_ascii_uppercase()),
+                "{name}: {help}"
+            );
+            assert!(!help.ends_with('.'), "{name}: {help}");
+            assert!(!help.contains("  "), "{name}: {help}");
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//!
+//! In-memory history of counter values, served as deltas on `/diff`.
+//!
+//! Counters are only meaningful as rates, which normally takes a Prometheus server. For
+//! on-host triage without one, the counters of every collection are kept for a retention
+//! period, and `/diff?window=5m` returns how much each of them has grown over the window.
+//!
+use prometheus::proto::{MetricFamily, MetricType};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+struct Snapshot {
+    time: SystemTime,
+    counters: HashMap<String, f64>,
+}
+
+pub struct History {
+    retention: Duration,
+    snapshots: Mutex<VecDeque<Snapshot>>,
+}
+
+#[derive(Serialize)]
+pub struct Diff {
+    /// Unix time of the earlier snapshot.
+    pub from: f64,
+    /// Unix time of the latest snapshot.
+    pub to: f64,
+    /// Increase of every counter series between the snapshots, keyed by the series in the
+    /// text exposition format, e.g., `pg_stat_database_xact_commit{datname="postgres"}`.
+    pub deltas: BTreeMap<String, f64>,
+}
+
+/// Returns the counter series in `metrics` keyed like `name{label="value",...}`.
+fn counters(metrics: &[MetricFamily]) -> HashMap<String, f64> {
+    let mut counters = HashMap::new();
+    for family in metrics {
+        if family.get_field_type() != MetricType::COUNTER {
+            continue;
+        }
+        for m in family.get_metric() {
+            let labels: Vec<String> = m
+                .get_label()
+                .iter()
+                .map(|l| format!("{}={:?}", l.get_name(), l.get_value()))
+                .collect();
+            let key = if labels.is_empty() {
+                family.get_name().to_string()
+            } else {
+                format!("{}{{{}}}", family.get_name(), labels.join(","))
+            };
+            counters.insert(key, m.get_counter().get_value());
+        }
+    }
+    counters
+}
+
+/// Returns the increase of the counters from `before` to `after`. A counter that went down
+/// was reset in between, so its increase is its current value as in Prometheus' `increase`.
+fn deltas(before: &HashMap<String, f64>, after: &HashMap<String, f64>) -> BTreeMap<String, f64> {
+    after
+        .iter()
+        .filter_map(|(key, value)| {
+            let previous = before.get(key)?;
+            let delta = if value >= previous {
+                value - previous
+            } else {
+                *value
+            };
+            Some((key.clone(), delta))
+        })
+        .collect()
+}
+
+fn unix_secs(time: SystemTime) -> f64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+/// Parses a window like `30s`, `5m` or `1h`.
+pub fn parse_window(s: &str) -> Option<Duration> {
+    let unit = match s.chars().last()? {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        _ => return None,
+    };
+    let n: u64 = s[..s.len() - 1].parse().ok()?;
+    Some(Duration::from_secs(n.checked_mul(unit)?))
+}
+
+impl History {
+    pub fn new(retention: Duration) -> Self {
+        History {
+            retention,
+            snapshots: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Records the counters in `metrics` collected now, forgetting the ones older than the
+    /// retention period.
+    pub fn record(&self, metrics: &[MetricFamily]) {
+        let now = SystemTime::now();
+        let mut snapshots = self.snapshots.lock().unwrap();
+        while snapshots.front().is_some_and(|s| {
+            now.duration_since(s.time)
+                .is_ok_and(|age| age > self.retention)
+        }) {
+            snapshots.pop_front();
+        }
+        snapshots.push_back(Snapshot {
+            time: now,
+            counters: counters(metrics),
+        });
+    }
+
+    /// Returns the deltas from the latest snapshot at least `window` older than the latest
+    /// one, or from the oldest snapshot if none is that old. `None` if fewer than two
+    /// snapshots are recorded.
+    pub fn diff(&self, window: Duration) -> Option<Diff> {
+        let snapshots = self.snapshots.lock().unwrap();
+        let latest = snapshots.back()?;
+        let since = latest.time.checked_sub(window)?;
+        let earlier = snapshots
+            .iter()
+            .rfind(|s| s.time <= since)
+            .or_else(|| snapshots.front())
+            .filter(|s| s.time < latest.time)?;
+        Some(Diff {
+            from: unix_secs(earlier.time),
+            to: unix_secs(latest.time),
+            deltas: deltas(&earlier.counters, &latest.counters),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests_history {
+    use crate::history::{counters, deltas, parse_window};
+    use prometheus::{core::Collector, IntCounter, IntCounterVec, IntGauge, Opts};
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_window() {
+        assert_eq!(parse_window("30s"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_window("5m"), Some(Duration::from_secs(300)));
+        assert_eq!(parse_window("1h"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse_window("5"), None);
+        assert_eq!(parse_window("m"), None);
+        assert_eq!(parse_window(""), None);
+    }
+
+    #[test]
+    fn test_deltas() {
+        let c = IntCounter::new("commits", "help").unwrap();
+        let v = IntCounterVec::new(Opts::new("rows", "help"), &["table"]).unwrap();
+        let g = IntGauge::new("backends", "help").unwrap();
+        let collect = || {
+            let mut metrics = c.collect();
+            metrics.append(&mut v.collect());
+            metrics.append(&mut g.collect());
+            counters(&metrics)
+        };
+
+        c.inc_by(10);
+        v.with_label_values(&["t"]).inc_by(5);
+        g.set(3);
+        let before = collect();
+        assert_eq!(before.len(), 2);
+
+        c.inc_by(4);
+        v.with_label_values(&["t"]).inc_by(1);
+        v.with_label_values(&["new"]).inc();
+        let deltas = deltas(&before, &collect());
+        assert_eq!(deltas.len(), 2);
+        assert_eq!(deltas["commits"], 4.0);
+        assert_eq!(deltas["rows{table=\"t\"}"], 1.0);
+
+        // A reset counter counts from zero
+        c.reset();
+        c.inc_by(2);
+        let deltas = crate::history::deltas(&before, &collect());
+        assert_eq!(deltas["commits"], 2.0);
+    }
+}
@@ -0,0 +1,108 @@
+//!
+//! Leader election between exporter instances monitoring the same database.
+//!
+//! Running two exporters against one database for redundancy doubles the monitoring load
+//! and produces duplicate series. In HA pair mode, every instance keeps a dedicated session
+//! trying to acquire a session-level advisory lock, and only the holder actively collects
+//! metrics. The others serve `pg_exporter_active 0` until the holder goes away and its
+//! session (and therefore the lock) is released by the server.
+//!
+//! See <https://www.postgresql.org/docs/15/explicit-locking.html#ADVISORY-LOCKS>
+//!
+use postgres::Client;
+use prometheus::{core::Collector, IntGauge};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::help;
+use crate::postgres_connection::PgConnectionConfig;
+
+pub struct LeaderElection {
+    active: Arc<AtomicBool>,
+}
+
+impl LeaderElection {
+    /// Starts a thread that tries to acquire the advisory lock `key` every `interval` and,
+    /// once acquired, checks that the session holding it is still alive.
+    pub fn spawn(
+        postgres: PgConnectionConfig,
+        key: i64,
+        interval: Duration,
+    ) -> std::io::Result<Self> {
+        let active = Arc::new(AtomicBool::new(false));
+
+        let flag = active.clone();
+        std::thread::Builder::new()
+            .name("leader election".into())
+            .spawn(move || {
+                let mut client: Option<Client> = None;
+                loop {
+                    let held_before = flag.load(Ordering::Relaxed);
+                    let held = try_hold_lock(&postgres, &mut client, key, held_before);
+                    flag.store(held, Ordering::Relaxed);
+                    if held != held_before {
+                        if held {
+                            tracing::info!(key, "acquired the advisory lock, collecting metrics");
+                        } else {
+                            tracing::warn!(key, "lost the advisory lock, standing by");
+                        }
+                    }
+                    std::thread::sleep(interval);
+                }
+            })?;
+
+        Ok(LeaderElection { active })
+    }
+
+    /// Returns true if this instance holds the lock and should collect metrics.
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    pub fn collect(&self) -> Vec<prometheus::proto::MetricFamily> {
+        let m = IntGauge::new("pg_exporter_active", help::PG_EXPORTER_ACTIVE).unwrap();
+        m.set(self.is_active() as i64);
+        m.collect()
+    }
+}
+
+/// Returns true if the session in `client` holds the lock, (re)connecting if needed.
+/// `held` tells whether the session acquired the lock before.
+fn try_hold_lock(
+    postgres: &PgConnectionConfig,
+    client: &mut Option<Client>,
+    key: i64,
+    mut held: bool,
+) -> bool {
+    if client.as_ref().map_or(true, |c| c.is_closed()) {
+        // A lock held by the previous session has been released with it
+        held = false;
+        match postgres.connect_blocking() {
+            Ok(c) => *client = Some(c),
+            Err(e) => {
+                tracing::warn!("failed to connect to {}: {e:#}", postgres.raw_address());
+                *client = None;
+                return false;
+            }
+        }
+    }
+
+    let c = client.as_mut().unwrap();
+    // Session-level advisory locks are reentrant, so once acquired, just check that the
+    // session holding the lock is still alive.
+    let res = if held {
+        c.simple_query("SELECT 1").map(|_| true)
+    } else {
+        c.query_one("SELECT pg_try_advisory_lock($1)", &[&key])
+            .map(|row| row.get(0))
+    };
+    match res {
+        Ok(held) => held,
+        Err(e) => {
+            tracing::warn!("failed to check the advisory lock: {e:#}");
+            *client = None;
+            false
+        }
+    }
+}
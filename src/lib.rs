@@ -1,9 +1,43 @@
+pub mod auth_modules;
+pub mod background;
+pub mod backup;
+pub mod bootstrap;
+pub mod cancellation;
+pub mod checkplugin;
+pub mod client_addr;
+pub mod compatibility;
+pub mod config;
+pub mod custom_queries;
+pub mod federation;
+pub mod filesystem;
+pub mod help;
+pub mod history;
+pub mod leader_election;
+pub mod log_tailer;
 pub mod logging;
+pub mod memory;
 pub mod metrics;
+pub mod notifier;
+pub mod patroni;
+pub mod pgpass;
+pub mod pool;
 pub mod postgres_connection;
+pub mod postgres_tls;
+pub mod privileges;
+pub mod probe;
+pub mod queries;
+pub mod redaction;
 pub mod routes;
+pub mod rows;
+pub mod secret_files;
+pub mod server_metrics;
+pub mod snapshot_file;
 pub mod tcp_listener;
+pub mod tls_config;
+pub mod top;
 pub mod tracing_utils;
+pub mod unix_socket;
+pub mod zabbix;
 
 /// This is a shortcut to embed git sha into binaries and avoid copying the same build script to all packages
 ///
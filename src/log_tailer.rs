@@ -0,0 +1,549 @@
+//!
+//! Tails PostgreSQL csvlog files and counts events that cumulative statistics views don't
+//! report, like pg_statsinfo does with its log analysis: messages per severity and SQLSTATE,
+//! authentication failures, checkpoint/autovacuum activity, and the write and sync times of
+//! the last checkpoint. Statements logged with their duration by `log_min_duration_statement`
+//! or `auto_explain` are observed in a histogram per database and user, giving latency
+//! distributions that the averages of `pg_stat_statements` can't.
+//!
+//! The tailer watches a log directory (`log_directory` with `log_destination = 'csvlog'`),
+//! follows the most recently modified `*.csv` file, and switches to a new file when the
+//! server rotates logs. Only lines written after the tailer started are counted.
+//!
+use once_cell::sync::Lazy;
+use prometheus::{core::Collector, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, Opts};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::help;
+
+// Column positions of the csvlog format, see
+// https://www.postgresql.org/docs/15/runtime-config-logging.html#RUNTIME-CONFIG-LOGGING-CSVLOG
+const USER_NAME: usize = 1;
+const DATABASE_NAME: usize = 2;
+const ERROR_SEVERITY: usize = 11;
+const SQL_STATE_CODE: usize = 12;
+const MESSAGE: usize = 13;
+
+static LOG_MESSAGES: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new("pg_log_messages_total", help::PG_LOG_MESSAGES_TOTAL),
+        &["severity", "sqlstate"],
+    )
+    .unwrap()
+});
+
+static CANCELED_STATEMENTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "pg_canceled_statements_total",
+            help::PG_CANCELED_STATEMENTS_TOTAL,
+        ),
+        &["reason"],
+    )
+    .unwrap()
+});
+
+static LOG_EVENTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new("pg_log_events_total", help::PG_LOG_EVENTS_TOTAL),
+        &["event"],
+    )
+    .unwrap()
+});
+
+static AUTH_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new("pg_auth_failures_total", help::PG_AUTH_FAILURES_TOTAL),
+        &["sqlstate"],
+    )
+    .unwrap()
+});
+
+static LAST_CHECKPOINT_DURATION: Lazy<GaugeVec> = Lazy::new(|| {
+    GaugeVec::new(
+        Opts::new(
+            "pg_log_last_checkpoint_duration_seconds",
+            help::PG_LOG_LAST_CHECKPOINT_DURATION_SECONDS,
+        ),
+        &["kind", "phase"],
+    )
+    .unwrap()
+});
+
+static LAST_CHECKPOINT_BUFFERS: Lazy<GaugeVec> = Lazy::new(|| {
+    GaugeVec::new(
+        Opts::new(
+            "pg_log_last_checkpoint_buffers_written",
+            help::PG_LOG_LAST_CHECKPOINT_BUFFERS_WRITTEN,
+        ),
+        &["kind"],
+    )
+    .unwrap()
+});
+
+static STATEMENT_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    HistogramVec::new(
+        HistogramOpts::new(
+            "pg_log_statement_duration_seconds",
+            help::PG_LOG_STATEMENT_DURATION_SECONDS,
+        )
+        .buckets(vec![
+            0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0,
+        ]),
+        &["datname", "usename", "source"],
+    )
+    .unwrap()
+});
+
+/// Follows the newest csvlog file in a directory and returns complete records appended to it.
+pub struct CsvLogTailer {
+    dir: PathBuf,
+    current: Option<PathBuf>,
+    offset: u64,
+    // Bytes of a record that has not been completely written yet
+    partial: Vec<u8>,
+}
+
+impl CsvLogTailer {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        CsvLogTailer {
+            dir: dir.as_ref().to_path_buf(),
+            current: None,
+            offset: 0,
+            partial: vec![],
+        }
+    }
+
+    /// Returns the records appended since the previous call. The first call skips
+    /// everything already written to the newest file.
+    pub fn poll(&mut self) -> io::Result<Vec<Vec<String>>> {
+        let newest = newest_csv_file(&self.dir)?;
+        let mut records = vec![];
+
+        match (&self.current, newest) {
+            (None, Some(newest)) => {
+                self.offset = fs::metadata(&newest)?.len();
+                self.current = Some(newest);
+            }
+            (Some(current), Some(newest)) if *current != newest => {
+                // Read the rest of the rotated file before switching to the new one
+                records.append(&mut self.read_appended()?);
+                self.current = Some(newest);
+                self.offset = 0;
+                self.partial.clear();
+            }
+            _ => {}
+        }
+
+        records.append(&mut self.read_appended()?);
+        Ok(records)
+    }
+
+    fn read_appended(&mut self) -> io::Result<Vec<Vec<String>>> {
+        let path = match &self.current {
+            Some(path) => path,
+            None => return Ok(vec![]),
+        };
+
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            // The file might have been removed by log rotation
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+        if file.metadata()?.len() < self.offset {
+            // Truncated by `log_truncate_on_rotation`
+            self.offset = 0;
+            self.partial.clear();
+        }
+        file.seek(SeekFrom::Start(self.offset))?;
+        let n = file.read_to_end(&mut self.partial)?;
+        self.offset += n as u64;
+
+        let (records, consumed) = parse_csv_records(&self.partial);
+        self.partial.drain(..consumed);
+        Ok(records)
+    }
+}
+
+fn newest_csv_file(dir: &Path) -> io::Result<Option<PathBuf>> {
+    let mut newest: Option<(std::time::SystemTime, PathBuf)> = None;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension().map_or(true, |ext| ext != "csv") {
+            continue;
+        }
+        let modified = entry.metadata()?.modified()?;
+        // Log file names usually embed a timestamp, so the name breaks ties
+        if newest
+            .as_ref()
+            .map_or(true, |(t, p)| (modified, &path) > (*t, p))
+        {
+            newest = Some((modified, path));
+        }
+    }
+    Ok(newest.map(|(_, path)| path))
+}
+
+/// Parses complete CSV records in `buf` and returns them with the number of bytes consumed.
+/// A trailing record without a terminating newline is left unconsumed.
+fn parse_csv_records(buf: &[u8]) -> (Vec<Vec<String>>, usize) {
+    let mut records = vec![];
+    let mut consumed = 0;
+
+    let mut fields = vec![];
+    let mut field: Vec<u8> = vec![];
+    let mut in_quotes = false;
+    let mut i = 0;
+    while i < buf.len() {
+        let c = buf[i];
+        match c {
+            b'"' if in_quotes && buf.get(i + 1) == Some(&b'"') => {
+                field.push(b'"');
+                i += 1;
+            }
+            b'"' => in_quotes = !in_quotes,
+            b',' if !in_quotes => {
+                fields.push(String::from_utf8_lossy(&field).into_owned());
+                field.clear();
+            }
+            b'\n' if !in_quotes => {
+                fields.push(String::from_utf8_lossy(&field).into_owned());
+                field.clear();
+                records.push(std::mem::take(&mut fields));
+                consumed = i + 1;
+            }
+            _ => field.push(c),
+        }
+        i += 1;
+    }
+
+    (records, consumed)
+}
+
+/// Returns the kind of event a LOG message reports, if any. Checkpoints are logged with
+/// `log_checkpoints` and autovacuum runs with `log_autovacuum_min_duration`.
+fn log_event(message: &str) -> Option<&'static str> {
+    if message.starts_with("checkpoint complete:") {
+        Some("checkpoint_complete")
+    } else if message.starts_with("restartpoint complete:") {
+        Some("restartpoint_complete")
+    } else if message.starts_with("automatic analyze of table") {
+        Some("autoanalyze")
+    } else if message.starts_with("automatic ") && message.contains(" vacuum ") {
+        // Also matches "automatic aggressive vacuum ..." and "automatic vacuum to prevent
+        // wraparound of table ..."
+        Some("autovacuum")
+    } else {
+        None
+    }
+}
+
+/// Statistics of a `checkpoint complete:` or `restartpoint complete:` message.
+#[derive(Debug, Default, PartialEq)]
+struct CheckpointStats {
+    buffers_written: f64,
+    write_seconds: f64,
+    sync_seconds: f64,
+    total_seconds: f64,
+}
+
+/// Parses a message like `checkpoint complete: wrote 3 buffers (0.0%); 0 WAL file(s) added,
+/// 0 removed, 0 recycled; write=0.002 s, sync=0.001 s, total=0.004 s; sync files=2, ...`,
+/// or `wrote 3 buffers (0.0%), wrote 1 SLRU buffers; ...` of PostgreSQL 18.
+fn checkpoint_stats(message: &str) -> Option<CheckpointStats> {
+    let rest = message
+        .strip_prefix("checkpoint complete: ")
+        .or_else(|| message.strip_prefix("restartpoint complete: "))?;
+    let mut stats = CheckpointStats::default();
+    for item in rest.split([';', ',']).map(str::trim) {
+        if let Some((buffers, unit)) = item.strip_prefix("wrote ").and_then(|s| s.split_once(' ')) {
+            // PostgreSQL 18 also reports `wrote M SLRU buffers`, which aren't shared buffers
+            if unit.starts_with("buffers (") {
+                stats.buffers_written = buffers.parse().ok()?;
+            }
+        } else if let Some((key, value)) = item.split_once('=') {
+            let Some(seconds) = value.strip_suffix(" s") else {
+                continue;
+            };
+            let seconds = seconds.parse().ok()?;
+            match key {
+                "write" => stats.write_seconds = seconds,
+                "sync" => stats.sync_seconds = seconds,
+                "total" => stats.total_seconds = seconds,
+                _ => {}
+            }
+        }
+    }
+    Some(stats)
+}
+
+/// Returns the duration in seconds and the source of a LOG message reporting the duration of a
+/// statement, e.g., `duration: 12.345 ms  statement: SELECT ...` by
+/// `log_min_duration_statement`, or `duration: 12.345 ms  plan: ...` by `auto_explain`. As
+/// both may log the same statement, the source tells them apart.
+fn statement_duration(message: &str) -> Option<(f64, &'static str)> {
+    let (millis, rest) = message.strip_prefix("duration: ")?.split_once(" ms")?;
+    let millis: f64 = millis.parse().ok()?;
+    let source = if rest.trim_start().starts_with("plan:") {
+        "auto_explain"
+    } else {
+        "statement"
+    };
+    Some((millis / 1000.0, source))
+}
+
+/// Returns why a statement was canceled from the message of a `query_canceled` (57014) error.
+fn cancel_reason(message: &str) -> &'static str {
+    match message {
+        "canceling statement due to statement timeout" => "statement_timeout",
+        "canceling statement due to user request" => "user_request",
+        "canceling autovacuum task" => "autovacuum",
+        _ => "other",
+    }
+}
+
+/// Updates the event counters for a csvlog record.
+fn record_event(fields: &[String]) {
+    let field = |i: usize| fields.get(i).map_or("", |s| s.as_str());
+    let severity = field(ERROR_SEVERITY);
+    let sqlstate = field(SQL_STATE_CODE);
+
+    match severity {
+        "ERROR" | "FATAL" | "PANIC" => {
+            LOG_MESSAGES.with_label_values(&[severity, sqlstate]).inc();
+        }
+        "LOG" => {
+            if let Some(event) = log_event(field(MESSAGE)) {
+                LOG_EVENTS.with_label_values(&[event]).inc();
+                if let Some(stats) = checkpoint_stats(field(MESSAGE)) {
+                    let kind = event.trim_end_matches("_complete");
+                    for (phase, seconds) in [
+                        ("write", stats.write_seconds),
+                        ("sync", stats.sync_seconds),
+                        ("total", stats.total_seconds),
+                    ] {
+                        LAST_CHECKPOINT_DURATION
+                            .with_label_values(&[kind, phase])
+                            .set(seconds);
+                    }
+                    LAST_CHECKPOINT_BUFFERS
+                        .with_label_values(&[kind])
+                        .set(stats.buffers_written);
+                }
+            } else if let Some((seconds, source)) = statement_duration(field(MESSAGE)) {
+                STATEMENT_DURATION
+                    .with_label_values(&[field(DATABASE_NAME), field(USER_NAME), source])
+                    .observe(seconds);
+            }
+        }
+        _ => {}
+    }
+
+    if severity == "ERROR" && sqlstate == "57014" {
+        let reason = cancel_reason(field(MESSAGE));
+        CANCELED_STATEMENTS.with_label_values(&[reason]).inc();
+        if reason == "autovacuum" {
+            // Autovacuum yields to a conflicting lock request, so that a table canceled
+            // repeatedly may never be vacuumed
+            LOG_EVENTS.with_label_values(&["autovacuum_canceled"]).inc();
+        }
+    }
+
+    // 28P01: invalid_password, 28000: invalid_authorization_specification (e.g., no
+    // pg_hba.conf entry or a failed ident/peer/cert check)
+    if severity == "FATAL" && matches!(sqlstate, "28P01" | "28000") {
+        AUTH_FAILURES.with_label_values(&[sqlstate]).inc();
+    }
+}
+
+/// Starts a thread that tails csvlog files in `dir` every `interval`.
+pub fn spawn<P: AsRef<Path>>(dir: P, interval: Duration) -> io::Result<()> {
+    let mut tailer = CsvLogTailer::new(dir);
+    // Fail fast on a misconfigured directory instead of logging errors forever
+    tailer.poll()?;
+
+    std::thread::Builder::new()
+        .name("log tailer".into())
+        .spawn(move || loop {
+            std::thread::sleep(interval);
+            match tailer.poll() {
+                Ok(records) => records.iter().for_each(|r| record_event(r)),
+                Err(e) => tracing::warn!("failed to read server logs: {e:#}"),
+            }
+        })?;
+
+    Ok(())
+}
+
+/// Returns the metrics counted from server logs so far.
+pub fn collect() -> Vec<prometheus::proto::MetricFamily> {
+    let mut metrics = LOG_MESSAGES.collect();
+    metrics.append(&mut LOG_EVENTS.collect());
+    metrics.append(&mut CANCELED_STATEMENTS.collect());
+    metrics.append(&mut AUTH_FAILURES.collect());
+    metrics.append(&mut LAST_CHECKPOINT_DURATION.collect());
+    metrics.append(&mut LAST_CHECKPOINT_BUFFERS.collect());
+    metrics.append(&mut STATEMENT_DURATION.collect());
+    metrics
+}
+
+#[cfg(test)]
+mod tests_parse_csv_records {
+    use crate::log_tailer::parse_csv_records;
+
+    #[test]
+    fn test_simple() {
+        let (records, consumed) = parse_csv_records(b"a,b,c\n1,,3\n");
+        assert_eq!(records, vec![vec!["a", "b", "c"], vec!["1", "", "3"]]);
+        assert_eq!(consumed, 11);
+    }
+
+    #[test]
+    fn test_quoted() {
+        let (records, _) = parse_csv_records(b"\"a,b\",\"say \"\"hi\"\"\",\"multi\nline\"\n");
+        assert_eq!(records, vec![vec!["a,b", "say \"hi\"", "multi\nline"]]);
+    }
+
+    #[test]
+    fn test_incomplete() {
+        let (records, consumed) = parse_csv_records(b"a,b\nc,\"d\n");
+        assert_eq!(records, vec![vec!["a", "b"]]);
+        assert_eq!(consumed, 4);
+    }
+}
+
+#[cfg(test)]
+mod tests_log_event {
+    use crate::log_tailer::{
+        cancel_reason, checkpoint_stats, log_event, statement_duration, CheckpointStats,
+    };
+
+    #[test]
+    fn test_events() {
+        assert_eq!(
+            log_event("checkpoint complete: wrote 3 buffers (0.0%); 0 WAL file(s) added"),
+            Some("checkpoint_complete")
+        );
+        assert_eq!(
+            log_event("automatic vacuum of table \"postgres.public.t\": index scans: 1"),
+            Some("autovacuum")
+        );
+        assert_eq!(
+            log_event("automatic analyze of table \"postgres.public.t\""),
+            Some("autoanalyze")
+        );
+        assert_eq!(
+            log_event("automatic aggressive vacuum to prevent wraparound of table \"x\""),
+            Some("autovacuum")
+        );
+        assert_eq!(log_event("checkpoint starting: time"), None);
+    }
+
+    #[test]
+    fn test_checkpoint_stats() {
+        assert_eq!(
+            checkpoint_stats(
+                "checkpoint complete: wrote 3 buffers (0.0%); 0 WAL file(s) added, 0 removed, \
+                 1 recycled; write=0.302 s, sync=0.015 s, total=0.327 s; sync files=2, \
+                 longest=0.010 s, average=0.008 s; distance=12 kB, estimate=40 kB"
+            ),
+            Some(CheckpointStats {
+                buffers_written: 3.0,
+                write_seconds: 0.302,
+                sync_seconds: 0.015,
+                total_seconds: 0.327,
+            })
+        );
+        assert_eq!(
+            checkpoint_stats("restartpoint complete: wrote 0 buffers (0.0%); write=0.001 s")
+                .map(|stats| stats.write_seconds),
+            Some(0.001)
+        );
+        assert_eq!(
+            checkpoint_stats(
+                "checkpoint complete: wrote 5 buffers (0.0%), wrote 2 SLRU buffers; \
+                 0 WAL file(s) added, 0 removed, 0 recycled; write=0.001 s"
+            )
+            .map(|stats| stats.buffers_written),
+            Some(5.0)
+        );
+        assert_eq!(checkpoint_stats("checkpoint starting: time"), None);
+    }
+
+    #[test]
+    fn test_statement_durations() {
+        assert_eq!(
+            statement_duration("duration: 1500.000 ms  statement: SELECT pg_sleep(1.5)"),
+            Some((1.5, "statement"))
+        );
+        assert_eq!(
+            statement_duration("duration: 2.000 ms  execute <unnamed>: SELECT $1"),
+            Some((0.002, "statement"))
+        );
+        assert_eq!(
+            statement_duration("duration: 0.500 ms"),
+            Some((0.0005, "statement"))
+        );
+        assert_eq!(
+            statement_duration("duration: 250.000 ms  plan:\nQuery Text: SELECT 1"),
+            Some((0.25, "auto_explain"))
+        );
+        assert_eq!(statement_duration("statement: SELECT 1"), None);
+    }
+
+    #[test]
+    fn test_cancel_reasons() {
+        assert_eq!(
+            cancel_reason("canceling statement due to statement timeout"),
+            "statement_timeout"
+        );
+        assert_eq!(
+            cancel_reason("canceling statement due to user request"),
+            "user_request"
+        );
+        assert_eq!(
+            cancel_reason("canceling statement due to lock timeout"),
+            "other"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_csv_log_tailer {
+    use crate::log_tailer::CsvLogTailer;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+
+    #[test]
+    fn test_follow_and_rotate() {
+        let dir = std::env::temp_dir().join(format!("pg_stats_exporter_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let first = dir.join("postgresql-1.csv");
+        fs::write(&first, "old,line\n").unwrap();
+
+        let mut tailer = CsvLogTailer::new(&dir);
+        assert!(tailer.poll().unwrap().is_empty());
+
+        let mut file = OpenOptions::new().append(true).open(&first).unwrap();
+        file.write_all(b"new,li").unwrap();
+        assert!(tailer.poll().unwrap().is_empty());
+        file.write_all(b"ne\n").unwrap();
+        assert_eq!(tailer.poll().unwrap(), vec![vec!["new", "line"]]);
+
+        // Make sure the modification time of the rotated file is newer
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        file.write_all(b"last,line\n").unwrap();
+        fs::write(dir.join("postgresql-2.csv"), "rotated,line\n").unwrap();
+        assert_eq!(
+            tailer.poll().unwrap(),
+            vec![vec!["last", "line"], vec!["rotated", "line"]]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,42 +1,137 @@
+use crate::config::{LogFormat, LoggingConfig};
+use crate::redaction;
 use crate::tracing_utils;
+use std::io;
+use tracing_log::{AsLog, LogTracer};
 use tracing_opentelemetry::OpenTelemetryLayer;
 use tracing_subscriber::{
     filter::{EnvFilter, LevelFilter},
     prelude::*,
+    reload, Registry,
 };
 
 /// Initialize logging and OpenTelemetry tracing and exporter.
 ///
-/// Logging can be configured using `RUST_LOG` environment variable.
+/// Logging can be configured using `RUST_LOG` environment variable, which is overridden by
+/// the filter directives of `config.level` if given.
 ///
 /// OpenTelemetry is configured with OTLP/HTTP exporter. It picks up
 /// configuration from environment variables. For example, to change the
 /// destination, set `OTEL_EXPORTER_OTLP_ENDPOINT=http://jaeger:4318`.
 /// See <https://opentelemetry.io/docs/reference/specification/sdk-environment-variables>
-pub async fn init(service_name: &str) -> anyhow::Result<LoggingGuard> {
-    let env_filter = EnvFilter::builder()
-        .with_default_directive(LevelFilter::INFO.into())
-        .from_env_lossy();
+///
+/// Sensitive data is redacted from the logs and the exported spans as set by
+/// `redaction::init`.
+pub async fn init(service_name: &str, config: &LoggingConfig) -> anyhow::Result<LoggingGuard> {
+    let (env_filter, filter_handle) = reload::Layer::new(env_filter(config.level.as_deref())?);
 
-    let fmt_layer = tracing_subscriber::fmt::layer()
-        .with_ansi(false)
-        .with_writer(std::io::stderr)
-        .with_target(false);
+    // The layers of the formats differ in type, so only one of them is set
+    let (text_layer, json_layer) = match config.format.unwrap_or_default() {
+        LogFormat::Text => (
+            Some(
+                tracing_subscriber::fmt::layer()
+                    .with_ansi(false)
+                    .with_writer(|| RedactingStderr)
+                    .with_target(false),
+            ),
+            None,
+        ),
+        LogFormat::Json => (
+            None,
+            Some(
+                tracing_subscriber::fmt::layer()
+                    .json()
+                    .with_writer(|| RedactingStderr)
+                    .with_target(false),
+            ),
+        ),
+    };
 
     let otlp_layer = tracing_utils::init_tracing(service_name)
         .await
         .map(OpenTelemetryLayer::new);
 
-    tracing_subscriber::registry()
+    let subscriber = tracing_subscriber::registry()
         .with(env_filter)
         .with(otlp_layer)
-        .with(fmt_layer)
-        .try_init()?;
+        .with(text_layer)
+        .with(json_layer);
+    tracing::subscriber::set_global_default(subscriber)?;
+    log::set_boxed_logger(Box::new(RedactingLogTracer(LogTracer::new())))?;
+    log::set_max_level(tracing::level_filters::LevelFilter::current().as_log());
+
+    Ok(LoggingGuard { filter_handle })
+}
 
-    Ok(LoggingGuard)
+fn env_filter(level: Option<&str>) -> anyhow::Result<EnvFilter> {
+    let builder = EnvFilter::builder().with_default_directive(LevelFilter::INFO.into());
+    Ok(match level {
+        Some(directives) => builder.parse(directives)?,
+        None => builder.from_env_lossy(),
+    })
 }
 
-pub struct LoggingGuard;
+/// Writes the log lines to stderr with the secrets in them masked. A line is written at once.
+struct RedactingStderr;
+
+impl io::Write for RedactingStderr {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match std::str::from_utf8(buf) {
+            Ok(line) => io::stderr().write_all(redaction::mask_secrets(line).as_bytes())?,
+            Err(_) => io::stderr().write_all(buf)?,
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+/// Forwards the records of the `log` crate, e.g., of `tokio_postgres`, to `tracing` with the
+/// SQL in them redacted.
+struct RedactingLogTracer(LogTracer);
+
+impl log::Log for RedactingLogTracer {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let message = record.args().to_string();
+        let message = redaction::redact_log_message(record.target(), &message);
+        self.0.log(
+            &log::Record::builder()
+                .metadata(record.metadata().clone())
+                .args(format_args!("{message}"))
+                .module_path(record.module_path())
+                .file(record.file())
+                .line(record.line())
+                .build(),
+        );
+    }
+
+    fn flush(&self) {
+        self.0.flush()
+    }
+}
+
+pub struct LoggingGuard {
+    filter_handle: reload::Handle<EnvFilter, Registry>,
+}
+
+impl LoggingGuard {
+    /// Replaces the filter directives given to `init`, e.g., when the configuration file
+    /// is read again.
+    pub fn set_level(&self, level: Option<&str>) -> anyhow::Result<()> {
+        self.filter_handle.reload(env_filter(level)?)?;
+        log::set_max_level(tracing::level_filters::LevelFilter::current().as_log());
+        Ok(())
+    }
+}
 
 impl Drop for LoggingGuard {
     fn drop(&mut self) {
@@ -0,0 +1,116 @@
+//!
+//! Allocation accounting of the exporter itself.
+//!
+//! With the `memory-accounting` feature, the global allocator counts the bytes allocated,
+//! which are exported as `pg_exporter_memory_allocated_bytes` and
+//! `pg_exporter_memory_peak_allocated_bytes`. A soft ceiling on them trips the cardinality
+//! guard, so that a sidecar stays within its cgroup limits during cardinality explosions.
+//!
+use prometheus::{core::Collector, IntGauge};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::help;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+/// Wraps the system allocator to count the bytes allocated.
+pub struct TrackingAllocator;
+
+impl TrackingAllocator {
+    fn add(size: usize) {
+        let current = ALLOCATED.fetch_add(size, Ordering::Relaxed) + size;
+        PEAK.fetch_max(current, Ordering::Relaxed);
+    }
+
+    fn sub(size: usize) {
+        ALLOCATED.fetch_sub(size, Ordering::Relaxed);
+    }
+}
+
+unsafe impl GlobalAlloc for TrackingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            Self::add(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            Self::add(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        Self::sub(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            Self::sub(layout.size());
+            Self::add(new_size);
+        }
+        new_ptr
+    }
+}
+
+#[cfg(feature = "memory-accounting")]
+#[global_allocator]
+static GLOBAL: TrackingAllocator = TrackingAllocator;
+
+/// Returns the bytes currently allocated, or `None` without the `memory-accounting` feature.
+pub fn allocated() -> Option<usize> {
+    cfg!(feature = "memory-accounting").then(|| ALLOCATED.load(Ordering::Relaxed))
+}
+
+pub fn collect() -> Vec<prometheus::proto::MetricFamily> {
+    if !cfg!(feature = "memory-accounting") {
+        return vec![];
+    }
+
+    let mut metrics = vec![];
+    let m = IntGauge::new(
+        "pg_exporter_memory_allocated_bytes",
+        help::PG_EXPORTER_MEMORY_ALLOCATED_BYTES,
+    )
+    .unwrap();
+    m.set(ALLOCATED.load(Ordering::Relaxed) as i64);
+    metrics.append(&mut m.collect());
+    let m = IntGauge::new(
+        "pg_exporter_memory_peak_allocated_bytes",
+        help::PG_EXPORTER_MEMORY_PEAK_ALLOCATED_BYTES,
+    )
+    .unwrap();
+    m.set(PEAK.load(Ordering::Relaxed) as i64);
+    metrics.append(&mut m.collect());
+    metrics
+}
+
+#[cfg(all(test, not(feature = "memory-accounting")))]
+mod tests_tracking_allocator {
+    use crate::memory::{TrackingAllocator, ALLOCATED, PEAK};
+    use std::alloc::{GlobalAlloc, Layout};
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn test_counts() {
+        // Without the feature, only the allocations below are counted
+        let layout = Layout::from_size_align(1024, 8).unwrap();
+        unsafe {
+            let ptr = TrackingAllocator.alloc(layout);
+            assert_eq!(ALLOCATED.load(Ordering::Relaxed), 1024);
+            let ptr = TrackingAllocator.realloc(ptr, layout, 4096);
+            assert_eq!(ALLOCATED.load(Ordering::Relaxed), 4096);
+            TrackingAllocator.dealloc(ptr, Layout::from_size_align(4096, 8).unwrap());
+        }
+        assert_eq!(ALLOCATED.load(Ordering::Relaxed), 0);
+        assert_eq!(PEAK.load(Ordering::Relaxed), 4096);
+    }
+}
@@ -1,8 +1,30 @@
-use postgres::{Client, Error};
-use prometheus::{core::Collector, IntGauge};
-use tracing;
+use once_cell::sync::{Lazy, OnceCell};
+use prometheus::{
+    core::Collector, Counter, CounterVec, Encoder, Gauge, GaugeVec, Histogram, HistogramOpts,
+    HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio_postgres::{Client, Error};
+use tracing::{self, instrument, Instrument};
 
+use crate::backup::{self, BackupSource};
+use crate::cancellation::ScrapeCancellation;
+use crate::compatibility::{Flavor, Profile};
+use crate::custom_queries::{self, CustomQuery};
+use crate::filesystem;
+use crate::help;
+use crate::log_tailer;
+use crate::notifier;
+use crate::patroni;
+use crate::pool::{Pool, PooledClient};
 use crate::postgres_connection::PgConnectionConfig;
+use crate::redaction;
+use crate::rows::{from_row, query_as, query_one_as};
 
 // TODO: Move this macro to `tracing_utils.rs`
 #[macro_export]
@@ -13,6 +35,107 @@ macro_rules! info_span {
     };
 }
 
+/// Switches for collectors that are disabled by default because they are expensive or
+/// export a metric per relation.
+#[derive(Clone, Debug)]
+pub struct CollectorOptions {
+    /// Export per-table HOT update counts and ratios from `pg_stat_user_tables`.
+    pub hot_updates: bool,
+    /// Export per-table TOAST relation sizes and access counts.
+    pub toast: bool,
+    /// Export per-statement calls and execution time per second since the previous scrape.
+    pub statements_delta: bool,
+    /// Export the top-N largest tables and indexes if set.
+    pub largest_relations: Option<i64>,
+    /// How long the result of the largest-relations query is reused across scrapes.
+    pub largest_relations_interval: Duration,
+    /// Export per-table ages of the last vacuum and analyze.
+    pub vacuum_recency: bool,
+    /// Regular expression matched against `<schema>.<table>` to select the tables whose
+    /// vacuum and analyze ages are exported. All the tables are selected if not set.
+    pub vacuum_recency_tables: Option<String>,
+    /// Names of the logical replication slots whose lag is exported. All the logical slots
+    /// are selected if not set.
+    pub logical_slots: Option<Vec<String>>,
+    /// Export the sizes and free space of the filesystems of the data directory, WAL and
+    /// tablespaces. The exporter has to run on the database host.
+    pub filesystem: bool,
+    /// Data directory to use instead of the `data_directory` setting of the server, e.g.,
+    /// where it is mounted in the container of the exporter.
+    pub data_directory: Option<PathBuf>,
+    /// Directory of the csvlog files tailed by `log_tailer` if set.
+    pub log_directory: Option<PathBuf>,
+    /// URL of the Patroni REST API `/patroni` endpoint to query the cluster role from.
+    pub patroni_url: Option<String>,
+    /// Backup tool to query the status of the backups from.
+    pub backup: Option<BackupSource>,
+    /// How long the status of the backups is reused across scrapes.
+    pub backup_interval: Duration,
+    /// Standby to run the collectors in `STANDBY_COLLECTORS` on instead of the primary.
+    pub standby: Option<PgConnectionConfig>,
+    /// Functions the role can't execute, mapped to the security-definer helpers replacing
+    /// them, or `None` to skip the collectors calling them. Set once privileges are checked.
+    pub function_fallbacks: Arc<OnceCell<HashMap<&'static str, Option<String>>>>,
+    /// Run the collectors of a scrape in a single REPEATABLE READ transaction, so that the
+    /// exported values are mutually consistent.
+    pub consistent_snapshot: bool,
+    /// Compatibility profile to use instead of detecting the server.
+    pub compatibility_profile: Option<Flavor>,
+    /// Collectors to disable instead of the ones of the compatibility profile.
+    pub compatibility_disabled_collectors: Option<Vec<String>>,
+    /// Compatibility profile in effect. Set once the server is detected.
+    pub profile: Arc<OnceCell<Profile>>,
+    /// User-defined queries whose results are exported.
+    pub custom_queries: Vec<CustomQuery>,
+    /// Run `custom_queries` as they are instead of in a read-only transaction.
+    pub allow_unsafe_queries: bool,
+    /// Collectors disabled with `--collector.<name>=false`.
+    pub disabled_collectors: Vec<String>,
+    /// Report the results to `notifier`, which `/probe` doesn't for the servers it probes.
+    pub notify: bool,
+}
+
+impl Default for CollectorOptions {
+    fn default() -> Self {
+        CollectorOptions {
+            hot_updates: false,
+            toast: false,
+            statements_delta: false,
+            largest_relations: None,
+            largest_relations_interval: Duration::from_secs(300),
+            vacuum_recency: false,
+            vacuum_recency_tables: None,
+            logical_slots: None,
+            filesystem: false,
+            data_directory: None,
+            log_directory: None,
+            patroni_url: None,
+            backup: None,
+            backup_interval: Duration::from_secs(300),
+            standby: None,
+            function_fallbacks: Arc::new(OnceCell::new()),
+            consistent_snapshot: false,
+            compatibility_profile: None,
+            compatibility_disabled_collectors: None,
+            profile: Arc::new(OnceCell::new()),
+            custom_queries: vec![],
+            allow_unsafe_queries: false,
+            disabled_collectors: vec![],
+            notify: true,
+        }
+    }
+}
+
+impl CollectorOptions {
+    /// Returns the function to call instead of `signature`, if any.
+    fn function_source<'a>(&'a self, signature: &'a str) -> Option<&'a str> {
+        match self.function_fallbacks.get().and_then(|f| f.get(signature)) {
+            Some(fallback) => fallback.as_deref(),
+            None => Some(signature),
+        }
+    }
+}
+
 // A definithin of `statsinfo.cpustats` is as follows:
 //
 //  CREATE FUNCTION statsinfo.cpustats
@@ -33,52 +156,67 @@ macro_rules! info_span {
 //  LANGUAGE C STRICT;
 //
 // https://github.com/ossc-db/pg_statsinfo/blob/15.1/agent/lib/pg_statsinfo.sql.in#L127-L142
-fn get_cpustats(conn: &mut Client) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
-    info_span!("get_cpustats");
+//
+// `source` is the function to call, which is `statsinfo.cpustats()` or a security-definer
+// helper wrapping it for roles other than superusers.
+#[instrument(skip_all)]
+async fn get_cpustats(
+    conn: &Client,
+    source: &str,
+) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    from_row! {
+        struct CpuStats {
+            cpu_id: String,
+            cpu_user: i64,
+            cpu_system: i64,
+            cpu_idle: i64,
+            cpu_iowait: i64,
+        }
+    }
 
-    // TODO: Checks if the query below always returns a single row
-    let row = conn.query_one(
-        "
-        SELECT
-            stats.cpu_id,
-            stats.cpu_system,
-            stats.cpu_idle,
-            stats.cpu_iowait
-        FROM
-            statsinfo.cpustats() AS stats
-        LIMIT 1
-    ",
+    let rows: Vec<CpuStats> = query_as(
+        conn,
+        &format!(
+            "
+            SELECT
+                stats.cpu_id,
+                stats.cpu_user,
+                stats.cpu_system,
+                stats.cpu_idle,
+                stats.cpu_iowait
+            FROM
+                {} AS stats
+        ",
+            source
+        ),
         &[],
-    )?;
+    )
+    .await?;
 
     let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
 
-    let cpu_id: String = row.get(0);
-    let stat_prefix = format!("cpustats_{}", cpu_id);
-
-    let mut append_stat = |value: i64, stat_name: &str, help: &str| {
-        // TODO: Is it okay to create a new `IntGauge` on the fly?
-        let m = IntGauge::new(format!("{}_{}", stat_prefix, stat_name), help).unwrap();
-        m.set(value);
-        metrics.append(&mut m.collect());
-    };
-
-    // TODO: How do we push `row.get` inside `append_stat`?
-    append_stat(
-        row.get(1),
-        "cpu_system",
-        "The amount of time CPUs spent in running the operating system functions",
-    );
-    append_stat(
-        row.get(2),
-        "cpu_idle",
-        "The amount of time CPUs weren't  busy",
-    );
-    append_stat(
-        row.get(3),
-        "cpu_iowait",
-        "The amount of time CPUs where idle during which the system had pending I/O requests",
-    );
+    // The times are read from `/proc/stat` in clock ticks, which are `USER_HZ` (100) per
+    // second on Linux
+    let m = CounterVec::new(
+        Opts::new(
+            "pg_statsinfo_cpu_seconds_total",
+            help::PG_STATSINFO_CPU_SECONDS_TOTAL,
+        ),
+        &["cpu_id", "mode"],
+    )
+    .unwrap();
+    for stats in rows {
+        for (mode, ticks) in [
+            ("user", stats.cpu_user),
+            ("system", stats.cpu_system),
+            ("idle", stats.cpu_idle),
+            ("iowait", stats.cpu_iowait),
+        ] {
+            m.with_label_values(&[&stats.cpu_id, mode])
+                .inc_by(ticks.max(0) as f64 / 100.0);
+        }
+    }
+    metrics.append(&mut m.collect());
 
     Ok(metrics)
 }
@@ -98,64 +236,4018 @@ fn get_cpustats(conn: &mut Client) -> Result<Vec<prometheus::proto::MetricFamily
 //  LANGUAGE C STRICT;
 //
 // https://github.com/ossc-db/pg_statsinfo/blob/15.1/agent/lib/pg_statsinfo.sql.in#L84-L97
-fn get_tablespaces_stats(conn: &mut Client) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
-    info_span!("get_tablespaces_stats");
+//
+// `source` is the function to call as in `get_cpustats`.
+#[instrument(skip_all)]
+async fn get_tablespaces_stats(
+    conn: &Client,
+    source: &str,
+) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    from_row! {
+        struct Tablespace {
+            name: String,
+            location: String,
+            device: Option<String>,
+            avail: Option<i64>,
+            total: Option<i64>,
+        }
+    }
+
+    let tablespaces: Vec<Tablespace> = query_as(
+        conn,
+        &format!(
+            "
+            SELECT
+                stats.name,
+                stats.location,
+                stats.device,
+                stats.avail,
+                stats.total
+            FROM
+                {} AS stats
+        ",
+            source
+        ),
+        &[],
+    )
+    .await?;
+
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    let m = IntGaugeVec::new(
+        Opts::new(
+            "pg_statsinfo_tablespace_bytes",
+            help::PG_STATSINFO_TABLESPACE_BYTES,
+        ),
+        &["tablespace", "location", "device", "kind"],
+    )
+    .unwrap();
+    for tablespace in tablespaces {
+        let device = tablespace.device.unwrap_or_default();
+        for (kind, value) in [("avail", tablespace.avail), ("total", tablespace.total)] {
+            if let Some(value) = value {
+                m.with_label_values(&[&tablespace.name, &tablespace.location, &device, kind])
+                    .set(value);
+            }
+        }
+    }
+    metrics.append(&mut m.collect());
+
+    Ok(metrics)
+}
+
+// A definithin of `statsinfo.loadavg` is as follows:
+//
+//  CREATE FUNCTION statsinfo.loadavg(
+//  	OUT loadavg1	real,
+//  	OUT loadavg5	real,
+//  	OUT loadavg15	real)
+//  RETURNS SETOF record
+//  AS 'MODULE_PATHNAME', 'statsinfo_loadavg'
+//  LANGUAGE C STRICT;
+//
+// https://github.com/ossc-db/pg_statsinfo/blob/15.1/agent/lib/pg_statsinfo.sql.in
+//
+// `source` is the function to call as in `get_cpustats`.
+#[instrument(skip_all)]
+async fn get_loadavg(
+    conn: &Client,
+    source: &str,
+) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    from_row! {
+        struct LoadAvg {
+            loadavg1: f32,
+            loadavg5: f32,
+            loadavg15: f32,
+        }
+    }
+
+    let rows: Vec<LoadAvg> = query_as(
+        conn,
+        &format!(
+            "
+            SELECT
+                stats.loadavg1,
+                stats.loadavg5,
+                stats.loadavg15
+            FROM
+                {} AS stats
+        ",
+            source
+        ),
+        &[],
+    )
+    .await?;
+
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    if let Some(stats) = rows.first() {
+        for (name, help, value) in [
+            (
+                "pg_statsinfo_load1",
+                help::PG_STATSINFO_LOAD1,
+                stats.loadavg1,
+            ),
+            (
+                "pg_statsinfo_load5",
+                help::PG_STATSINFO_LOAD5,
+                stats.loadavg5,
+            ),
+            (
+                "pg_statsinfo_load15",
+                help::PG_STATSINFO_LOAD15,
+                stats.loadavg15,
+            ),
+        ] {
+            let m = Gauge::new(name, help).unwrap();
+            m.set(value as f64);
+            metrics.append(&mut m.collect());
+        }
+    }
+
+    Ok(metrics)
+}
+
+// A definithin of `statsinfo.memory` is as follows:
+//
+//  CREATE FUNCTION statsinfo.memory(
+//  	OUT memfree		bigint,
+//  	OUT buffers		bigint,
+//  	OUT cached		bigint,
+//  	OUT swap		bigint,
+//  	OUT dirty		bigint)
+//  RETURNS SETOF record
+//  AS 'MODULE_PATHNAME', 'statsinfo_memory'
+//  LANGUAGE C STRICT;
+//
+// https://github.com/ossc-db/pg_statsinfo/blob/15.1/agent/lib/pg_statsinfo.sql.in
+//
+// `source` is the function to call as in `get_cpustats`.
+#[instrument(skip_all)]
+async fn get_memory_stats(
+    conn: &Client,
+    source: &str,
+) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    from_row! {
+        struct Memory {
+            memfree: i64,
+            buffers: i64,
+            cached: i64,
+            swap: i64,
+        }
+    }
+
+    let rows: Vec<Memory> = query_as(
+        conn,
+        &format!(
+            "
+            SELECT
+                stats.memfree,
+                stats.buffers,
+                stats.cached,
+                stats.swap
+            FROM
+                {} AS stats
+        ",
+            source
+        ),
+        &[],
+    )
+    .await?;
+
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    // The sizes are read from `/proc/meminfo` in kB
+    let m = IntGaugeVec::new(
+        Opts::new("pg_statsinfo_memory_bytes", help::PG_STATSINFO_MEMORY_BYTES),
+        &["kind"],
+    )
+    .unwrap();
+    if let Some(stats) = rows.first() {
+        for (kind, kbytes) in [
+            ("memfree", stats.memfree),
+            ("buffers", stats.buffers),
+            ("cached", stats.cached),
+            ("swap", stats.swap),
+        ] {
+            m.with_label_values(&[kind]).set(kbytes * 1024);
+        }
+    }
+    metrics.append(&mut m.collect());
+
+    Ok(metrics)
+}
+
+// A definithin of `statsinfo.devicestats` is as follows:
+//
+//  CREATE FUNCTION statsinfo.devicestats(
+//  	OUT device_major		text,
+//  	OUT device_minor		text,
+//  	OUT device_name			text,
+//  	OUT device_readsector	bigint,
+//  	OUT device_readtime		bigint,
+//  	OUT device_writesector	bigint,
+//  	OUT device_writetime	bigint,
+//  	OUT device_ioqueue		bigint,
+//  	OUT device_iototaltime	bigint,
+//  	OUT device_rsps_max		float8,
+//  	OUT device_wsps_max		float8,
+//  	OUT overflow_drs		smallint,
+//  	OUT overflow_drt		smallint,
+//  	OUT overflow_dws		smallint,
+//  	OUT overflow_dwt		smallint,
+//  	OUT overflow_dit		smallint,
+//  	OUT device_tblspaces	name[])
+//  RETURNS SETOF record
+//  AS 'MODULE_PATHNAME', 'statsinfo_devicestats'
+//  LANGUAGE C STRICT;
+//
+// https://github.com/ossc-db/pg_statsinfo/blob/15.1/agent/lib/pg_statsinfo.sql.in
+//
+// `source` is the function to call as in `get_cpustats`.
+#[instrument(skip_all)]
+async fn get_devicestats(
+    conn: &Client,
+    source: &str,
+) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    from_row! {
+        struct DeviceStats {
+            device_name: String,
+            device_readsector: i64,
+            device_readtime: i64,
+            device_writesector: i64,
+            device_writetime: i64,
+            device_ioqueue: i64,
+            device_iototaltime: i64,
+            overflow_drs: i16,
+            overflow_drt: i16,
+            overflow_dws: i16,
+            overflow_dwt: i16,
+            overflow_dit: i16,
+        }
+    }
+
+    let rows: Vec<DeviceStats> = query_as(
+        conn,
+        &format!(
+            "
+            SELECT
+                stats.device_name,
+                stats.device_readsector,
+                stats.device_readtime,
+                stats.device_writesector,
+                stats.device_writetime,
+                stats.device_ioqueue,
+                stats.device_iototaltime,
+                stats.overflow_drs,
+                stats.overflow_drt,
+                stats.overflow_dws,
+                stats.overflow_dwt,
+                stats.overflow_dit
+            FROM
+                {} AS stats
+        ",
+            source
+        ),
+        &[],
+    )
+    .await?;
+
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    // The sectors and the times are read from `/proc/diskstats`, in 512-byte sectors and
+    // in milliseconds
+    type Column = (&'static str, &'static str, fn(&DeviceStats) -> f64);
+    let columns: [Column; 5] = [
+        (
+            "pg_statsinfo_device_read_bytes_total",
+            help::PG_STATSINFO_DEVICE_READ_BYTES_TOTAL,
+            |d| d.device_readsector as f64 * 512.0,
+        ),
+        (
+            "pg_statsinfo_device_written_bytes_total",
+            help::PG_STATSINFO_DEVICE_WRITTEN_BYTES_TOTAL,
+            |d| d.device_writesector as f64 * 512.0,
+        ),
+        (
+            "pg_statsinfo_device_read_time_seconds_total",
+            help::PG_STATSINFO_DEVICE_READ_TIME_SECONDS_TOTAL,
+            |d| d.device_readtime as f64 / 1000.0,
+        ),
+        (
+            "pg_statsinfo_device_write_time_seconds_total",
+            help::PG_STATSINFO_DEVICE_WRITE_TIME_SECONDS_TOTAL,
+            |d| d.device_writetime as f64 / 1000.0,
+        ),
+        (
+            "pg_statsinfo_device_io_time_seconds_total",
+            help::PG_STATSINFO_DEVICE_IO_TIME_SECONDS_TOTAL,
+            |d| d.device_iototaltime as f64 / 1000.0,
+        ),
+    ];
+    for (name, help, value) in columns {
+        let m = CounterVec::new(Opts::new(name, help), &["device"]).unwrap();
+        for stats in &rows {
+            m.with_label_values(&[&stats.device_name])
+                .inc_by(value(stats).max(0.0));
+        }
+        metrics.append(&mut m.collect());
+    }
+
+    let m = IntGaugeVec::new(
+        Opts::new(
+            "pg_statsinfo_device_io_queue",
+            help::PG_STATSINFO_DEVICE_IO_QUEUE,
+        ),
+        &["device"],
+    )
+    .unwrap();
+    for stats in &rows {
+        m.with_label_values(&[&stats.device_name])
+            .set(stats.device_ioqueue);
+    }
+    metrics.append(&mut m.collect());
+
+    let m = IntGaugeVec::new(
+        Opts::new(
+            "pg_statsinfo_device_overflows",
+            help::PG_STATSINFO_DEVICE_OVERFLOWS,
+        ),
+        &["device", "counter"],
+    )
+    .unwrap();
+    for stats in &rows {
+        for (counter, overflows) in [
+            ("read_sectors", stats.overflow_drs),
+            ("read_time", stats.overflow_drt),
+            ("write_sectors", stats.overflow_dws),
+            ("write_time", stats.overflow_dwt),
+            ("io_time", stats.overflow_dit),
+        ] {
+            m.with_label_values(&[&stats.device_name, counter])
+                .set(overflows as i64);
+        }
+    }
+    metrics.append(&mut m.collect());
+
+    Ok(metrics)
+}
+
+// TODO: Adds more methods for the other metrics of `pg_statsinfo`
+
+/// Returns true if the extension named `extname` is installed in the connected database.
+pub(crate) async fn has_extension(conn: &Client, extname: &str) -> Result<bool, Error> {
+    let row = conn
+        .query_one(
+            "SELECT EXISTS (SELECT 1 FROM pg_extension WHERE extname = $1)",
+            &[&extname],
+        )
+        .await?;
+    Ok(row.get(0))
+}
+
+/// Returns the schema `extname` is installed in, quoted as an identifier, if installed.
+async fn extension_schema(conn: &Client, extname: &str) -> Result<Option<String>, Error> {
+    let row = conn
+        .query_opt(
+            "
+        SELECT quote_ident(n.nspname)
+        FROM pg_extension AS e JOIN pg_namespace AS n ON n.oid = e.extnamespace
+        WHERE e.extname = $1
+    ",
+            &[&extname],
+        )
+        .await?;
+    Ok(row.map(|row| row.get(0)))
+}
+
+/// Returns `server_version_num` of the connected server, e.g. 150004 for PostgreSQL 15.4.
+async fn server_version_num(conn: &Client) -> Result<i32, Error> {
+    let row = conn
+        .query_one("SELECT current_setting('server_version_num')::int", &[])
+        .await?;
+    Ok(row.get(0))
+}
+
+/// Returns a key telling the connected server and database apart from the others the
+/// exporter collects from, e.g., with `/probe`. The start time of the postmaster keeps
+/// state from surviving a restart or a failover to a server at the same address.
+async fn server_key(conn: &Client) -> Result<String, Error> {
+    let row = conn
+        .query_one(
+            "
+        SELECT
+            concat_ws(
+                ' ',
+                inet_server_addr(),
+                inet_server_port(),
+                pg_postmaster_start_time(),
+                current_database()
+            )
+    ",
+            &[],
+        )
+        .await?;
+    Ok(row.get(0))
+}
+
+// A definithin of `pg_stat_statements_info` (PostgreSQL 14 or later) is as follows:
+//
+//  CREATE VIEW pg_stat_statements_info AS
+//    SELECT * FROM pg_stat_statements_info();
+//
+//  CREATE FUNCTION pg_stat_statements_info(
+//      OUT dealloc bigint,
+//      OUT stats_reset timestamp with time zone
+//  )
+//  RETURNS record
+//  AS 'MODULE_PATHNAME'
+//  LANGUAGE C STRICT VOLATILE PARALLEL SAFE;
+//
+// `dealloc` counts how many times the least-executed statements were evicted because
+// more distinct statements than `pg_stat_statements.max` were observed. A growing value
+// means the hash table is churning and top-N statement data is unreliable.
+//
+// https://github.com/postgres/postgres/blob/REL_15_STABLE/contrib/pg_stat_statements/pg_stat_statements--1.8--1.9.sql
+#[instrument(skip_all)]
+async fn get_pg_stat_statements_info(
+    conn: &Client,
+) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    // `pg_stat_statements` is optional and `pg_stat_statements_info` is only available
+    // in PostgreSQL 14 or later, so nothing is exported if either does not hold.
+    if server_version_num(conn).await? < 140000
+        || !has_extension(conn, "pg_stat_statements").await?
+    {
+        return Ok(metrics);
+    }
 
-    let row = conn.query(
+    from_row! {
+        struct Info {
+            dealloc: i64,
+            stats_reset_age: f64,
+        }
+    }
+
+    let info: Info = query_one_as(
+        conn,
         "
         SELECT
-            stats.name,
-            stats.location,
-            stats.avail,
-            stats.total
+            info.dealloc,
+            EXTRACT(EPOCH FROM now() - info.stats_reset)::float8 AS stats_reset_age
         FROM
-            statsinfo.tablespaces() AS stats
+            pg_stat_statements_info AS info
     ",
         &[],
-    )?;
+    )
+    .await?;
+
+    let m = IntCounter::new(
+        "pg_stat_statements_dealloc_total",
+        help::PG_STAT_STATEMENTS_DEALLOC_TOTAL,
+    )
+    .unwrap();
+    m.inc_by(info.dealloc as u64);
+    metrics.append(&mut m.collect());
+
+    let m = Gauge::new(
+        "pg_stat_statements_stats_reset_age_seconds",
+        help::PG_STAT_STATEMENTS_STATS_RESET_AGE_SECONDS,
+    )
+    .unwrap();
+    m.set(info.stats_reset_age);
+    metrics.append(&mut m.collect());
 
+    Ok(metrics)
+}
+
+// `stats_reset` columns hold the time the counters of a cumulative statistics view were
+// last reset. They are exported as ages so that `rate()` consumers can detect counter
+// resets and dashboards can annotate them. `pg_stat_statements` is covered by
+// `get_pg_stat_statements_info`.
+//
+// https://www.postgresql.org/docs/15/monitoring-stats.html#MONITORING-PG-STAT-DATABASE-VIEW
+// https://www.postgresql.org/docs/15/monitoring-stats.html#MONITORING-PG-STAT-BGWRITER-VIEW
+#[instrument(skip_all)]
+async fn get_stats_reset_ages(
+    conn: &Client,
+) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
     let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
 
-    let mut append_stat = |value: i64, stat_name: &str, help: &str| {
-        // TODO: Is it okay to create a new `IntGauge` on the fly?
-        let m = IntGauge::new(stat_name, help).unwrap();
-        m.set(value);
+    from_row! {
+        struct DatabaseStatsReset {
+            datname: String,
+            stats_reset_age: f64,
+        }
+    }
+
+    // `stats_reset` is NULL until the statistics of a database are reset for the first time
+    let databases: Vec<DatabaseStatsReset> = query_as(
+        conn,
+        "
+        SELECT
+            stats.datname,
+            EXTRACT(EPOCH FROM now() - stats.stats_reset)::float8 AS stats_reset_age
+        FROM
+            pg_stat_database AS stats
+        WHERE
+            stats.datname IS NOT NULL AND stats.stats_reset IS NOT NULL
+    ",
+        &[],
+    )
+    .await?;
+
+    let m = GaugeVec::new(
+        Opts::new(
+            "pg_stat_database_stats_reset_age_seconds",
+            help::PG_STAT_DATABASE_STATS_RESET_AGE_SECONDS,
+        ),
+        &["datname"],
+    )
+    .unwrap();
+    // `TextEncoder` refuses a family without samples, e.g., before any database is reset
+    if !databases.is_empty() {
+        for database in databases {
+            m.with_label_values(&[&database.datname])
+                .set(database.stats_reset_age);
+        }
         metrics.append(&mut m.collect());
-    };
+    }
+
+    let row = conn
+        .query_one(
+            "
+        SELECT
+            EXTRACT(EPOCH FROM now() - stats.stats_reset)::float8
+        FROM
+            pg_stat_bgwriter AS stats
+    ",
+            &[],
+        )
+        .await?;
 
-    for row in row.iter() {
-        let name: String = row.get(0);
-        let stat_prefix = format!("tablespaces_{}", name);
-        let location: String = row.get(1);
+    let stats_reset_age: Option<f64> = row.get(0);
+    if let Some(stats_reset_age) = stats_reset_age {
+        let m = Gauge::new(
+            "pg_stat_bgwriter_stats_reset_age_seconds",
+            help::PG_STAT_BGWRITER_STATS_RESET_AGE_SECONDS,
+        )
+        .unwrap();
+        m.set(stats_reset_age);
+        metrics.append(&mut m.collect());
+    }
 
-        // TODO: How do we push `row.get` inside `append_stat`?
-        append_stat(
-            row.get(2),
-            &format!("{}_avail", stat_prefix),
-            &format!("Available space in {}", location),
-        );
-        append_stat(
-            row.get(3),
-            &format!("{}_total", stat_prefix),
-            &format!("Total space in {}", location),
-        );
+    Ok(metrics)
+}
+
+// `n_tup_hot_upd` counts updates that did not need a new index entry (heap-only tuples).
+// A low ratio of HOT updates to all updates on an update-heavy table usually means
+// its fillfactor is too high to leave room on the page for new tuple versions.
+//
+// https://www.postgresql.org/docs/15/monitoring-stats.html#MONITORING-PG-STAT-ALL-TABLES-VIEW
+#[instrument(skip_all)]
+async fn get_hot_update_stats(
+    conn: &Client,
+) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    let rows = conn
+        .query(
+            "
+        SELECT
+            stats.schemaname::text,
+            stats.relname::text,
+            stats.n_tup_upd,
+            stats.n_tup_hot_upd
+        FROM
+            pg_stat_user_tables AS stats
+    ",
+            &[],
+        )
+        .await?;
+
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    let labels = ["schemaname", "relname"];
+    let n_tup_upd = IntCounterVec::new(
+        Opts::new(
+            "pg_stat_user_tables_n_tup_upd_total",
+            help::PG_STAT_USER_TABLES_N_TUP_UPD_TOTAL,
+        ),
+        &labels,
+    )
+    .unwrap();
+    let n_tup_hot_upd = IntCounterVec::new(
+        Opts::new(
+            "pg_stat_user_tables_n_tup_hot_upd_total",
+            help::PG_STAT_USER_TABLES_N_TUP_HOT_UPD_TOTAL,
+        ),
+        &labels,
+    )
+    .unwrap();
+    let hot_update_ratio = GaugeVec::new(
+        Opts::new(
+            "pg_stat_user_tables_hot_update_ratio",
+            help::PG_STAT_USER_TABLES_HOT_UPDATE_RATIO,
+        ),
+        &labels,
+    )
+    .unwrap();
+
+    for row in rows.iter() {
+        let schemaname: String = row.get(0);
+        let relname: String = row.get(1);
+        let label_values = [schemaname.as_str(), relname.as_str()];
+        let upd: i64 = row.get(2);
+        let hot_upd: i64 = row.get(3);
+
+        n_tup_upd
+            .with_label_values(&label_values)
+            .inc_by(upd as u64);
+        n_tup_hot_upd
+            .with_label_values(&label_values)
+            .inc_by(hot_upd as u64);
+        // The ratio is undefined for tables that have never been updated
+        if upd > 0 {
+            hot_update_ratio
+                .with_label_values(&label_values)
+                .set(hot_upd as f64 / upd as f64);
+        }
     }
 
+    metrics.append(&mut n_tup_upd.collect());
+    metrics.append(&mut n_tup_hot_upd.collect());
+    metrics.append(&mut hot_update_ratio.collect());
+
     Ok(metrics)
 }
 
-// TODO: Adds more methods for the other metrics of `pg_statsinfo`
+// Out-of-line values of a table are stored in its TOAST relation (`reltoastrelid`), whose
+// size and I/O are not included in the statistics of the main relation. Bloat there is
+// invisible unless TOAST relations are reported explicitly per parent table.
+//
+// https://www.postgresql.org/docs/15/storage-toast.html
+// https://www.postgresql.org/docs/15/monitoring-stats.html#MONITORING-PG-STATIO-ALL-TABLES-VIEW
+#[instrument(skip_all)]
+async fn get_toast_stats(conn: &Client) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    let rows = conn
+        .query(
+            "
+        SELECT
+            n.nspname::text,
+            c.relname::text,
+            pg_total_relation_size(c.reltoastrelid),
+            COALESCE(io.toast_blks_read, 0),
+            COALESCE(io.toast_blks_hit, 0),
+            COALESCE(toast.idx_scan, 0)
+        FROM
+            pg_class AS c
+            JOIN pg_namespace AS n ON n.oid = c.relnamespace
+            LEFT JOIN pg_statio_all_tables AS io ON io.relid = c.oid
+            LEFT JOIN pg_stat_all_tables AS toast ON toast.relid = c.reltoastrelid
+        WHERE
+            c.reltoastrelid <> 0
+            AND c.relkind IN ('r', 'm')
+            AND n.nspname NOT IN ('pg_catalog', 'information_schema')
+    ",
+            &[],
+        )
+        .await?;
 
-/// Gathers all Prometheus metrics via a PostgreSQL connection.
-pub fn gather(postgres: &PgConnectionConfig) -> Vec<prometheus::proto::MetricFamily> {
     let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
 
-    let mut conn = postgres
-        .connect_no_tls()
-        .unwrap_or_else(|_| panic!("Failed to connect to {}", postgres.raw_address()));
-    metrics.append(&mut get_cpustats(&mut conn).unwrap());
-    metrics.append(&mut get_tablespaces_stats(&mut conn).unwrap());
-    metrics
+    let labels = ["schemaname", "relname"];
+    let size = IntGaugeVec::new(
+        Opts::new("pg_toast_size_bytes", help::PG_TOAST_SIZE_BYTES),
+        &labels,
+    )
+    .unwrap();
+    let blks_read = IntCounterVec::new(
+        Opts::new("pg_toast_blks_read_total", help::PG_TOAST_BLKS_READ_TOTAL),
+        &labels,
+    )
+    .unwrap();
+    let blks_hit = IntCounterVec::new(
+        Opts::new("pg_toast_blks_hit_total", help::PG_TOAST_BLKS_HIT_TOTAL),
+        &labels,
+    )
+    .unwrap();
+    let idx_scan = IntCounterVec::new(
+        Opts::new("pg_toast_idx_scan_total", help::PG_TOAST_IDX_SCAN_TOTAL),
+        &labels,
+    )
+    .unwrap();
+
+    for row in rows.iter() {
+        // The size is NULL if the table was dropped after the catalogs were read
+        let Some(bytes) = row.get::<_, Option<i64>>(2) else {
+            continue;
+        };
+        let schemaname: String = row.get(0);
+        let relname: String = row.get(1);
+        let label_values = [schemaname.as_str(), relname.as_str()];
+
+        size.with_label_values(&label_values).set(bytes);
+        blks_read
+            .with_label_values(&label_values)
+            .inc_by(row.get::<_, i64>(3) as u64);
+        blks_hit
+            .with_label_values(&label_values)
+            .inc_by(row.get::<_, i64>(4) as u64);
+        idx_scan
+            .with_label_values(&label_values)
+            .inc_by(row.get::<_, i64>(5) as u64);
+    }
+
+    metrics.append(&mut size.collect());
+    metrics.append(&mut blks_read.collect());
+    metrics.append(&mut blks_hit.collect());
+    metrics.append(&mut idx_scan.collect());
+
+    Ok(metrics)
 }
 
-// TODO: Add tests for the functions in this file
+// Exports the number of roles per attribute and how long the password of each role stays
+// valid, so security posture can be monitored. `pg_roles` is used instead of `pg_authid`
+// because the latter is readable by superusers only.
+//
+// https://www.postgresql.org/docs/15/view-pg-roles.html
+#[instrument(skip_all)]
+async fn get_role_stats(conn: &Client) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    let row = conn
+        .query_one(
+            "
+        SELECT
+            count(*),
+            count(*) FILTER (WHERE roles.rolsuper),
+            count(*) FILTER (WHERE roles.rolcanlogin),
+            count(*) FILTER (WHERE roles.rolreplication),
+            count(*) FILTER (WHERE roles.rolbypassrls)
+        FROM
+            pg_roles AS roles
+    ",
+            &[],
+        )
+        .await?;
+
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    let m = IntGaugeVec::new(
+        Opts::new("pg_role_count", help::PG_ROLE_COUNT),
+        &["attribute"],
+    )
+    .unwrap();
+    for (i, attribute) in ["all", "superuser", "login", "replication", "bypassrls"]
+        .iter()
+        .enumerate()
+    {
+        m.with_label_values(&[attribute]).set(row.get(i));
+    }
+    metrics.append(&mut m.collect());
+
+    // Roles without `rolvaliduntil` or with 'infinity' have passwords that never expire
+    let rows = conn
+        .query(
+            "
+        SELECT
+            roles.rolname::text,
+            EXTRACT(EPOCH FROM roles.rolvaliduntil - now())::float8
+        FROM
+            pg_roles AS roles
+        WHERE
+            roles.rolvaliduntil IS NOT NULL AND roles.rolvaliduntil <> 'infinity'
+    ",
+            &[],
+        )
+        .await?;
+
+    let m = GaugeVec::new(
+        Opts::new(
+            "pg_role_password_expiry_seconds",
+            help::PG_ROLE_PASSWORD_EXPIRY_SECONDS,
+        ),
+        &["rolname"],
+    )
+    .unwrap();
+    // `TextEncoder` refuses a family without samples, e.g., if no password expires
+    if !rows.is_empty() {
+        for row in rows.iter() {
+            let rolname: String = row.get(0);
+            m.with_label_values(&[&rolname]).set(row.get(1));
+        }
+        metrics.append(&mut m.collect());
+    }
+
+    Ok(metrics)
+}
+
+/// Settings excluded from the `pg_settings` checksum because they change per session or
+/// transaction rather than by configuration.
+const VOLATILE_SETTINGS: &[&str] = &[
+    "application_name",
+    "in_hot_standby",
+    "is_superuser",
+    "role",
+    "session_authorization",
+    "transaction_deferrable",
+    "transaction_isolation",
+    "transaction_read_only",
+];
+
+// Exports a checksum of the effective server configuration as an info metric, so that
+// drifted settings between servers or unexpected changes over time are alertable, and the
+// number of settings changed in the configuration files but not applied until restart.
+//
+// https://www.postgresql.org/docs/15/view-pg-settings.html
+#[instrument(skip_all)]
+async fn get_settings_stats(conn: &Client) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    // Settings given by the client (e.g., `DateStyle`) or in the session are excluded too
+    let row = conn
+        .query_one(
+            "
+        SELECT
+            md5(
+                string_agg(settings.name || '=' || settings.setting, ',' ORDER BY settings.name)
+                    FILTER (
+                        WHERE NOT (settings.name = ANY($1))
+                            AND settings.source NOT IN ('client', 'session')
+                    )
+            ),
+            count(*) FILTER (WHERE settings.pending_restart)
+        FROM
+            pg_settings AS settings
+    ",
+            &[&VOLATILE_SETTINGS],
+        )
+        .await?;
+
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    let checksum: String = row.get(0);
+    let m = IntGaugeVec::new(
+        Opts::new("pg_settings_checksum_info", help::PG_SETTINGS_CHECKSUM_INFO),
+        &["checksum"],
+    )
+    .unwrap();
+    m.with_label_values(&[&checksum]).set(1);
+    metrics.append(&mut m.collect());
+
+    let m = IntGauge::new(
+        "pg_settings_pending_restart",
+        help::PG_SETTINGS_PENDING_RESTART,
+    )
+    .unwrap();
+    m.set(row.get(1));
+    metrics.append(&mut m.collect());
+
+    Ok(metrics)
+}
+
+// Exports the number of entries, the number of invalid lines and a checksum of the
+// currently loaded `pg_hba.conf` (and `pg_ident.conf` in PostgreSQL 15 or later), so that
+// unexpected edits and auth misconfiguration surface before a reload rejects clients.
+// The underlying functions are executable by superusers only unless granted explicitly,
+// so nothing is exported without the privilege. `hba_source` is the function to call for
+// `pg_hba.conf`, which is `pg_hba_file_rules()` or a security-definer helper wrapping it.
+//
+// https://www.postgresql.org/docs/15/view-pg-hba-file-rules.html
+// https://www.postgresql.org/docs/15/view-pg-ident-file-mappings.html
+#[instrument(skip_all)]
+async fn get_hba_file_stats(
+    conn: &Client,
+    hba_source: Option<&str>,
+) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    async fn file_stats(
+        conn: &Client,
+        view: &str,
+        source: &str,
+        help: [&'static str; 3],
+    ) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+        let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+        let row = conn
+            .query_one(
+                &format!(
+                    "
+                SELECT
+                    count(*) FILTER (WHERE rules.error IS NULL),
+                    count(*) FILTER (WHERE rules.error IS NOT NULL),
+                    md5(COALESCE(string_agg(rules::text, E'\\n' ORDER BY rules.line_number), ''))
+                FROM
+                    {} AS rules
+            ",
+                    source
+                ),
+                &[],
+            )
+            .await?;
+
+        let m = IntGauge::new(format!("{}_count", view), help[0]).unwrap();
+        m.set(row.get(0));
+        metrics.append(&mut m.collect());
+
+        let m = IntGauge::new(format!("{}_errors", view), help[1]).unwrap();
+        m.set(row.get(1));
+        metrics.append(&mut m.collect());
+
+        let checksum: String = row.get(2);
+        let m = IntGaugeVec::new(
+            Opts::new(format!("{}_checksum_info", view), help[2]),
+            &["checksum"],
+        )
+        .unwrap();
+        m.with_label_values(&[&checksum]).set(1);
+        metrics.append(&mut m.collect());
+
+        Ok(metrics)
+    }
+
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    if let Some(source) = hba_source {
+        let row = conn
+            .query_one("SELECT has_function_privilege($1, 'EXECUTE')", &[&source])
+            .await?;
+        if row.get(0) {
+            metrics.append(
+                &mut file_stats(
+                    conn,
+                    "pg_hba_file_rules",
+                    source,
+                    [
+                        help::PG_HBA_FILE_RULES_COUNT,
+                        help::PG_HBA_FILE_RULES_ERRORS,
+                        help::PG_HBA_FILE_RULES_CHECKSUM_INFO,
+                    ],
+                )
+                .await?,
+            );
+        }
+    }
+
+    if server_version_num(conn).await? >= 150000 {
+        let row = conn
+            .query_one(
+                "SELECT has_function_privilege('pg_ident_file_mappings()', 'EXECUTE')",
+                &[],
+            )
+            .await?;
+        if row.get(0) {
+            metrics.append(
+                &mut file_stats(
+                    conn,
+                    "pg_ident_file_mappings",
+                    "pg_ident_file_mappings()",
+                    [
+                        help::PG_IDENT_FILE_MAPPINGS_COUNT,
+                        help::PG_IDENT_FILE_MAPPINGS_ERRORS,
+                        help::PG_IDENT_FILE_MAPPINGS_CHECKSUM_INFO,
+                    ],
+                )
+                .await?,
+            );
+        }
+    }
+
+    Ok(metrics)
+}
+
+// Exports deadlocks and queries canceled by recovery conflicts on standbys per database.
+// Statements canceled by `statement_timeout` are only visible in the server log, so they
+// are counted by `log_tailer`.
+//
+// https://www.postgresql.org/docs/15/monitoring-stats.html#MONITORING-PG-STAT-DATABASE-VIEW
+// https://www.postgresql.org/docs/15/monitoring-stats.html#MONITORING-PG-STAT-DATABASE-CONFLICTS-VIEW
+#[instrument(skip_all)]
+async fn get_deadlock_and_conflict_stats(
+    conn: &Client,
+) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    let rows = conn
+        .query(
+            "
+        SELECT
+            db.datname::text,
+            db.deadlocks,
+            conflicts.confl_tablespace,
+            conflicts.confl_lock,
+            conflicts.confl_snapshot,
+            conflicts.confl_bufferpin,
+            conflicts.confl_deadlock
+        FROM
+            pg_stat_database AS db
+            JOIN pg_stat_database_conflicts AS conflicts ON conflicts.datid = db.datid
+        WHERE
+            db.datname IS NOT NULL
+    ",
+            &[],
+        )
+        .await?;
+
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    let deadlocks = IntCounterVec::new(
+        Opts::new(
+            "pg_stat_database_deadlocks_total",
+            help::PG_STAT_DATABASE_DEADLOCKS_TOTAL,
+        ),
+        &["datname"],
+    )
+    .unwrap();
+    let conflicts = IntCounterVec::new(
+        Opts::new(
+            "pg_stat_database_conflicts_total",
+            help::PG_STAT_DATABASE_CONFLICTS_TOTAL,
+        ),
+        &["datname", "reason"],
+    )
+    .unwrap();
+
+    for row in rows.iter() {
+        let datname: String = row.get(0);
+        deadlocks
+            .with_label_values(&[&datname])
+            .inc_by(row.get::<_, i64>(1) as u64);
+        for (i, reason) in ["tablespace", "lock", "snapshot", "bufferpin", "deadlock"]
+            .iter()
+            .enumerate()
+        {
+            conflicts
+                .with_label_values(&[&datname, reason])
+                .inc_by(row.get::<_, i64>(i + 2) as u64);
+        }
+    }
+
+    metrics.append(&mut deadlocks.collect());
+    metrics.append(&mut conflicts.collect());
+
+    Ok(metrics)
+}
+
+// `pg_stat_database` has one row per database with cumulative transaction, block I/O, tuple
+// and temporary file counters. Session statistics were added in PostgreSQL 14. Deadlocks
+// are exported by `get_deadlock_and_conflict_stats` along with the recovery conflicts.
+//
+// https://www.postgresql.org/docs/15/monitoring-stats.html#MONITORING-PG-STAT-DATABASE-VIEW
+#[instrument(skip_all)]
+async fn get_database_stats(conn: &Client) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    from_row! {
+        struct Database {
+            datname: String,
+            numbackends: i32,
+            xact_commit: i64,
+            xact_rollback: i64,
+            blks_read: i64,
+            blks_hit: i64,
+            tup_returned: i64,
+            tup_fetched: i64,
+            tup_inserted: i64,
+            tup_updated: i64,
+            tup_deleted: i64,
+            temp_files: i64,
+            temp_bytes: i64,
+            session_time: Option<f64>,
+            active_time: Option<f64>,
+            idle_in_transaction_time: Option<f64>,
+            sessions: Option<i64>,
+            sessions_abandoned: Option<i64>,
+            sessions_fatal: Option<i64>,
+            sessions_killed: Option<i64>,
+        }
+    }
+    // Metric name, help text and the value of a column, which is NULL if not supported
+    type Column<T> = (&'static str, &'static str, fn(&Database) -> Option<T>);
+
+    let session_columns = if server_version_num(conn).await? >= 140000 {
+        "
+            stats.session_time,
+            stats.active_time,
+            stats.idle_in_transaction_time,
+            stats.sessions,
+            stats.sessions_abandoned,
+            stats.sessions_fatal,
+            stats.sessions_killed
+        "
+    } else {
+        "
+            NULL::float8 AS session_time,
+            NULL::float8 AS active_time,
+            NULL::float8 AS idle_in_transaction_time,
+            NULL::bigint AS sessions,
+            NULL::bigint AS sessions_abandoned,
+            NULL::bigint AS sessions_fatal,
+            NULL::bigint AS sessions_killed
+        "
+    };
+    let databases: Vec<Database> = query_as(
+        conn,
+        &format!(
+            "
+            SELECT
+                stats.datname::text,
+                stats.numbackends,
+                stats.xact_commit,
+                stats.xact_rollback,
+                stats.blks_read,
+                stats.blks_hit,
+                stats.tup_returned,
+                stats.tup_fetched,
+                stats.tup_inserted,
+                stats.tup_updated,
+                stats.tup_deleted,
+                stats.temp_files,
+                stats.temp_bytes,
+                {session_columns}
+            FROM
+                pg_stat_database AS stats
+            WHERE
+                stats.datname IS NOT NULL
+        "
+        ),
+        &[],
+    )
+    .await?;
+
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    let numbackends = IntGaugeVec::new(
+        Opts::new(
+            "pg_stat_database_numbackends",
+            help::PG_STAT_DATABASE_NUMBACKENDS,
+        ),
+        &["datname"],
+    )
+    .unwrap();
+    for database in databases.iter() {
+        numbackends
+            .with_label_values(&[&database.datname])
+            .set(database.numbackends as i64);
+    }
+    metrics.append(&mut numbackends.collect());
+
+    let counters: [Column<i64>; 15] = [
+        (
+            "pg_stat_database_xact_commit_total",
+            help::PG_STAT_DATABASE_XACT_COMMIT_TOTAL,
+            |d| Some(d.xact_commit),
+        ),
+        (
+            "pg_stat_database_xact_rollback_total",
+            help::PG_STAT_DATABASE_XACT_ROLLBACK_TOTAL,
+            |d| Some(d.xact_rollback),
+        ),
+        (
+            "pg_stat_database_blks_read_total",
+            help::PG_STAT_DATABASE_BLKS_READ_TOTAL,
+            |d| Some(d.blks_read),
+        ),
+        (
+            "pg_stat_database_blks_hit_total",
+            help::PG_STAT_DATABASE_BLKS_HIT_TOTAL,
+            |d| Some(d.blks_hit),
+        ),
+        (
+            "pg_stat_database_tup_returned_total",
+            help::PG_STAT_DATABASE_TUP_RETURNED_TOTAL,
+            |d| Some(d.tup_returned),
+        ),
+        (
+            "pg_stat_database_tup_fetched_total",
+            help::PG_STAT_DATABASE_TUP_FETCHED_TOTAL,
+            |d| Some(d.tup_fetched),
+        ),
+        (
+            "pg_stat_database_tup_inserted_total",
+            help::PG_STAT_DATABASE_TUP_INSERTED_TOTAL,
+            |d| Some(d.tup_inserted),
+        ),
+        (
+            "pg_stat_database_tup_updated_total",
+            help::PG_STAT_DATABASE_TUP_UPDATED_TOTAL,
+            |d| Some(d.tup_updated),
+        ),
+        (
+            "pg_stat_database_tup_deleted_total",
+            help::PG_STAT_DATABASE_TUP_DELETED_TOTAL,
+            |d| Some(d.tup_deleted),
+        ),
+        (
+            "pg_stat_database_temp_files_total",
+            help::PG_STAT_DATABASE_TEMP_FILES_TOTAL,
+            |d| Some(d.temp_files),
+        ),
+        (
+            "pg_stat_database_temp_bytes_total",
+            help::PG_STAT_DATABASE_TEMP_BYTES_TOTAL,
+            |d| Some(d.temp_bytes),
+        ),
+        (
+            "pg_stat_database_sessions_total",
+            help::PG_STAT_DATABASE_SESSIONS_TOTAL,
+            |d| d.sessions,
+        ),
+        (
+            "pg_stat_database_sessions_abandoned_total",
+            help::PG_STAT_DATABASE_SESSIONS_ABANDONED_TOTAL,
+            |d| d.sessions_abandoned,
+        ),
+        (
+            "pg_stat_database_sessions_fatal_total",
+            help::PG_STAT_DATABASE_SESSIONS_FATAL_TOTAL,
+            |d| d.sessions_fatal,
+        ),
+        (
+            "pg_stat_database_sessions_killed_total",
+            help::PG_STAT_DATABASE_SESSIONS_KILLED_TOTAL,
+            |d| d.sessions_killed,
+        ),
+    ];
+    for (name, help, value) in counters {
+        let m = IntCounterVec::new(Opts::new(name, help), &["datname"]).unwrap();
+        for database in databases.iter() {
+            if let Some(value) = value(database) {
+                m.with_label_values(&[&database.datname])
+                    .inc_by(value as u64);
+            }
+        }
+        metrics.append(&mut m.collect());
+    }
+
+    // Session times are reported in milliseconds
+    let times: [Column<f64>; 3] = [
+        (
+            "pg_stat_database_session_time_seconds_total",
+            help::PG_STAT_DATABASE_SESSION_TIME_SECONDS_TOTAL,
+            |d| d.session_time,
+        ),
+        (
+            "pg_stat_database_active_time_seconds_total",
+            help::PG_STAT_DATABASE_ACTIVE_TIME_SECONDS_TOTAL,
+            |d| d.active_time,
+        ),
+        (
+            "pg_stat_database_idle_in_transaction_time_seconds_total",
+            help::PG_STAT_DATABASE_IDLE_IN_TRANSACTION_TIME_SECONDS_TOTAL,
+            |d| d.idle_in_transaction_time,
+        ),
+    ];
+    for (name, help, value) in times {
+        let m = CounterVec::new(Opts::new(name, help), &["datname"]).unwrap();
+        for database in databases.iter() {
+            if let Some(value) = value(database) {
+                m.with_label_values(&[&database.datname])
+                    .inc_by(value / 1000.0);
+            }
+        }
+        metrics.append(&mut m.collect());
+    }
+
+    Ok(metrics)
+}
+
+// PostgreSQL 17 moved the checkpoint statistics of `pg_stat_bgwriter` into
+// `pg_stat_checkpointer` and dropped `buffers_backend`, whose writes are now reported per
+// backend type in `pg_stat_io`. The metrics keep their names across versions so that
+// dashboards don't break on upgrade.
+//
+// https://www.postgresql.org/docs/16/monitoring-stats.html#MONITORING-PG-STAT-BGWRITER-VIEW
+// https://www.postgresql.org/docs/17/monitoring-stats.html#MONITORING-PG-STAT-CHECKPOINTER-VIEW
+#[instrument(skip_all)]
+async fn get_bgwriter_stats(conn: &Client) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    from_row! {
+        struct Bgwriter {
+            checkpoints_timed: i64,
+            checkpoints_req: i64,
+            checkpoint_write_time: f64,
+            buffers_checkpoint: i64,
+            buffers_clean: i64,
+            buffers_backend: Option<i64>,
+            maxwritten_clean: i64,
+        }
+    }
+
+    let query = if server_version_num(conn).await? >= 170000 {
+        "
+        SELECT
+            checkpointer.num_timed AS checkpoints_timed,
+            checkpointer.num_requested AS checkpoints_req,
+            checkpointer.write_time AS checkpoint_write_time,
+            checkpointer.buffers_written AS buffers_checkpoint,
+            bgwriter.buffers_clean,
+            NULL::bigint AS buffers_backend,
+            bgwriter.maxwritten_clean
+        FROM
+            pg_stat_checkpointer AS checkpointer,
+            pg_stat_bgwriter AS bgwriter
+    "
+    } else {
+        "
+        SELECT
+            bgwriter.checkpoints_timed,
+            bgwriter.checkpoints_req,
+            bgwriter.checkpoint_write_time,
+            bgwriter.buffers_checkpoint,
+            bgwriter.buffers_clean,
+            bgwriter.buffers_backend,
+            bgwriter.maxwritten_clean
+        FROM
+            pg_stat_bgwriter AS bgwriter
+    "
+    };
+    let stats: Bgwriter = query_one_as(conn, query, &[]).await?;
+
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    let counters = [
+        (
+            "pg_stat_bgwriter_checkpoints_timed_total",
+            help::PG_STAT_BGWRITER_CHECKPOINTS_TIMED_TOTAL,
+            Some(stats.checkpoints_timed),
+        ),
+        (
+            "pg_stat_bgwriter_checkpoints_req_total",
+            help::PG_STAT_BGWRITER_CHECKPOINTS_REQ_TOTAL,
+            Some(stats.checkpoints_req),
+        ),
+        (
+            "pg_stat_bgwriter_buffers_checkpoint_total",
+            help::PG_STAT_BGWRITER_BUFFERS_CHECKPOINT_TOTAL,
+            Some(stats.buffers_checkpoint),
+        ),
+        (
+            "pg_stat_bgwriter_buffers_clean_total",
+            help::PG_STAT_BGWRITER_BUFFERS_CLEAN_TOTAL,
+            Some(stats.buffers_clean),
+        ),
+        (
+            "pg_stat_bgwriter_buffers_backend_total",
+            help::PG_STAT_BGWRITER_BUFFERS_BACKEND_TOTAL,
+            stats.buffers_backend,
+        ),
+        (
+            "pg_stat_bgwriter_maxwritten_clean_total",
+            help::PG_STAT_BGWRITER_MAXWRITTEN_CLEAN_TOTAL,
+            Some(stats.maxwritten_clean),
+        ),
+    ];
+    for (name, help, value) in counters {
+        if let Some(value) = value {
+            let m = IntCounter::new(name, help).unwrap();
+            m.inc_by(value as u64);
+            metrics.append(&mut m.collect());
+        }
+    }
+
+    // `checkpoint_write_time` is reported in milliseconds
+    let m = Counter::new(
+        "pg_stat_bgwriter_checkpoint_write_time_seconds_total",
+        help::PG_STAT_BGWRITER_CHECKPOINT_WRITE_TIME_SECONDS_TOTAL,
+    )
+    .unwrap();
+    m.inc_by(stats.checkpoint_write_time / 1000.0);
+    metrics.append(&mut m.collect());
+
+    Ok(metrics)
+}
+
+/// Histogram buckets in seconds for ages of sessions and queries in `pg_stat_activity`.
+const ACTIVITY_AGE_BUCKETS: &[f64] = &[
+    1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0,
+];
+
+// Exports how long sessions have been idle in a transaction as a histogram, so SLOs like
+// "no transaction idle more than 5 minutes" can be expressed directly in PromQL. Such
+// sessions hold locks and prevent vacuum from removing dead tuples.
+//
+// https://www.postgresql.org/docs/15/monitoring-stats.html#MONITORING-PG-STAT-ACTIVITY-VIEW
+#[instrument(skip_all)]
+async fn get_idle_in_transaction_ages(
+    conn: &Client,
+) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    let rows = conn
+        .query(
+            "
+        SELECT
+            EXTRACT(EPOCH FROM now() - activity.state_change)::float8
+        FROM
+            pg_stat_activity AS activity
+        WHERE
+            activity.state IN ('idle in transaction', 'idle in transaction (aborted)')
+    ",
+            &[],
+        )
+        .await?;
+
+    let m = Histogram::with_opts(
+        HistogramOpts::new(
+            "pg_stat_activity_idle_in_transaction_age_seconds",
+            help::PG_STAT_ACTIVITY_IDLE_IN_TRANSACTION_AGE_SECONDS,
+        )
+        .buckets(ACTIVITY_AGE_BUCKETS.to_vec()),
+    )
+    .unwrap();
+    for row in rows.iter() {
+        m.observe(row.get(0));
+    }
+
+    Ok(m.collect())
+}
+
+// Counts client connections by state and wait event type in a single aggregate over
+// `pg_stat_activity`, which is cheap enough to run on every scrape. The ages of the longest
+// transaction and query point at the sessions holding back vacuum or hogging locks. The
+// transaction of the exporter itself is excluded, which is held for the whole scrape with
+// `--consistent-snapshot`.
+//
+// https://www.postgresql.org/docs/15/monitoring-stats.html#MONITORING-PG-STAT-ACTIVITY-VIEW
+#[instrument(skip_all)]
+async fn get_activity_stats(conn: &Client) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    from_row! {
+        struct Activity {
+            datname: String,
+            state: String,
+            wait_event_type: String,
+            connections: i64,
+            max_transaction_age: Option<f64>,
+            max_query_age: Option<f64>,
+        }
+    }
+
+    let groups: Vec<Activity> = query_as(
+        conn,
+        "
+        SELECT
+            COALESCE(activity.datname, '')::text AS datname,
+            COALESCE(activity.state, '')::text AS state,
+            COALESCE(activity.wait_event_type, '')::text AS wait_event_type,
+            count(*) AS connections,
+            max(EXTRACT(EPOCH FROM now() - activity.xact_start)::float8)
+                FILTER (WHERE activity.pid <> pg_backend_pid()) AS max_transaction_age,
+            max(EXTRACT(EPOCH FROM now() - activity.query_start)::float8)
+                FILTER (WHERE activity.state = 'active' AND activity.pid <> pg_backend_pid())
+                AS max_query_age
+        FROM
+            pg_stat_activity AS activity
+        WHERE
+            activity.backend_type = 'client backend'
+        GROUP BY
+            1, 2, 3
+    ",
+        &[],
+    )
+    .await?;
+
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    let connections = IntGaugeVec::new(
+        Opts::new(
+            "pg_stat_activity_connections",
+            help::PG_STAT_ACTIVITY_CONNECTIONS,
+        ),
+        &["datname", "state", "wait_event_type"],
+    )
+    .unwrap();
+    let mut max_transaction_ages: HashMap<&str, f64> = HashMap::new();
+    let mut max_query_ages: HashMap<&str, f64> = HashMap::new();
+    for group in groups.iter() {
+        connections
+            .with_label_values(&[&group.datname, &group.state, &group.wait_event_type])
+            .set(group.connections);
+        for (ages, age) in [
+            (&mut max_transaction_ages, group.max_transaction_age),
+            (&mut max_query_ages, group.max_query_age),
+        ] {
+            if let Some(age) = age {
+                let max = ages.entry(group.datname.as_str()).or_insert(age);
+                *max = max.max(age);
+            }
+        }
+    }
+    metrics.append(&mut connections.collect());
+
+    for (name, help, ages) in [
+        (
+            "pg_stat_activity_max_transaction_age_seconds",
+            help::PG_STAT_ACTIVITY_MAX_TRANSACTION_AGE_SECONDS,
+            max_transaction_ages,
+        ),
+        (
+            "pg_stat_activity_max_query_age_seconds",
+            help::PG_STAT_ACTIVITY_MAX_QUERY_AGE_SECONDS,
+            max_query_ages,
+        ),
+    ] {
+        let m = GaugeVec::new(Opts::new(name, help), &["datname"]).unwrap();
+        for (datname, age) in ages {
+            m.with_label_values(&[datname]).set(age);
+        }
+        metrics.append(&mut m.collect());
+    }
+
+    Ok(metrics)
+}
+
+/// Number of statements by total execution time to export the latency of.
+const TOP_STATEMENTS: i64 = 10;
+
+// Exports ages of currently running queries as a histogram and, when `pg_stat_statements`
+// is installed, the mean and standard deviation of the execution time of the top
+// statements by total execution time. Together they give a latency distribution view
+// without full statement logging.
+//
+// https://www.postgresql.org/docs/15/monitoring-stats.html#MONITORING-PG-STAT-ACTIVITY-VIEW
+// https://www.postgresql.org/docs/15/pgstatstatements.html
+#[instrument(skip_all)]
+async fn get_query_runtime_stats(
+    conn: &Client,
+) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    let rows = conn
+        .query(
+            "
+        SELECT
+            EXTRACT(EPOCH FROM now() - activity.query_start)::float8
+        FROM
+            pg_stat_activity AS activity
+        WHERE
+            activity.state = 'active'
+            AND activity.backend_type = 'client backend'
+            AND activity.pid <> pg_backend_pid()
+    ",
+            &[],
+        )
+        .await?;
+
+    let m = Histogram::with_opts(
+        HistogramOpts::new(
+            "pg_stat_activity_query_age_seconds",
+            help::PG_STAT_ACTIVITY_QUERY_AGE_SECONDS,
+        )
+        .buckets(ACTIVITY_AGE_BUCKETS.to_vec()),
+    )
+    .unwrap();
+    for row in rows.iter() {
+        m.observe(row.get(0));
+    }
+    metrics.append(&mut m.collect());
+
+    if !has_extension(conn, "pg_stat_statements").await? {
+        return Ok(metrics);
+    }
+
+    // The columns were renamed from `*_time` to `*_exec_time` in PostgreSQL 13
+    let (total, mean, stddev) = if server_version_num(conn).await? >= 130000 {
+        ("total_exec_time", "mean_exec_time", "stddev_exec_time")
+    } else {
+        ("total_time", "mean_time", "stddev_time")
+    };
+    let rows = conn
+        .query(
+            &format!(
+                "
+            SELECT
+                db.datname::text,
+                stats.queryid::text,
+                stats.{mean} / 1000.0,
+                stats.{stddev} / 1000.0
+            FROM
+                pg_stat_statements AS stats
+                JOIN pg_database AS db ON db.oid = stats.dbid
+            WHERE
+                stats.queryid IS NOT NULL
+            ORDER BY
+                stats.{total} DESC
+            LIMIT $1
+        "
+            ),
+            &[&TOP_STATEMENTS],
+        )
+        .await?;
+
+    let labels = ["datname", "queryid"];
+    let mean_exec_time = GaugeVec::new(
+        Opts::new(
+            "pg_stat_statements_mean_exec_time_seconds",
+            help::PG_STAT_STATEMENTS_MEAN_EXEC_TIME_SECONDS,
+        ),
+        &labels,
+    )
+    .unwrap();
+    let stddev_exec_time = GaugeVec::new(
+        Opts::new(
+            "pg_stat_statements_stddev_exec_time_seconds",
+            help::PG_STAT_STATEMENTS_STDDEV_EXEC_TIME_SECONDS,
+        ),
+        &labels,
+    )
+    .unwrap();
+    for row in rows.iter() {
+        let datname: String = row.get(0);
+        let queryid: String = row.get(1);
+        mean_exec_time
+            .with_label_values(&[&datname, &queryid])
+            .set(row.get(2));
+        stddev_exec_time
+            .with_label_values(&[&datname, &queryid])
+            .set(row.get(3));
+    }
+    metrics.append(&mut mean_exec_time.collect());
+    metrics.append(&mut stddev_exec_time.collect());
+
+    Ok(metrics)
+}
+
+/// Cumulative calls and execution time in seconds of the statements in `pg_stat_statements`
+/// by (datname, queryid), at the time they were sampled.
+struct StatementsSample {
+    taken_at: Instant,
+    counters: HashMap<(String, String), (i64, f64)>,
+}
+
+/// How long the sample of a server is kept without being replaced.
+const STATEMENTS_SAMPLE_TTL: Duration = Duration::from_secs(3600);
+
+/// The previous sample of each server, keyed by `server_key`, so that the servers don't mix
+/// up and a restart, which resets the counters, starts over.
+static STATEMENTS_SAMPLES: Lazy<Mutex<HashMap<String, StatementsSample>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Returns the calls and execution time per second between two samples of the statements
+/// executed in between. Statements whose counters went backwards, e.g., by
+/// `pg_stat_statements_reset()` or eviction, are skipped.
+fn statement_rates<'a>(
+    previous: &StatementsSample,
+    current: &'a StatementsSample,
+) -> Vec<(&'a (String, String), f64, f64)> {
+    let elapsed = current
+        .taken_at
+        .saturating_duration_since(previous.taken_at)
+        .as_secs_f64();
+    if elapsed == 0.0 {
+        return vec![];
+    }
+    current
+        .counters
+        .iter()
+        .filter_map(|(key, &(calls, exec_time))| {
+            // Statements first executed since the previous sample start from zero
+            let (prev_calls, prev_exec_time) =
+                previous.counters.get(key).copied().unwrap_or_default();
+            (calls > prev_calls && exec_time >= prev_exec_time).then(|| {
+                (
+                    key,
+                    (calls - prev_calls) as f64 / elapsed,
+                    (exec_time - prev_exec_time) / elapsed,
+                )
+            })
+        })
+        .collect()
+}
+
+// Exports the calls and execution time per second of each statement executed since the
+// previous scrape, computed from the previous sample of `pg_stat_statements` kept in the
+// exporter. Unlike the raw counters, only active statements are exported, which keeps the
+// cardinality low. Nothing is exported on the first scrape. Only the collections of the
+// configured server run this, since a probe of the same server in between would shrink
+// the window of the next scrape to the time since the probe.
+//
+// https://www.postgresql.org/docs/15/pgstatstatements.html
+#[instrument(skip_all)]
+async fn get_statements_delta_stats(
+    conn: &Client,
+) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    if !has_extension(conn, "pg_stat_statements").await? {
+        return Ok(vec![]);
+    }
+
+    let server = server_key(conn).await?;
+
+    let total = if server_version_num(conn).await? >= 130000 {
+        "total_exec_time"
+    } else {
+        "total_time"
+    };
+    // The same statement has an entry per user and nesting level
+    let rows = conn
+        .query(
+            &format!(
+                "
+            SELECT
+                db.datname::text,
+                stats.queryid::text,
+                sum(stats.calls)::int8,
+                sum(stats.{total})::float8 / 1000.0
+            FROM
+                pg_stat_statements AS stats
+                JOIN pg_database AS db ON db.oid = stats.dbid
+            WHERE
+                stats.queryid IS NOT NULL
+            GROUP BY
+                db.datname,
+                stats.queryid
+        "
+            ),
+            &[],
+        )
+        .await?;
+    let current = StatementsSample {
+        taken_at: Instant::now(),
+        counters: rows
+            .iter()
+            .map(|row| ((row.get(0), row.get(1)), (row.get(2), row.get(3))))
+            .collect(),
+    };
+
+    let labels = ["datname", "queryid"];
+    let calls = GaugeVec::new(
+        Opts::new(
+            "pg_stat_statements_calls_per_second",
+            help::PG_STAT_STATEMENTS_CALLS_PER_SECOND,
+        ),
+        &labels,
+    )
+    .unwrap();
+    let exec_time = GaugeVec::new(
+        Opts::new(
+            "pg_stat_statements_exec_time_seconds_per_second",
+            help::PG_STAT_STATEMENTS_EXEC_TIME_SECONDS_PER_SECOND,
+        ),
+        &labels,
+    )
+    .unwrap();
+
+    let mut samples = STATEMENTS_SAMPLES.lock().unwrap();
+    if let Some(previous) = samples.get(&server) {
+        for ((datname, queryid), calls_rate, exec_time_rate) in statement_rates(previous, &current)
+        {
+            calls.with_label_values(&[datname, queryid]).set(calls_rate);
+            exec_time
+                .with_label_values(&[datname, queryid])
+                .set(exec_time_rate);
+        }
+    }
+    samples.retain(|_, sample| sample.taken_at.elapsed() < STATEMENTS_SAMPLE_TTL);
+    samples.insert(server, current);
+    drop(samples);
+
+    let mut metrics = calls.collect();
+    metrics.append(&mut exec_time.collect());
+    Ok(metrics)
+}
+
+// Exports seconds since the last vacuum and analyze, whether manual or automatic, as the
+// maximum over all tables and, if `per_table` is set, for each table matching `tables`.
+// Tables never vacuumed or analyzed are not exported. Stale tables mean autovacuum
+// cannot keep up, and stale statistics lead to bad plans.
+//
+// https://www.postgresql.org/docs/15/monitoring-stats.html#MONITORING-PG-STAT-ALL-TABLES-VIEW
+#[instrument(skip_all)]
+async fn get_vacuum_recency_stats(
+    conn: &Client,
+    per_table: bool,
+    tables: Option<&str>,
+) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    // Let the server evaluate the regular expression to select tables
+    let rows = conn.query(
+        "
+        SELECT
+            stats.schemaname::text,
+            stats.relname::text,
+            EXTRACT(EPOCH FROM now() - GREATEST(stats.last_vacuum, stats.last_autovacuum))::float8,
+            EXTRACT(EPOCH FROM now() - GREATEST(stats.last_analyze, stats.last_autoanalyze))::float8,
+            $1::text IS NULL OR (stats.schemaname || '.' || stats.relname) ~ $1::text
+        FROM
+            pg_stat_user_tables AS stats
+    ",
+        &[&tables],
+    ).await?;
+
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    let labels = ["schemaname", "relname"];
+    let vacuum_age = GaugeVec::new(
+        Opts::new(
+            "pg_stat_user_tables_last_vacuum_age_seconds",
+            help::PG_STAT_USER_TABLES_LAST_VACUUM_AGE_SECONDS,
+        ),
+        &labels,
+    )
+    .unwrap();
+    let analyze_age = GaugeVec::new(
+        Opts::new(
+            "pg_stat_user_tables_last_analyze_age_seconds",
+            help::PG_STAT_USER_TABLES_LAST_ANALYZE_AGE_SECONDS,
+        ),
+        &labels,
+    )
+    .unwrap();
+    let mut max_vacuum_age: Option<f64> = None;
+    let mut max_analyze_age: Option<f64> = None;
+
+    for row in rows.iter() {
+        let schemaname: String = row.get(0);
+        let relname: String = row.get(1);
+        let vacuum: Option<f64> = row.get(2);
+        let analyze: Option<f64> = row.get(3);
+        let selected: bool = row.get(4);
+
+        if let Some(vacuum) = vacuum {
+            max_vacuum_age = Some(max_vacuum_age.map_or(vacuum, |m| m.max(vacuum)));
+            if per_table && selected {
+                vacuum_age
+                    .with_label_values(&[&schemaname, &relname])
+                    .set(vacuum);
+            }
+        }
+        if let Some(analyze) = analyze {
+            max_analyze_age = Some(max_analyze_age.map_or(analyze, |m| m.max(analyze)));
+            if per_table && selected {
+                analyze_age
+                    .with_label_values(&[&schemaname, &relname])
+                    .set(analyze);
+            }
+        }
+    }
+
+    metrics.append(&mut vacuum_age.collect());
+    metrics.append(&mut analyze_age.collect());
+
+    let mut append_max = |value: Option<f64>, name: &str, help: &str| {
+        if let Some(value) = value {
+            let m = Gauge::new(name, help).unwrap();
+            m.set(value);
+            metrics.append(&mut m.collect());
+        }
+    };
+    append_max(
+        max_vacuum_age,
+        "pg_stat_user_tables_max_last_vacuum_age_seconds",
+        help::PG_STAT_USER_TABLES_MAX_LAST_VACUUM_AGE_SECONDS,
+    );
+    append_max(
+        max_analyze_age,
+        "pg_stat_user_tables_max_last_analyze_age_seconds",
+        help::PG_STAT_USER_TABLES_MAX_LAST_ANALYZE_AGE_SECONDS,
+    );
+
+    Ok(metrics)
+}
+
+// Exports the number of foreign servers per foreign-data wrapper and the number of foreign
+// tables and user mappings per foreign server, for environments federating over FDW.
+//
+// Note that the connection cache of `postgres_fdw` (`postgres_fdw_get_connections()`)
+// only covers connections opened by the calling session, so it is not exported.
+//
+// https://www.postgresql.org/docs/15/catalog-pg-foreign-server.html
+// https://www.postgresql.org/docs/15/view-pg-user-mappings.html
+#[instrument(skip_all)]
+async fn get_foreign_data_stats(
+    conn: &Client,
+) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    // `pg_user_mappings` is used instead of `pg_user_mapping` readable by superusers only
+    let rows = conn
+        .query(
+            "
+        SELECT
+            fdw.fdwname::text,
+            srv.srvname::text,
+            (SELECT count(*) FROM pg_foreign_table AS ft WHERE ft.ftserver = srv.oid),
+            (SELECT count(*) FROM pg_user_mappings AS um WHERE um.srvid = srv.oid)
+        FROM
+            pg_foreign_server AS srv
+            JOIN pg_foreign_data_wrapper AS fdw ON fdw.oid = srv.srvfdw
+    ",
+            &[],
+        )
+        .await?;
+
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    let servers = IntGaugeVec::new(
+        Opts::new("pg_foreign_servers", help::PG_FOREIGN_SERVERS),
+        &["fdwname"],
+    )
+    .unwrap();
+    let tables = IntGaugeVec::new(
+        Opts::new("pg_foreign_tables", help::PG_FOREIGN_TABLES),
+        &["fdwname", "srvname"],
+    )
+    .unwrap();
+    let user_mappings = IntGaugeVec::new(
+        Opts::new("pg_user_mappings", help::PG_USER_MAPPINGS),
+        &["fdwname", "srvname"],
+    )
+    .unwrap();
+
+    for row in rows.iter() {
+        let fdwname: String = row.get(0);
+        let srvname: String = row.get(1);
+        servers.with_label_values(&[&fdwname]).inc();
+        tables
+            .with_label_values(&[&fdwname, &srvname])
+            .set(row.get(2));
+        user_mappings
+            .with_label_values(&[&fdwname, &srvname])
+            .set(row.get(3));
+    }
+
+    metrics.append(&mut servers.collect());
+    metrics.append(&mut tables.collect());
+    metrics.append(&mut user_mappings.collect());
+
+    Ok(metrics)
+}
+
+/// Metrics reused across scrapes with the time they were collected.
+type CachedMetrics = (Instant, Vec<prometheus::proto::MetricFamily>);
+
+/// The cached result of a server, locked while it's refreshed.
+type CachedMetricsEntry = Arc<tokio::sync::Mutex<Option<CachedMetrics>>>;
+
+/// The results of `get_largest_relations` by `server_key`. Sizing every relation is
+/// expensive on large clusters, so the result is refreshed on a slow interval. The entry of
+/// a server is locked while refreshing, so that concurrent scrapes of that server wait for
+/// the result instead of sizing again, without holding up the scrapes of other servers.
+static LARGEST_RELATIONS_CACHE: Lazy<Mutex<HashMap<String, CachedMetricsEntry>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+// Exports the `limit` largest tables and the `limit` largest indexes by on-disk size
+// (`pg_table_size`, which includes the TOAST table, free space map and visibility map,
+// but not indexes), so capacity dashboards show what is actually consuming space.
+//
+// https://www.postgresql.org/docs/15/functions-admin.html#FUNCTIONS-ADMIN-DBSIZE
+#[instrument(skip_all)]
+async fn get_largest_relations(
+    conn: &Client,
+    limit: i64,
+    interval: Duration,
+) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    let server = server_key(conn).await?;
+    let entry = {
+        let mut cache = LARGEST_RELATIONS_CACHE.lock().unwrap();
+        // Forget the servers not collected from any more, e.g., probed once or restarted,
+        // skipping the entries being refreshed
+        cache.retain(|key, entry| {
+            *key == server
+                || entry.try_lock().map_or(true, |cached| {
+                    cached
+                        .as_ref()
+                        .is_some_and(|(collected_at, _)| collected_at.elapsed() < interval)
+                })
+        });
+        cache.entry(server).or_default().clone()
+    };
+    let mut cached = entry.lock().await;
+    if let Some((collected_at, metrics)) = &*cached {
+        if collected_at.elapsed() < interval {
+            return Ok(metrics.clone());
+        }
+    }
+
+    let rows = conn
+        .query(
+            "
+        SELECT
+            ranked.schemaname,
+            ranked.relname,
+            ranked.relkind,
+            ranked.size
+        FROM (
+            SELECT
+                n.nspname::text AS schemaname,
+                c.relname::text AS relname,
+                CASE WHEN c.relkind = 'i' THEN 'index' ELSE 'table' END AS relkind,
+                pg_table_size(c.oid) AS size,
+                row_number() OVER (
+                    PARTITION BY c.relkind = 'i' ORDER BY pg_table_size(c.oid) DESC
+                ) AS rank
+            FROM
+                pg_class AS c
+                JOIN pg_namespace AS n ON n.oid = c.relnamespace
+            WHERE
+                c.relkind IN ('r', 'm', 'i')
+                AND n.nspname NOT IN ('pg_catalog', 'information_schema')
+                AND n.nspname !~ '^pg_toast'
+        ) AS ranked
+        WHERE
+            ranked.rank <= $1
+            -- The size is NULL if the relation was dropped after the catalogs were read
+            AND ranked.size IS NOT NULL
+    ",
+            &[&limit],
+        )
+        .await?;
+
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    let m = IntGaugeVec::new(
+        Opts::new(
+            "pg_largest_relation_size_bytes",
+            help::PG_LARGEST_RELATION_SIZE_BYTES,
+        ),
+        &["schemaname", "relname", "relkind"],
+    )
+    .unwrap();
+    for row in rows.iter() {
+        let schemaname: String = row.get(0);
+        let relname: String = row.get(1);
+        let relkind: String = row.get(2);
+        m.with_label_values(&[&schemaname, &relname, &relkind])
+            .set(row.get(3));
+    }
+    metrics.append(&mut m.collect());
+
+    *cached = Some((Instant::now(), metrics.clone()));
+    Ok(metrics)
+}
+
+// TimescaleDB 2.x exposes hypertables and background jobs in `timescaledb_information`,
+// and sizes and compression statistics through functions taking a hypertable.
+//
+// https://docs.timescale.com/api/latest/informational-views/
+#[instrument(skip_all)]
+async fn get_timescaledb_stats(
+    conn: &Client,
+) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    let Some(schema) = extension_schema(conn, "timescaledb").await? else {
+        return Ok(metrics);
+    };
+
+    let rows = conn
+        .query(
+            &format!(
+                "
+            SELECT
+                h.hypertable_schema::text,
+                h.hypertable_name::text,
+                h.num_chunks::bigint,
+                {schema}.hypertable_size(h.oid),
+                c.number_compressed_chunks::bigint,
+                c.before_compression_total_bytes,
+                c.after_compression_total_bytes
+            FROM (
+                SELECT
+                    *,
+                    format('%I.%I', hypertable_schema, hypertable_name)::regclass AS oid
+                FROM
+                    timescaledb_information.hypertables
+            ) AS h
+            LEFT JOIN LATERAL {schema}.hypertable_compression_stats(h.oid) AS c
+                ON h.compression_enabled
+        "
+            ),
+            &[],
+        )
+        .await?;
+
+    let labels = ["schemaname", "hypertable"];
+    let chunks = IntGaugeVec::new(
+        Opts::new(
+            "timescaledb_hypertable_chunks",
+            help::TIMESCALEDB_HYPERTABLE_CHUNKS,
+        ),
+        &labels,
+    )
+    .unwrap();
+    let size = IntGaugeVec::new(
+        Opts::new(
+            "timescaledb_hypertable_size_bytes",
+            help::TIMESCALEDB_HYPERTABLE_SIZE_BYTES,
+        ),
+        &labels,
+    )
+    .unwrap();
+    let compressed_chunks = IntGaugeVec::new(
+        Opts::new(
+            "timescaledb_hypertable_compressed_chunks",
+            help::TIMESCALEDB_HYPERTABLE_COMPRESSED_CHUNKS,
+        ),
+        &labels,
+    )
+    .unwrap();
+    let compression_ratio = GaugeVec::new(
+        Opts::new(
+            "timescaledb_hypertable_compression_ratio",
+            help::TIMESCALEDB_HYPERTABLE_COMPRESSION_RATIO,
+        ),
+        &labels,
+    )
+    .unwrap();
+
+    for row in rows.iter() {
+        let schemaname: String = row.get(0);
+        let hypertable: String = row.get(1);
+        let label_values = [schemaname.as_str(), hypertable.as_str()];
+        chunks
+            .with_label_values(&label_values)
+            .set(row.get::<_, Option<i64>>(2).unwrap_or_default());
+        size.with_label_values(&label_values)
+            .set(row.get::<_, Option<i64>>(3).unwrap_or_default());
+        if let Some(n) = row.get::<_, Option<i64>>(4) {
+            compressed_chunks.with_label_values(&label_values).set(n);
+        }
+        // The ratio is undefined until a chunk is compressed
+        if let (Some(before), Some(after)) =
+            (row.get::<_, Option<i64>>(5), row.get::<_, Option<i64>>(6))
+        {
+            if after > 0 {
+                compression_ratio
+                    .with_label_values(&label_values)
+                    .set(before as f64 / after as f64);
+            }
+        }
+    }
+
+    metrics.append(&mut chunks.collect());
+    metrics.append(&mut size.collect());
+    metrics.append(&mut compressed_chunks.collect());
+    metrics.append(&mut compression_ratio.collect());
+
+    let rows = conn
+        .query(
+            "
+        SELECT
+            j.job_id::text,
+            j.application_name::text,
+            s.total_runs,
+            s.total_failures,
+            EXTRACT(EPOCH FROM s.last_run_duration)::float8,
+            s.last_run_status = 'Success'
+        FROM
+            timescaledb_information.jobs AS j
+            JOIN timescaledb_information.job_stats AS s USING (job_id)
+    ",
+            &[],
+        )
+        .await?;
+
+    let labels = ["job_id", "application_name"];
+    let runs = IntCounterVec::new(
+        Opts::new(
+            "timescaledb_job_runs_total",
+            help::TIMESCALEDB_JOB_RUNS_TOTAL,
+        ),
+        &labels,
+    )
+    .unwrap();
+    let failures = IntCounterVec::new(
+        Opts::new(
+            "timescaledb_job_failures_total",
+            help::TIMESCALEDB_JOB_FAILURES_TOTAL,
+        ),
+        &labels,
+    )
+    .unwrap();
+    let last_run_duration = GaugeVec::new(
+        Opts::new(
+            "timescaledb_job_last_run_duration_seconds",
+            help::TIMESCALEDB_JOB_LAST_RUN_DURATION_SECONDS,
+        ),
+        &labels,
+    )
+    .unwrap();
+    let last_run_success = IntGaugeVec::new(
+        Opts::new(
+            "timescaledb_job_last_run_success",
+            help::TIMESCALEDB_JOB_LAST_RUN_SUCCESS,
+        ),
+        &labels,
+    )
+    .unwrap();
+
+    for row in rows.iter() {
+        let job_id: String = row.get(0);
+        let application_name: String = row.get(1);
+        let label_values = [job_id.as_str(), application_name.as_str()];
+        runs.with_label_values(&label_values)
+            .inc_by(row.get::<_, Option<i64>>(2).unwrap_or_default() as u64);
+        failures
+            .with_label_values(&label_values)
+            .inc_by(row.get::<_, Option<i64>>(3).unwrap_or_default() as u64);
+        // Jobs that have never run have no last run
+        if let Some(duration) = row.get::<_, Option<f64>>(4) {
+            last_run_duration
+                .with_label_values(&label_values)
+                .set(duration);
+        }
+        if let Some(success) = row.get::<_, Option<bool>>(5) {
+            last_run_success
+                .with_label_values(&label_values)
+                .set(success as i64);
+        }
+    }
+
+    metrics.append(&mut runs.collect());
+    metrics.append(&mut failures.collect());
+    metrics.append(&mut last_run_duration.collect());
+    metrics.append(&mut last_run_success.collect());
+
+    Ok(metrics)
+}
+
+// Citus keeps the nodes of the cluster in `pg_dist_node` and the shard placements in
+// `citus_shards` (Citus 10 or later), whose sizes are fetched from the workers.
+//
+// https://docs.citusdata.com/en/stable/develop/api_metadata.html
+#[instrument(skip_all)]
+async fn get_citus_stats(conn: &Client) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    if !has_extension(conn, "citus").await? {
+        return Ok(metrics);
+    }
+
+    let rows = conn
+        .query(
+            "
+        SELECT
+            node.nodename || ':' || node.nodeport,
+            node.noderole::text,
+            node.isactive
+        FROM
+            pg_dist_node AS node
+    ",
+            &[],
+        )
+        .await?;
+
+    let node_active = IntGaugeVec::new(
+        Opts::new("citus_node_active", help::CITUS_NODE_ACTIVE),
+        &["node", "role"],
+    )
+    .unwrap();
+    for row in rows.iter() {
+        let node: String = row.get(0);
+        let role: String = row.get(1);
+        node_active
+            .with_label_values(&[&node, &role])
+            .set(row.get::<_, bool>(2) as i64);
+    }
+    metrics.append(&mut node_active.collect());
+
+    let rows = conn
+        .query(
+            "
+        SELECT
+            shards.table_name::text,
+            shards.nodename || ':' || shards.nodeport,
+            count(*),
+            COALESCE(sum(shards.shard_size), 0)::bigint
+        FROM
+            citus_shards AS shards
+        GROUP BY
+            1, 2
+    ",
+            &[],
+        )
+        .await?;
+
+    let labels = ["table", "node"];
+    let shards = IntGaugeVec::new(
+        Opts::new("citus_table_shards", help::CITUS_TABLE_SHARDS),
+        &labels,
+    )
+    .unwrap();
+    let shard_size = IntGaugeVec::new(
+        Opts::new(
+            "citus_table_shard_size_bytes",
+            help::CITUS_TABLE_SHARD_SIZE_BYTES,
+        ),
+        &labels,
+    )
+    .unwrap();
+    for row in rows.iter() {
+        let table: String = row.get(0);
+        let node: String = row.get(1);
+        let label_values = [table.as_str(), node.as_str()];
+        shards.with_label_values(&label_values).set(row.get(2));
+        shard_size.with_label_values(&label_values).set(row.get(3));
+    }
+    metrics.append(&mut shards.collect());
+    metrics.append(&mut shard_size.collect());
+
+    // `progress` is 0 for the moves waiting, 1 for the one running, and 2 for the ones done
+    let rows = conn
+        .query(
+            "
+        SELECT
+            moves.progress::int,
+            count(*)
+        FROM
+            get_rebalance_progress() AS moves
+        GROUP BY
+            1
+    ",
+            &[],
+        )
+        .await?;
+
+    let rebalance_moves = IntGaugeVec::new(
+        Opts::new(
+            "citus_rebalance_shard_moves",
+            help::CITUS_REBALANCE_SHARD_MOVES,
+        ),
+        &["state"],
+    )
+    .unwrap();
+    // Export zeros when no rebalance is running so that alerts can tell it from no data
+    for state in ["waiting", "moving", "done"] {
+        rebalance_moves.with_label_values(&[state]).set(0);
+    }
+    for row in rows.iter() {
+        let state = match row.get::<_, i32>(0) {
+            0 => "waiting",
+            1 => "moving",
+            _ => "done",
+        };
+        rebalance_moves
+            .with_label_values(&[state])
+            .add(row.get::<_, i64>(1));
+    }
+    metrics.append(&mut rebalance_moves.collect());
+
+    Ok(metrics)
+}
+
+// pgvector adds the `hnsw` and `ivfflat` index access methods. Their build parameters are
+// reloptions, which are omitted from `pg_class` when the defaults are used.
+//
+// https://github.com/pgvector/pgvector#indexing
+#[instrument(skip_all)]
+async fn get_pgvector_index_stats(
+    conn: &Client,
+) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    if !has_extension(conn, "vector").await? {
+        return Ok(metrics);
+    }
+
+    let rows = conn.query(
+        "
+        SELECT
+            n.nspname::text,
+            c.relname::text,
+            t.relname::text,
+            am.amname::text,
+            opc.opcname::text,
+            pg_relation_size(c.oid),
+            COALESCE(
+                (SELECT option_value FROM pg_options_to_table(c.reloptions) WHERE option_name = 'm'),
+                CASE WHEN am.amname = 'hnsw' THEN '16' ELSE '' END
+            ),
+            COALESCE(
+                (SELECT option_value FROM pg_options_to_table(c.reloptions) WHERE option_name = 'ef_construction'),
+                CASE WHEN am.amname = 'hnsw' THEN '64' ELSE '' END
+            ),
+            COALESCE(
+                (SELECT option_value FROM pg_options_to_table(c.reloptions) WHERE option_name = 'lists'),
+                CASE WHEN am.amname = 'ivfflat' THEN '100' ELSE '' END
+            )
+        FROM
+            pg_class AS c
+            JOIN pg_am AS am ON am.oid = c.relam
+            JOIN pg_namespace AS n ON n.oid = c.relnamespace
+            JOIN pg_index AS i ON i.indexrelid = c.oid
+            JOIN pg_class AS t ON t.oid = i.indrelid
+            JOIN pg_opclass AS opc ON opc.oid = i.indclass[0]
+        WHERE
+            am.amname IN ('hnsw', 'ivfflat')
+    ",
+        &[],
+    ).await?;
+
+    let indexes = IntGaugeVec::new(
+        Opts::new("pgvector_indexes", help::PGVECTOR_INDEXES),
+        &["method"],
+    )
+    .unwrap();
+    let size = IntGaugeVec::new(
+        Opts::new("pgvector_index_size_bytes", help::PGVECTOR_INDEX_SIZE_BYTES),
+        &["schemaname", "indexname", "method"],
+    )
+    .unwrap();
+    let info = IntGaugeVec::new(
+        Opts::new("pgvector_index_info", help::PGVECTOR_INDEX_INFO),
+        &[
+            "schemaname",
+            "indexname",
+            "tablename",
+            "method",
+            "opclass",
+            "m",
+            "ef_construction",
+            "lists",
+        ],
+    )
+    .unwrap();
+
+    // Export zeros for both methods so that the counts are never absent
+    for method in ["hnsw", "ivfflat"] {
+        indexes.with_label_values(&[method]).set(0);
+    }
+    for row in rows.iter() {
+        let schemaname: String = row.get(0);
+        let indexname: String = row.get(1);
+        let tablename: String = row.get(2);
+        let method: String = row.get(3);
+        let opclass: String = row.get(4);
+        let m: String = row.get(6);
+        let ef_construction: String = row.get(7);
+        let lists: String = row.get(8);
+        indexes.with_label_values(&[&method]).inc();
+        size.with_label_values(&[&schemaname, &indexname, &method])
+            .set(row.get(5));
+        info.with_label_values(&[
+            &schemaname,
+            &indexname,
+            &tablename,
+            &method,
+            &opclass,
+            &m,
+            &ef_construction,
+            &lists,
+        ])
+        .set(1);
+    }
+
+    metrics.append(&mut indexes.collect());
+    metrics.append(&mut size.collect());
+    metrics.append(&mut info.collect());
+
+    Ok(metrics)
+}
+
+/// Components reported by `postgis_full_version()`, exported as labels of `postgis_info`.
+const POSTGIS_COMPONENTS: &[&str] = &["POSTGIS", "PGSQL", "GEOS", "PROJ", "GDAL", "LIBXML"];
+
+/// Parses the output of `postgis_full_version()`, e.g.,
+/// `POSTGIS="3.4.0 0874ea3" [EXTENSION] PGSQL="150" GEOS="3.12.0-CAPI-1.18.0" PROJ="9.3.0"`,
+/// into the version of every component in `POSTGIS_COMPONENTS`, empty if not reported.
+fn parse_postgis_full_version(s: &str) -> Vec<String> {
+    POSTGIS_COMPONENTS
+        .iter()
+        .map(|component| {
+            let key = format!("{component}=\"");
+            // Match whole keys only, e.g., not `PROJ` in `LIBPROJ`
+            let value = s
+                .match_indices(&key)
+                .find(|(i, _)| *i == 0 || s.as_bytes()[i - 1] == b' ')
+                .and_then(|(i, _)| s[i + key.len()..].split('"').next());
+            // Values may carry details after the version, e.g., the commit of PostGIS
+            value
+                .and_then(|v| v.split_whitespace().next())
+                .unwrap_or_default()
+                .to_string()
+        })
+        .collect()
+}
+
+#[instrument(skip_all)]
+async fn get_postgis_stats(conn: &Client) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    let Some(schema) = extension_schema(conn, "postgis").await? else {
+        return Ok(metrics);
+    };
+
+    let row = conn
+        .query_one(
+            &format!(
+                "
+            SELECT
+                {schema}.postgis_full_version(),
+                (SELECT count(*) FROM {schema}.geometry_columns),
+                (SELECT count(*) FROM {schema}.geography_columns),
+                (
+                    SELECT
+                        count(DISTINCT i.indexrelid)
+                    FROM
+                        pg_index AS i
+                        JOIN pg_class AS c ON c.oid = i.indexrelid
+                        JOIN pg_am AS am ON am.oid = c.relam
+                        JOIN pg_attribute AS a
+                            ON a.attrelid = i.indrelid AND a.attnum = ANY(i.indkey)
+                        JOIN pg_type AS t ON t.oid = a.atttypid
+                    WHERE
+                        t.typname IN ('geometry', 'geography')
+                        AND am.amname IN ('gist', 'spgist', 'brin')
+                )
+        "
+            ),
+            &[],
+        )
+        .await?;
+
+    let labels: Vec<String> = POSTGIS_COMPONENTS
+        .iter()
+        .map(|c| c.to_lowercase())
+        .collect();
+    let labels: Vec<&str> = labels.iter().map(|s| s.as_str()).collect();
+    let info = IntGaugeVec::new(Opts::new("postgis_info", help::POSTGIS_INFO), &labels).unwrap();
+    let versions = parse_postgis_full_version(&row.get::<_, String>(0));
+    let versions: Vec<&str> = versions.iter().map(|s| s.as_str()).collect();
+    info.with_label_values(&versions).set(1);
+    metrics.append(&mut info.collect());
+
+    let columns = IntGaugeVec::new(
+        Opts::new("postgis_columns", help::POSTGIS_COLUMNS),
+        &["type"],
+    )
+    .unwrap();
+    columns.with_label_values(&["geometry"]).set(row.get(1));
+    columns.with_label_values(&["geography"]).set(row.get(2));
+    metrics.append(&mut columns.collect());
+
+    let indexes = IntGauge::new("postgis_spatial_indexes", help::POSTGIS_SPATIAL_INDEXES).unwrap();
+    indexes.set(row.get(3));
+    metrics.append(&mut indexes.collect());
+
+    Ok(metrics)
+}
+
+// pg_partman keeps the partition sets it maintains in `part_config`, whose
+// `maintenance_last_run` is set by `run_maintenance()` since 4.5. The bounds of the child
+// tables come from `show_partition_info()`, which are timestamps for time-based sets only.
+//
+// https://github.com/pgpartman/pg_partman/blob/master/doc/pg_partman.md
+#[instrument(skip_all)]
+async fn get_pg_partman_stats(
+    conn: &Client,
+) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    let Some(schema) = extension_schema(conn, "pg_partman").await? else {
+        return Ok(metrics);
+    };
+
+    let rows = conn
+        .query(
+            &format!(
+                "
+            SELECT
+                cfg.parent_table::text,
+                EXTRACT(EPOCH FROM now() - cfg.maintenance_last_run)::float8,
+                count(*) FILTER (WHERE info.child_start_time > now()),
+                EXTRACT(EPOCH FROM max(info.child_end_time) - now())::float8
+            FROM
+                {schema}.part_config AS cfg
+                LEFT JOIN LATERAL {schema}.show_partitions(cfg.parent_table) AS part ON true
+                LEFT JOIN LATERAL {schema}.show_partition_info(
+                    part.partition_schemaname || '.' || part.partition_tablename,
+                    cfg.partition_interval,
+                    cfg.parent_table
+                ) AS info ON true
+            GROUP BY
+                cfg.parent_table, cfg.maintenance_last_run
+        "
+            ),
+            &[],
+        )
+        .await?;
+
+    let labels = ["parent_table"];
+    let last_run_age = GaugeVec::new(
+        Opts::new(
+            "pg_partman_maintenance_last_run_age_seconds",
+            help::PG_PARTMAN_MAINTENANCE_LAST_RUN_AGE_SECONDS,
+        ),
+        &labels,
+    )
+    .unwrap();
+    let premade = IntGaugeVec::new(
+        Opts::new(
+            "pg_partman_premade_partitions",
+            help::PG_PARTMAN_PREMADE_PARTITIONS,
+        ),
+        &labels,
+    )
+    .unwrap();
+    let remaining = GaugeVec::new(
+        Opts::new(
+            "pg_partman_newest_partition_remaining_seconds",
+            help::PG_PARTMAN_NEWEST_PARTITION_REMAINING_SECONDS,
+        ),
+        &labels,
+    )
+    .unwrap();
+
+    for row in rows.iter() {
+        let parent_table: String = row.get(0);
+        if let Some(age) = row.get::<_, Option<f64>>(1) {
+            last_run_age.with_label_values(&[&parent_table]).set(age);
+        }
+        // Only time-based sets have end times, and premade counts are meaningless otherwise
+        if let Some(secs) = row.get::<_, Option<f64>>(3) {
+            premade.with_label_values(&[&parent_table]).set(row.get(2));
+            remaining.with_label_values(&[&parent_table]).set(secs);
+        }
+    }
+
+    metrics.append(&mut last_run_age.collect());
+    metrics.append(&mut premade.collect());
+    metrics.append(&mut remaining.collect());
+
+    Ok(metrics)
+}
+
+// pg_cron records every run of the jobs in `cron.job` in `cron.job_run_details`, with the
+// status `succeeded` or `failed` once finished.
+//
+// https://github.com/citusdata/pg_cron#viewing-job-run-details
+#[instrument(skip_all)]
+async fn get_pg_cron_stats(conn: &Client) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    if !has_extension(conn, "pg_cron").await? {
+        return Ok(metrics);
+    }
+
+    // `job_run_details` has no index on `jobid`, so it is scanned once per aggregate
+    // instead of once per job
+    let rows = conn
+        .query(
+            "
+        WITH last_run AS (
+            SELECT DISTINCT ON (jobid)
+                jobid, status, start_time, end_time
+            FROM
+                cron.job_run_details
+            ORDER BY
+                jobid, runid DESC
+        ), last_success AS (
+            SELECT
+                jobid, max(end_time) AS end_time
+            FROM
+                cron.job_run_details
+            WHERE
+                status = 'succeeded'
+            GROUP BY
+                jobid
+        )
+        SELECT
+            job.jobid::text,
+            COALESCE(job.jobname, '')::text,
+            job.active,
+            last_run.status,
+            EXTRACT(EPOCH FROM last_run.end_time - last_run.start_time)::float8,
+            EXTRACT(EPOCH FROM now() - last_success.end_time)::float8
+        FROM
+            cron.job AS job
+            LEFT JOIN last_run USING (jobid)
+            LEFT JOIN last_success USING (jobid)
+    ",
+            &[],
+        )
+        .await?;
+
+    let labels = ["jobid", "jobname"];
+    let active = IntGaugeVec::new(
+        Opts::new("pg_cron_job_active", help::PG_CRON_JOB_ACTIVE),
+        &labels,
+    )
+    .unwrap();
+    let last_run_success = IntGaugeVec::new(
+        Opts::new(
+            "pg_cron_job_last_run_success",
+            help::PG_CRON_JOB_LAST_RUN_SUCCESS,
+        ),
+        &labels,
+    )
+    .unwrap();
+    let last_run_duration = GaugeVec::new(
+        Opts::new(
+            "pg_cron_job_last_run_duration_seconds",
+            help::PG_CRON_JOB_LAST_RUN_DURATION_SECONDS,
+        ),
+        &labels,
+    )
+    .unwrap();
+    let last_success_age = GaugeVec::new(
+        Opts::new(
+            "pg_cron_job_last_success_age_seconds",
+            help::PG_CRON_JOB_LAST_SUCCESS_AGE_SECONDS,
+        ),
+        &labels,
+    )
+    .unwrap();
+
+    for row in rows.iter() {
+        let jobid: String = row.get(0);
+        let jobname: String = row.get(1);
+        let label_values = [jobid.as_str(), jobname.as_str()];
+        active
+            .with_label_values(&label_values)
+            .set(row.get::<_, bool>(2) as i64);
+        // Runs in progress have no outcome yet
+        if let Some(status @ ("succeeded" | "failed")) = row.get::<_, Option<&str>>(3) {
+            last_run_success
+                .with_label_values(&label_values)
+                .set((status == "succeeded") as i64);
+            if let Some(duration) = row.get::<_, Option<f64>>(4) {
+                last_run_duration
+                    .with_label_values(&label_values)
+                    .set(duration);
+            }
+        }
+        if let Some(age) = row.get::<_, Option<f64>>(5) {
+            last_success_age.with_label_values(&label_values).set(age);
+        }
+    }
+
+    metrics.append(&mut active.collect());
+    metrics.append(&mut last_run_success.collect());
+    metrics.append(&mut last_run_duration.collect());
+    metrics.append(&mut last_success_age.collect());
+
+    Ok(metrics)
+}
+
+// CDC consumers such as Debezium acknowledge decoded changes by advancing the
+// `confirmed_flush_lsn` of their logical slot, so the WAL written since is the lag of the
+// consumer. `pg_stat_replication_slots`, available in PostgreSQL 14 or later, counts the
+// transactions spilled to disk when decoding exceeds `logical_decoding_work_mem`.
+//
+// See https://www.postgresql.org/docs/15/monitoring-stats.html#MONITORING-PG-STAT-REPLICATION-SLOTS-VIEW
+#[instrument(skip_all)]
+async fn get_logical_slot_stats(
+    conn: &Client,
+    slots: Option<&[String]>,
+) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    let (spill_columns, stats_join) = if server_version_num(conn).await? >= 140000 {
+        (
+            "stats.spill_txns, stats.spill_count, stats.spill_bytes",
+            "LEFT JOIN pg_stat_replication_slots AS stats USING (slot_name)",
+        )
+    } else {
+        ("NULL::bigint, NULL::bigint, NULL::bigint", "")
+    };
+    // WAL is received rather than written on a standby, which can have logical slots since
+    // PostgreSQL 16
+    let rows = conn
+        .query(
+            &format!(
+                "
+            SELECT
+                slots.slot_name::text,
+                COALESCE(slots.plugin, '')::text,
+                COALESCE(slots.database, '')::text,
+                slots.active,
+                pg_wal_lsn_diff(
+                    CASE WHEN pg_is_in_recovery()
+                        THEN pg_last_wal_receive_lsn()
+                        ELSE pg_current_wal_lsn()
+                    END,
+                    slots.confirmed_flush_lsn
+                )::float8,
+                {spill_columns}
+            FROM
+                pg_replication_slots AS slots
+                {stats_join}
+            WHERE
+                slots.slot_type = 'logical'
+                AND ($1::text[] IS NULL OR slots.slot_name = ANY($1::text[]))
+        "
+            ),
+            &[&slots],
+        )
+        .await?;
+
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    let labels = ["slot_name", "plugin", "database"];
+    let active = IntGaugeVec::new(
+        Opts::new(
+            "pg_replication_slots_logical_active",
+            help::PG_REPLICATION_SLOTS_LOGICAL_ACTIVE,
+        ),
+        &labels,
+    )
+    .unwrap();
+    let lag = GaugeVec::new(
+        Opts::new(
+            "pg_replication_slots_confirmed_flush_lag_bytes",
+            help::PG_REPLICATION_SLOTS_CONFIRMED_FLUSH_LAG_BYTES,
+        ),
+        &labels,
+    )
+    .unwrap();
+    let spill_txns = IntCounterVec::new(
+        Opts::new(
+            "pg_stat_replication_slots_spill_txns_total",
+            help::PG_STAT_REPLICATION_SLOTS_SPILL_TXNS_TOTAL,
+        ),
+        &labels,
+    )
+    .unwrap();
+    let spill_count = IntCounterVec::new(
+        Opts::new(
+            "pg_stat_replication_slots_spill_count_total",
+            help::PG_STAT_REPLICATION_SLOTS_SPILL_COUNT_TOTAL,
+        ),
+        &labels,
+    )
+    .unwrap();
+    let spill_bytes = IntCounterVec::new(
+        Opts::new(
+            "pg_stat_replication_slots_spill_bytes_total",
+            help::PG_STAT_REPLICATION_SLOTS_SPILL_BYTES_TOTAL,
+        ),
+        &labels,
+    )
+    .unwrap();
+
+    for row in rows.iter() {
+        let slot_name: String = row.get(0);
+        let plugin: String = row.get(1);
+        let database: String = row.get(2);
+        let label_values = [slot_name.as_str(), plugin.as_str(), database.as_str()];
+        active
+            .with_label_values(&label_values)
+            .set(row.get::<_, bool>(3) as i64);
+        // A slot that has never been consumed has no confirmed position
+        if let Some(bytes) = row.get::<_, Option<f64>>(4) {
+            lag.with_label_values(&label_values).set(bytes);
+        }
+        for (i, m) in [(5, &spill_txns), (6, &spill_count), (7, &spill_bytes)] {
+            if let Some(value) = row.get::<_, Option<i64>>(i) {
+                m.with_label_values(&label_values).inc_by(value as u64);
+            }
+        }
+    }
+
+    metrics.append(&mut active.collect());
+    metrics.append(&mut lag.collect());
+    metrics.append(&mut spill_txns.collect());
+    metrics.append(&mut spill_count.collect());
+    metrics.append(&mut spill_bytes.collect());
+
+    Ok(metrics)
+}
+
+// `pg_stat_replication` has one row per WAL sender, i.e., per streaming standby or
+// logical replication subscriber connected to this server. The lag of each position is the
+// WAL this server has written, or received on a cascading standby, since the position.
+//
+// https://www.postgresql.org/docs/15/monitoring-stats.html#MONITORING-PG-STAT-REPLICATION-VIEW
+#[instrument(skip_all)]
+async fn get_replication_stats(
+    conn: &Client,
+) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    from_row! {
+        struct Replication {
+            application_name: String,
+            client_addr: String,
+            sync_state: String,
+            sent_lag: Option<f64>,
+            write_lag: Option<f64>,
+            flush_lag: Option<f64>,
+            replay_lag: Option<f64>,
+        }
+    }
+
+    let replicas: Vec<Replication> = query_as(
+        conn,
+        "
+        WITH current AS (
+            SELECT
+                CASE WHEN pg_is_in_recovery()
+                    THEN pg_last_wal_receive_lsn()
+                    ELSE pg_current_wal_lsn()
+                END AS lsn
+        )
+        SELECT
+            stats.application_name::text,
+            COALESCE(host(stats.client_addr), 'local') AS client_addr,
+            COALESCE(stats.sync_state, '')::text AS sync_state,
+            pg_wal_lsn_diff(current.lsn, stats.sent_lsn)::float8 AS sent_lag,
+            pg_wal_lsn_diff(current.lsn, stats.write_lsn)::float8 AS write_lag,
+            pg_wal_lsn_diff(current.lsn, stats.flush_lsn)::float8 AS flush_lag,
+            pg_wal_lsn_diff(current.lsn, stats.replay_lsn)::float8 AS replay_lag
+        FROM
+            pg_stat_replication AS stats,
+            current
+    ",
+        &[],
+    )
+    .await?;
+
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    let lag = GaugeVec::new(
+        Opts::new(
+            "pg_stat_replication_lag_bytes",
+            help::PG_STAT_REPLICATION_LAG_BYTES,
+        ),
+        &["application_name", "client_addr", "lsn"],
+    )
+    .unwrap();
+    let sync_state = IntGaugeVec::new(
+        Opts::new(
+            "pg_stat_replication_sync_state",
+            help::PG_STAT_REPLICATION_SYNC_STATE,
+        ),
+        &["application_name", "client_addr", "sync_state"],
+    )
+    .unwrap();
+
+    for replica in replicas.iter() {
+        // Positions are NULL until the standby reports them
+        for (lsn, bytes) in [
+            ("sent", replica.sent_lag),
+            ("write", replica.write_lag),
+            ("flush", replica.flush_lag),
+            ("replay", replica.replay_lag),
+        ] {
+            if let Some(bytes) = bytes {
+                lag.with_label_values(&[&replica.application_name, &replica.client_addr, lsn])
+                    .set(bytes);
+            }
+        }
+        sync_state
+            .with_label_values(&[
+                &replica.application_name,
+                &replica.client_addr,
+                &replica.sync_state,
+            ])
+            .set(1);
+    }
+
+    metrics.append(&mut lag.collect());
+    metrics.append(&mut sync_state.collect());
+
+    Ok(metrics)
+}
+
+// A replication slot keeps the WAL since its `restart_lsn` from being removed, so an
+// abandoned slot fills up the disk. `wal_status`, available in PostgreSQL 13 or later, tells
+// whether the WAL is still within `max_wal_size` and `wal_keep_size`, or is about to be or
+// already has been removed. `inactive_since` was added in PostgreSQL 17, and older servers
+// don't record when a slot became inactive.
+//
+// https://www.postgresql.org/docs/15/view-pg-replication-slots.html
+#[instrument(skip_all)]
+async fn get_replication_slot_stats(
+    conn: &Client,
+) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    from_row! {
+        struct ReplicationSlot {
+            slot_name: String,
+            slot_type: String,
+            database: String,
+            retained_bytes: Option<f64>,
+            wal_status: Option<String>,
+            inactive_seconds: Option<f64>,
+        }
+    }
+
+    let version = server_version_num(conn).await?;
+    let wal_status = if version >= 130000 {
+        "slots.wal_status::text"
+    } else {
+        "NULL::text"
+    };
+    let inactive_seconds = if version >= 170000 {
+        "EXTRACT(EPOCH FROM now() - slots.inactive_since)::float8"
+    } else {
+        "NULL::float8"
+    };
+    let slots: Vec<ReplicationSlot> = query_as(
+        conn,
+        &format!(
+            "
+            SELECT
+                slots.slot_name::text,
+                slots.slot_type::text,
+                COALESCE(slots.database, '')::text AS database,
+                pg_wal_lsn_diff(
+                    CASE WHEN pg_is_in_recovery()
+                        THEN pg_last_wal_receive_lsn()
+                        ELSE pg_current_wal_lsn()
+                    END,
+                    slots.restart_lsn
+                )::float8 AS retained_bytes,
+                {wal_status} AS wal_status,
+                {inactive_seconds} AS inactive_seconds
+            FROM
+                pg_replication_slots AS slots
+        "
+        ),
+        &[],
+    )
+    .await?;
+
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    let labels = ["slot_name", "slot_type", "database"];
+    let retained = GaugeVec::new(
+        Opts::new(
+            "pg_replication_slots_retained_bytes",
+            help::PG_REPLICATION_SLOTS_RETAINED_BYTES,
+        ),
+        &labels,
+    )
+    .unwrap();
+    let wal_status = IntGaugeVec::new(
+        Opts::new(
+            "pg_replication_slots_wal_status",
+            help::PG_REPLICATION_SLOTS_WAL_STATUS,
+        ),
+        &["slot_name", "slot_type", "database", "wal_status"],
+    )
+    .unwrap();
+    let inactive = GaugeVec::new(
+        Opts::new(
+            "pg_replication_slots_inactive_seconds",
+            help::PG_REPLICATION_SLOTS_INACTIVE_SECONDS,
+        ),
+        &labels,
+    )
+    .unwrap();
+
+    for slot in slots.iter() {
+        let label_values = [
+            slot.slot_name.as_str(),
+            slot.slot_type.as_str(),
+            slot.database.as_str(),
+        ];
+        // `restart_lsn` is NULL for a slot that has never reserved WAL
+        if let Some(bytes) = slot.retained_bytes {
+            retained.with_label_values(&label_values).set(bytes);
+        }
+        if let Some(status) = &slot.wal_status {
+            wal_status
+                .with_label_values(&[&slot.slot_name, &slot.slot_type, &slot.database, status])
+                .set(1);
+        }
+        if let Some(seconds) = slot.inactive_seconds {
+            inactive.with_label_values(&label_values).set(seconds);
+        }
+    }
+
+    metrics.append(&mut retained.collect());
+    metrics.append(&mut wal_status.collect());
+    metrics.append(&mut inactive.collect());
+
+    Ok(metrics)
+}
+
+// Ages exported by the collectors are computed with `now()` of the server, while
+// Prometheus compares timestamps with the clock of its host, so a skewed clock shifts
+// every age and lag. The skew is estimated from `clock_timestamp()` against the midpoint of
+// the round trip, which bounds the error by half the round trip.
+#[instrument(skip_all)]
+async fn get_clock_skew(conn: &Client) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    let sent = SystemTime::now();
+    let row = conn
+        .query_one("SELECT EXTRACT(EPOCH FROM clock_timestamp())::float8", &[])
+        .await?;
+    let received = SystemTime::now();
+    let server_time: f64 = row.get(0);
+
+    let unix_secs = |t: SystemTime| {
+        t.duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64()
+    };
+    let round_trip = received.duration_since(sent).unwrap_or_default();
+    let midpoint = unix_secs(sent) + round_trip.as_secs_f64() / 2.0;
+
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    let m = Gauge::new(
+        "pg_exporter_clock_skew_seconds",
+        help::PG_EXPORTER_CLOCK_SKEW_SECONDS,
+    )
+    .unwrap();
+    m.set(server_time - midpoint);
+    metrics.append(&mut m.collect());
+
+    let m = Gauge::new(
+        "pg_exporter_clock_skew_uncertainty_seconds",
+        help::PG_EXPORTER_CLOCK_SKEW_UNCERTAINTY_SECONDS,
+    )
+    .unwrap();
+    m.set(round_trip.as_secs_f64() / 2.0);
+    metrics.append(&mut m.collect());
+
+    Ok(metrics)
+}
+
+/// Times of the last scrape of a target and of the last successful one, so alerting can
+/// detect stale data being served, e.g., from the background collection snapshot. Whether
+/// the last scrape could reach the target is exported as `pg_up`.
+pub struct ScrapeTimestamps {
+    target: String,
+    // (last scrape, last success)
+    times: Mutex<(Option<SystemTime>, Option<SystemTime>)>,
+    /// Whether the last scrape of each group succeeded.
+    up: Mutex<BTreeMap<u64, bool>>,
+}
+
+impl ScrapeTimestamps {
+    pub fn new(target: String) -> Self {
+        ScrapeTimestamps {
+            target,
+            times: Mutex::new((None, None)),
+            up: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn record_scrape(&self) {
+        self.times.lock().unwrap().0 = Some(SystemTime::now());
+    }
+
+    pub fn record_success(&self) {
+        self.record_group_success(0);
+    }
+
+    pub fn record_failure(&self) {
+        self.record_group_failure(0);
+    }
+
+    /// Records the success of the collectors in `group`, e.g., the ones sharing a
+    /// background collection interval, which are collected separately from the others.
+    pub fn record_group_success(&self, group: u64) {
+        self.times.lock().unwrap().1 = Some(SystemTime::now());
+        self.up.lock().unwrap().insert(group, true);
+    }
+
+    pub fn record_group_failure(&self, group: u64) {
+        self.up.lock().unwrap().insert(group, false);
+    }
+
+    /// Returns true if the last scrapes of all the groups succeeded.
+    pub fn is_up(&self) -> bool {
+        let up = self.up.lock().unwrap();
+        !up.is_empty() && up.values().all(|up| *up)
+    }
+
+    pub fn collect(&self) -> Vec<prometheus::proto::MetricFamily> {
+        let up = self.is_up();
+        let (last_scrape, last_success) = *self.times.lock().unwrap();
+
+        let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+        let m = IntGauge::new("pg_up", help::PG_UP).unwrap();
+        m.set(up as i64);
+        metrics.append(&mut m.collect());
+        // Failures of individual collectors are in `pg_stats_exporter_collector_success`
+        let m = IntGauge::new("pg_stats_exporter_up", help::PG_STATS_EXPORTER_UP).unwrap();
+        m.set(up as i64);
+        metrics.append(&mut m.collect());
+
+        let mut append_timestamp = |time: Option<SystemTime>, name: &str, help: &str| {
+            let m = GaugeVec::new(Opts::new(name, help), &["target"]).unwrap();
+            if let Some(time) = time {
+                let secs = time
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs_f64();
+                m.with_label_values(&[&self.target]).set(secs);
+            }
+            metrics.append(&mut m.collect());
+        };
+        append_timestamp(
+            last_scrape,
+            "pg_exporter_last_scrape_timestamp_seconds",
+            help::PG_EXPORTER_LAST_SCRAPE_TIMESTAMP_SECONDS,
+        );
+        append_timestamp(
+            last_success,
+            "pg_exporter_last_success_timestamp_seconds",
+            help::PG_EXPORTER_LAST_SUCCESS_TIMESTAMP_SECONDS,
+        );
+
+        // Drop the families of timestamps not recorded yet
+        metrics.retain(|m| !m.get_metric().is_empty());
+        metrics
+    }
+}
+
+/// A source of metrics run by `gather`.
+pub trait PgCollector: Send + Sync {
+    /// Name of the collector, e.g., in `--collector.<name>=false`.
+    fn name(&self) -> &'static str;
+
+    /// Whether the collector is configured to run by `options`. Opt-in collectors, e.g.,
+    /// the ones exporting a metric per relation, return false until enabled.
+    fn is_configured(&self, _options: &CollectorOptions) -> bool {
+        true
+    }
+
+    fn collect<'a>(&'a self, conn: &'a Client, options: &'a CollectorOptions) -> CollectFuture<'a>;
+}
+
+/// The result of `PgCollector::collect`, which borrows the connection and the options.
+pub type CollectFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Vec<prometheus::proto::MetricFamily>, Error>> + Send + 'a>>;
+
+type CollectFn = for<'a> fn(&'a Client, &'a CollectorOptions) -> CollectFuture<'a>;
+
+/// A collector made of functions, which is how the built-in ones are implemented.
+struct FnCollector {
+    name: &'static str,
+    configured: fn(&CollectorOptions) -> bool,
+    collect: CollectFn,
+}
+
+impl PgCollector for FnCollector {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn is_configured(&self, options: &CollectorOptions) -> bool {
+        (self.configured)(options)
+    }
+
+    fn collect<'a>(&'a self, conn: &'a Client, options: &'a CollectorOptions) -> CollectFuture<'a> {
+        (self.collect)(conn, options)
+    }
+}
+
+fn collector(name: &'static str, collect: CollectFn) -> Box<dyn PgCollector> {
+    opt_in_collector(name, |_| true, collect)
+}
+
+fn opt_in_collector(
+    name: &'static str,
+    configured: fn(&CollectorOptions) -> bool,
+    collect: CollectFn,
+) -> Box<dyn PgCollector> {
+    Box::new(FnCollector {
+        name,
+        configured,
+        collect,
+    })
+}
+
+/// The collectors run by `gather`, in order.
+pub static REGISTRY: Lazy<Vec<Box<dyn PgCollector>>> = Lazy::new(|| {
+    vec![
+        collector("cpustats", |conn, options| {
+            Box::pin(async move {
+                match options.function_source("statsinfo.cpustats()") {
+                    Some(source) => get_cpustats(conn, source).await,
+                    None => Ok(vec![]),
+                }
+            })
+        }),
+        collector("tablespaces", |conn, options| {
+            Box::pin(async move {
+                match options.function_source("statsinfo.tablespaces()") {
+                    Some(source) => get_tablespaces_stats(conn, source).await,
+                    None => Ok(vec![]),
+                }
+            })
+        }),
+        collector("loadavg", |conn, options| {
+            Box::pin(async move {
+                match options.function_source("statsinfo.loadavg()") {
+                    Some(source) => get_loadavg(conn, source).await,
+                    None => Ok(vec![]),
+                }
+            })
+        }),
+        collector("memory", |conn, options| {
+            Box::pin(async move {
+                match options.function_source("statsinfo.memory()") {
+                    Some(source) => get_memory_stats(conn, source).await,
+                    None => Ok(vec![]),
+                }
+            })
+        }),
+        collector("devicestats", |conn, options| {
+            Box::pin(async move {
+                match options.function_source("statsinfo.devicestats()") {
+                    Some(source) => get_devicestats(conn, source).await,
+                    None => Ok(vec![]),
+                }
+            })
+        }),
+        collector("pg_stat_statements_info", |conn, _| {
+            Box::pin(get_pg_stat_statements_info(conn))
+        }),
+        collector("stats_reset", |conn, _| {
+            Box::pin(get_stats_reset_ages(conn))
+        }),
+        collector("roles", |conn, _| Box::pin(get_role_stats(conn))),
+        collector("settings", |conn, _| Box::pin(get_settings_stats(conn))),
+        collector("hba_file", |conn, options| {
+            Box::pin(async move {
+                get_hba_file_stats(conn, options.function_source("pg_hba_file_rules()")).await
+            })
+        }),
+        collector("database", |conn, _| Box::pin(get_database_stats(conn))),
+        collector("bgwriter", |conn, _| Box::pin(get_bgwriter_stats(conn))),
+        collector("deadlocks", |conn, _| {
+            Box::pin(get_deadlock_and_conflict_stats(conn))
+        }),
+        collector("activity", |conn, _| Box::pin(get_activity_stats(conn))),
+        collector("idle_in_transaction", |conn, _| {
+            Box::pin(get_idle_in_transaction_ages(conn))
+        }),
+        collector("query_runtime", |conn, _| {
+            Box::pin(get_query_runtime_stats(conn))
+        }),
+        collector("vacuum_recency", |conn, options| {
+            Box::pin(async move {
+                get_vacuum_recency_stats(
+                    conn,
+                    options.vacuum_recency,
+                    options.vacuum_recency_tables.as_deref(),
+                )
+                .await
+            })
+        }),
+        collector("foreign_data", |conn, _| {
+            Box::pin(get_foreign_data_stats(conn))
+        }),
+        opt_in_collector(
+            "hot_updates",
+            |options| options.hot_updates,
+            |conn, _| Box::pin(get_hot_update_stats(conn)),
+        ),
+        opt_in_collector(
+            "statements_delta",
+            |options| options.statements_delta,
+            |conn, _| Box::pin(get_statements_delta_stats(conn)),
+        ),
+        opt_in_collector(
+            "toast",
+            |options| options.toast,
+            |conn, _| Box::pin(get_toast_stats(conn)),
+        ),
+        opt_in_collector(
+            "largest_relations",
+            |options| options.largest_relations.is_some(),
+            |conn, options| {
+                Box::pin(async move {
+                    match options.largest_relations {
+                        Some(limit) => {
+                            get_largest_relations(conn, limit, options.largest_relations_interval)
+                                .await
+                        }
+                        None => Ok(vec![]),
+                    }
+                })
+            },
+        ),
+        opt_in_collector(
+            "log",
+            |options| options.log_directory.is_some(),
+            |_, _| Box::pin(async move { Ok(log_tailer::collect()) }),
+        ),
+        opt_in_collector(
+            "patroni",
+            |options| options.patroni_url.is_some(),
+            |_, options| {
+                Box::pin(async move {
+                    Ok(match &options.patroni_url {
+                        Some(url) => patroni::collect(url).await,
+                        None => vec![],
+                    })
+                })
+            },
+        ),
+        collector("timescaledb", |conn, _| {
+            Box::pin(get_timescaledb_stats(conn))
+        }),
+        collector("citus", |conn, _| Box::pin(get_citus_stats(conn))),
+        collector("pgvector", |conn, _| {
+            Box::pin(get_pgvector_index_stats(conn))
+        }),
+        collector("postgis", |conn, _| Box::pin(get_postgis_stats(conn))),
+        collector("pg_partman", |conn, _| Box::pin(get_pg_partman_stats(conn))),
+        collector("pg_cron", |conn, _| Box::pin(get_pg_cron_stats(conn))),
+        collector("replication", |conn, _| {
+            Box::pin(get_replication_stats(conn))
+        }),
+        collector("replication_slots", |conn, _| {
+            Box::pin(get_replication_slot_stats(conn))
+        }),
+        collector("logical_slots", |conn, options| {
+            Box::pin(get_logical_slot_stats(
+                conn,
+                options.logical_slots.as_deref(),
+            ))
+        }),
+        opt_in_collector(
+            "backup",
+            |options| options.backup.is_some(),
+            |_, options| {
+                Box::pin(async move {
+                    let Some(source) = &options.backup else {
+                        return Ok(vec![]);
+                    };
+                    Ok(backup::collect(source, options.backup_interval).await)
+                })
+            },
+        ),
+        opt_in_collector(
+            "filesystem",
+            |options| options.filesystem,
+            |conn, options| Box::pin(filesystem::collect(conn, options.data_directory.as_deref())),
+        ),
+        collector("clock_skew", |conn, _| Box::pin(get_clock_skew(conn))),
+        opt_in_collector(
+            "custom_queries",
+            |options| !options.custom_queries.is_empty(),
+            |conn, options| {
+                // The transaction of a consistent snapshot is read-only already
+                let read_only = !options.allow_unsafe_queries && !options.consistent_snapshot;
+                Box::pin(custom_queries::collect(
+                    conn,
+                    &options.custom_queries,
+                    read_only,
+                ))
+            },
+        ),
+    ]
+});
+
+/// Names of the collectors in `REGISTRY`, in order.
+pub static COLLECTORS: Lazy<Vec<&'static str>> =
+    Lazy::new(|| REGISTRY.iter().map(|c| c.name()).collect());
+
+/// Returns true if the collector `name` is enabled by `options`.
+pub fn is_enabled(name: &str, options: &CollectorOptions) -> bool {
+    if options
+        .profile
+        .get()
+        .is_some_and(|p| p.disabled_collectors.iter().any(|n| n == name))
+        || options.disabled_collectors.iter().any(|n| n == name)
+    {
+        return false;
+    }
+    REGISTRY
+        .iter()
+        .find(|c| c.name() == name)
+        .is_some_and(|c| c.is_configured(options))
+}
+
+async fn collect(
+    name: &str,
+    conn: &Client,
+    options: &CollectorOptions,
+) -> Result<Vec<prometheus::proto::MetricFamily>, Error> {
+    match REGISTRY.iter().find(|c| c.name() == name) {
+        Some(collector) => collector.collect(conn, options).await,
+        None => Ok(vec![]),
+    }
+}
+
+static SCRAPE_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    HistogramVec::new(
+        HistogramOpts::new(
+            "pg_stats_exporter_scrape_duration_seconds",
+            help::PG_STATS_EXPORTER_SCRAPE_DURATION_SECONDS,
+        ),
+        &["collector"],
+    )
+    .unwrap()
+});
+
+static SCRAPE_ERRORS: Lazy<IntCounter> = Lazy::new(|| {
+    IntCounter::new(
+        "pg_stats_exporter_scrape_errors_total",
+        help::PG_STATS_EXPORTER_SCRAPE_ERRORS_TOTAL,
+    )
+    .unwrap()
+});
+
+/// Unlike `pg_exporter_last_success_timestamp_seconds` of `ScrapeTimestamps`, which is set
+/// once PostgreSQL is reachable, this is set only if no collector failed, by target so that
+/// `/probe` doesn't set it for the configured server.
+static LAST_SCRAPE_SUCCESS: Lazy<GaugeVec> = Lazy::new(|| {
+    GaugeVec::new(
+        Opts::new(
+            "pg_stats_exporter_last_scrape_success_timestamp_seconds",
+            help::PG_STATS_EXPORTER_LAST_SCRAPE_SUCCESS_TIMESTAMP_SECONDS,
+        ),
+        &["target"],
+    )
+    .unwrap()
+});
+
+/// Metrics about the collections of the exporter itself, updated by `gather_collectors`
+/// whichever of the scrapes, the background collection or `/probe` runs it.
+static SELF_METRICS: Lazy<Registry> = Lazy::new(|| {
+    let registry = Registry::new();
+    registry
+        .register(Box::new(SCRAPE_DURATION.clone()))
+        .unwrap();
+    registry.register(Box::new(SCRAPE_ERRORS.clone())).unwrap();
+    registry
+        .register(Box::new(LAST_SCRAPE_SUCCESS.clone()))
+        .unwrap();
+    registry
+});
+
+/// Returns the metrics about the collections of the exporter, to serve on `/metrics`.
+pub fn self_metrics() -> Vec<prometheus::proto::MetricFamily> {
+    SELF_METRICS.gather()
+}
+
+/// Gathers all Prometheus metrics via a PostgreSQL connection taken from `pool`. Fails if
+/// PostgreSQL is unreachable.
+pub async fn gather(
+    pool: &Pool,
+    postgres: &PgConnectionConfig,
+    options: &CollectorOptions,
+    cancellation: &ScrapeCancellation,
+) -> anyhow::Result<Vec<prometheus::proto::MetricFamily>> {
+    gather_collectors(pool, postgres, options, &COLLECTORS, cancellation).await
+}
+
+/// Collectors of heavy statistics that give the same results on a standby, so they can be
+/// offloaded from the primary. Cumulative statistics such as the TOAST counters and
+/// `pg_stat_statements_info` are kept per instance, so they can't.
+pub const STANDBY_COLLECTORS: &[&str] = &["largest_relations"];
+
+/// Connects to `standby` if it is still in recovery. After a failover, the configured
+/// standby may have been promoted, and then the collectors are run on the primary as usual.
+async fn connect_standby(pool: &Pool, standby: &PgConnectionConfig) -> Option<PooledClient> {
+    let conn = match pool.get(standby).await {
+        Ok(conn) => conn,
+        Err(e) => {
+            tracing::warn!("failed to connect to {}: {e:#}", standby.raw_address());
+            return None;
+        }
+    };
+    match conn.query_one("SELECT pg_is_in_recovery()", &[]).await {
+        Ok(row) if row.get::<_, bool>(0) => Some(conn),
+        Ok(_) => {
+            tracing::warn!(
+                "{} is not in recovery, collecting from the primary",
+                standby.raw_address()
+            );
+            None
+        }
+        Err(e) => {
+            tracing::warn!("failed to query {}: {e:#}", standby.raw_address());
+            None
+        }
+    }
+}
+
+/// Starts a transaction in which all the collectors see the same snapshot of the catalogs
+/// and, since PostgreSQL 15, of the cumulative statistics.
+///
+/// See <https://www.postgresql.org/docs/15/runtime-config-statistics.html#GUC-STATS-FETCH-CONSISTENCY>
+async fn begin_snapshot(conn: &Client) -> Result<(), Error> {
+    conn.batch_execute("BEGIN ISOLATION LEVEL REPEATABLE READ READ ONLY")
+        .await?;
+    let version: i32 = conn
+        .query_one("SELECT current_setting('server_version_num')::int", &[])
+        .await?
+        .get(0);
+    if version >= 150000 {
+        conn.batch_execute("SET LOCAL stats_fetch_consistency = snapshot")
+            .await?;
+    }
+    Ok(())
+}
+
+/// Runs the collectors in `names` over a single connection taken from `pool`, or two if a standby is
+/// configured for `STANDBY_COLLECTORS` and `CollectorOptions::consistent_snapshot` is not
+/// set. The role and `search_path` are reset after every collector. A failed collector is
+/// reported in `pg_stats_exporter_collector_success` instead of failing the collection, and
+/// only a failure to connect does. Once `cancellation` is cancelled, the remaining
+/// collectors are skipped and the metrics collected so far are returned.
+pub async fn gather_collectors(
+    pool: &Pool,
+    postgres: &PgConnectionConfig,
+    options: &CollectorOptions,
+    names: &[&str],
+    cancellation: &ScrapeCancellation,
+) -> anyhow::Result<Vec<prometheus::proto::MetricFamily>> {
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    let target = postgres.raw_address();
+    let mut conn = match pool.get(postgres).await {
+        Ok(conn) => conn,
+        Err(e) => {
+            SCRAPE_ERRORS.inc();
+            if options.notify {
+                notifier::report(&target, None, Err(format!("{e:#}")));
+            }
+            return Err(e.into());
+        }
+    };
+    if options.notify {
+        notifier::report(&target, None, Ok(()));
+    }
+    cancellation.register(&conn, postgres.tls());
+    if options.consistent_snapshot {
+        if let Err(e) = begin_snapshot(&conn).await {
+            SCRAPE_ERRORS.inc();
+            return Err(e.into());
+        }
+    }
+    let mut standby_conn = match &options.standby {
+        // The snapshot of the primary can't be shared with the standby
+        Some(_) if options.consistent_snapshot => None,
+        Some(standby) if names.iter().any(|n| STANDBY_COLLECTORS.contains(n)) => {
+            let standby_conn = connect_standby(pool, standby).await;
+            if let Some(standby_conn) = &standby_conn {
+                cancellation.register(standby_conn, standby.tls());
+            }
+            let m = IntGauge::new(
+                "pg_exporter_standby_routing",
+                help::PG_EXPORTER_STANDBY_ROUTING,
+            )
+            .unwrap();
+            m.set(standby_conn.is_some() as i64);
+            metrics.append(&mut m.collect());
+            standby_conn
+        }
+        _ => None,
+    };
+    let success = IntGaugeVec::new(
+        Opts::new(
+            "pg_stats_exporter_collector_success",
+            help::PG_STATS_EXPORTER_COLLECTOR_SUCCESS,
+        ),
+        &["collector"],
+    )
+    .unwrap();
+    let mut broken = false;
+    let mut failed = false;
+    for (i, name) in names.iter().enumerate() {
+        if cancellation.is_cancelled() {
+            tracing::warn!(
+                "scrape cancelled, skipping collectors: {}",
+                names[i..].join(", ")
+            );
+            break;
+        }
+        if !is_enabled(name, options) {
+            continue;
+        }
+        let conn = match &standby_conn {
+            Some(standby_conn) if STANDBY_COLLECTORS.contains(name) => standby_conn,
+            _ => &conn,
+        };
+        // A failed query aborts the transaction of the snapshot, so every collector runs
+        // in a savepoint to keep it from failing the next ones
+        if options.consistent_snapshot {
+            conn.batch_execute("SAVEPOINT collector").await?;
+        }
+        // The backend PID and the start time tell the queries of the collector apart in
+        // `pg_stat_activity` and the server logs
+        let query_start = SystemTime::now();
+        let span = tracing::info_span!(
+            "collector",
+            collector = name,
+            backend_pid = tracing::field::Empty,
+            query_start = query_start
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64(),
+        );
+        if let Some(pid) = conn.backend_pid() {
+            span.record("backend_pid", pid);
+        }
+        let result = collect(name, conn, options).instrument(span.clone()).await;
+        let elapsed = query_start.elapsed().unwrap_or_default();
+        let cleanup = match &result {
+            Ok(_) if options.consistent_snapshot => {
+                "RELEASE SAVEPOINT collector; RESET ROLE; RESET search_path"
+            }
+            Err(_) if options.consistent_snapshot => {
+                "ROLLBACK TO SAVEPOINT collector; RESET ROLE; RESET search_path"
+            }
+            _ => "RESET ROLE; RESET search_path",
+        };
+        if !cancellation.is_cancelled() {
+            SCRAPE_DURATION
+                .with_label_values(&[name])
+                .observe(elapsed.as_secs_f64());
+        }
+        match result {
+            Ok(mut m) => {
+                span.in_scope(|| tracing::debug!(?elapsed, "collector finished"));
+                metrics.append(&mut m);
+                success.with_label_values(&[name]).set(1);
+                if options.notify {
+                    notifier::report(&target, Some(name), Ok(()));
+                }
+            }
+            // The query in flight was canceled by the cancellation
+            Err(e) if cancellation.is_cancelled() => {
+                span.in_scope(|| tracing::warn!(?elapsed, "scrape cancelled: {e:#}"));
+                continue;
+            }
+            Err(e) => {
+                let e = redaction::redact_error_detail(&format!("{e:#}")).into_owned();
+                span.in_scope(|| tracing::warn!(?elapsed, "collector failed: {e}"));
+                success.with_label_values(&[name]).set(0);
+                SCRAPE_ERRORS.inc();
+                failed = true;
+                if options.notify {
+                    notifier::report(&target, Some(name), Err(e));
+                }
+            }
+        }
+        // Keep a role or a search_path set by a collector from affecting the next
+        if let Err(e) = conn.batch_execute(cleanup).await {
+            tracing::warn!(
+                "failed to reset the connection after collector {name}, skipping collectors: {}: {e:#}",
+                names[i + 1..].join(", ")
+            );
+            broken = true;
+            break;
+        }
+    }
+    metrics.append(&mut success.collect());
+    cancellation.finish();
+    // A canceled query aborts the transaction, which isn't committed but ends with the
+    // connection discarded below
+    if options.consistent_snapshot && !cancellation.is_cancelled() && !broken {
+        conn.batch_execute("COMMIT").await?;
+    }
+    if !(cancellation.is_cancelled() || broken || failed) {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        LAST_SCRAPE_SUCCESS
+            .with_label_values(&[&target])
+            .set(now.as_secs_f64());
+    }
+    if cancellation.is_cancelled() || broken {
+        // A cancel request sent late would hit the next user of a pooled connection, and a
+        // connection that failed to reset can't be reused either
+        conn.discard();
+        if let Some(standby_conn) = &mut standby_conn {
+            standby_conn.discard();
+        }
+    }
+
+    // Labeled metrics without any label values (e.g., no role has a password expiry)
+    // have no samples, and `TextEncoder` refuses to encode such families.
+    metrics.retain(|m| !m.get_metric().is_empty());
+    Ok(metrics)
+}
+
+/// Drops what Prometheus would reject from the exposition: families sharing the name of an
+/// earlier family of another type, and samples repeating the label set of an earlier sample
+/// of the same name. Families of the same name and type are merged since a family must be
+/// exposed contiguously. The first occurrence always wins, so the result only depends on
+/// the order of `metrics`. Returns the number of dropped samples.
+pub fn drop_duplicates(metrics: &mut Vec<prometheus::proto::MetricFamily>) -> usize {
+    let mut dropped = 0;
+    let mut families: Vec<prometheus::proto::MetricFamily> = Vec::with_capacity(metrics.len());
+    let mut label_sets: Vec<HashSet<Vec<(String, String)>>> = vec![];
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for mut family in metrics.drain(..) {
+        let samples = family.take_metric();
+        let i = match index.get(family.get_name()) {
+            Some(&i) if families[i].get_field_type() != family.get_field_type() => {
+                tracing::warn!(
+                    metric = family.get_name(),
+                    "dropped a metric family of type {:?} conflicting with an earlier one of type {:?}",
+                    family.get_field_type(),
+                    families[i].get_field_type(),
+                );
+                dropped += samples.len();
+                continue;
+            }
+            Some(&i) => i,
+            None => {
+                index.insert(family.get_name().to_string(), families.len());
+                families.push(family);
+                label_sets.push(HashSet::new());
+                families.len() - 1
+            }
+        };
+
+        let mut duplicates = 0;
+        for sample in samples {
+            let mut labels: Vec<(String, String)> = sample
+                .get_label()
+                .iter()
+                .map(|l| (l.get_name().to_string(), l.get_value().to_string()))
+                .collect();
+            labels.sort();
+            if label_sets[i].insert(labels) {
+                families[i].mut_metric().push(sample);
+            } else {
+                duplicates += 1;
+            }
+        }
+        if duplicates > 0 {
+            tracing::warn!(
+                metric = families[i].get_name(),
+                duplicates,
+                "dropped samples with duplicate label sets"
+            );
+            dropped += duplicates;
+        }
+    }
+    *metrics = families;
+    dropped
+}
+
+/// Drops metric families so that their text exposition fits in `max_bytes`, protecting
+/// Prometheus from pathological cardinality events. Families are kept in order up to the
+/// first one that doesn't fit, so the output is truncated at a family boundary. Returns
+/// the names of the dropped families.
+pub fn truncate_to_size(
+    metrics: &mut Vec<prometheus::proto::MetricFamily>,
+    max_bytes: usize,
+) -> Vec<String> {
+    let encoder = TextEncoder::new();
+    let mut total = 0;
+    let mut buf = vec![];
+    let keep = metrics
+        .iter()
+        .take_while(|m| {
+            buf.clear();
+            // Families that fail to encode are kept to fail the response as before
+            let _ = encoder.encode(std::slice::from_ref(*m), &mut buf);
+            total += buf.len();
+            total <= max_bytes
+        })
+        .count();
+
+    metrics
+        .drain(keep..)
+        .map(|m| m.get_name().to_string())
+        .collect()
+}
+
+/// Returns the metric indicating whether families were dropped by `truncate_to_size`.
+pub fn truncation_metrics(dropped: usize) -> Vec<prometheus::proto::MetricFamily> {
+    let m = IntGauge::new(
+        "pg_exporter_response_truncated_families",
+        help::PG_EXPORTER_RESPONSE_TRUNCATED_FAMILIES,
+    )
+    .unwrap();
+    m.set(dropped as i64);
+    m.collect()
+}
+
+// TODO: Add tests for the functions in this file
+
+#[cfg(test)]
+mod tests_statement_rates {
+    use crate::metrics::{statement_rates, StatementsSample};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_statement_rates() {
+        let key = |queryid: &str| ("postgres".to_string(), queryid.to_string());
+        let taken_at = Instant::now();
+        let previous = StatementsSample {
+            taken_at,
+            counters: [
+                (key("1"), (10, 1.0)),
+                (key("2"), (5, 1.0)),
+                (key("3"), (8, 2.0)),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let current = StatementsSample {
+            taken_at: taken_at + Duration::from_secs(10),
+            // "2" is idle, "3" was reset and "4" is new
+            counters: [
+                (key("1"), (30, 6.0)),
+                (key("2"), (5, 1.0)),
+                (key("3"), (2, 0.5)),
+                (key("4"), (5, 0.5)),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let mut rates = statement_rates(&previous, &current);
+        rates.sort_by(|a, b| a.0.cmp(b.0));
+        assert_eq!(rates, vec![(&key("1"), 2.0, 0.5), (&key("4"), 0.5, 0.05)]);
+
+        assert!(statement_rates(&current, &current).is_empty());
+    }
+}
+
+#[cfg(test)]
+mod tests_drop_duplicates {
+    use crate::metrics::drop_duplicates;
+    use prometheus::{core::Collector, Gauge, IntCounter, IntGaugeVec, Opts};
+
+    fn gauge_vec(name: &str, values: &[&str]) -> Vec<prometheus::proto::MetricFamily> {
+        let m = IntGaugeVec::new(Opts::new(name, "help"), &["label"]).unwrap();
+        for value in values {
+            m.with_label_values(&[value]).set(1);
+        }
+        m.collect()
+    }
+
+    #[test]
+    fn test_no_duplicates() {
+        let mut metrics = gauge_vec("first", &["a", "b"]);
+        metrics.append(&mut gauge_vec("second", &["a"]));
+        assert_eq!(drop_duplicates(&mut metrics), 0);
+        assert_eq!(metrics.len(), 2);
+        assert_eq!(metrics[0].get_metric().len(), 2);
+    }
+
+    #[test]
+    fn test_conflicting_types() {
+        let mut metrics = IntCounter::new("first", "help").unwrap().collect();
+        metrics.append(&mut Gauge::new("first", "help").unwrap().collect());
+        metrics.append(&mut Gauge::new("second", "help").unwrap().collect());
+        assert_eq!(drop_duplicates(&mut metrics), 1);
+        let names: Vec<_> = metrics.iter().map(|m| m.get_name()).collect();
+        assert_eq!(names, vec!["first", "second"]);
+        assert_eq!(
+            metrics[0].get_field_type(),
+            prometheus::proto::MetricType::COUNTER
+        );
+    }
+
+    #[test]
+    fn test_duplicate_label_sets() {
+        let mut metrics = gauge_vec("first", &["a", "b"]);
+        metrics.append(&mut gauge_vec("second", &["a"]));
+        metrics.append(&mut gauge_vec("first", &["b", "c"]));
+        assert_eq!(drop_duplicates(&mut metrics), 1);
+        let names: Vec<_> = metrics.iter().map(|m| m.get_name()).collect();
+        assert_eq!(names, vec!["first", "second"]);
+        // A vec collects its samples in no particular order
+        let mut values: Vec<_> = metrics[0]
+            .get_metric()
+            .iter()
+            .map(|m| m.get_label()[0].get_value())
+            .collect();
+        values.sort();
+        assert_eq!(values, vec!["a", "b", "c"]);
+    }
+}
+
+#[cfg(test)]
+mod tests_truncate_to_size {
+    use crate::metrics::truncate_to_size;
+    use prometheus::{core::Collector, IntGauge};
+
+    fn families() -> Vec<prometheus::proto::MetricFamily> {
+        ["first", "second", "third"]
+            .iter()
+            .flat_map(|name| IntGauge::new(*name, "help").unwrap().collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_fits() {
+        let mut metrics = families();
+        assert!(truncate_to_size(&mut metrics, usize::MAX).is_empty());
+        assert_eq!(metrics.len(), 3);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut metrics = families();
+        // "# HELP first help\n# TYPE first gauge\nfirst 0\n" is 46 bytes
+        let dropped = truncate_to_size(&mut metrics, 60);
+        assert_eq!(dropped, vec!["second", "third"]);
+        assert_eq!(metrics.len(), 1);
+    }
+}
+
+#[cfg(test)]
+mod tests_parse_postgis_full_version {
+    use crate::metrics::parse_postgis_full_version;
+
+    #[test]
+    fn test_parse_postgis_full_version() {
+        assert_eq!(
+            parse_postgis_full_version(
+                "LIBPROJ=\"0\" POSTGIS=\"3.4.0 0874ea3\" [EXTENSION] PGSQL=\"150\" GEOS=\"3.12.0-CAPI-1.18.0\" \
+                 PROJ=\"9.3.0 NETWORK_ENABLED=OFF URL_ENDPOINT=https://cdn.proj.org\" \
+                 LIBXML=\"2.9.14\" LIBJSON=\"0.17\""
+            ),
+            vec!["3.4.0", "150", "3.12.0-CAPI-1.18.0", "9.3.0", "", "2.9.14"]
+        );
+        assert_eq!(parse_postgis_full_version(""), vec![""; 6]);
+    }
+}
+
+#[cfg(test)]
+mod tests_scrape_timestamps {
+    use crate::metrics::ScrapeTimestamps;
+
+    #[test]
+    fn test_is_up() {
+        let timestamps = ScrapeTimestamps::new("db:5432".to_string());
+        assert!(!timestamps.is_up());
+        timestamps.record_group_success(10);
+        timestamps.record_group_failure(300);
+        assert!(!timestamps.is_up());
+        // Another success of the first group doesn't hide the failure of the second
+        timestamps.record_group_success(10);
+        assert!(!timestamps.is_up());
+        timestamps.record_group_success(300);
+        assert!(timestamps.is_up());
+    }
+}
@@ -0,0 +1,536 @@
+//!
+//! Notifications of events to a webhook, for sites without Alertmanager.
+//!
+//! Events are POSTed as JSON to `--notify-webhook` when the exporter starts and stops, when
+//! a target becomes unreachable or a collector starts failing, and when they recover. Only
+//! the transitions are sent, so a target down for hours is notified once. The body is a
+//! generic JSON object by default, or with `--notify-webhook-format`, a message of a Slack
+//! incoming webhook or an event of the PagerDuty Events API v2, where a down event triggers
+//! an incident resolved by the matching up event. Only the configured servers are reported,
+//! not those of `/probe`, which would otherwise page for whatever is probed and keep the
+//! status of every probed address.
+//!
+//! The threshold alerts of pg_statsinfo aren't notified, since they are computed in its
+//! repository database rather than the monitored server; they are left to a collector of
+//! their own.
+//!
+//! HTTPS webhooks are verified with the CA bundle of the system, or `SSL_CERT_FILE` if set.
+//!
+use anyhow::{bail, Context};
+use hyper::header::{CONTENT_TYPE, HOST};
+use hyper::{Body, Request, Response, Uri};
+use once_cell::sync::OnceCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerName};
+use tokio_rustls::TlsConnector;
+
+use crate::redaction;
+use crate::secret_files::SecretFile;
+use crate::tls_config::load_certs;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// CA bundles of the major distributions, tried in order.
+const CA_BUNDLES: &[&str] = &[
+    "/etc/ssl/certs/ca-certificates.crt",
+    "/etc/pki/tls/certs/ca-bundle.crt",
+    "/etc/ssl/cert.pem",
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WebhookFormat {
+    #[default]
+    Generic,
+    Slack,
+    PagerDuty,
+}
+
+impl WebhookFormat {
+    pub const VALUES: [&'static str; 3] = ["generic", "slack", "pagerduty"];
+}
+
+impl FromStr for WebhookFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "generic" => WebhookFormat::Generic,
+            "slack" => WebhookFormat::Slack,
+            "pagerduty" => WebhookFormat::PagerDuty,
+            _ => bail!("unknown webhook format `{s}`"),
+        })
+    }
+}
+
+impl fmt::Display for WebhookFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            WebhookFormat::Generic => "generic",
+            WebhookFormat::Slack => "slack",
+            WebhookFormat::PagerDuty => "pagerduty",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    Started,
+    Stopped,
+    TargetDown {
+        target: String,
+        error: String,
+    },
+    TargetUp {
+        target: String,
+    },
+    CollectorDown {
+        target: String,
+        collector: String,
+        error: String,
+    },
+    CollectorUp {
+        target: String,
+        collector: String,
+    },
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::Started => "exporter_started",
+            Event::Stopped => "exporter_stopped",
+            Event::TargetDown { .. } => "target_down",
+            Event::TargetUp { .. } => "target_up",
+            Event::CollectorDown { .. } => "collector_down",
+            Event::CollectorUp { .. } => "collector_up",
+        }
+    }
+
+    fn is_problem(&self) -> bool {
+        matches!(self, Event::TargetDown { .. } | Event::CollectorDown { .. })
+    }
+
+    fn message(&self) -> String {
+        match self {
+            Event::Started => "pg_stats_exporter started".to_string(),
+            Event::Stopped => "pg_stats_exporter stopped".to_string(),
+            Event::TargetDown { target, error } => format!("{target} is unreachable: {error}"),
+            Event::TargetUp { target } => format!("{target} is reachable again"),
+            Event::CollectorDown {
+                target,
+                collector,
+                error,
+            } => format!("collector {collector} failed on {target}: {error}"),
+            Event::CollectorUp { target, collector } => {
+                format!("collector {collector} recovered on {target}")
+            }
+        }
+    }
+
+    /// Returns the key shared by a down event and the up event resolving it.
+    fn dedup_key(&self) -> Option<String> {
+        match self {
+            Event::Started | Event::Stopped => None,
+            Event::TargetDown { target, .. } | Event::TargetUp { target } => {
+                Some(format!("pg_stats_exporter/{target}"))
+            }
+            Event::CollectorDown {
+                target, collector, ..
+            }
+            | Event::CollectorUp { target, collector } => {
+                Some(format!("pg_stats_exporter/{target}/{collector}"))
+            }
+        }
+    }
+}
+
+/// Returns the body of `event` in `format`, or `None` if it isn't sent in the format, i.e.,
+/// the start and stop of the exporter, which aren't incidents, to PagerDuty.
+fn body(
+    event: &Event,
+    format: WebhookFormat,
+    routing_key: &str,
+    version: &str,
+    timestamp: f64,
+) -> Option<serde_json::Value> {
+    let body = match format {
+        WebhookFormat::Generic => {
+            let mut body = serde_json::json!({
+                "event": event.name(),
+                "severity": if event.is_problem() { "error" } else { "info" },
+                "message": event.message(),
+                "version": version,
+                "timestamp": timestamp,
+            });
+            match event {
+                Event::Started | Event::Stopped => {}
+                Event::TargetDown { target, .. } | Event::TargetUp { target } => {
+                    body["target"] = target.as_str().into();
+                }
+                Event::CollectorDown {
+                    target, collector, ..
+                }
+                | Event::CollectorUp { target, collector } => {
+                    body["target"] = target.as_str().into();
+                    body["collector"] = collector.as_str().into();
+                }
+            }
+            body
+        }
+        WebhookFormat::Slack => serde_json::json!({
+            "text": format!("[pg_stats_exporter] {}", event.message()),
+        }),
+        WebhookFormat::PagerDuty => {
+            let dedup_key = event.dedup_key()?;
+            if !event.is_problem() {
+                return Some(serde_json::json!({
+                    "routing_key": routing_key,
+                    "event_action": "resolve",
+                    "dedup_key": dedup_key,
+                }));
+            }
+            let (source, component) = match event {
+                Event::CollectorDown {
+                    target, collector, ..
+                } => (target, Some(collector)),
+                Event::TargetDown { target, .. } => (target, None),
+                _ => unreachable!("only down events are problems"),
+            };
+            serde_json::json!({
+                "routing_key": routing_key,
+                "event_action": "trigger",
+                "dedup_key": dedup_key,
+                "payload": {
+                    "summary": event.message(),
+                    "source": source,
+                    "severity": "error",
+                    "component": component,
+                    "custom_details": { "version": version },
+                },
+            })
+        }
+    };
+    Some(body)
+}
+
+/// Returns the event of a change of the status of `collector` on `target`, or of `target`
+/// itself if `collector` is `None`. Unknown statuses are taken as up, so that a failure at
+/// startup is notified but not a success.
+fn transition(
+    status: &mut HashMap<(String, Option<String>), bool>,
+    target: &str,
+    collector: Option<&str>,
+    result: Result<(), String>,
+) -> Option<Event> {
+    let up = result.is_ok();
+    let previous = status.insert((target.to_string(), collector.map(|c| c.to_string())), up);
+    if previous.unwrap_or(true) == up {
+        return None;
+    }
+    let target = target.to_string();
+    Some(match (collector, result) {
+        (None, Ok(())) => Event::TargetUp { target },
+        (None, Err(error)) => Event::TargetDown { target, error },
+        (Some(collector), Ok(())) => Event::CollectorUp {
+            target,
+            collector: collector.to_string(),
+        },
+        (Some(collector), Err(error)) => Event::CollectorDown {
+            target,
+            collector: collector.to_string(),
+            error,
+        },
+    })
+}
+
+/// Sends events to a webhook.
+pub struct Notifier {
+    url: Uri,
+    format: WebhookFormat,
+    /// Required by PagerDuty.
+    routing_key: Option<SecretFile>,
+    /// `None` for plain HTTP.
+    tls: Option<TlsConnector>,
+    version: String,
+}
+
+impl Notifier {
+    pub fn new(
+        url: &str,
+        format: WebhookFormat,
+        routing_key: Option<SecretFile>,
+        version: String,
+    ) -> anyhow::Result<Self> {
+        let url: Uri = url.parse().context("invalid webhook URL")?;
+        if url.host().is_none() {
+            bail!("the webhook URL has no host");
+        }
+        let tls = match url.scheme_str() {
+            Some("http") => None,
+            Some("https") => {
+                let config = ClientConfig::builder()
+                    .with_safe_defaults()
+                    .with_root_certificates(load_system_roots()?)
+                    .with_no_client_auth();
+                Some(TlsConnector::from(Arc::new(config)))
+            }
+            _ => bail!("the webhook URL must be http or https"),
+        };
+        if format == WebhookFormat::PagerDuty && routing_key.is_none() {
+            bail!("the pagerduty format requires a routing key");
+        }
+        Ok(Notifier {
+            url,
+            format,
+            routing_key,
+            tls,
+            version,
+        })
+    }
+
+    /// POSTs `event` to the webhook.
+    pub async fn send(&self, event: &Event) -> anyhow::Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let routing_key = self.routing_key.as_ref().map(|key| key.get());
+        let Some(body) = body(
+            event,
+            self.format,
+            routing_key.as_deref().unwrap_or_default(),
+            &self.version,
+            timestamp,
+        ) else {
+            return Ok(());
+        };
+        let response = tokio::time::timeout(TIMEOUT, self.post(body.to_string()))
+            .await
+            .context("timed out")??;
+        if !response.status().is_success() {
+            bail!("responded {}", response.status());
+        }
+        Ok(())
+    }
+
+    async fn post(&self, body: String) -> anyhow::Result<Response<Body>> {
+        // The host was checked in `new`
+        let host = self.url.host().unwrap_or_default();
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let port = self
+            .url
+            .port_u16()
+            .unwrap_or(if self.tls.is_some() { 443 } else { 80 });
+        let path = self
+            .url
+            .path_and_query()
+            .map_or("/", |path_and_query| path_and_query.as_str());
+        let request = Request::post(path)
+            .header(HOST, self.url.authority().unwrap().as_str())
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body))?;
+
+        let stream = TcpStream::connect((host, port)).await?;
+        match &self.tls {
+            Some(connector) => {
+                let stream = connector
+                    .connect(ServerName::try_from(host)?, stream)
+                    .await?;
+                send_request(stream, request).await
+            }
+            None => send_request(stream, request).await,
+        }
+    }
+}
+
+async fn send_request<S>(stream: S, request: Request<Body>) -> anyhow::Result<Response<Body>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, conn) = hyper::client::conn::handshake(stream).await?;
+    tokio::spawn(async move {
+        if let Err(e) = conn.await {
+            tracing::debug!("webhook connection failed: {e:#}");
+        }
+    });
+    Ok(sender.send_request(request).await?)
+}
+
+fn load_system_roots() -> anyhow::Result<RootCertStore> {
+    let path = match std::env::var_os("SSL_CERT_FILE") {
+        Some(path) => path.into(),
+        None => CA_BUNDLES
+            .iter()
+            .map(Path::new)
+            .find(|path| path.exists())
+            .context("no CA bundle found, set SSL_CERT_FILE")?
+            .to_path_buf(),
+    };
+    let certs: Vec<Vec<u8>> = load_certs(&path)?.into_iter().map(|c| c.0).collect();
+    let mut roots = RootCertStore::empty();
+    // System bundles may contain certificates rustls can't parse
+    roots.add_parsable_certificates(&certs);
+    Ok(roots)
+}
+
+/// The notifier of the process and the events queued to it, which are sent in order.
+struct Notifications {
+    notifier: Arc<Notifier>,
+    queue: mpsc::UnboundedSender<Event>,
+    status: Mutex<HashMap<(String, Option<String>), bool>>,
+}
+
+static NOTIFICATIONS: OnceCell<Notifications> = OnceCell::new();
+
+/// Sets the notifier of the process and starts sending the events queued to it. Must be
+/// called in a Tokio runtime.
+pub fn init(notifier: Notifier) {
+    let notifier = Arc::new(notifier);
+    let (queue, mut events) = mpsc::unbounded_channel::<Event>();
+    let sender = notifier.clone();
+    tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            if let Err(e) = sender.send(&event).await {
+                // The URL may embed a token, e.g., of Slack
+                tracing::warn!(
+                    "failed to notify {} of {}: {e:#}",
+                    sender.url.host().unwrap_or_default(),
+                    event.name()
+                );
+            }
+        }
+    });
+    let _ = NOTIFICATIONS.set(Notifications {
+        notifier,
+        queue,
+        status: Mutex::new(HashMap::new()),
+    });
+}
+
+/// Queues `event` if a notifier is set.
+pub fn notify(event: Event) {
+    if let Some(notifications) = NOTIFICATIONS.get() {
+        let _ = notifications.queue.send(event);
+    }
+}
+
+/// Sends `event` right away if a notifier is set, e.g., before the process exits.
+pub async fn notify_now(event: Event) {
+    if let Some(notifications) = NOTIFICATIONS.get() {
+        if let Err(e) = notifications.notifier.send(&event).await {
+            tracing::warn!(
+                "failed to notify {} of {}: {e:#}",
+                notifications.notifier.url.host().unwrap_or_default(),
+                event.name()
+            );
+        }
+    }
+}
+
+/// Reports the result of connecting to `target`, or of running `collector` on it, and
+/// notifies the change from the previous result if any.
+pub fn report(target: &str, collector: Option<&str>, result: Result<(), String>) {
+    let Some(notifications) = NOTIFICATIONS.get() else {
+        return;
+    };
+    let result = result.map_err(|e| redaction::mask_secrets(&e).into_owned());
+    let event = transition(
+        &mut notifications.status.lock().unwrap(),
+        target,
+        collector,
+        result,
+    );
+    if let Some(event) = event {
+        let _ = notifications.queue.send(event);
+    }
+}
+
+#[cfg(test)]
+mod tests_notifier {
+    use crate::notifier::{body, transition, Event, WebhookFormat};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_transition() {
+        let mut status = HashMap::new();
+        assert_eq!(transition(&mut status, "db1:5432", None, Ok(())), None);
+        assert_eq!(
+            transition(&mut status, "db1:5432", Some("toast"), Err("boom".into())),
+            Some(Event::CollectorDown {
+                target: "db1:5432".into(),
+                collector: "toast".into(),
+                error: "boom".into(),
+            })
+        );
+        assert_eq!(
+            transition(&mut status, "db1:5432", Some("toast"), Err("boom".into())),
+            None
+        );
+        assert_eq!(
+            transition(&mut status, "db1:5432", Some("toast"), Ok(())),
+            Some(Event::CollectorUp {
+                target: "db1:5432".into(),
+                collector: "toast".into(),
+            })
+        );
+        assert_eq!(
+            transition(&mut status, "db1:5432", None, Err("refused".into())),
+            Some(Event::TargetDown {
+                target: "db1:5432".into(),
+                error: "refused".into(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_body() {
+        let down = Event::TargetDown {
+            target: "db1:5432".into(),
+            error: "refused".into(),
+        };
+        let up = Event::TargetUp {
+            target: "db1:5432".into(),
+        };
+
+        let generic = body(&down, WebhookFormat::Generic, "", "0.1.0", 1.0).unwrap();
+        assert_eq!(generic["event"], "target_down");
+        assert_eq!(generic["severity"], "error");
+        assert_eq!(generic["target"], "db1:5432");
+
+        let slack = body(&up, WebhookFormat::Slack, "", "0.1.0", 1.0).unwrap();
+        assert_eq!(
+            slack,
+            serde_json::json!({ "text": "[pg_stats_exporter] db1:5432 is reachable again" })
+        );
+
+        let trigger = body(&down, WebhookFormat::PagerDuty, "key", "0.1.0", 1.0).unwrap();
+        let resolve = body(&up, WebhookFormat::PagerDuty, "key", "0.1.0", 1.0).unwrap();
+        assert_eq!(trigger["event_action"], "trigger");
+        assert_eq!(trigger["payload"]["source"], "db1:5432");
+        assert_eq!(resolve["event_action"], "resolve");
+        assert_eq!(trigger["dedup_key"], resolve["dedup_key"]);
+        assert_eq!(
+            body(
+                &Event::Started,
+                WebhookFormat::PagerDuty,
+                "key",
+                "0.1.0",
+                1.0
+            ),
+            None
+        );
+
+        for value in WebhookFormat::VALUES {
+            let format: WebhookFormat = value.parse().unwrap();
+            assert_eq!(format.to_string(), value);
+        }
+    }
+}
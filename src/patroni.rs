@@ -0,0 +1,115 @@
+//!
+//! Queries the REST API of Patroni managing the target PostgreSQL and exports the role of
+//! the node, so role flaps can be correlated with database stats in one exporter.
+//!
+//! See <https://patroni.readthedocs.io/en/latest/rest_api.html#monitoring-endpoint>
+//!
+use anyhow::Context;
+use prometheus::{core::Collector, IntGauge, IntGaugeVec, Opts};
+use serde::Deserialize;
+use std::time::Duration;
+
+use crate::help;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A subset of the response of `GET /patroni`.
+#[derive(Debug, Deserialize)]
+struct PatroniStatus {
+    role: String,
+    timeline: Option<i64>,
+    // Only present while the cluster is in maintenance mode
+    #[serde(default)]
+    pause: bool,
+}
+
+impl PatroniStatus {
+    /// Normalizes the role names that differ between Patroni versions.
+    fn role(&self) -> &str {
+        match self.role.as_str() {
+            "master" | "primary" => "leader",
+            role => role,
+        }
+    }
+}
+
+async fn fetch_status(url: &str) -> anyhow::Result<PatroniStatus> {
+    let uri: hyper::Uri = url.parse().context("Invalid Patroni URL")?;
+    let response = tokio::time::timeout(REQUEST_TIMEOUT, hyper::Client::new().get(uri))
+        .await
+        .context("Timed out")??;
+    // Patroni returns 503 from `/patroni` on some failures, but still with a valid body
+    let body = hyper::body::to_bytes(response.into_body()).await?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+fn status_metrics(status: Option<&PatroniStatus>) -> Vec<prometheus::proto::MetricFamily> {
+    let mut metrics: Vec<prometheus::proto::MetricFamily> = vec![];
+
+    let m = IntGauge::new("pg_patroni_up", help::PG_PATRONI_UP).unwrap();
+    m.set(status.is_some() as i64);
+    metrics.append(&mut m.collect());
+
+    let status = match status {
+        Some(status) => status,
+        None => return metrics,
+    };
+
+    let m = IntGaugeVec::new(
+        Opts::new("pg_patroni_role", help::PG_PATRONI_ROLE),
+        &["role"],
+    )
+    .unwrap();
+    m.with_label_values(&[status.role()]).set(1);
+    metrics.append(&mut m.collect());
+
+    if let Some(timeline) = status.timeline {
+        let m = IntGauge::new("pg_patroni_timeline", help::PG_PATRONI_TIMELINE).unwrap();
+        m.set(timeline);
+        metrics.append(&mut m.collect());
+    }
+
+    let m = IntGauge::new("pg_patroni_paused", help::PG_PATRONI_PAUSED).unwrap();
+    m.set(status.pause as i64);
+    metrics.append(&mut m.collect());
+
+    metrics
+}
+
+/// Gathers metrics from the Patroni REST API at `url`, e.g., `http://127.0.0.1:8008/patroni`.
+#[tracing::instrument(name = "get_patroni_status", skip_all)]
+pub async fn collect(url: &str) -> Vec<prometheus::proto::MetricFamily> {
+    match fetch_status(url).await {
+        Ok(status) => status_metrics(Some(&status)),
+        Err(e) => {
+            tracing::warn!("failed to query Patroni at {url}: {e:#}");
+            status_metrics(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_patroni_status {
+    use crate::patroni::PatroniStatus;
+
+    #[test]
+    fn test_leader() {
+        let status: PatroniStatus = serde_json::from_str(
+            r#"{"state": "running", "role": "master", "server_version": 150004, "timeline": 3}"#,
+        )
+        .unwrap();
+        assert_eq!(status.role(), "leader");
+        assert_eq!(status.timeline, Some(3));
+        assert!(!status.pause);
+    }
+
+    #[test]
+    fn test_paused_replica() {
+        let status: PatroniStatus =
+            serde_json::from_str(r#"{"state": "running", "role": "replica", "pause": true}"#)
+                .unwrap();
+        assert_eq!(status.role(), "replica");
+        assert_eq!(status.timeline, None);
+        assert!(status.pause);
+    }
+}
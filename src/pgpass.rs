@@ -0,0 +1,107 @@
+//!
+//! Passwords in a password file of libpq, `~/.pgpass` or `$PGPASSFILE`.
+//!
+//! Each line is `hostname:port:database:username:password`, where the first four fields may
+//! be `*` to match anything, and `:` and `\` in a field are escaped by `\`. The first line
+//! matching a connection is used. As in libpq, the file is ignored if it is readable by
+//! others than the owner.
+//!
+use anyhow::Context;
+use std::path::{Path, PathBuf};
+
+/// Returns the password file to use, if any.
+pub fn default_path() -> Option<PathBuf> {
+    match std::env::var_os("PGPASSFILE") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => std::env::var_os("HOME").map(|home| Path::new(&home).join(".pgpass")),
+    }
+}
+
+/// Returns the password of the first line in `path` matching the connection, or `None` if
+/// no line matches or the file doesn't exist.
+pub fn lookup(
+    path: &Path,
+    host: &str,
+    port: u16,
+    dbname: &str,
+    user: &str,
+) -> anyhow::Result<Option<String>> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(path)?.permissions().mode();
+        if mode & 0o077 != 0 {
+            tracing::warn!(
+                "{} has group or world access, so it is ignored; permissions should be u=rw (0600) or less",
+                path.display()
+            );
+            return Ok(None);
+        }
+    }
+    Ok(find(&content, host, port, dbname, user))
+}
+
+fn find(content: &str, host: &str, port: u16, dbname: &str, user: &str) -> Option<String> {
+    let port = port.to_string();
+    let wanted = [host, &port, dbname, user];
+    content
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(split)
+        .find(|fields| {
+            fields[..4]
+                .iter()
+                .zip(wanted)
+                .all(|(field, value)| field == "*" || field == value)
+        })
+        .map(|mut fields| fields.swap_remove(4))
+}
+
+/// Splits a line into the five fields, unescaping them.
+fn split(line: &str) -> Option<Vec<String>> {
+    let mut fields = vec![String::new()];
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => fields.last_mut()?.push(chars.next().unwrap_or('\\')),
+            // The password may contain unescaped colons
+            ':' if fields.len() < 5 => fields.push(String::new()),
+            c => fields.last_mut()?.push(c),
+        }
+    }
+    (fields.len() == 5).then_some(fields)
+}
+
+#[cfg(test)]
+mod tests_pgpass {
+    use crate::pgpass::find;
+
+    #[test]
+    fn test_find() {
+        let content = "\
+# comment
+db1.example.com:5432:app:monitor:first
+*:5433:*:monitor:second
+*:*:*:*:with\\:colon:and\\\\backslash
+broken:line
+";
+        assert_eq!(
+            find(content, "db1.example.com", 5432, "app", "monitor"),
+            Some("first".to_string())
+        );
+        assert_eq!(
+            find(content, "db2.example.com", 5433, "app", "monitor"),
+            Some("second".to_string())
+        );
+        assert_eq!(
+            find(content, "db1.example.com", 5432, "postgres", "monitor"),
+            Some("with:colon:and\\backslash".to_string())
+        );
+        assert_eq!(find("", "db1.example.com", 5432, "app", "monitor"), None);
+    }
+}
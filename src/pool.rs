@@ -0,0 +1,417 @@
+//!
+//! Connections the exporter opens to collect metrics, and metrics about them.
+//!
+//! Establishing a connection costs a fork of a backend on every scrape, so up to
+//! `MAX_IDLE_PER_DATABASE` connections per database are kept open and reused. A connection
+//! is reset with `DISCARD ALL` before it goes back to the pool, so that a role or a
+//! `search_path` set by one collection can't leak into the next, and it is dropped instead
+//! if the reset fails, e.g., because a transaction is left open.
+//!
+//! After a failure to connect, connecting to the same database is not retried for a backoff
+//! doubling on every failure, so that scrapes of a server that is down fail fast instead of
+//! piling up handshakes. `--max-connections` bounds the connections open to a database, and
+//! a scrape waits for one to be returned at the limit.
+//!
+use once_cell::sync::Lazy;
+use prometheus::{
+    core::Collector, Histogram, HistogramOpts, IntCounter, IntGauge, IntGaugeVec, Opts,
+};
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_postgres::Client;
+
+use crate::help;
+use crate::postgres_connection::PgConnectionConfig;
+
+/// Number of idle connections kept open per database.
+const MAX_IDLE_PER_DATABASE: usize = 2;
+
+/// Time a pooled connection has to answer before it's considered broken.
+const VALIDATION_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Time before connecting again after a failure, doubled on every failure in a row.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+static POOL_SIZE: Lazy<IntGauge> =
+    Lazy::new(|| IntGauge::new("pg_exporter_pool_size", help::PG_EXPORTER_POOL_SIZE).unwrap());
+
+static POOL_IDLE: Lazy<IntGauge> = Lazy::new(|| {
+    IntGauge::new(
+        "pg_exporter_pool_idle_connections",
+        help::PG_EXPORTER_POOL_IDLE_CONNECTIONS,
+    )
+    .unwrap()
+});
+
+static POOL_CONNECTIONS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    IntGaugeVec::new(
+        Opts::new(
+            "pg_exporter_pool_connections",
+            help::PG_EXPORTER_POOL_CONNECTIONS,
+        ),
+        &["database"],
+    )
+    .unwrap()
+});
+
+static POOL_WAIT: Lazy<Histogram> = Lazy::new(|| {
+    Histogram::with_opts(HistogramOpts::new(
+        "pg_exporter_pool_wait_seconds",
+        help::PG_EXPORTER_POOL_WAIT_SECONDS,
+    ))
+    .unwrap()
+});
+
+static POOL_ERRORS: Lazy<IntCounter> = Lazy::new(|| {
+    IntCounter::new(
+        "pg_exporter_pool_connection_errors_total",
+        help::PG_EXPORTER_POOL_CONNECTION_ERRORS_TOTAL,
+    )
+    .unwrap()
+});
+
+#[derive(Debug, Error)]
+pub enum PoolError {
+    #[error(transparent)]
+    Connect(#[from] tokio_postgres::Error),
+    #[error("not connecting to {address} for {remaining:?} after failing to connect")]
+    Backoff {
+        address: String,
+        remaining: Duration,
+    },
+}
+
+/// An open connection and the PID of its backend.
+struct Connection {
+    client: Client,
+    /// `None` if the server doesn't tell it.
+    backend_pid: Option<i32>,
+}
+
+/// The connections to a database.
+#[derive(Default)]
+struct Database {
+    idle: Vec<Connection>,
+    /// Bounds the connections open if `--max-connections` is given.
+    semaphore: Option<Arc<Semaphore>>,
+    /// Failures to connect in a row.
+    failures: u32,
+    /// When connecting is allowed again after the last failure.
+    retry_at: Option<Instant>,
+}
+
+struct Inner {
+    max_connections: Option<usize>,
+    /// Databases by `PgConnectionConfig::pool_key`.
+    databases: Mutex<HashMap<String, Database>>,
+}
+
+/// Connections to the servers the exporter collects from, shared by scrapes.
+#[derive(Clone)]
+pub struct Pool {
+    inner: Arc<Inner>,
+}
+
+impl Default for Pool {
+    fn default() -> Self {
+        Pool::new(None)
+    }
+}
+
+/// A connection accounted in `pg_exporter_pool_size` until dropped, which returns it to the
+/// pool.
+pub struct PooledClient {
+    conn: Option<Connection>,
+    key: String,
+    database: String,
+    pool: Pool,
+    permit: Option<OwnedSemaphorePermit>,
+    /// Set while a cancel request may be sent to the connection, which is then closed when
+    /// dropped instead of returned to the pool.
+    discard_on_drop: Arc<AtomicBool>,
+}
+
+impl PooledClient {
+    fn new(
+        conn: Connection,
+        key: String,
+        database: String,
+        pool: Pool,
+        permit: Option<OwnedSemaphorePermit>,
+    ) -> Self {
+        PooledClient {
+            conn: Some(conn),
+            key,
+            database,
+            pool,
+            permit,
+            discard_on_drop: Arc::default(),
+        }
+    }
+
+    /// Closes the connection when dropped instead of returning it to the pool, e.g., when a
+    /// cancel request may still be on its way to it.
+    pub fn discard(&mut self) {
+        self.conn = None;
+    }
+
+    /// Returns the flag closing the connection when dropped while it's set, which stays set
+    /// if the owner of the connection is dropped before clearing it.
+    pub(crate) fn discard_on_drop(&self) -> Arc<AtomicBool> {
+        self.discard_on_drop.clone()
+    }
+
+    /// Returns the PID of the backend, to match the collections with `pg_stat_activity` and
+    /// the server logs.
+    pub fn backend_pid(&self) -> Option<i32> {
+        self.conn.as_ref().and_then(|conn| conn.backend_pid)
+    }
+}
+
+impl Deref for PooledClient {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        &self.conn.as_ref().expect("connection is discarded").client
+    }
+}
+
+impl DerefMut for PooledClient {
+    fn deref_mut(&mut self) -> &mut Client {
+        &mut self.conn.as_mut().expect("connection is discarded").client
+    }
+}
+
+fn release(database: &str) {
+    POOL_SIZE.dec();
+    POOL_CONNECTIONS.with_label_values(&[database]).dec();
+}
+
+impl Drop for PooledClient {
+    fn drop(&mut self) {
+        if self.discard_on_drop.load(Ordering::Acquire) {
+            self.conn = None;
+        }
+        let Some(conn) = self.conn.take() else {
+            release(&self.database);
+            return;
+        };
+        // A drop can't wait for the reset, so it's done in a task, which holds the permit
+        // until the connection is back in the pool
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                let pool = self.pool.clone();
+                let key = std::mem::take(&mut self.key);
+                let database = std::mem::take(&mut self.database);
+                let permit = self.permit.take();
+                handle.spawn(async move {
+                    pool.put_idle(conn, key, database).await;
+                    drop(permit);
+                });
+            }
+            Err(_) => release(&self.database),
+        }
+    }
+}
+
+impl Pool {
+    /// Creates a pool opening up to `max_connections` connections per database if given.
+    pub fn new(max_connections: Option<usize>) -> Self {
+        Pool {
+            inner: Arc::new(Inner {
+                max_connections,
+                databases: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Resets `conn` and returns it to the pool, or closes it if the reset fails or the
+    /// pool is full.
+    async fn put_idle(&self, conn: Connection, key: String, database: String) {
+        if conn.client.batch_execute("DISCARD ALL").await.is_ok() {
+            let mut databases = self.inner.databases.lock().unwrap();
+            let idle = &mut databases.entry(key).or_default().idle;
+            if idle.len() < MAX_IDLE_PER_DATABASE {
+                idle.push(conn);
+                POOL_IDLE.inc();
+                return;
+            }
+        }
+        release(&database);
+    }
+
+    /// Takes an idle connection to `key` that still answers, closing the broken ones.
+    async fn take_idle(&self, key: &str, database: &str) -> Option<Connection> {
+        loop {
+            let conn = self
+                .inner
+                .databases
+                .lock()
+                .unwrap()
+                .get_mut(key)?
+                .idle
+                .pop()?;
+            POOL_IDLE.dec();
+            let valid = !conn.client.is_closed()
+                && tokio::time::timeout(VALIDATION_TIMEOUT, conn.client.simple_query(""))
+                    .await
+                    .is_ok_and(|res| res.is_ok());
+            if valid {
+                return Some(conn);
+            }
+            release(database);
+        }
+    }
+
+    /// Waits for a connection to `key` to be available under `--max-connections`.
+    async fn acquire(&self, key: &str) -> Option<OwnedSemaphorePermit> {
+        let max_connections = self.inner.max_connections?;
+        let semaphore = self
+            .inner
+            .databases
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_default()
+            .semaphore
+            .get_or_insert_with(|| Arc::new(Semaphore::new(max_connections)))
+            .clone();
+        // The semaphore is never closed
+        semaphore.acquire_owned().await.ok()
+    }
+
+    /// Returns the time left until connecting to `key` is allowed again, if in a backoff.
+    fn backoff(&self, key: &str) -> Option<Duration> {
+        let databases = self.inner.databases.lock().unwrap();
+        let retry_at = databases.get(key)?.retry_at?;
+        retry_at.checked_duration_since(Instant::now())
+    }
+
+    /// Records the result of connecting to `key`, and returns the backoff after a failure.
+    fn record_connect(&self, key: &str, success: bool) -> Duration {
+        let mut databases = self.inner.databases.lock().unwrap();
+        let database = databases.entry(key.to_string()).or_default();
+        if success {
+            database.failures = 0;
+            database.retry_at = None;
+            return Duration::ZERO;
+        }
+        database.failures += 1;
+        let backoff = INITIAL_BACKOFF
+            .saturating_mul(1 << (database.failures - 1).min(16))
+            .min(MAX_BACKOFF);
+        database.retry_at = Some(Instant::now() + backoff);
+        backoff
+    }
+
+    /// Returns a connection to `postgres`, reusing an idle one if any.
+    pub async fn get(&self, postgres: &PgConnectionConfig) -> Result<PooledClient, PoolError> {
+        let key = postgres.pool_key();
+        let database = postgres.database();
+        let started = Instant::now();
+        let permit = self.acquire(&key).await;
+        if let Some(conn) = self.take_idle(&key, &database).await {
+            POOL_WAIT.observe(started.elapsed().as_secs_f64());
+            return Ok(PooledClient::new(conn, key, database, self.clone(), permit));
+        }
+        if let Some(remaining) = self.backoff(&key) {
+            return Err(PoolError::Backoff {
+                address: postgres.raw_address(),
+                remaining,
+            });
+        }
+        let res = postgres.connect().await;
+        POOL_WAIT.observe(started.elapsed().as_secs_f64());
+        match res {
+            Ok(client) => {
+                self.record_connect(&key, true);
+                POOL_SIZE.inc();
+                POOL_CONNECTIONS.with_label_values(&[&database]).inc();
+                let backend_pid = match client.query_one("SELECT pg_backend_pid()", &[]).await {
+                    Ok(row) => row.try_get(0).ok(),
+                    Err(e) => {
+                        tracing::debug!("failed to get the backend PID: {e:#}");
+                        None
+                    }
+                };
+                let conn = Connection {
+                    client,
+                    backend_pid,
+                };
+                Ok(PooledClient::new(conn, key, database, self.clone(), permit))
+            }
+            Err(e) => {
+                let backoff = self.record_connect(&key, false);
+                tracing::debug!(
+                    "failed to connect to {}, not retrying for {backoff:?}",
+                    postgres.raw_address()
+                );
+                POOL_ERRORS.inc();
+                Err(e.into())
+            }
+        }
+    }
+}
+
+pub fn collect() -> Vec<prometheus::proto::MetricFamily> {
+    let mut metrics = POOL_SIZE.collect();
+    metrics.append(&mut POOL_IDLE.collect());
+    metrics.append(&mut POOL_CONNECTIONS.collect());
+    metrics.append(&mut POOL_WAIT.collect());
+    metrics.append(&mut POOL_ERRORS.collect());
+    metrics.retain(|m| !m.get_metric().is_empty());
+    metrics
+}
+
+#[cfg(test)]
+mod tests_pool {
+    use crate::pool::{Pool, PoolError};
+    use crate::postgres_connection::PgConnectionConfig;
+    use std::time::Duration;
+    use url::Host;
+
+    #[test]
+    fn test_backoff() {
+        let pool = Pool::new(None);
+        assert_eq!(pool.backoff("db"), None);
+        let backoffs: Vec<u64> = (0..7)
+            .map(|_| pool.record_connect("db", false).as_secs())
+            .collect();
+        assert_eq!(backoffs, [1, 2, 4, 8, 16, 30, 30]);
+        assert!(pool.backoff("db").is_some());
+        assert_eq!(pool.backoff("other"), None);
+
+        pool.record_connect("db", true);
+        assert_eq!(pool.backoff("db"), None);
+        assert_eq!(pool.record_connect("db", false), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_get_in_backoff() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        // Nothing listens on port 1, so connecting is refused right away
+        let postgres = PgConnectionConfig::new_host_port(Host::parse("127.0.0.1").unwrap(), 1);
+        let pool = Pool::new(Some(1));
+        runtime.block_on(async {
+            assert!(matches!(
+                pool.get(&postgres).await,
+                Err(PoolError::Connect(_))
+            ));
+            // Not retried, and the permit of the failed attempt is released
+            assert!(matches!(
+                pool.get(&postgres).await,
+                Err(PoolError::Backoff { .. })
+            ));
+        });
+    }
+}
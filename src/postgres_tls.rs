@@ -0,0 +1,338 @@
+//!
+//! TLS of the connections to PostgreSQL, with the `sslmode`s of libpq.
+//!
+//! As in libpq, `prefer` and `require` only encrypt the connections and don't verify the
+//! server certificate, except that `require` verifies it like `verify-ca` if a root
+//! certificate is given. `verify-full` also checks the certificate against the host name.
+//!
+use anyhow::bail;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_postgres::tls::{ChannelBinding, MakeTlsConnect, TlsConnect, TlsStream};
+use tokio_rustls::rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use tokio_rustls::rustls::{
+    Certificate, CertificateError, ClientConfig, Error, RootCertStore, ServerName,
+};
+use tokio_rustls::TlsConnector;
+
+use crate::tls_config::{load_certs, load_private_key};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SslMode {
+    #[default]
+    Disable,
+    Prefer,
+    Require,
+    VerifyCa,
+    VerifyFull,
+}
+
+impl SslMode {
+    pub const VALUES: [&'static str; 5] =
+        ["disable", "prefer", "require", "verify-ca", "verify-full"];
+}
+
+impl FromStr for SslMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "disable" => SslMode::Disable,
+            "prefer" => SslMode::Prefer,
+            "require" => SslMode::Require,
+            "verify-ca" => SslMode::VerifyCa,
+            "verify-full" => SslMode::VerifyFull,
+            _ => bail!("unknown sslmode `{s}`"),
+        })
+    }
+}
+
+impl fmt::Display for SslMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            SslMode::Disable => "disable",
+            SslMode::Prefer => "prefer",
+            SslMode::Require => "require",
+            SslMode::VerifyCa => "verify-ca",
+            SslMode::VerifyFull => "verify-full",
+        })
+    }
+}
+
+impl From<SslMode> for tokio_postgres::config::SslMode {
+    fn from(mode: SslMode) -> Self {
+        // The verification is up to the certificate verifier of `PgTls`
+        match mode {
+            SslMode::Disable => tokio_postgres::config::SslMode::Disable,
+            SslMode::Prefer => tokio_postgres::config::SslMode::Prefer,
+            _ => tokio_postgres::config::SslMode::Require,
+        }
+    }
+}
+
+/// The TLS parameters of a connection string, overridden by the command line options.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SslParams {
+    pub mode: Option<SslMode>,
+    pub root_cert: Option<PathBuf>,
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+}
+
+impl SslParams {
+    /// Sets the parameter `key` to `value`, returning false if it isn't a TLS parameter.
+    pub fn set(&mut self, key: &str, value: &str) -> anyhow::Result<bool> {
+        match key {
+            "sslmode" => self.mode = Some(value.parse()?),
+            "sslrootcert" => self.root_cert = Some(value.into()),
+            "sslcert" => self.cert = Some(value.into()),
+            "sslkey" => self.key = Some(value.into()),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Builds the settings, with `prefer` if no mode is given as libpq.
+    pub fn load(&self) -> anyhow::Result<PgTls> {
+        let client_cert = match (&self.cert, &self.key) {
+            (Some(cert), Some(key)) => Some((cert.as_path(), key.as_path())),
+            (None, None) => None,
+            _ => bail!("sslcert and sslkey must be given together"),
+        };
+        PgTls::load(
+            self.mode.unwrap_or(SslMode::Prefer),
+            self.root_cert.as_deref(),
+            client_cert,
+        )
+    }
+}
+
+/// The TLS settings of connections, which is a `MakeTlsConnect` of `tokio_postgres`.
+/// The clones share the settings, so that those replaced by `replace` are used by the next
+/// connections of every clone.
+#[derive(Clone, Default)]
+pub struct PgTls {
+    mode: SslMode,
+    /// `None` if `mode` is `disable`.
+    config: Arc<RwLock<Option<Arc<ClientConfig>>>>,
+}
+
+impl PgTls {
+    /// Builds the settings of `mode` with a root certificate to verify the server with and
+    /// a pair of a certificate and a private key to authenticate the client with, in PEM.
+    pub fn load(
+        mode: SslMode,
+        root_cert: Option<&Path>,
+        client_cert: Option<(&Path, &Path)>,
+    ) -> anyhow::Result<Self> {
+        let verifier: Arc<dyn ServerCertVerifier> = match (mode, root_cert) {
+            (SslMode::Disable, _) => return Ok(PgTls::default()),
+            (SslMode::VerifyCa | SslMode::VerifyFull, None) => {
+                bail!("sslmode `{mode}` requires a root certificate")
+            }
+            (SslMode::Prefer, _) | (SslMode::Require, None) => Arc::new(NoVerifier),
+            (SslMode::Require | SslMode::VerifyCa, Some(path)) => {
+                Arc::new(CaVerifier(WebPkiVerifier::new(load_roots(path)?, None)))
+            }
+            (SslMode::VerifyFull, Some(path)) => {
+                Arc::new(WebPkiVerifier::new(load_roots(path)?, None))
+            }
+        };
+        let builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(verifier);
+        let config = match client_cert {
+            Some((cert, key)) => {
+                builder.with_client_auth_cert(load_certs(cert)?, load_private_key(key)?)?
+            }
+            None => builder.with_no_client_auth(),
+        };
+        Ok(PgTls {
+            mode,
+            config: Arc::new(RwLock::new(Some(Arc::new(config)))),
+        })
+    }
+
+    pub fn mode(&self) -> SslMode {
+        self.mode
+    }
+
+    /// Replaces the settings with those of `other`, e.g., loaded again from rotated
+    /// certificates. The mode is kept.
+    pub fn replace(&self, other: &PgTls) {
+        let config = other.config.read().unwrap().clone();
+        *self.config.write().unwrap() = config;
+    }
+}
+
+fn load_roots(path: &Path) -> anyhow::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots.add(&cert)?;
+    }
+    Ok(roots)
+}
+
+/// Accepts any server certificate, for `prefer` and `require`.
+struct NoVerifier;
+
+impl ServerCertVerifier for NoVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// Verifies that the server certificate is issued by a root certificate but not the host
+/// name, for `verify-ca`.
+struct CaVerifier(WebPkiVerifier);
+
+impl ServerCertVerifier for CaVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        match self.0.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        ) {
+            // The name is checked only after the chain is verified
+            Err(Error::InvalidCertificate(CertificateError::NotValidForName)) => {
+                Ok(ServerCertVerified::assertion())
+            }
+            res => res,
+        }
+    }
+}
+
+impl<S> MakeTlsConnect<S> for PgTls
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Stream = RustlsStream<S>;
+    type TlsConnect = RustlsConnect;
+    type Error = io::Error;
+
+    fn make_tls_connect(&mut self, domain: &str) -> io::Result<RustlsConnect> {
+        // Called even if TLS is disabled, in which case `connect` is never called, e.g., with
+        // the empty domain of a Unix-domain socket
+        let config = self.config.read().unwrap().clone();
+        let Some(config) = config.filter(|_| !domain.is_empty()) else {
+            return Ok(RustlsConnect(None));
+        };
+        // IPv6 addresses are given in brackets
+        let domain = domain.trim_start_matches('[').trim_end_matches(']');
+        let server_name = ServerName::try_from(domain)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        Ok(RustlsConnect(Some((
+            TlsConnector::from(config),
+            server_name,
+        ))))
+    }
+}
+
+pub struct RustlsConnect(Option<(TlsConnector, ServerName)>);
+
+impl<S> TlsConnect<S> for RustlsConnect
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Stream = RustlsStream<S>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<RustlsStream<S>>> + Send>>;
+
+    fn connect(self, stream: S) -> Self::Future {
+        Box::pin(async move {
+            let Some((connector, server_name)) = self.0 else {
+                return Err(io::Error::new(io::ErrorKind::Other, "TLS is disabled"));
+            };
+            let stream = connector.connect(server_name, stream).await?;
+            Ok(RustlsStream(stream))
+        })
+    }
+}
+
+pub struct RustlsStream<S>(tokio_rustls::client::TlsStream<S>);
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for RustlsStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for RustlsStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> TlsStream for RustlsStream<S> {
+    fn channel_binding(&self) -> ChannelBinding {
+        ChannelBinding::none()
+    }
+}
+
+#[cfg(test)]
+mod tests_postgres_tls {
+    use crate::postgres_tls::{PgTls, SslMode};
+
+    #[test]
+    fn test_load() {
+        for value in SslMode::VALUES {
+            let mode: SslMode = value.parse().unwrap();
+            assert_eq!(mode.to_string(), value);
+        }
+        assert!("allow".parse::<SslMode>().is_err());
+
+        assert!(PgTls::load(SslMode::Disable, None, None).is_ok());
+        assert!(PgTls::load(SslMode::Require, None, None).is_ok());
+        assert!(PgTls::load(SslMode::VerifyCa, None, None).is_err());
+        assert!(PgTls::load(SslMode::VerifyFull, None, None).is_err());
+
+        // The clones share the replaced settings
+        let tls = PgTls::load(SslMode::Disable, None, None).unwrap();
+        let clone = tls.clone();
+        tls.replace(&PgTls::load(SslMode::Require, None, None).unwrap());
+        assert!(clone.config.read().unwrap().is_some());
+    }
+}
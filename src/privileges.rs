@@ -0,0 +1,221 @@
+//!
+//! Checks the privileges the collectors require.
+//!
+//! Missing privileges otherwise show up later as opaque query errors or, worse, as silently
+//! incomplete metrics, e.g., `pg_stat_activity` hides the state of other users' sessions.
+//! The check runs at startup, logs a report per collector with the statement to fix it,
+//! and exports `pg_exporter_collector_privileges_ok`.
+//!
+//! For functions the role can't execute, e.g., the superuser-only ones of pg_statsinfo for
+//! a plain `pg_monitor` role, the collectors call the security-definer helpers created by
+//! `bootstrap --helpers` instead, or are skipped if no helper is available.
+//!
+//! See <https://www.postgresql.org/docs/15/predefined-roles.html>
+//!
+use prometheus::{core::Collector, IntGaugeVec, Opts};
+use std::collections::HashMap;
+use tokio_postgres::{Client, Error};
+
+use crate::bootstrap::{helper_name, HELPER_SCHEMA};
+use crate::help;
+use crate::metrics::{self, CollectorOptions};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Requirement {
+    /// Membership in a predefined role, all of which `pg_monitor` is a member of.
+    Role(&'static str),
+    /// EXECUTE privilege on a function, given by its signature.
+    Function(&'static str),
+}
+
+impl Requirement {
+    /// Returns the statement granting the requirement to `user`.
+    pub fn grant_statement(&self, user: &str) -> String {
+        match self {
+            Requirement::Role(_) => format!("GRANT pg_monitor TO {user};"),
+            Requirement::Function(f) => match f.split_once('.') {
+                Some((schema, _)) => format!(
+                    "GRANT USAGE ON SCHEMA {schema} TO {user}; GRANT EXECUTE ON FUNCTION {f} TO {user};"
+                ),
+                None => format!("GRANT EXECUTE ON FUNCTION {f} TO {user};"),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Requirement {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Requirement::Role(role) => write!(f, "membership in {role}"),
+            Requirement::Function(func) => write!(f, "EXECUTE on {func}"),
+        }
+    }
+}
+
+/// Returns the privileges the collector `name` requires to export complete metrics with
+/// `options`.
+pub fn requirements(name: &str, options: &CollectorOptions) -> &'static [Requirement] {
+    match name {
+        // The data directory is read from the settings unless given
+        "filesystem" if options.data_directory.is_some() => &[],
+        "cpustats" => &[Requirement::Function("statsinfo.cpustats()")],
+        "tablespaces" => &[Requirement::Function("statsinfo.tablespaces()")],
+        "loadavg" => &[Requirement::Function("statsinfo.loadavg()")],
+        "memory" => &[Requirement::Function("statsinfo.memory()")],
+        "devicestats" => &[Requirement::Function("statsinfo.devicestats()")],
+        "settings" | "filesystem" => &[Requirement::Role("pg_read_all_settings")],
+        "hba_file" => &[Requirement::Function("pg_hba_file_rules()")],
+        "idle_in_transaction" | "query_runtime" => &[Requirement::Role("pg_read_all_stats")],
+        _ => &[],
+    }
+}
+
+/// Returns true if the function `signature` exists and is executable.
+async fn can_execute(conn: &Client, signature: &str) -> Result<bool, Error> {
+    // Resolving a function in a schema without USAGE fails, so check it first
+    if let Some((schema, _)) = signature.split_once('.') {
+        let row = conn
+            .query_one(
+                "SELECT COALESCE(has_schema_privilege(to_regnamespace($1)::oid, 'USAGE'), false)",
+                &[&schema],
+            )
+            .await?;
+        if !row.get::<_, bool>(0) {
+            return Ok(false);
+        }
+    }
+    let row = conn
+        .query_one(
+            "SELECT COALESCE(has_function_privilege(to_regprocedure($1)::oid, 'EXECUTE'), false)",
+            &[&signature],
+        )
+        .await?;
+    Ok(row.get(0))
+}
+
+async fn is_satisfied(conn: &Client, requirement: &Requirement) -> Result<bool, Error> {
+    match requirement {
+        Requirement::Role(role) => Ok(conn
+            .query_one("SELECT pg_has_role($1, 'USAGE')", &[role])
+            .await?
+            .get(0)),
+        // A missing function is reported as well, since the collector fails the same way
+        Requirement::Function(f) => can_execute(conn, f).await,
+    }
+}
+
+pub struct PrivilegeReport {
+    /// The role checked, quoted as an identifier.
+    user: String,
+    /// Enabled collectors having requirements, with the ones not satisfied.
+    collectors: Vec<(&'static str, Vec<Requirement>)>,
+    /// Functions the role can't execute, mapped to the helpers replacing them if any.
+    function_fallbacks: HashMap<&'static str, Option<String>>,
+}
+
+impl PrivilegeReport {
+    /// Checks the requirements of the collectors enabled by `options`.
+    pub async fn check(conn: &Client, options: &CollectorOptions) -> Result<Self, Error> {
+        let user = conn
+            .query_one("SELECT quote_ident(current_user)", &[])
+            .await?
+            .get(0);
+        let mut collectors = vec![];
+        let mut function_fallbacks = HashMap::new();
+        for name in metrics::COLLECTORS.iter() {
+            if !metrics::is_enabled(name, options) || requirements(name, options).is_empty() {
+                continue;
+            }
+            let mut missing = vec![];
+            for requirement in requirements(name, options) {
+                if is_satisfied(conn, requirement).await? {
+                    continue;
+                }
+                // Fall back to the helper created by `bootstrap --helpers` if executable
+                if let Requirement::Function(f) = requirement {
+                    let helper = format!("{HELPER_SCHEMA}.{}()", helper_name(f));
+                    if can_execute(conn, &helper).await? {
+                        function_fallbacks.insert(*f, Some(helper));
+                        continue;
+                    }
+                    function_fallbacks.insert(*f, None);
+                }
+                missing.push(*requirement);
+            }
+            collectors.push((*name, missing));
+        }
+        Ok(PrivilegeReport {
+            user,
+            collectors,
+            function_fallbacks,
+        })
+    }
+
+    /// Returns the functions to replace in `CollectorOptions::function_fallbacks`, so that
+    /// the collectors call the helpers instead or are skipped rather than failing.
+    pub fn function_fallbacks(&self) -> HashMap<&'static str, Option<String>> {
+        self.function_fallbacks.clone()
+    }
+
+    /// Logs the missing privileges of every collector with statements granting them.
+    pub fn log(&self) {
+        for (f, fallback) in &self.function_fallbacks {
+            if let Some(helper) = fallback {
+                tracing::info!("calling {helper} instead of {f} lacking EXECUTE on it");
+            }
+        }
+        let mut ok = true;
+        for (name, missing) in &self.collectors {
+            for requirement in missing {
+                ok = false;
+                tracing::warn!(
+                    collector = name,
+                    "missing {requirement}, metrics may be incomplete or fail; run `{}`",
+                    requirement.grant_statement(&self.user)
+                );
+            }
+        }
+        if ok {
+            tracing::info!("all the enabled collectors have the required privileges");
+        }
+    }
+
+    pub fn collect(&self) -> Vec<prometheus::proto::MetricFamily> {
+        let m = IntGaugeVec::new(
+            Opts::new(
+                "pg_exporter_collector_privileges_ok",
+                help::PG_EXPORTER_COLLECTOR_PRIVILEGES_OK,
+            ),
+            &["collector"],
+        )
+        .unwrap();
+        for (name, missing) in &self.collectors {
+            m.with_label_values(&[name]).set(missing.is_empty() as i64);
+        }
+        let mut metrics = m.collect();
+        metrics.retain(|m| !m.get_metric().is_empty());
+        metrics
+    }
+}
+
+#[cfg(test)]
+mod tests_requirement {
+    use crate::privileges::Requirement;
+
+    #[test]
+    fn test_grant_statement() {
+        assert_eq!(
+            Requirement::Role("pg_read_all_stats").grant_statement("mon"),
+            "GRANT pg_monitor TO mon;"
+        );
+        assert_eq!(
+            Requirement::Function("pg_hba_file_rules()").grant_statement("mon"),
+            "GRANT EXECUTE ON FUNCTION pg_hba_file_rules() TO mon;"
+        );
+        assert_eq!(
+            Requirement::Function("statsinfo.cpustats()").grant_statement("mon"),
+            "GRANT USAGE ON SCHEMA statsinfo TO mon; \
+             GRANT EXECUTE ON FUNCTION statsinfo.cpustats() TO mon;"
+        );
+    }
+}
@@ -0,0 +1,161 @@
+//!
+//! Target validation of `/probe`.
+//!
+//! `/probe?target=<host>[:<port>]` collects from a PostgreSQL server other than the one
+//! configured, so that a single exporter can serve many servers like the multi-target
+//! exporters of Prometheus. Since it connects wherever it is asked to, a target must be
+//! allowed by `--probe-allowed-targets`, or the exporter could be used to reach arbitrary
+//! hosts of the internal network.
+//!
+use anyhow::bail;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::str::FromStr;
+use url::Host;
+
+use crate::client_addr::IpCidr;
+use crate::postgres_connection::parse_host_port;
+
+/// A target allowed by `--probe-allowed-targets`: a network in the CIDR notation with an
+/// optional port, e.g., `10.0.0.0/8:6432` or `[fd00::/8]:6432`, or a host name with an
+/// optional port. A network allows only the default port of PostgreSQL if no port is
+/// given, so that it can't be used to scan the other services of its hosts.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AllowedTarget {
+    Network(IpCidr, Option<u16>),
+    Host(String, Option<u16>),
+}
+
+/// Port of a target if not given.
+const DEFAULT_PORT: u16 = 5432;
+
+impl FromStr for AllowedTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(network) = s.parse::<IpCidr>() {
+            return Ok(AllowedTarget::Network(network, None));
+        }
+        let network_port = match s.strip_prefix('[') {
+            Some(rest) => rest.split_once("]:"),
+            None => s.rsplit_once(':'),
+        };
+        if let Some((network, port)) = network_port {
+            if let Ok(network) = network.parse::<IpCidr>() {
+                let port = port
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("invalid port in `{s}`"))?;
+                return Ok(AllowedTarget::Network(network, Some(port)));
+            }
+        }
+        match parse_host_port(s)? {
+            (Host::Domain(name), port) => Ok(AllowedTarget::Host(name.to_ascii_lowercase(), port)),
+            _ => bail!("expected a network in the CIDR notation or a host name, got `{s}`"),
+        }
+    }
+}
+
+/// Returns the host and the port to connect to for `target`, or why it is not allowed.
+///
+/// A host name that is not allowed by name is resolved, and allowed only if all of its
+/// addresses are. Then the first address is returned instead of the name, so that the name
+/// can't resolve to another address, e.g., by DNS rebinding, when connecting.
+pub fn resolve(target: &str, allowed: &[AllowedTarget]) -> anyhow::Result<(Host, u16)> {
+    let (host, port) = parse_host_port(target)?;
+    let port = port.unwrap_or(DEFAULT_PORT);
+    if let Host::Domain(name) = &host {
+        let allowed_by_name = allowed.iter().any(|a| match a {
+            AllowedTarget::Host(n, p) => {
+                n.eq_ignore_ascii_case(name) && p.map_or(true, |p| p == port)
+            }
+            AllowedTarget::Network(..) => false,
+        });
+        if allowed_by_name {
+            return Ok((host, port));
+        }
+    }
+
+    let addrs: Vec<IpAddr> = match &host {
+        Host::Ipv4(addr) => vec![IpAddr::V4(*addr)],
+        Host::Ipv6(addr) => vec![IpAddr::V6(*addr)],
+        Host::Domain(name) => (name.as_str(), port)
+            .to_socket_addrs()?
+            .map(|addr| addr.ip())
+            .collect(),
+    };
+    let denied = addrs.iter().find(|addr| {
+        !allowed.iter().any(|a| match a {
+            AllowedTarget::Network(network, p) => {
+                network.contains(**addr) && p.unwrap_or(DEFAULT_PORT) == port
+            }
+            AllowedTarget::Host(..) => false,
+        })
+    });
+    match (addrs.first(), denied) {
+        (_, Some(addr)) => bail!("port {port} of {addr} is not allowed by --probe-allowed-targets"),
+        (None, None) => bail!("`{target}` resolves to no address"),
+        (Some(IpAddr::V4(addr)), None) => Ok((Host::Ipv4(*addr), port)),
+        (Some(IpAddr::V6(addr)), None) => Ok((Host::Ipv6(*addr), port)),
+    }
+}
+
+#[cfg(test)]
+mod tests_resolve {
+    use crate::probe::{resolve, AllowedTarget};
+    use url::Host;
+
+    fn allowed(targets: &[&str]) -> Vec<AllowedTarget> {
+        targets.iter().map(|t| t.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(matches!(
+            "10.0.0.0/8".parse::<AllowedTarget>().unwrap(),
+            AllowedTarget::Network(_, None)
+        ));
+        assert!(matches!(
+            "10.0.0.0/8:6432".parse::<AllowedTarget>().unwrap(),
+            AllowedTarget::Network(_, Some(6432))
+        ));
+        assert!(matches!(
+            "[fd00::/8]:6432".parse::<AllowedTarget>().unwrap(),
+            AllowedTarget::Network(_, Some(6432))
+        ));
+        assert_eq!(
+            "DB.example.com:6432".parse::<AllowedTarget>().unwrap(),
+            AllowedTarget::Host("db.example.com".to_string(), Some(6432))
+        );
+        assert!("10.0.0.0/8:x".parse::<AllowedTarget>().is_err());
+    }
+
+    #[test]
+    fn test_network() {
+        let allowed = allowed(&["10.0.0.0/8", "[::1]:6432"]);
+        assert_eq!(
+            resolve("10.1.2.3", &allowed).unwrap(),
+            (Host::Ipv4([10, 1, 2, 3].into()), 5432)
+        );
+        assert_eq!(resolve("[::1]:6432", &allowed).unwrap().1, 6432);
+        assert!(resolve("10.1.2.3:22", &allowed).is_err());
+        assert!(resolve("::1", &allowed).is_err());
+        assert!(resolve("192.168.0.1", &allowed).is_err());
+        assert!(resolve("169.254.169.254:80", &allowed).is_err());
+    }
+
+    #[test]
+    fn test_host_name() {
+        let allowed = allowed(&["db.example.com", "pooler.example.com:6432"]);
+        assert_eq!(
+            resolve("db.example.com:5433", &allowed).unwrap(),
+            (Host::Domain("db.example.com".to_string()), 5433)
+        );
+        assert!(resolve("pooler.example.com:6432", &allowed).is_ok());
+        assert!(resolve("pooler.example.com", &allowed).is_err());
+        assert!(resolve("10.0.0.1", &allowed).is_err());
+    }
+
+    #[test]
+    fn test_nothing_allowed() {
+        assert!(resolve("127.0.0.1", &[]).is_err());
+    }
+}
@@ -0,0 +1,92 @@
+//!
+//! Text of the statements in `pg_stat_statements` by queryid, served on
+//! `/queries/{queryid}`, so that dashboards can link from the queryid of a metric to the SQL
+//! without access to the database.
+//!
+use serde::Serialize;
+use tokio_postgres::{Client, Error};
+
+use crate::metrics::has_extension;
+use crate::redaction;
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct QueryText {
+    /// As text, since JSON numbers lose the precision of 64-bit integers in JavaScript.
+    pub queryid: String,
+    /// Normalized text of the statement, which is `<insufficient privilege>` if the role
+    /// lacks `pg_read_all_stats` and didn't execute the statement.
+    pub query: String,
+    /// Whether `query` is truncated to the maximum length.
+    pub truncated: bool,
+    /// Databases the statement was executed in.
+    pub datnames: Vec<String>,
+}
+
+/// Returns the text of the statement `queryid`, redacted and truncated to `max_length`
+/// characters, or `None` if `pg_stat_statements` has no such statement or isn't installed.
+pub async fn lookup(
+    conn: &Client,
+    queryid: i64,
+    max_length: usize,
+) -> Result<Option<QueryText>, Error> {
+    if !has_extension(conn, "pg_stat_statements").await? {
+        return Ok(None);
+    }
+    // An entry exists per user and database, whose texts may differ only in whitespace
+    let row = conn
+        .query_one(
+            "
+        SELECT
+            (array_agg(stats.query))[1],
+            array_agg(DISTINCT db.datname::text)
+        FROM
+            pg_stat_statements AS stats
+            JOIN pg_database AS db ON db.oid = stats.dbid
+        WHERE
+            stats.queryid = $1
+    ",
+            &[&queryid],
+        )
+        .await?;
+    let Some(query) = row.get::<_, Option<String>>(0) else {
+        return Ok(None);
+    };
+    let (query, truncated) = truncate(redaction::redact_sql(&query).into_owned(), max_length);
+    Ok(Some(QueryText {
+        queryid: queryid.to_string(),
+        query,
+        truncated,
+        datnames: row.get(1),
+    }))
+}
+
+fn truncate(mut s: String, max_length: usize) -> (String, bool) {
+    match s.char_indices().nth(max_length) {
+        Some((end, _)) => {
+            s.truncate(end);
+            (s, true)
+        }
+        None => (s, false),
+    }
+}
+
+#[cfg(test)]
+mod tests_queries {
+    use crate::queries::truncate;
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(
+            truncate("SELECT 1".to_string(), 8),
+            ("SELECT 1".to_string(), false)
+        );
+        assert_eq!(
+            truncate("SELECT 1".to_string(), 6),
+            ("SELECT".to_string(), true)
+        );
+        assert_eq!(
+            truncate("SELECT 'é'".to_string(), 9),
+            ("SELECT 'é".to_string(), true)
+        );
+    }
+}
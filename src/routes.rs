@@ -1,16 +1,42 @@
-use hyper::{header::CONTENT_TYPE, Body, Method, Request, Response, StatusCode};
-use prometheus::{Encoder, TextEncoder};
+use hyper::header::{
+    HeaderValue, ACCESS_CONTROL_ALLOW_ORIGIN, CACHE_CONTROL, CONTENT_TYPE, ORIGIN, RETRY_AFTER,
+    VARY,
+};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use once_cell::sync::{Lazy, OnceCell};
+use prometheus::core::Collector;
+use prometheus::{Counter, Encoder, Histogram, HistogramOpts, IntCounter, IntGauge, TextEncoder};
 use routerify::ext::RequestExt;
 use routerify::{RouteError, Router, RouterBuilder};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::error::Error as StdError;
 use std::future::Future;
-use std::sync::Arc;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 use thiserror::Error;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use tracing::{self, debug, error, info, info_span, instrument, Instrument};
 
-use crate::metrics;
+use crate::auth_modules::AuthModules;
+use crate::background::BackgroundCollector;
+use crate::cancellation::{self, CancelReason, ScrapeCancellation};
+use crate::client_addr::{self, IpCidr};
+use crate::federation::Federation;
+use crate::help;
+use crate::history::{self, History};
+use crate::leader_election::LeaderElection;
+use crate::memory;
+use crate::metrics::{self, CollectorOptions, ScrapeTimestamps};
+use crate::pgpass;
+use crate::pool::{self, Pool};
 use crate::postgres_connection::PgConnectionConfig;
+use crate::privileges::PrivilegeReport;
+use crate::probe::{self, AllowedTarget};
+use crate::queries;
+use crate::server_metrics;
 
 #[derive(Debug, Error)]
 pub enum ApiError {
@@ -32,52 +58,98 @@ pub enum ApiError {
     #[error("Precondition failed: {0}")]
     PreconditionFailed(Box<str>),
 
+    #[error("Too many requests: {msg}")]
+    TooManyRequests {
+        msg: String,
+        retry_after: Option<Duration>,
+    },
+
+    #[error("Service unavailable: {msg}")]
+    ServiceUnavailable {
+        msg: String,
+        retry_after: Option<Duration>,
+    },
+
+    #[error("Gateway timeout: {0}")]
+    GatewayTimeout(String),
+
     #[error(transparent)]
     InternalServerError(anyhow::Error),
 }
 
 impl ApiError {
-    pub fn into_response(self) -> Response<Body> {
+    /// Returns the stable identifier of the error kind, so that clients can branch on it
+    /// instead of parsing messages.
+    pub fn code(&self) -> &'static str {
         match self {
-            ApiError::BadRequest(err) => HttpErrorBody::response_from_msg_and_status(
-                format!("{err:#?}"), // use debug printing so that we give the cause
-                StatusCode::BAD_REQUEST,
-            ),
-            ApiError::Forbidden(_) => {
-                HttpErrorBody::response_from_msg_and_status(self.to_string(), StatusCode::FORBIDDEN)
-            }
-            ApiError::Unauthorized(_) => HttpErrorBody::response_from_msg_and_status(
-                self.to_string(),
-                StatusCode::UNAUTHORIZED,
-            ),
-            ApiError::NotFound(_) => {
-                HttpErrorBody::response_from_msg_and_status(self.to_string(), StatusCode::NOT_FOUND)
-            }
-            ApiError::Conflict(_) => {
-                HttpErrorBody::response_from_msg_and_status(self.to_string(), StatusCode::CONFLICT)
-            }
-            ApiError::PreconditionFailed(_) => HttpErrorBody::response_from_msg_and_status(
-                self.to_string(),
-                StatusCode::PRECONDITION_FAILED,
-            ),
-            ApiError::InternalServerError(err) => HttpErrorBody::response_from_msg_and_status(
-                err.to_string(),
-                StatusCode::INTERNAL_SERVER_ERROR,
-            ),
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::PreconditionFailed(_) => "precondition_failed",
+            ApiError::TooManyRequests { .. } => "too_many_requests",
+            ApiError::ServiceUnavailable { .. } => "service_unavailable",
+            ApiError::GatewayTimeout(_) => "gateway_timeout",
+            ApiError::InternalServerError(_) => "internal_server_error",
         }
     }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            ApiError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::ServiceUnavailable { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::GatewayTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            ApiError::InternalServerError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn into_response(self, request_id: &str) -> Response<Body> {
+        let msg = match &self {
+            // use debug printing so that we give the cause
+            ApiError::BadRequest(err) => format!("{err:#?}"),
+            ApiError::InternalServerError(err) => err.to_string(),
+            _ => self.to_string(),
+        };
+        let body = HttpErrorBody {
+            msg,
+            code: self.code().to_string(),
+            request_id: request_id.to_string(),
+        };
+        let mut response = body.to_response(self.status());
+        if let ApiError::TooManyRequests {
+            retry_after: Some(retry_after),
+            ..
+        }
+        | ApiError::ServiceUnavailable {
+            retry_after: Some(retry_after),
+            ..
+        } = self
+        {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(retry_after.as_secs()));
+        }
+        response
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 struct HttpErrorBody {
     pub msg: String,
+    /// One of `ApiError::code`.
+    pub code: String,
+    /// Identifies the request in the exporter's logs, empty if unknown.
+    pub request_id: String,
 }
 
 impl HttpErrorBody {
-    pub fn response_from_msg_and_status(msg: String, status: StatusCode) -> Response<Body> {
-        HttpErrorBody { msg }.to_response(status)
-    }
-
     pub fn to_response(&self, status: StatusCode) -> Response<Body> {
         Response::builder()
             .status(status)
@@ -91,15 +163,22 @@ impl HttpErrorBody {
 #[derive(Debug, Default, Clone)]
 struct RequestId(String);
 
+const X_REQUEST_ID: &str = "x-request-id";
+
+/// Scrape timeout Prometheus sends with every scrape.
+const X_PROMETHEUS_SCRAPE_TIMEOUT_SECONDS: &str = "x-prometheus-scrape-timeout-seconds";
+
 struct RequestCancelled {
     warn: Option<tracing::Span>,
+    cancellation: ScrapeCancellation,
 }
 
 impl RequestCancelled {
     /// Create the drop guard using the [`tracing::Span::current`] as the span.
-    fn warn_when_dropped_without_responding() -> Self {
+    fn warn_when_dropped_without_responding(cancellation: ScrapeCancellation) -> Self {
         RequestCancelled {
             warn: Some(tracing::Span::current()),
+            cancellation,
         }
     }
 
@@ -109,6 +188,24 @@ impl RequestCancelled {
     }
 }
 
+impl Drop for RequestCancelled {
+    fn drop(&mut self) {
+        if let Some(span) = self.warn.take() {
+            span.in_scope(|| tracing::warn!("request was dropped before completing"));
+            // Nobody waits for the collectors anymore, so stop the query in flight rather
+            // than leaving the database executing it
+            self.cancellation.cancel();
+            if let Ok(handle) = tokio::runtime::Handle::try_current() {
+                let cancellation = self.cancellation.clone();
+                handle.spawn(
+                    async move { cancellation.cancel_queries(CancelReason::Disconnect).await }
+                        .instrument(span),
+                );
+            }
+        }
+    }
+}
+
 /// Adds a tracing info_span! instrumentation around the handler events,
 /// logs the request start and end events for non-GET requests and non-200 responses.
 ///
@@ -141,19 +238,48 @@ impl RequestCancelled {
 /// tries to achive with its `.instrument` used in the current approach.
 ///
 /// If needed, a declarative macro to substitute the |r| ... closure boilerplate could be introduced.
+#[allow(clippy::doc_lazy_continuation)]
 async fn request_span<R, H>(request: Request<Body>, handler: H) -> R::Output
 where
     R: Future<Output = Result<Response<Body>, ApiError>> + Send + 'static,
     H: FnOnce(Request<Body>) -> R + Send + Sync + 'static,
 {
-    let request_id = request.context::<RequestId>().unwrap_or_default().0;
+    // Reuse the ID assigned by a proxy in front, so that both logs can be correlated
+    let request_id = match request.context::<RequestId>() {
+        Some(RequestId(id)) if !id.is_empty() => id,
+        _ => request
+            .headers()
+            .get(X_REQUEST_ID)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("{:016x}", rand::random::<u64>())),
+    };
     let method = request.method();
     let path = request.uri().path();
-    let request_span = info_span!("request", %method, %path, %request_id);
+    let client = client_addr::client_addr(
+        request.remote_addr().ip(),
+        request.headers(),
+        &get_state(&request).trusted_proxies,
+    );
+    let request_span = info_span!("request", %method, %path, %request_id, %client);
+    let allowed_origin = allowed_origin(&request);
+
+    // Collectors are cancelled once the client disconnects or stops waiting
+    let deadline = request
+        .headers()
+        .get(X_PROMETHEUS_SCRAPE_TIMEOUT_SECONDS)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<f64>().ok())
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .or(get_state(&request).scrape_timeout)
+        .map(|timeout| std::time::Instant::now() + timeout);
+    let cancellation = ScrapeCancellation::new(deadline);
+    request.set_context(cancellation.clone());
 
     let log_quietly = method == Method::GET;
     async move {
-        let cancellation_guard = RequestCancelled::warn_when_dropped_without_responding();
+        let cancellation_guard =
+            RequestCancelled::warn_when_dropped_without_responding(cancellation);
         if log_quietly {
             debug!("Handling request");
         } else {
@@ -176,7 +302,7 @@ where
         // (Because we convert errors to Ok response, we never actually return an error,
         // and we could declare the function to return the never type (`!`). However,
         // using `routerify::RouterBuilder` requires a proper error type.)
-        match res {
+        let mut response = match res {
             Ok(response) => {
                 let response_status = response.status();
                 if log_quietly && response_status.is_success() {
@@ -184,26 +310,634 @@ where
                 } else {
                     info!("Request handled, status: {response_status}");
                 }
-                Ok(response)
+                response
             }
-            Err(err) => Ok(api_error_handler(err)),
+            Err(err) => api_error_handler(err, &request_id),
+        };
+        if let Some(origin) = allowed_origin {
+            let headers = response.headers_mut();
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+            headers.insert(VARY, HeaderValue::from_static("Origin"));
         }
+        Ok(response)
     }
     .instrument(request_span)
     .await
 }
 
+/// Returns the value of `Access-Control-Allow-Origin` if the request comes from an origin
+/// allowed by `State::cors_allowed_origins`, so that in-browser tooling can read responses.
+fn allowed_origin(request: &Request<Body>) -> Option<HeaderValue> {
+    let origin = request.headers().get(ORIGIN)?;
+    let allowed = &get_state(request).cors_allowed_origins;
+    if allowed.iter().any(|o| o == "*") {
+        Some(HeaderValue::from_static("*"))
+    } else if allowed.iter().any(|o| o.as_bytes() == origin.as_bytes()) {
+        Some(origin.clone())
+    } else {
+        None
+    }
+}
+
+type Handler =
+    fn(Request<Body>) -> Pin<Box<dyn Future<Output = Result<Response<Body>, ApiError>> + Send>>;
+
+/// A GET route registered by `make_router`, described in `/api/openapi.json`.
+struct RouteSpec {
+    /// Path with parameters as `{name}`, as in OpenAPI.
+    path: &'static str,
+    summary: &'static str,
+    content_type: &'static str,
+    handler: Handler,
+    /// Error statuses the route may respond with, with `HttpErrorBody`.
+    errors: &'static [(StatusCode, &'static str)],
+}
+
+const ROUTES: &[RouteSpec] = &[
+    RouteSpec {
+        path: "/",
+        summary: "HTML page linking the endpoints, with the version and the target of the exporter",
+        content_type: "text/html; charset=utf-8",
+        handler: |r| Box::pin(landing_handler(r)),
+        errors: &[],
+    },
+    RouteSpec {
+        path: "/metrics",
+        summary: "Metrics of PostgreSQL in the Prometheus text format",
+        content_type: "text/plain; version=0.0.4",
+        handler: |r| Box::pin(prometheus_metrics_handler(r)),
+        errors: &[(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Too many scrapes in flight, or PostgreSQL is unreachable with `--scrape-error-behavior=unavailable`",
+        )],
+    },
+    RouteSpec {
+        path: "/diff",
+        summary: "Increase of every counter over `window` (e.g., `30s`, `5m` or `1h`, defaults to `5m`) as JSON",
+        content_type: "application/json",
+        handler: |r| Box::pin(diff_handler(r)),
+        errors: &[
+            (StatusCode::BAD_REQUEST, "Invalid window"),
+            (StatusCode::NOT_FOUND, "History is disabled without `--diff-retention`"),
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "No earlier collection is recorded yet, or too many scrapes in flight",
+            ),
+        ],
+    },
+    RouteSpec {
+        path: "/probe",
+        summary: "Metrics of the PostgreSQL server `target` (`<host>[:<port>]`) in the Prometheus text format, connecting with the credential profile `auth_module` if given",
+        content_type: "text/plain; version=0.0.4",
+        handler: |r| Box::pin(probe_handler(r)),
+        errors: &[
+            (StatusCode::BAD_REQUEST, "No target is given, or `auth_module` is unknown"),
+            (StatusCode::FORBIDDEN, "The target is not allowed by `--probe-allowed-targets`"),
+            (StatusCode::SERVICE_UNAVAILABLE, "Too many scrapes in flight"),
+        ],
+    },
+    RouteSpec {
+        path: "/queries/{queryid}",
+        summary: "Normalized text of the statement `queryid` of `pg_stat_statements` as JSON, truncated to `--query-text-max-length` characters",
+        content_type: "application/json",
+        handler: |r| Box::pin(query_text_handler(r)),
+        errors: &[
+            (StatusCode::BAD_REQUEST, "Invalid queryid"),
+            (StatusCode::NOT_FOUND, "No such statement, or `pg_stat_statements` is not installed"),
+            (StatusCode::SERVICE_UNAVAILABLE, "PostgreSQL is unreachable, or too many scrapes are in flight"),
+            (StatusCode::GATEWAY_TIMEOUT, "PostgreSQL didn't respond in the scrape timeout"),
+        ],
+    },
+    RouteSpec {
+        path: "/healthz",
+        summary: "`ok` while the exporter is running, for liveness probes",
+        content_type: "text/plain",
+        handler: |r| Box::pin(healthz_handler(r)),
+        errors: &[],
+    },
+    RouteSpec {
+        path: "/readyz",
+        summary: "`ok` if a test query against PostgreSQL succeeds within `--readiness-timeout`, for readiness probes",
+        content_type: "text/plain",
+        handler: |r| Box::pin(readyz_handler(r)),
+        errors: &[(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "PostgreSQL is unreachable or the test query failed or timed out",
+        )],
+    },
+    RouteSpec {
+        path: "/version",
+        summary: "Version of the exporter and the addresses it is bound to as JSON",
+        content_type: "application/json",
+        handler: |r| Box::pin(version_handler(r)),
+        errors: &[],
+    },
+    RouteSpec {
+        path: "/api/openapi.json",
+        summary: "This OpenAPI document",
+        content_type: "application/json",
+        handler: |r| Box::pin(openapi_handler(r)),
+        errors: &[],
+    },
+];
+
+/// Returns the OpenAPI 3 document describing `ROUTES`.
+fn openapi_document() -> serde_json::Value {
+    let paths: serde_json::Map<String, serde_json::Value> = ROUTES
+        .iter()
+        .map(|route| {
+            let mut responses = serde_json::Map::new();
+            responses.insert(
+                "200".to_string(),
+                serde_json::json!({
+                    "description": "OK",
+                    "content": { route.content_type: {} },
+                }),
+            );
+            for (status, description) in route.errors {
+                responses.insert(
+                    status.as_str().to_string(),
+                    serde_json::json!({
+                        "description": description,
+                        "content": {
+                            "application/json": {
+                                "schema": { "$ref": "#/components/schemas/HttpErrorBody" },
+                            },
+                        },
+                    }),
+                );
+            }
+            let parameters: Vec<_> = route
+                .path
+                .split('/')
+                .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+                .map(|name| {
+                    serde_json::json!({
+                        "name": name,
+                        "in": "path",
+                        "required": true,
+                        "schema": { "type": "string" },
+                    })
+                })
+                .collect();
+            let operation = serde_json::json!({
+                "get": {
+                    "summary": route.summary,
+                    "parameters": parameters,
+                    "responses": responses,
+                },
+            });
+            (route.path.to_string(), operation)
+        })
+        .collect();
+    serde_json::json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "pg_stats_exporter",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "schemas": {
+                "HttpErrorBody": {
+                    "type": "object",
+                    "properties": {
+                        "msg": { "type": "string" },
+                        "code": { "type": "string" },
+                        "request_id": { "type": "string" },
+                    },
+                    "required": ["msg", "code", "request_id"],
+                },
+            },
+        },
+    })
+}
+
+/// Collects the metrics and returns the deltas of the counters from an earlier collection.
+async fn diff_handler(req: Request<Body>) -> Result<Response<Body>, ApiError> {
+    let state = req
+        .data::<Arc<State>>()
+        .expect("unknown state type")
+        .clone();
+    if state.history.is_none() {
+        return Err(ApiError::NotFound(
+            "history is disabled without --diff-retention".into(),
+        ));
+    }
+    let window = url::form_urlencoded::parse(req.uri().query().unwrap_or_default().as_bytes())
+        .find(|(key, _)| key == "window")
+        .map_or_else(|| "5m".to_string(), |(_, value)| value.into_owned());
+    let window = history::parse_window(&window).ok_or_else(|| {
+        ApiError::BadRequest(anyhow::anyhow!(
+            "invalid window `{window}`, expected e.g. `30s`, `5m` or `1h`"
+        ))
+    })?;
+    let cancellation = req
+        .context::<ScrapeCancellation>()
+        .expect("request_span sets the cancellation");
+
+    let _permit = try_acquire_scrape_permit(&state)?;
+    collect_metrics(&state, &cancellation).await?;
+    let diff = state
+        .history
+        .as_ref()
+        .and_then(|h| h.diff(window))
+        .ok_or_else(|| ApiError::ServiceUnavailable {
+            msg: "no earlier collection is recorded yet".to_string(),
+            retry_after: None,
+        })?;
+
+    Ok(Response::builder()
+        .status(200)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&diff).unwrap()))
+        .unwrap())
+}
+
+/// Collects the metrics of another PostgreSQL server given as `target`, which must be
+/// allowed by `--probe-allowed-targets`. The connection settings other than the host and
+/// the port are the same as the configured server's.
+async fn probe_handler(req: Request<Body>) -> Result<Response<Body>, ApiError> {
+    let state = req
+        .data::<Arc<State>>()
+        .expect("unknown state type")
+        .clone();
+    let params: HashMap<String, String> =
+        url::form_urlencoded::parse(req.uri().query().unwrap_or_default().as_bytes())
+            .into_owned()
+            .collect();
+    let target = params
+        .get("target")
+        .cloned()
+        .ok_or_else(|| ApiError::BadRequest(anyhow::anyhow!("no target is given")))?;
+    let auth_module = params.get("auth_module").cloned();
+    let cancellation = req
+        .context::<ScrapeCancellation>()
+        .expect("request_span sets the cancellation");
+
+    // Names are resolved here, so this runs in a blocking task
+    let (host, port) = {
+        let state = state.clone();
+        let target = target.clone();
+        tokio::task::spawn_blocking(move || probe::resolve(&target, &state.probe_allowed_targets))
+    }
+    .await
+    .map_err(|e| ApiError::InternalServerError(e.into()))?
+    .map_err(|e| ApiError::Forbidden(format!("target `{target}`: {e:#}")))?;
+    let auth_module = state
+        .auth_modules
+        .select(auth_module.as_deref(), &target)
+        .map_err(ApiError::BadRequest)?;
+    let postgres = state.pgnode.clone().set_host(host).set_port(port);
+    let postgres = match auth_module {
+        Some(auth_module) => auth_module
+            .apply(postgres)
+            .map_err(ApiError::InternalServerError)?,
+        // The password of the exporter is only for the configured server, so that of the
+        // target can only be looked up in the password file by its host
+        None => postgres
+            .set_password(None)
+            .set_passfile(pgpass::default_path()),
+    };
+    // The compatibility profile, the standby, the helpers, the samples of
+    // `pg_stat_statements`, the notifications and whatever is read on the host of the
+    // exporter are of the configured server
+    let options = CollectorOptions {
+        standby: None,
+        profile: Arc::new(OnceCell::new()),
+        function_fallbacks: Arc::new(OnceCell::new()),
+        filesystem: false,
+        data_directory: None,
+        log_directory: None,
+        patroni_url: None,
+        backup: None,
+        custom_queries: vec![],
+        statements_delta: false,
+        notify: false,
+        ..state.collector_options.clone()
+    };
+    let _permit = try_acquire_scrape_permit(&state)?;
+    let (mut metrics, up) =
+        match metrics::gather(&state.pool, &postgres, &options, &cancellation).await {
+            Ok(metrics) => (metrics, true),
+            Err(e) => {
+                tracing::warn!("failed to collect from {}: {e:#}", postgres.raw_address());
+                (vec![], false)
+            }
+        };
+    let m = IntGauge::new("pg_up", help::PG_UP).unwrap();
+    m.set(up as i64);
+    metrics.append(&mut m.collect());
+    metrics::drop_duplicates(&mut metrics);
+
+    let encoder = TextEncoder::new();
+    let mut buf = vec![];
+    encoder
+        .encode(&metrics, &mut buf)
+        .map_err(|e| ApiError::InternalServerError(e.into()))?;
+    Ok(Response::builder()
+        .status(200)
+        .header(CONTENT_TYPE, encoder.format_type())
+        .body(Body::from(buf))
+        .unwrap())
+}
+
+/// How long `/queries/{queryid}` waits for PostgreSQL without a scrape timeout.
+const QUERY_TEXT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Returns the text of a statement in `pg_stat_statements`.
+async fn query_text_handler(req: Request<Body>) -> Result<Response<Body>, ApiError> {
+    let state = get_state(&req);
+    let param = req.param("queryid").expect("routed with a queryid");
+    let queryid: i64 = param
+        .parse()
+        .map_err(|_| ApiError::BadRequest(anyhow::anyhow!("invalid queryid `{param}`")))?;
+    let cancellation = req
+        .context::<ScrapeCancellation>()
+        .expect("request_span sets the cancellation");
+
+    let _permit = try_acquire_scrape_permit(state)?;
+    let deadline = cancellation
+        .deadline()
+        .unwrap_or_else(|| Instant::now() + QUERY_TEXT_TIMEOUT);
+    let no_response =
+        || ApiError::GatewayTimeout(format!("no response from {}", state.pgnode.raw_address()));
+    let mut conn = tokio::time::timeout_at(deadline.into(), state.pool.get(state.pgnode))
+        .await
+        .map_err(|_| no_response())?
+        .map_err(|e| ApiError::ServiceUnavailable {
+            msg: format!("{e:#}"),
+            retry_after: None,
+        })?;
+    cancellation.register(&conn, state.pgnode.tls());
+    // Only the lookup is timed, so that the connection is still here to be discarded
+    // before the cancel request is sent to it
+    let lookup = queries::lookup(&conn, queryid, state.query_text_max_length);
+    let text = match tokio::time::timeout_at(deadline.into(), lookup).await {
+        Ok(text) => {
+            cancellation.finish();
+            text.map_err(|e| ApiError::InternalServerError(e.into()))?
+        }
+        Err(_) => {
+            conn.discard();
+            cancellation.cancel_queries(CancelReason::Deadline).await;
+            return Err(no_response());
+        }
+    };
+    let text = text.ok_or_else(|| {
+        ApiError::NotFound(
+            format!("no statement with queryid {queryid} in pg_stat_statements").into(),
+        )
+    })?;
+    Ok(Response::builder()
+        .status(200)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&text).unwrap()))
+        .unwrap())
+}
+
+/// Serves a page for humans opening the exporter in a browser, like node_exporter does.
+async fn landing_handler(req: Request<Body>) -> Result<Response<Body>, ApiError> {
+    let state = get_state(&req);
+    let targets = match &state.federation {
+        Some(federation) => federation
+            .downstreams()
+            .iter()
+            .map(|downstream| downstream.redacted())
+            .collect(),
+        None => vec![state.pgnode.raw_address()],
+    };
+    Ok(Response::builder()
+        .status(200)
+        .header(CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(Body::from(landing_page(&state.version, &targets)))
+        .unwrap())
+}
+
+fn landing_page(version: &str, targets: &[String]) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head><title>pg_stats_exporter</title></head>\n<body>\n\
+         <h1>pg_stats_exporter</h1>\n",
+    );
+    html.push_str(&format!("<p>Version: {}</p>\n", escape_html(version)));
+    html.push_str(&format!(
+        "<p>Target: {}</p>\n<ul>\n",
+        escape_html(&targets.join(", "))
+    ));
+    // Routes with path parameters can't be linked as they are
+    for route in ROUTES
+        .iter()
+        .filter(|route| route.path != "/" && !route.path.contains('{'))
+    {
+        html.push_str(&format!(
+            "<li><a href=\"{path}\">{path}</a>: {summary}</li>\n",
+            path = route.path,
+            summary = escape_html(route.summary),
+        ));
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+    html
+}
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+async fn healthz_handler(_req: Request<Body>) -> Result<Response<Body>, ApiError> {
+    Ok(Response::builder()
+        .status(200)
+        .header(CONTENT_TYPE, "text/plain")
+        .body(Body::from("ok"))
+        .unwrap())
+}
+
+/// Responds `ok` if PostgreSQL answers a test query in time. Exporters federating others
+/// don't access PostgreSQL, so they are always ready.
+async fn readyz_handler(req: Request<Body>) -> Result<Response<Body>, ApiError> {
+    let state = get_state(&req);
+    if state.federation.is_none() {
+        let check = async {
+            let conn = state.pool.get(state.pgnode).await?;
+            conn.simple_query("SELECT 1").await?;
+            anyhow::Ok(())
+        };
+        let res = tokio::time::timeout(state.readiness_timeout, check)
+            .await
+            .unwrap_or_else(|_| {
+                Err(anyhow::anyhow!(
+                    "no response in {}s",
+                    state.readiness_timeout.as_secs_f64()
+                ))
+            });
+        if let Err(e) = res {
+            return Err(ApiError::ServiceUnavailable {
+                msg: format!("{} is not ready: {e:#}", state.pgnode.raw_address()),
+                retry_after: None,
+            });
+        }
+    }
+    Ok(Response::builder()
+        .status(200)
+        .header(CONTENT_TYPE, "text/plain")
+        .body(Body::from("ok"))
+        .unwrap())
+}
+
+async fn version_handler(req: Request<Body>) -> Result<Response<Body>, ApiError> {
+    let state = get_state(&req);
+    let body = serde_json::json!({
+        "version": state.version,
+        "listen": state
+            .listen_addresses
+            .read()
+            .unwrap()
+            .iter()
+            .map(|addr| addr.to_string())
+            .collect::<Vec<_>>(),
+    });
+    Ok(Response::builder()
+        .status(200)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap())
+}
+
+async fn openapi_handler(_req: Request<Body>) -> Result<Response<Body>, ApiError> {
+    Ok(Response::builder()
+        .status(200)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(openapi_document().to_string()))
+        .unwrap())
+}
+
 pub fn make_router(state: Arc<State>) -> anyhow::Result<RouterBuilder<hyper::Body, ApiError>> {
-    let router = Router::builder()
-        .data(state)
-        .get("/metrics", |r| request_span(r, prometheus_metrics_handler))
-        .err_handler(route_error_handler);
+    let mut router = Router::builder().data(state);
+    for route in ROUTES {
+        // Routerify takes path parameters as `:name`
+        let path = route.path.replace('{', ":").replace('}', "");
+        let handler = route.handler;
+        router = router.get(path, move |r| request_span(r, handler));
+    }
 
-    Ok(router)
+    Ok(router.err_handler(route_error_handler))
+}
+
+static RESPONSE_FLUSHES: Lazy<IntCounter> = Lazy::new(|| {
+    IntCounter::new(
+        "pg_exporter_response_flushes_total",
+        help::PG_EXPORTER_RESPONSE_FLUSHES_TOTAL,
+    )
+    .unwrap()
+});
+
+static RESPONSE_SEND_BLOCKED: Lazy<Counter> = Lazy::new(|| {
+    Counter::new(
+        "pg_exporter_response_send_blocked_seconds_total",
+        help::PG_EXPORTER_RESPONSE_SEND_BLOCKED_SECONDS_TOTAL,
+    )
+    .unwrap()
+});
+
+static RESPONSE_BYTES: Lazy<Histogram> = Lazy::new(|| {
+    Histogram::with_opts(
+        HistogramOpts::new(
+            "pg_exporter_response_bytes",
+            help::PG_EXPORTER_RESPONSE_BYTES,
+        )
+        .buckets(prometheus::exponential_buckets(4096.0, 4.0, 8).unwrap()),
+    )
+    .unwrap()
+});
+
+/// Returns the metrics about the streaming of previous `/metrics` responses, which tell
+/// slow clients stalling the encoding.
+fn response_metrics() -> Vec<prometheus::proto::MetricFamily> {
+    let mut metrics = RESPONSE_FLUSHES.collect();
+    metrics.append(&mut RESPONSE_SEND_BLOCKED.collect());
+    metrics.append(&mut RESPONSE_BYTES.collect());
+    metrics
+}
+
+/// How `/metrics` responds when PostgreSQL is unreachable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScrapeErrorBehavior {
+    /// Respond 200 with `pg_up 0`, so that Prometheus' `up` only reflects the exporter.
+    Up,
+    /// Respond 503, so that Prometheus' `up` reflects the reachability of PostgreSQL.
+    Unavailable,
 }
 
 pub struct State {
     pub pgnode: &'static PgConnectionConfig,
+    /// Connections to `pgnode`, the standby, and the targets of `/probe`.
+    pub pool: Pool,
+    pub collector_options: CollectorOptions,
+    pub leader_election: Option<Arc<LeaderElection>>,
+    pub background: Option<BackgroundCollector>,
+    pub scrape_timestamps: Arc<ScrapeTimestamps>,
+    pub max_response_bytes: Option<usize>,
+    /// Privileges checked at startup, set once PostgreSQL is reachable and the check succeeds.
+    pub privilege_report: Arc<OnceCell<PrivilegeReport>>,
+    pub scrape_error_behavior: ScrapeErrorBehavior,
+    /// Bytes allocated by the exporter above which collected metrics are dropped.
+    pub memory_soft_limit: Option<usize>,
+    /// Peers whose `Forwarded`/`X-Forwarded-For` headers tell the client address.
+    pub trusted_proxies: Vec<IpCidr>,
+    /// Origins allowed to read responses in browsers, or `*` for any.
+    pub cors_allowed_origins: Vec<String>,
+    /// Counters of the past collections served as deltas on `/diff` if set.
+    pub history: Option<History>,
+    /// Downstream exporters to serve the metrics of instead of PostgreSQL's if set.
+    pub federation: Option<Federation>,
+    /// Bounds the number of scrapes in flight if set.
+    pub scrape_semaphore: Option<Arc<Semaphore>>,
+    /// Targets `/probe` may collect from. Any other target is rejected.
+    pub probe_allowed_targets: Vec<AllowedTarget>,
+    /// Credential profiles `/probe` connects to targets with.
+    pub auth_modules: AuthModules,
+    /// Time proxies may cache `/metrics` responses for when served from the background
+    /// snapshots if set.
+    pub metrics_cache_max_age: Option<Duration>,
+    /// Time a scrape may take if Prometheus doesn't send its scrape timeout, unbounded if
+    /// not set.
+    pub scrape_timeout: Option<Duration>,
+    /// Maximum number of characters of the statements served on `/queries/{queryid}`.
+    pub query_text_max_length: usize,
+    /// Time the test query of `/readyz` may take.
+    pub readiness_timeout: Duration,
+    /// The warm-up collection at startup with the time it completed, served to the first
+    /// scrape instead of collecting again if fresh.
+    pub warm_up: Arc<Mutex<Option<WarmUp>>>,
+    /// Version of the exporter served on `/version`.
+    pub version: String,
+    /// Addresses the metrics server is bound to, served on `/version`.
+    pub listen_addresses: RwLock<Vec<SocketAddr>>,
+}
+
+impl State {
+    /// Returns the `Cache-Control` of `/metrics` responses. Only the background snapshots
+    /// may be cached, since a scrape otherwise collects the metrics anew.
+    fn metrics_cache_control(&self) -> String {
+        let active = self
+            .leader_election
+            .as_ref()
+            .map_or(true, |election| election.is_active());
+        match (self.metrics_cache_max_age, &self.background) {
+            (Some(max_age), Some(_)) if active => format!("max-age={}", max_age.as_secs()),
+            _ => "no-store".to_string(),
+        }
+    }
 }
 
 #[inline(always)]
@@ -214,6 +948,144 @@ fn get_state(request: &Request<Body>) -> &State {
         .as_ref()
 }
 
+/// A warm-up collection with the time it completed.
+pub type WarmUp = (Instant, Vec<prometheus::proto::MetricFamily>);
+
+/// How old the warm-up collection may be to be served to the first scrape.
+const WARM_UP_MAX_AGE: Duration = Duration::from_secs(60);
+
+/// Collects the metrics of `pgnode` for a scrape, which are empty if it is unreachable.
+async fn collect_now(
+    state: &State,
+    cancellation: &ScrapeCancellation,
+) -> Vec<prometheus::proto::MetricFamily> {
+    state.scrape_timestamps.record_scrape();
+    match metrics::gather(
+        &state.pool,
+        state.pgnode,
+        &state.collector_options,
+        cancellation,
+    )
+    .await
+    {
+        Ok(metrics) => {
+            state.scrape_timestamps.record_success();
+            metrics
+        }
+        Err(e) => {
+            tracing::warn!(
+                "failed to collect from {}: {e:#}",
+                state.pgnode.raw_address()
+            );
+            state.scrape_timestamps.record_failure();
+            vec![]
+        }
+    }
+}
+
+/// Takes a permit of `--max-concurrent-scrapes` if set for a collection. If none is left,
+/// the collection is rejected immediately instead of queueing work that will likely exceed
+/// the scrape timeout anyway.
+pub(crate) fn try_acquire_scrape_permit(
+    state: &State,
+) -> Result<Option<OwnedSemaphorePermit>, ApiError> {
+    match &state.scrape_semaphore {
+        Some(semaphore) => match semaphore.clone().try_acquire_owned() {
+            Ok(permit) => Ok(Some(permit)),
+            Err(_) => Err(ApiError::ServiceUnavailable {
+                msg: "too many scrapes in flight".to_string(),
+                retry_after: Some(SCRAPE_RETRY_AFTER),
+            }),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Returns the metrics to serve on `/metrics`.
+pub(crate) async fn collect_metrics(
+    state: &State,
+    cancellation: &ScrapeCancellation,
+) -> Result<Vec<prometheus::proto::MetricFamily>, ApiError> {
+    let mut metrics = match (&state.leader_election, &state.background) {
+        // Standby instances only report that they are not collecting
+        (Some(election), _) if !election.is_active() => return Ok(election.collect()),
+        (_, Some(background)) => background.latest(),
+        (_, None) => {
+            let warm_up = state.warm_up.lock().unwrap().take();
+            if let Some((_, metrics)) =
+                warm_up.filter(|(collected_at, _)| collected_at.elapsed() < WARM_UP_MAX_AGE)
+            {
+                metrics
+            } else {
+                collect_now(state, cancellation).await
+            }
+        }
+    };
+
+    if state.scrape_error_behavior == ScrapeErrorBehavior::Unavailable
+        && !state.scrape_timestamps.is_up()
+    {
+        return Err(ApiError::ServiceUnavailable {
+            msg: format!("failed to collect from {}", state.pgnode.raw_address()),
+            retry_after: None,
+        });
+    }
+
+    // Drop the collected metrics once the exporter exceeds its memory budget, which is
+    // most likely caused by a cardinality explosion
+    let mut dropped = vec![];
+    if let (Some(limit), Some(allocated)) = (state.memory_soft_limit, memory::allocated()) {
+        if allocated > limit {
+            tracing::warn!(
+                allocated,
+                limit,
+                "dropped all collected metric families exceeding the memory soft limit"
+            );
+            dropped.extend(metrics.drain(..).map(|m| m.get_name().to_string()));
+        }
+    }
+
+    metrics.append(&mut state.scrape_timestamps.collect());
+    metrics.append(&mut metrics::self_metrics());
+    metrics.append(&mut memory::collect());
+    metrics.append(&mut response_metrics());
+    metrics.append(&mut server_metrics::collect());
+    metrics.append(&mut cancellation::collect());
+    metrics.append(&mut pool::collect());
+    if let Some(profile) = state.collector_options.profile.get() {
+        metrics.append(&mut profile.collect());
+    }
+    if let Some(report) = state.privilege_report.get() {
+        metrics.append(&mut report.collect());
+    }
+    if let Some(election) = &state.leader_election {
+        metrics.append(&mut election.collect());
+    }
+
+    metrics::drop_duplicates(&mut metrics);
+    if let Some(max_bytes) = state.max_response_bytes {
+        let truncated = metrics::truncate_to_size(&mut metrics, max_bytes);
+        if !truncated.is_empty() {
+            tracing::warn!(
+                max_bytes,
+                "dropped metric families exceeding the maximum response size: {}",
+                truncated.join(", ")
+            );
+        }
+        dropped.extend(truncated);
+    }
+    if state.max_response_bytes.is_some() || state.memory_soft_limit.is_some() {
+        metrics.append(&mut metrics::truncation_metrics(dropped.len()));
+    }
+    if let Some(history) = &state.history {
+        history.record(&metrics);
+    }
+    Ok(metrics)
+}
+
+/// How long clients are asked to wait when too many scrapes are in flight.
+const SCRAPE_RETRY_AFTER: Duration = Duration::from_secs(1);
+
 #[instrument(skip_all)]
 async fn prometheus_metrics_handler(_req: Request<Body>) -> Result<Response<Body>, ApiError> {
     use bytes::{Bytes, BytesMut};
@@ -223,6 +1095,9 @@ async fn prometheus_metrics_handler(_req: Request<Body>) -> Result<Response<Body
 
     // SERVE_METRICS_COUNT.inc();
 
+    // The permit is held until the response is written out
+    let permit = try_acquire_scrape_permit(get_state(&_req))?;
+
     /// An [`std::io::Write`] implementation on top of a channel sending [`bytes::Bytes`] chunks.
     struct ChannelWriter {
         buffer: BytesMut,
@@ -254,6 +1129,7 @@ async fn prometheus_metrics_handler(_req: Request<Body>) -> Result<Response<Body
 
             // not ideal to call from blocking code to block_on, but we are sure that this
             // operation does not spawn_blocking other tasks
+            let blocked_at = std::time::Instant::now();
             let res: Result<(), ()> = tokio::runtime::Handle::current().block_on(async {
                 self.tx.send(Ok(ready)).await.map_err(|_| ())?;
 
@@ -264,9 +1140,11 @@ async fn prometheus_metrics_handler(_req: Request<Body>) -> Result<Response<Body
                 // sending it to the client.
                 Ok(())
             });
+            RESPONSE_SEND_BLOCKED.inc_by(blocked_at.elapsed().as_secs_f64());
             if res.is_err() {
                 return Err(std::io::ErrorKind::BrokenPipe.into());
             }
+            RESPONSE_FLUSHES.inc();
             self.written += n;
             Ok(n)
         }
@@ -305,6 +1183,46 @@ async fn prometheus_metrics_handler(_req: Request<Body>) -> Result<Response<Body
 
     let started_at = std::time::Instant::now();
 
+    if let Some(federation) = &get_state(&_req).federation {
+        let body = federation.gather().await;
+        tracing::info!(
+            bytes = body.len(),
+            elapsed_ms = started_at.elapsed().as_millis(),
+            "responded /metrics"
+        );
+        return Ok(Response::builder()
+            .status(200)
+            .header(CONTENT_TYPE, TextEncoder::new().format_type())
+            .header(CACHE_CONTROL, "no-store")
+            .body(Body::from(body))
+            .unwrap());
+    }
+
+    // Collect before responding, so that the status can tell collection failures
+    let span = info_span!("blocking");
+    let state = _req
+        .data::<Arc<State>>()
+        .expect("unknown state type")
+        .clone();
+    let cancellation = _req
+        .context::<ScrapeCancellation>()
+        .expect("request_span sets the cancellation");
+    // Cancel the query in flight once the client stops waiting for the response
+    let deadline_timer = cancellation.deadline().map(|deadline| {
+        let cancellation = cancellation.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep_until(deadline.into()).await;
+            cancellation.cancel_queries(CancelReason::Deadline).await;
+        })
+    });
+    let res = collect_metrics(&state, &cancellation)
+        .instrument(span.clone())
+        .await;
+    if let Some(deadline_timer) = deadline_timer {
+        deadline_timer.abort();
+    }
+    let metrics = res?;
+
     let (tx, rx) = mpsc::channel(1);
 
     let body = hyper::Body::wrap_stream(ReceiverStream::new(rx));
@@ -316,19 +1234,20 @@ async fn prometheus_metrics_handler(_req: Request<Body>) -> Result<Response<Body
     let response = Response::builder()
         .status(200)
         .header(CONTENT_TYPE, encoder.format_type())
+        .header(CACHE_CONTROL, state.metrics_cache_control())
         .body(body)
         .unwrap();
 
-    let span = info_span!("blocking");
     tokio::task::spawn_blocking(move || {
         let _span = span.entered();
-        let metrics = metrics::gather(get_state(&_req).pgnode);
+        let _permit = permit;
         let res = encoder
             .encode(&metrics, &mut writer)
             .and_then(|_| writer.flush().map_err(|e| e.into()));
 
         match res {
             Ok(()) => {
+                RESPONSE_BYTES.observe(writer.flushed_bytes() as f64);
                 tracing::info!(
                     bytes = writer.flushed_bytes(),
                     elapsed_ms = started_at.elapsed().as_millis(),
@@ -355,20 +1274,18 @@ async fn prometheus_metrics_handler(_req: Request<Body>) -> Result<Response<Body
 
 async fn route_error_handler(err: RouteError) -> Response<Body> {
     match err.downcast::<ApiError>() {
-        Ok(api_error) => api_error_handler(*api_error),
+        Ok(api_error) => api_error_handler(*api_error, ""),
         Err(other_error) => {
             // We expect all the request handlers to return an ApiError, so this should
             // not be reached. But just in case.
             error!("Error processing HTTP request: {other_error:?}");
-            HttpErrorBody::response_from_msg_and_status(
-                other_error.to_string(),
-                StatusCode::INTERNAL_SERVER_ERROR,
-            )
+            ApiError::InternalServerError(anyhow::anyhow!(other_error.to_string()))
+                .into_response("")
         }
     }
 }
 
-fn api_error_handler(api_error: ApiError) -> Response<Body> {
+fn api_error_handler(api_error: ApiError, request_id: &str) -> Response<Body> {
     // Print a stack trace for Internal Server errors
     if let ApiError::InternalServerError(_) = api_error {
         error!("Error processing HTTP request: {api_error:?}");
@@ -376,5 +1293,5 @@ fn api_error_handler(api_error: ApiError) -> Response<Body> {
         error!("Error processing HTTP request: {api_error:#}");
     }
 
-    api_error.into_response()
+    api_error.into_response(request_id)
 }
@@ -0,0 +1,66 @@
+//!
+//! Mapping of result rows into structs by column name.
+//!
+//! Reading columns by position with `row.get(N)` breaks silently when a query's select list
+//! changes, and panics on a type mismatch. `from_row!` declares a struct whose fields are
+//! read from the columns of the same names, returning an error that names the column and
+//! both types instead:
+//!
+//! ```ignore
+//! from_row! {
+//!     struct Tablespace {
+//!         name: String,
+//!         avail: i64,
+//!     }
+//! }
+//! let tablespaces: Vec<Tablespace> = query_as(conn, "SELECT name, avail FROM ...", &[]).await?;
+//! ```
+//!
+use tokio_postgres::types::ToSql;
+use tokio_postgres::{Client, Error, Row};
+
+pub trait FromRow: Sized {
+    fn from_row(row: &Row) -> Result<Self, Error>;
+}
+
+/// Declares a struct implementing `FromRow` by reading each field from the column of the
+/// same name.
+macro_rules! from_row {
+    ($(#[$meta:meta])* $vis:vis struct $name:ident { $($field_vis:vis $field:ident: $ty:ty),* $(,)? }) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $($field_vis $field: $ty),*
+        }
+
+        impl $crate::rows::FromRow for $name {
+            fn from_row(row: &::tokio_postgres::Row) -> Result<Self, ::tokio_postgres::Error> {
+                Ok($name {
+                    $($field: row.try_get(stringify!($field))?),*
+                })
+            }
+        }
+    };
+}
+pub(crate) use from_row;
+
+/// Runs `query` and maps every row into `T`.
+pub async fn query_as<T: FromRow>(
+    conn: &Client,
+    query: &str,
+    params: &[&(dyn ToSql + Sync)],
+) -> Result<Vec<T>, Error> {
+    conn.query(query, params)
+        .await?
+        .iter()
+        .map(T::from_row)
+        .collect()
+}
+
+/// Runs `query`, which must return exactly one row, and maps it into `T`.
+pub async fn query_one_as<T: FromRow>(
+    conn: &Client,
+    query: &str,
+    params: &[&(dyn ToSql + Sync)],
+) -> Result<T, Error> {
+    T::from_row(&conn.query_one(query, params).await?)
+}
@@ -0,0 +1,200 @@
+//!
+//! Secrets read from files, e.g., passwords and certificates mounted from Kubernetes
+//! secrets, and a watcher picking up their rotated contents.
+//!
+//! The parent directory of a file is watched rather than the file itself, since Kubernetes
+//! rotates a mounted secret by atomically swapping the `..data` symlink of the directory,
+//! which replaces the file without writing to it. A watched file is reported as changed
+//! only when its contents differ from what was read before, so that the several events of
+//! a single rotation are reported once.
+//!
+use anyhow::Context;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+/// The contents of a file, kept up to date by `watch_secrets`, or else read every time.
+#[derive(Clone)]
+pub struct SecretFile {
+    path: PathBuf,
+    contents: Arc<RwLock<String>>,
+    /// Whether `contents` is kept up to date by a watcher.
+    watched: Arc<AtomicBool>,
+}
+
+impl SecretFile {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let secret = SecretFile {
+            path: path.to_path_buf(),
+            contents: Arc::new(RwLock::new(String::new())),
+            watched: Arc::new(AtomicBool::new(false)),
+        };
+        secret.reload()?;
+        Ok(secret)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the contents without the trailing newline most editors and `kubectl` add.
+    /// Unless the file is watched, it's read again, keeping the previous contents if it
+    /// can't be.
+    pub fn get(&self) -> String {
+        if !self.watched.load(Ordering::Relaxed) {
+            if let Err(e) = self.reload() {
+                tracing::warn!("{e:#}");
+            }
+        }
+        self.contents.read().unwrap().clone()
+    }
+
+    /// Reads the file again. The previous contents are kept if it can't be read.
+    pub fn reload(&self) -> anyhow::Result<()> {
+        let contents = std::fs::read_to_string(&self.path)
+            .with_context(|| format!("failed to read {}", self.path.display()))?;
+        *self.contents.write().unwrap() = contents.trim_end_matches(['\r', '\n']).to_string();
+        Ok(())
+    }
+}
+
+impl fmt::Debug for SecretFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Only the path, so that the secret is not logged by accident
+        f.debug_struct("SecretFile")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+/// Calls `on_change` in a thread with the path of a file in `paths` whenever its contents
+/// change, until the process exits.
+#[cfg(target_os = "linux")]
+pub fn watch<F>(paths: Vec<PathBuf>, mut on_change: F) -> anyhow::Result<()>
+where
+    F: FnMut(&Path) + Send + 'static,
+{
+    use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+
+    let inotify = Inotify::init(InitFlags::IN_CLOEXEC)?;
+    for path in &paths {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        inotify
+            .add_watch(
+                dir,
+                AddWatchFlags::IN_CREATE
+                    | AddWatchFlags::IN_CLOSE_WRITE
+                    | AddWatchFlags::IN_MOVED_TO
+                    | AddWatchFlags::IN_DELETE
+                    | AddWatchFlags::IN_ATTRIB,
+            )
+            .with_context(|| format!("failed to watch {}", dir.display()))?;
+    }
+    let mut files: Vec<(PathBuf, Option<Vec<u8>>)> = paths
+        .into_iter()
+        .map(|path| {
+            let contents = std::fs::read(&path).ok();
+            (path, contents)
+        })
+        .collect();
+
+    std::thread::Builder::new()
+        .name("secret watcher".to_string())
+        .spawn(move || loop {
+            if let Err(e) = inotify.read_events() {
+                tracing::warn!("stopped watching secret files: {e:#}");
+                return;
+            }
+            for (path, previous) in files.iter_mut() {
+                // A file is briefly missing while it is replaced, which isn't a change
+                let Ok(contents) = std::fs::read(&*path) else {
+                    continue;
+                };
+                if previous.as_ref() != Some(&contents) {
+                    *previous = Some(contents);
+                    on_change(path);
+                }
+            }
+        })?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn watch<F>(_paths: Vec<PathBuf>, _on_change: F) -> anyhow::Result<()>
+where
+    F: FnMut(&Path) + Send + 'static,
+{
+    anyhow::bail!("watching files is only supported on Linux")
+}
+
+/// Reloads `secrets` whenever their files change.
+pub fn watch_secrets(secrets: Vec<SecretFile>) -> anyhow::Result<()> {
+    let paths = secrets.iter().map(|s| s.path.clone()).collect();
+    let watched: Vec<_> = secrets.iter().map(|s| s.watched.clone()).collect();
+    watch(paths, move |path| {
+        for secret in secrets.iter().filter(|s| s.path == path) {
+            match secret.reload() {
+                Ok(()) => tracing::info!("reloaded {}", path.display()),
+                Err(e) => tracing::warn!("failed to reload a secret file: {e:#}"),
+            }
+        }
+    })?;
+    for watched in watched {
+        watched.store(true, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests_secret_files {
+    use crate::secret_files::{watch_secrets, SecretFile};
+    use std::time::{Duration, Instant};
+
+    fn wait_for(secret: &SecretFile, expected: &str) {
+        let started = Instant::now();
+        while secret.get() != expected {
+            assert!(
+                started.elapsed() < Duration::from_secs(5),
+                "`{}` was not reloaded",
+                secret.get()
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_watch_secrets() {
+        let dir = std::env::temp_dir().join(format!("secrets-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("password");
+        std::fs::write(&path, "first\n").unwrap();
+
+        let secret = SecretFile::load(&path).unwrap();
+        assert_eq!(secret.get(), "first");
+        assert_eq!(
+            format!("{secret:?}"),
+            format!("SecretFile {{ path: {path:?} }}")
+        );
+
+        // Read every time until it's watched
+        std::fs::write(&path, "unwatched\n").unwrap();
+        assert_eq!(secret.get(), "unwatched");
+        watch_secrets(vec![secret.clone()]).unwrap();
+
+        // Written in place
+        std::fs::write(&path, "second\n").unwrap();
+        wait_for(&secret, "second");
+
+        // Replaced by a rename, like the symlink swap of Kubernetes
+        let tmp = dir.join("password.tmp");
+        std::fs::write(&tmp, "third").unwrap();
+        std::fs::rename(&tmp, &path).unwrap();
+        wait_for(&secret, "third");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,156 @@
+//!
+//! Metrics about the connections to the metrics server, which tell network-side issues
+//! apart from slow collections, e.g., load balancer health checks opening a connection
+//! per request, or clients failing TLS handshakes.
+//!
+use once_cell::sync::Lazy;
+use prometheus::{core::Collector, Histogram, HistogramOpts, IntCounter, IntGauge};
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::help;
+
+static CONNECTIONS_ACCEPTED: Lazy<IntCounter> = Lazy::new(|| {
+    IntCounter::new(
+        "pg_exporter_http_connections_accepted_total",
+        help::PG_EXPORTER_HTTP_CONNECTIONS_ACCEPTED_TOTAL,
+    )
+    .unwrap()
+});
+
+static CONNECTIONS_ACTIVE: Lazy<IntGauge> = Lazy::new(|| {
+    IntGauge::new(
+        "pg_exporter_http_connections_active",
+        help::PG_EXPORTER_HTTP_CONNECTIONS_ACTIVE,
+    )
+    .unwrap()
+});
+
+static TLS_HANDSHAKE_FAILURES: Lazy<IntCounter> = Lazy::new(|| {
+    IntCounter::new(
+        "pg_exporter_http_tls_handshake_failures_total",
+        help::PG_EXPORTER_HTTP_TLS_HANDSHAKE_FAILURES_TOTAL,
+    )
+    .unwrap()
+});
+
+static CONNECTION_REQUESTS: Lazy<Histogram> = Lazy::new(|| {
+    Histogram::with_opts(
+        HistogramOpts::new(
+            "pg_exporter_http_connection_requests",
+            help::PG_EXPORTER_HTTP_CONNECTION_REQUESTS,
+        )
+        .buckets(vec![1.0, 2.0, 5.0, 10.0, 100.0, 1000.0]),
+    )
+    .unwrap()
+});
+
+/// Counts a TCP connection accepted by a listener.
+pub fn connection_accepted() {
+    CONNECTIONS_ACCEPTED.inc();
+}
+
+pub fn tls_handshake_failed() {
+    TLS_HANDSHAKE_FAILURES.inc();
+}
+
+/// A connection counted as active until dropped, when the number of requests served on it
+/// is observed.
+pub struct TrackedConnection<T> {
+    inner: T,
+    requests: Arc<AtomicU64>,
+}
+
+impl<T> TrackedConnection<T> {
+    pub fn new(inner: T) -> Self {
+        CONNECTIONS_ACTIVE.inc();
+        TrackedConnection {
+            inner,
+            requests: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns the counter to increment on every request served on the connection.
+    pub fn requests(&self) -> Arc<AtomicU64> {
+        self.requests.clone()
+    }
+}
+
+impl<T> Drop for TrackedConnection<T> {
+    fn drop(&mut self) {
+        CONNECTIONS_ACTIVE.dec();
+        CONNECTION_REQUESTS.observe(self.requests.load(Ordering::Relaxed) as f64);
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for TrackedConnection<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for TrackedConnection<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
+
+pub fn collect() -> Vec<prometheus::proto::MetricFamily> {
+    let mut metrics = CONNECTIONS_ACCEPTED.collect();
+    metrics.append(&mut CONNECTIONS_ACTIVE.collect());
+    metrics.append(&mut TLS_HANDSHAKE_FAILURES.collect());
+    metrics.append(&mut CONNECTION_REQUESTS.collect());
+    metrics
+}
+
+#[cfg(test)]
+mod tests_server_metrics {
+    use crate::server_metrics::{TrackedConnection, CONNECTIONS_ACTIVE, CONNECTION_REQUESTS};
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn test_tracked_connection() {
+        let observed = CONNECTION_REQUESTS.get_sample_count();
+        let conn = TrackedConnection::new(());
+        assert!(CONNECTIONS_ACTIVE.get() >= 1);
+        conn.requests().fetch_add(3, Ordering::Relaxed);
+        drop(conn);
+        assert!(CONNECTION_REQUESTS.get_sample_count() > observed);
+    }
+}
@@ -0,0 +1,205 @@
+//!
+//! Persists snapshots of metric families to a file.
+//!
+//! The protobuf support of the `prometheus` crate is disabled, so families are converted
+//! into a JSON representation covering the metric types this exporter produces.
+//!
+use prometheus::proto::{
+    Bucket, Counter, Gauge, Histogram, LabelPair, Metric, MetricFamily, MetricType,
+};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PersistedFamily {
+    name: String,
+    help: String,
+    #[serde(rename = "type")]
+    metric_type: String,
+    metrics: Vec<PersistedMetric>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PersistedMetric {
+    labels: Vec<(String, String)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    value: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    histogram: Option<PersistedHistogram>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timestamp_ms: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PersistedHistogram {
+    sample_count: u64,
+    sample_sum: f64,
+    buckets: Vec<(f64, u64)>,
+}
+
+fn to_persisted(family: &MetricFamily) -> Option<PersistedFamily> {
+    let metric_type = match family.get_field_type() {
+        MetricType::COUNTER => "counter",
+        MetricType::GAUGE => "gauge",
+        MetricType::HISTOGRAM => "histogram",
+        // Not exported by this exporter
+        MetricType::UNTYPED | MetricType::SUMMARY => return None,
+    };
+    let metrics = family
+        .get_metric()
+        .iter()
+        .map(|m| PersistedMetric {
+            labels: m
+                .get_label()
+                .iter()
+                .map(|l| (l.get_name().to_string(), l.get_value().to_string()))
+                .collect(),
+            value: match family.get_field_type() {
+                MetricType::COUNTER => Some(m.get_counter().get_value()),
+                MetricType::GAUGE => Some(m.get_gauge().get_value()),
+                _ => None,
+            },
+            histogram: (family.get_field_type() == MetricType::HISTOGRAM).then(|| {
+                let h = m.get_histogram();
+                PersistedHistogram {
+                    sample_count: h.get_sample_count(),
+                    sample_sum: h.get_sample_sum(),
+                    buckets: h
+                        .get_bucket()
+                        .iter()
+                        .map(|b| (b.get_upper_bound(), b.get_cumulative_count()))
+                        .collect(),
+                }
+            }),
+            timestamp_ms: (m.get_timestamp_ms() != 0).then(|| m.get_timestamp_ms()),
+        })
+        .collect();
+    Some(PersistedFamily {
+        name: family.get_name().to_string(),
+        help: family.get_help().to_string(),
+        metric_type: metric_type.to_string(),
+        metrics,
+    })
+}
+
+fn from_persisted(persisted: PersistedFamily) -> Option<MetricFamily> {
+    let metric_type = match persisted.metric_type.as_str() {
+        "counter" => MetricType::COUNTER,
+        "gauge" => MetricType::GAUGE,
+        "histogram" => MetricType::HISTOGRAM,
+        _ => return None,
+    };
+    let mut family = MetricFamily::default();
+    family.set_name(persisted.name);
+    family.set_help(persisted.help);
+    family.set_field_type(metric_type);
+    for persisted in persisted.metrics {
+        let mut m = Metric::default();
+        m.set_label(
+            persisted
+                .labels
+                .into_iter()
+                .map(|(name, value)| {
+                    let mut l = LabelPair::default();
+                    l.set_name(name);
+                    l.set_value(value);
+                    l
+                })
+                .collect(),
+        );
+        let value = persisted.value.unwrap_or_default();
+        match metric_type {
+            MetricType::COUNTER => {
+                let mut c = Counter::default();
+                c.set_value(value);
+                m.set_counter(c);
+            }
+            MetricType::GAUGE => {
+                let mut g = Gauge::default();
+                g.set_value(value);
+                m.set_gauge(g);
+            }
+            _ => {
+                let persisted = persisted.histogram?;
+                let mut h = Histogram::default();
+                h.set_sample_count(persisted.sample_count);
+                h.set_sample_sum(persisted.sample_sum);
+                h.set_bucket(
+                    persisted
+                        .buckets
+                        .into_iter()
+                        .map(|(upper_bound, cumulative_count)| {
+                            let mut b = Bucket::default();
+                            b.set_upper_bound(upper_bound);
+                            b.set_cumulative_count(cumulative_count);
+                            b
+                        })
+                        .collect(),
+                );
+                m.set_histogram(h);
+            }
+        }
+        if let Some(timestamp_ms) = persisted.timestamp_ms {
+            m.set_timestamp_ms(timestamp_ms);
+        }
+        family.mut_metric().push(m);
+    }
+    Some(family)
+}
+
+/// Writes `metrics` to `path`. The file is replaced atomically, so a crash while writing
+/// leaves the previous snapshot intact.
+pub fn write(path: &Path, metrics: &[MetricFamily]) -> anyhow::Result<()> {
+    let persisted: Vec<PersistedFamily> = metrics.iter().filter_map(to_persisted).collect();
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, serde_json::to_vec(&persisted)?)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Reads a snapshot written by `write`.
+pub fn read(path: &Path) -> anyhow::Result<Vec<MetricFamily>> {
+    let persisted: Vec<PersistedFamily> = serde_json::from_slice(&std::fs::read(path)?)?;
+    Ok(persisted.into_iter().filter_map(from_persisted).collect())
+}
+
+#[cfg(test)]
+mod tests_snapshot_file {
+    use crate::snapshot_file::{read, write};
+    use prometheus::{
+        core::Collector, Encoder, HistogramOpts, HistogramVec, IntCounter, IntGaugeVec, Opts,
+        TextEncoder,
+    };
+
+    fn encode(metrics: &[prometheus::proto::MetricFamily]) -> String {
+        let mut buf = vec![];
+        TextEncoder::new().encode(metrics, &mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut metrics = vec![];
+        let c = IntCounter::new("test_total", "A counter").unwrap();
+        c.inc_by(3);
+        metrics.append(&mut c.collect());
+        let g = IntGaugeVec::new(Opts::new("test_gauge", "A gauge"), &["a", "b"]).unwrap();
+        g.with_label_values(&["x", "y"]).set(-1);
+        metrics.append(&mut g.collect());
+        let h = HistogramVec::new(
+            HistogramOpts::new("test_seconds", "A histogram").buckets(vec![1.0, 10.0]),
+            &["a"],
+        )
+        .unwrap();
+        h.with_label_values(&["x"]).observe(5.0);
+        metrics.append(&mut h.collect());
+        metrics[0].mut_metric()[0].set_timestamp_ms(1700000000123);
+
+        let path = std::env::temp_dir().join(format!("snapshot-{}.json", std::process::id()));
+        write(&path, &metrics).unwrap();
+        let restored = read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(encode(&restored), encode(&metrics));
+    }
+}
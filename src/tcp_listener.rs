@@ -1,16 +1,99 @@
 use std::{
     io,
-    net::{TcpListener, ToSocketAddrs},
-    os::unix::prelude::AsRawFd,
+    net::{SocketAddr, TcpListener},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
 };
 
-use nix::sys::socket::{setsockopt, sockopt::ReuseAddr};
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+use nix::sys::socket::{
+    bind as bind_socket, listen, setsockopt, socket,
+    sockopt::{Ipv6V6Only, ReuseAddr, ReusePort},
+    AddressFamily, SockFlag, SockType, SockaddrIn, SockaddrIn6,
+};
+
+/// Length of the queue of connections not accepted yet, the same as the standard library's.
+const BACKLOG: usize = 128;
+
+/// Socket options of the listeners.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SocketOptions {
+    /// `SO_REUSEADDR`, so that a restarted exporter can bind while connections of the
+    /// previous one are in `TIME_WAIT`.
+    pub reuse_address: bool,
+    /// `SO_REUSEPORT`, so that several exporters can share the addresses.
+    pub reuse_port: bool,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        SocketOptions {
+            reuse_address: true,
+            reuse_port: false,
+        }
+    }
+}
+
+/// Bind a [`TcpListener`] to `addr` with `options`. An IPv6 listener accepts IPv4
+/// connections too unless `v6_only` is set.
+pub fn bind(addr: SocketAddr, options: &SocketOptions, v6_only: bool) -> io::Result<TcpListener> {
+    let family = match addr {
+        SocketAddr::V4(_) => AddressFamily::Inet,
+        SocketAddr::V6(_) => AddressFamily::Inet6,
+    };
+    let fd = socket(family, SockType::Stream, SockFlag::empty(), None)?;
+    // Owned right away so that the socket is closed on errors
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    fcntl(fd.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+
+    // The options must be set before binding to take effect
+    setsockopt(fd.as_raw_fd(), ReuseAddr, &options.reuse_address)?;
+    setsockopt(fd.as_raw_fd(), ReusePort, &options.reuse_port)?;
+    match addr {
+        SocketAddr::V4(addr) => bind_socket(fd.as_raw_fd(), &SockaddrIn::from(addr))?,
+        SocketAddr::V6(addr) => {
+            setsockopt(fd.as_raw_fd(), Ipv6V6Only, &v6_only)?;
+            bind_socket(fd.as_raw_fd(), &SockaddrIn6::from(addr))?
+        }
+    }
+    listen(fd.as_raw_fd(), BACKLOG)?;
+
+    Ok(TcpListener::from(fd))
+}
+
+/// Bind a [`TcpListener`] to each of `addrs`. The IPv6 listeners only accept IPv6
+/// connections if an IPv4 address is given too, so that e.g. `0.0.0.0:9753` and
+/// `[::]:9753` can be bound together for dual-stack.
+pub fn bind_all(addrs: &[SocketAddr], options: &SocketOptions) -> io::Result<Vec<TcpListener>> {
+    let v6_only = addrs.iter().any(|addr| addr.is_ipv4());
+    addrs
+        .iter()
+        .map(|addr| {
+            bind(*addr, options, v6_only)
+                .map_err(|e| io::Error::new(e.kind(), format!("Failed to bind {addr}: {e}")))
+        })
+        .collect()
+}
 
-/// Bind a [`TcpListener`] to addr with `SO_REUSEADDR` set to true.
-pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<TcpListener> {
-    let listener = TcpListener::bind(addr)?;
+#[cfg(test)]
+mod tests_tcp_listener {
+    use crate::tcp_listener::{bind_all, SocketOptions};
+    use std::net::{SocketAddr, TcpStream};
 
-    setsockopt(listener.as_raw_fd(), ReuseAddr, &true)?;
+    #[test]
+    fn test_bind_all() {
+        let addrs: Vec<SocketAddr> = vec!["127.0.0.1:0".parse().unwrap()];
+        let listeners = bind_all(&addrs, &SocketOptions::default()).unwrap();
+        let addr = listeners[0].local_addr().unwrap();
+        assert!(TcpStream::connect(addr).is_ok());
 
-    Ok(listener)
+        // The same port can be bound twice with `SO_REUSEPORT`
+        let options = SocketOptions {
+            reuse_port: true,
+            ..Default::default()
+        };
+        let first = bind_all(&addrs, &options).unwrap();
+        let addrs = vec![first[0].local_addr().unwrap()];
+        assert!(bind_all(&addrs, &options).is_ok());
+        assert!(bind_all(&addrs, &SocketOptions::default()).is_err());
+    }
 }
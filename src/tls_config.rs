@@ -0,0 +1,55 @@
+//!
+//! TLS configuration of the metrics server.
+//!
+//! HTTP/2 is negotiated with ALPN, falling back to HTTP/1.1 for clients not supporting it.
+//! The PEM loaders are shared with the client side in `postgres_tls`.
+//!
+use anyhow::{bail, Context};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+
+/// Loads a certificate chain in PEM from `path`.
+pub fn load_certs(path: &Path) -> anyhow::Result<Vec<Certificate>> {
+    let mut reader = BufReader::new(
+        File::open(path).with_context(|| format!("Failed to open {}", path.display()))?,
+    );
+    let certs: Vec<Certificate> = rustls_pemfile::certs(&mut reader)?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if certs.is_empty() {
+        bail!("No certificate found in {}", path.display());
+    }
+    Ok(certs)
+}
+
+/// Loads the first private key in PEM from `path`.
+pub fn load_private_key(path: &Path) -> anyhow::Result<PrivateKey> {
+    let mut reader = BufReader::new(
+        File::open(path).with_context(|| format!("Failed to open {}", path.display()))?,
+    );
+    loop {
+        match rustls_pemfile::read_one(&mut reader)? {
+            Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => return Ok(PrivateKey(key)),
+            Some(_) => continue,
+            None => bail!("No private key found in {}", path.display()),
+        }
+    }
+}
+
+/// Loads a certificate chain and a private key in PEM from `cert_path` and `key_path`.
+pub fn load_server_config(cert_path: &Path, key_path: &Path) -> anyhow::Result<ServerConfig> {
+    let certs = load_certs(cert_path)?;
+    let key = load_private_key(key_path)?;
+
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(config)
+}
@@ -0,0 +1,248 @@
+//!
+//! `top` subcommand rendering key metrics in the terminal.
+//!
+//! During an incident, Grafana may be unavailable, so `pg_stats_exporter top` collects a few
+//! key metrics every interval and redraws them like `top`. Session ages and CPU times come
+//! from the collectors, and the rest from a small query on the cumulative statistics.
+//!
+use prometheus::proto::MetricFamily;
+use std::fmt::Write as _;
+use std::time::{Duration, Instant};
+use tokio_postgres::Client;
+
+use crate::cancellation::ScrapeCancellation;
+use crate::compatibility;
+use crate::metrics::{self, CollectorOptions};
+use crate::pool::Pool;
+use crate::postgres_connection::PgConnectionConfig;
+use crate::privileges::PrivilegeReport;
+
+/// Collectors whose metrics are shown.
+const COLLECTORS: &[&str] = &[
+    "cpustats",
+    "idle_in_transaction",
+    "query_runtime",
+    "deadlocks",
+];
+
+#[derive(Debug, Default)]
+struct Sample {
+    backends: i64,
+    /// Cumulative number of committed and rolled back transactions.
+    xacts: i64,
+    /// Replay lag of the most lagging standby, if any.
+    replication_lag: Option<f64>,
+    active: u64,
+    idle_in_transaction: u64,
+    /// Cumulative number of deadlocks.
+    deadlocks: f64,
+    /// Cumulative CPU times from statsinfo as `(system, idle, iowait)`.
+    cpu: Option<(f64, f64, f64)>,
+}
+
+async fn query_sample(conn: &Client) -> Result<Sample, tokio_postgres::Error> {
+    let row = conn
+        .query_one(
+            "
+        SELECT
+            (SELECT count(*) FROM pg_stat_activity WHERE backend_type = 'client backend'),
+            (SELECT sum(xact_commit + xact_rollback)::bigint FROM pg_stat_database),
+            (SELECT extract(epoch FROM max(replay_lag))::float8 FROM pg_stat_replication)
+        ",
+            &[],
+        )
+        .await?;
+    Ok(Sample {
+        backends: row.get(0),
+        xacts: row.get::<_, Option<i64>>(1).unwrap_or_default(),
+        replication_lag: row.get(2),
+        ..Default::default()
+    })
+}
+
+/// Fills `sample` with the values in `metrics` collected by `COLLECTORS`.
+fn read_metrics(sample: &mut Sample, metrics: &[MetricFamily]) {
+    let mut cpu = (None, None, None);
+    for family in metrics {
+        let name = family.get_name();
+        let histogram_count = || {
+            family
+                .get_metric()
+                .iter()
+                .map(|m| m.get_histogram().get_sample_count())
+                .sum()
+        };
+        match name {
+            "pg_stat_activity_query_age_seconds" => sample.active = histogram_count(),
+            "pg_stat_activity_idle_in_transaction_age_seconds" => {
+                sample.idle_in_transaction = histogram_count()
+            }
+            "pg_stat_database_deadlocks_total" => {
+                sample.deadlocks = family
+                    .get_metric()
+                    .iter()
+                    .map(|m| m.get_counter().get_value())
+                    .sum()
+            }
+            "pg_statsinfo_cpu_seconds_total" => {
+                for m in family.get_metric() {
+                    let mode = m.get_label().iter().find(|l| l.get_name() == "mode");
+                    let value = m.get_counter().get_value();
+                    let total = match mode.map(|l| l.get_value()) {
+                        Some("system") => &mut cpu.0,
+                        Some("idle") => &mut cpu.1,
+                        Some("iowait") => &mut cpu.2,
+                        _ => continue,
+                    };
+                    *total = Some(total.unwrap_or(0.0) + value);
+                }
+            }
+            _ => {}
+        }
+    }
+    if let (Some(system), Some(idle), Some(iowait)) = cpu {
+        sample.cpu = Some((system, idle, iowait));
+    }
+}
+
+/// Renders `current`, with rates computed from `previous` taken `elapsed` before if any.
+fn render(address: &str, current: &Sample, previous: Option<(&Sample, Duration)>) -> String {
+    let rate = |f: &dyn Fn(&Sample) -> f64| match previous {
+        Some((previous, elapsed)) if !elapsed.is_zero() => {
+            format!(
+                "{:.1}",
+                (f(current) - f(previous)).max(0.0) / elapsed.as_secs_f64()
+            )
+        }
+        _ => "-".to_string(),
+    };
+
+    let mut out = String::new();
+    let _ = writeln!(out, "pg_stats_exporter top - {address}");
+    let _ = writeln!(out);
+    let _ = writeln!(
+        out,
+        "Sessions: {} total, {} active, {} idle in transaction",
+        current.backends, current.active, current.idle_in_transaction
+    );
+    let _ = writeln!(
+        out,
+        "TPS: {}    Deadlocks/s: {}",
+        rate(&|s| s.xacts as f64),
+        rate(&|s| s.deadlocks)
+    );
+    let _ = writeln!(
+        out,
+        "Replication lag: {}",
+        current
+            .replication_lag
+            .map_or("-".to_string(), |lag| format!("{lag:.3}s"))
+    );
+    let previous_cpu = previous.and_then(|(previous, elapsed)| Some((previous.cpu?, elapsed)));
+    let cpu = |f: fn(&(f64, f64, f64)) -> f64| match (&current.cpu, previous_cpu) {
+        (Some(cpu), Some((previous, elapsed))) if !elapsed.is_zero() => {
+            format!(
+                "{:.1}",
+                (f(cpu) - f(&previous)).max(0.0) / elapsed.as_secs_f64()
+            )
+        }
+        _ => "-".to_string(),
+    };
+    let _ = writeln!(
+        out,
+        "CPU seconds/s: {} system, {} idle, {} iowait",
+        cpu(|c| c.0),
+        cpu(|c| c.1),
+        cpu(|c| c.2)
+    );
+    out
+}
+
+/// Redraws the key metrics of `postgres` every `interval` until interrupted, or `iterations`
+/// times if set.
+pub async fn run(
+    postgres: &PgConnectionConfig,
+    options: &CollectorOptions,
+    interval: Duration,
+    iterations: Option<u64>,
+) -> anyhow::Result<()> {
+    // Skip the collectors the role lacks the privileges for instead of failing
+    let conn = postgres.connect().await?;
+    compatibility::init(&conn, options).await?;
+    let report = PrivilegeReport::check(&conn, options).await?;
+    let _ = options.function_fallbacks.set(report.function_fallbacks());
+
+    let pool = Pool::default();
+
+    let mut previous: Option<(Sample, Instant)> = None;
+    let mut n = 0;
+    loop {
+        let metrics = metrics::gather_collectors(
+            &pool,
+            postgres,
+            options,
+            COLLECTORS,
+            &ScrapeCancellation::default(),
+        )
+        .await?;
+        let conn = pool.get(postgres).await?;
+        let mut sample = query_sample(&conn).await?;
+        read_metrics(&mut sample, &metrics);
+        let now = Instant::now();
+
+        let screen = render(
+            &postgres.raw_address(),
+            &sample,
+            previous
+                .as_ref()
+                .map(|(sample, time)| (sample, now.duration_since(*time))),
+        );
+        // Clear the screen and move the cursor home before redrawing
+        print!("\x1b[2J\x1b[H{screen}");
+        previous = Some((sample, now));
+
+        n += 1;
+        if iterations.is_some_and(|iterations| n >= iterations) {
+            return Ok(());
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests_render {
+    use crate::top::{render, Sample};
+    use std::time::Duration;
+
+    #[test]
+    fn test_render() {
+        let previous = Sample {
+            xacts: 100,
+            cpu: Some((10.0, 100.0, 0.0)),
+            ..Default::default()
+        };
+        let current = Sample {
+            backends: 5,
+            xacts: 300,
+            replication_lag: Some(0.25),
+            active: 2,
+            idle_in_transaction: 1,
+            cpu: Some((30.0, 280.0, 2.0)),
+            ..Default::default()
+        };
+        let screen = render(
+            "localhost:5432",
+            &current,
+            Some((&previous, Duration::from_secs(2))),
+        );
+        assert!(screen.contains("Sessions: 5 total, 2 active, 1 idle in transaction"));
+        assert!(screen.contains("TPS: 100.0    Deadlocks/s: 0.0"));
+        assert!(screen.contains("Replication lag: 0.250s"));
+        assert!(screen.contains("CPU seconds/s: 10.0 system, 90.0 idle, 1.0 iowait"));
+
+        // Rates need two samples
+        let screen = render("localhost:5432", &current, None);
+        assert!(screen.contains("TPS: -"));
+        assert!(screen.contains("CPU seconds/s: - system"));
+    }
+}
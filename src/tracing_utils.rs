@@ -11,6 +11,7 @@
 //! ```rust,no_run
 //! use tracing_subscriber::prelude::*;
 //! use tracing_opentelemetry::OpenTelemetryLayer;
+//! # use pg_stats_exporter::tracing_utils;
 //!
 //! #[tokio::main]
 //! async fn main() {
@@ -34,10 +35,20 @@
 //! ```
 
 use opentelemetry;
+use opentelemetry::sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry::sdk::trace::{BatchSpanProcessor, EvictedQueue, TracerProvider};
 use opentelemetry::sdk::Resource;
-use opentelemetry::KeyValue;
+use opentelemetry::trace::{Status, TracerProvider as _};
+use opentelemetry::{KeyValue, Value};
 use opentelemetry_otlp::WithExportConfig;
-use opentelemetry_otlp::{OTEL_EXPORTER_OTLP_ENDPOINT, OTEL_EXPORTER_OTLP_TRACES_ENDPOINT};
+use opentelemetry_otlp::{
+    SpanExporterBuilder, OTEL_EXPORTER_OTLP_ENDPOINT, OTEL_EXPORTER_OTLP_TRACES_ENDPOINT,
+};
+use std::borrow::Cow;
+use std::future::Future;
+use std::pin::Pin;
+
+use crate::redaction;
 
 pub use tracing_opentelemetry::OpenTelemetryLayer;
 
@@ -151,17 +162,91 @@ fn init_tracing_internal(service_name: String) -> opentelemetry::sdk::trace::Tra
         opentelemetry::sdk::propagation::TraceContextPropagator::new(),
     );
 
-    opentelemetry_otlp::new_pipeline()
-        .tracing()
-        .with_exporter(exporter)
-        .with_trace_config(
+    // As `install_batch` of the OTLP pipeline, but with the secrets masked before export
+    let exporter = SpanExporterBuilder::from(exporter)
+        .build_span_exporter()
+        .expect("could not initialize opentelemetry exporter");
+    let processor =
+        BatchSpanProcessor::builder(RedactingExporter(exporter), opentelemetry::runtime::Tokio)
+            .build();
+    let provider = TracerProvider::builder()
+        .with_span_processor(processor)
+        .with_config(
             opentelemetry::sdk::trace::config().with_resource(Resource::new(vec![KeyValue::new(
                 opentelemetry_semantic_conventions::resource::SERVICE_NAME,
                 service_name,
             )])),
         )
-        .install_batch(opentelemetry::runtime::Tokio)
-        .expect("could not initialize opentelemetry exporter")
+        .build();
+    let tracer =
+        provider.versioned_tracer("opentelemetry-otlp", Some(env!("CARGO_PKG_VERSION")), None);
+    let _ = opentelemetry::global::set_tracer_provider(provider);
+    tracer
+}
+
+/// Masks the secrets in the attributes and events of spans as in the logs on stderr, since
+/// the OpenTelemetry layer records the fields of the events as they are.
+#[derive(Debug)]
+struct RedactingExporter<E>(E);
+
+impl<E: SpanExporter> SpanExporter for RedactingExporter<E> {
+    fn export(
+        &mut self,
+        mut batch: Vec<SpanData>,
+    ) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+        for span in &mut batch {
+            redact_span(span);
+        }
+        self.0.export(batch)
+    }
+
+    fn shutdown(&mut self) {
+        self.0.shutdown()
+    }
+
+    fn force_flush(&mut self) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+        self.0.force_flush()
+    }
+}
+
+fn redact_value(value: &Value) -> Option<Value> {
+    match value {
+        Value::String(s) => match redaction::mask_secrets(s.as_str()) {
+            Cow::Owned(masked) => Some(Value::String(masked.into())),
+            Cow::Borrowed(_) => None,
+        },
+        _ => None,
+    }
+}
+
+fn redact_span(span: &mut SpanData) {
+    // A key inserted again replaces the value
+    let masked: Vec<KeyValue> = span
+        .attributes
+        .iter()
+        .filter_map(|(key, value)| Some(KeyValue::new(key.clone(), redact_value(value)?)))
+        .collect();
+    for kv in masked {
+        span.attributes.insert(kv);
+    }
+
+    let mut events: Vec<_> = std::mem::replace(&mut span.events, EvictedQueue::new(u32::MAX))
+        .into_iter()
+        .collect();
+    for event in &mut events {
+        // The message of a `tracing` event is the name of the OpenTelemetry event
+        event.name = redaction::mask_secrets(&event.name).into_owned().into();
+        for kv in &mut event.attributes {
+            if let Some(value) = redact_value(&kv.value) {
+                kv.value = value;
+            }
+        }
+    }
+    span.events.append_vec(&mut events);
+
+    if let Status::Error { description } = &mut span.status {
+        *description = redaction::mask_secrets(description).into_owned().into();
+    }
 }
 
 // Shutdown trace pipeline gracefully, so that it has a chance to send any
@@ -0,0 +1,59 @@
+//!
+//! Plain-text dump of the metrics over a Unix socket.
+//!
+//! Host agents, e.g., custom checks of Datadog or Telegraf, can read the metrics in the
+//! Prometheus text format without HTTP plumbing: every connection to the socket gets a
+//! single dump and is closed.
+//!
+use anyhow::bail;
+use prometheus::{Encoder, TextEncoder};
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::net::UnixListener;
+
+use crate::cancellation::ScrapeCancellation;
+use crate::routes::{self, State};
+
+async fn dump(state: Arc<State>) -> anyhow::Result<Vec<u8>> {
+    let metrics = routes::collect_metrics(&state, &ScrapeCancellation::default()).await?;
+    let mut buf = vec![];
+    TextEncoder::new().encode(&metrics, &mut buf)?;
+    Ok(buf)
+}
+
+/// Binds `path`, replacing a stale socket left by a previous run, and serves dumps. Any
+/// other file at `path` is left alone, since it is likely a mistyped path.
+pub fn spawn(path: &Path, state: Arc<State>) -> anyhow::Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+        Ok(_) => bail!("{} exists and is not a socket", path.display()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    let listener = UnixListener::bind(path)?;
+    tokio::spawn(async move {
+        loop {
+            let mut stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    tracing::warn!("failed to accept a connection on the unix socket: {e:#}");
+                    continue;
+                }
+            };
+            let state = state.clone();
+            tokio::spawn(async move {
+                let res = match dump(state).await {
+                    Ok(buf) => stream.write_all(&buf).await.map_err(|e| e.into()),
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = res {
+                    tracing::warn!("failed to dump metrics on the unix socket: {e:#}");
+                }
+                let _ = stream.shutdown().await;
+            });
+        }
+    });
+    Ok(())
+}
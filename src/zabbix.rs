@@ -0,0 +1,224 @@
+//!
+//! Push of the metrics to Zabbix as trapper items.
+//!
+//! Every interval, the metrics are collected and sent with the Zabbix sender protocol, so
+//! that sites standardized on Zabbix can monitor PostgreSQL without Prometheus. A sample is
+//! sent as the item `<prefix><name>[<label values>]` of the configured host, e.g.,
+//! `pg_stat_database_deadlocks_total[postgres]`, and histograms as their `_count` and
+//! `_sum`. The items have to be created as "Zabbix trapper" items on the server.
+//!
+//! See <https://www.zabbix.com/documentation/current/en/manual/appendix/protocols/zabbix_sender>
+//!
+use anyhow::bail;
+use prometheus::proto::{MetricFamily, MetricType};
+use serde::Serialize;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crate::cancellation::ScrapeCancellation;
+use crate::routes::{self, State};
+
+const HEADER: &[u8] = b"ZBXD\x01";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, PartialEq, Serialize)]
+struct Item {
+    host: String,
+    key: String,
+    value: String,
+    clock: u64,
+}
+
+#[derive(Serialize)]
+struct Request<'a> {
+    request: &'static str,
+    data: &'a [Item],
+}
+
+/// Where and as which host the items are sent.
+#[derive(Clone, Debug)]
+pub struct ZabbixTarget {
+    /// Address of the Zabbix server or proxy, e.g., `zabbix:10051`.
+    pub server: String,
+    /// Host name the items belong to in Zabbix.
+    pub host: String,
+    /// Prefix of the item keys.
+    pub key_prefix: String,
+}
+
+fn key(prefix: &str, name: &str, label_values: &[&str]) -> String {
+    if label_values.is_empty() {
+        return format!("{prefix}{name}");
+    }
+    // Parameters with special characters have to be quoted
+    let params: Vec<String> = label_values
+        .iter()
+        .map(|v| {
+            if v.contains([',', '[', ']', '"', ' ']) {
+                format!("\"{}\"", v.replace('"', "\\\""))
+            } else {
+                v.to_string()
+            }
+        })
+        .collect();
+    format!("{prefix}{name}[{}]", params.join(","))
+}
+
+/// Converts `metrics` into the items of `target`.
+fn items(metrics: &[MetricFamily], target: &ZabbixTarget, clock: u64) -> Vec<Item> {
+    let mut items = vec![];
+    let mut push = |name: &str, label_values: &[&str], value: f64| {
+        items.push(Item {
+            host: target.host.clone(),
+            key: key(&target.key_prefix, name, label_values),
+            value: value.to_string(),
+            clock,
+        })
+    };
+    for family in metrics {
+        let name = family.get_name();
+        for m in family.get_metric() {
+            let label_values: Vec<&str> = m.get_label().iter().map(|l| l.get_value()).collect();
+            match family.get_field_type() {
+                MetricType::COUNTER => push(name, &label_values, m.get_counter().get_value()),
+                MetricType::GAUGE => push(name, &label_values, m.get_gauge().get_value()),
+                MetricType::HISTOGRAM => {
+                    let h = m.get_histogram();
+                    push(
+                        &format!("{name}_count"),
+                        &label_values,
+                        h.get_sample_count() as f64,
+                    );
+                    push(&format!("{name}_sum"), &label_values, h.get_sample_sum());
+                }
+                MetricType::SUMMARY => {
+                    let s = m.get_summary();
+                    push(
+                        &format!("{name}_count"),
+                        &label_values,
+                        s.get_sample_count() as f64,
+                    );
+                    push(&format!("{name}_sum"), &label_values, s.get_sample_sum());
+                }
+                MetricType::UNTYPED => {}
+            }
+        }
+    }
+    items
+}
+
+/// Frames `payload` with the header and the length of the protocol.
+fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(HEADER.len() + 8 + payload.len());
+    buf.extend_from_slice(HEADER);
+    buf.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    buf.extend_from_slice(payload);
+    buf
+}
+
+/// Sends `items` to `server` and returns the `info` of its response, e.g.,
+/// `processed: 10; failed: 0; total: 10; seconds spent: 0.000055`.
+fn send(server: &str, items: &[Item]) -> anyhow::Result<String> {
+    let payload = serde_json::to_vec(&Request {
+        request: "sender data",
+        data: items,
+    })?;
+    let mut stream = TcpStream::connect(server)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    stream.write_all(&frame(&payload))?;
+
+    let mut response = vec![];
+    stream.read_to_end(&mut response)?;
+    let Some(body) = response.get(HEADER.len() + 8..) else {
+        bail!("truncated response");
+    };
+    let body: serde_json::Value = serde_json::from_slice(body)?;
+    if body["response"] != "success" {
+        bail!("server responded {body}");
+    }
+    Ok(body["info"].as_str().unwrap_or_default().to_string())
+}
+
+/// Pushes the metrics to `target` every `interval`.
+pub fn spawn(target: ZabbixTarget, interval: Duration, state: Arc<State>) {
+    tokio::spawn(async move {
+        loop {
+            // Pushes count as scrapes for `--max-concurrent-scrapes`
+            let collected = match routes::try_acquire_scrape_permit(&state) {
+                Ok(_permit) => {
+                    routes::collect_metrics(&state, &ScrapeCancellation::default()).await
+                }
+                Err(e) => Err(e),
+            };
+            let res = match collected {
+                Ok(metrics) => {
+                    let target = target.clone();
+                    let clock = SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs();
+                    // The Zabbix protocol is spoken over a blocking socket
+                    tokio::task::spawn_blocking(move || {
+                        send(&target.server, &items(&metrics, &target, clock))
+                    })
+                    .await
+                }
+                Err(e) => Ok(Err(e.into())),
+            };
+            match res {
+                Ok(Ok(info)) => tracing::debug!("pushed metrics to Zabbix: {info}"),
+                Ok(Err(e)) => tracing::warn!("failed to push metrics to Zabbix: {e:#}"),
+                Err(e) => tracing::warn!("failed to push metrics to Zabbix: {e:#}"),
+            }
+            tokio::time::sleep(interval).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests_zabbix {
+    use crate::zabbix::{frame, items, Item, ZabbixTarget};
+    use prometheus::{core::Collector, Histogram, HistogramOpts, IntGauge, IntGaugeVec, Opts};
+
+    #[test]
+    fn test_items() {
+        let target = ZabbixTarget {
+            server: "zabbix:10051".to_string(),
+            host: "db1".to_string(),
+            key_prefix: "pgse.".to_string(),
+        };
+        let up = IntGauge::new("pg_up", "help").unwrap();
+        up.set(1);
+        let rows = IntGaugeVec::new(Opts::new("pg_rows", "help"), &["schema", "table"]).unwrap();
+        rows.with_label_values(&["public", "a b"]).set(3);
+        let wait = Histogram::with_opts(HistogramOpts::new("pg_wait_seconds", "help")).unwrap();
+        wait.observe(0.5);
+
+        let mut metrics = up.collect();
+        metrics.append(&mut rows.collect());
+        metrics.append(&mut wait.collect());
+        let item = |key: &str, value: &str| Item {
+            host: "db1".to_string(),
+            key: key.to_string(),
+            value: value.to_string(),
+            clock: 100,
+        };
+        assert_eq!(
+            items(&metrics, &target, 100),
+            vec![
+                item("pgse.pg_up", "1"),
+                item("pgse.pg_rows[public,\"a b\"]", "3"),
+                item("pgse.pg_wait_seconds_count", "1"),
+                item("pgse.pg_wait_seconds_sum", "0.5"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_frame() {
+        assert_eq!(frame(b"{}"), b"ZBXD\x01\x02\x00\x00\x00\x00\x00\x00\x00{}");
+    }
+}